@@ -0,0 +1,15 @@
+// Runs random ROMs with random key input on every backend. Panics are bugs,
+// faults have to be reported through chip8::Error and agree between backends.
+#![no_main]
+
+use emu_rs::emu::arch::chip8::fuzz;
+use emu_rs::emu::arch::chip8::lockstep::Backend;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let interpreter = fuzz::run(data, Backend::Interpreter);
+
+    assert_eq!(fuzz::run(data, Backend::Cached), interpreter);
+    #[cfg(feature = "jit")]
+    assert_eq!(fuzz::run(data, Backend::Jit), interpreter);
+});
@@ -0,0 +1,26 @@
+// Loads arbitrary data as a ROM and as an input movie
+#![no_main]
+
+use emu_rs::emu::arch::chip8::{Keyboard, Movie, CPU};
+use emu_rs::emu::core::FrameBuffer;
+use libfuzzer_sys::fuzz_target;
+
+use std::sync::{Arc, Mutex};
+
+fuzz_target!(|data: &[u8]| {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    let _ = cpu.load_program(data);
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(movie) = Movie::parse(text) {
+            let mut keyboard = Keyboard::new();
+            for frame in 0..movie.len().min(1000) {
+                movie.apply(frame, &mut keyboard);
+            }
+            assert_eq!(Movie::parse(&movie.to_string()), Ok(movie));
+        }
+    }
+});
@@ -0,0 +1,31 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8;
+
+const USAGE: &str = "Usage: emu_rs asm <source> -o <rom>
+
+Assembles Cowgod style mnemonics as printed by `emu_rs disasm` into a ROM
+loaded at 0x200. Labels end in ':', comments start with ';', DB and DW emit
+raw bytes and words.
+
+Options:
+  -o <file>   ROM to write";
+
+// Entry point of the asm subcommand, `args` starts after "asm"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let source_path = args.positional("source file to assemble");
+    let mut output = None;
+
+    while let Some(flag) = args.next_arg() {
+        match flag {
+            "-o" => output = Some(args.value(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    let output = output.unwrap_or_else(|| args.fail("Please specify the ROM to write with -o"));
+    let rom = chip8::assemble(&cli::read_to_string(source_path))
+        .unwrap_or_else(|e| cli::error(&format!("{}: {}", source_path, e)));
+    cli::write(output, &rom);
+    println!("Assembled {} bytes into {}", rom.len(), output);
+}
@@ -0,0 +1,55 @@
+use emu_rs::emu::core::Buzzer;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Plays the buzzer on the default output device while `sounding` is set.
+// Sound stops when the returned stream is dropped, which has to happen on
+// the thread that opened it.
+pub fn open(sounding: Arc<AtomicBool>) -> Result<Stream, String> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("No audio output device")?;
+    let config = device.default_output_config().map_err(|e| e.to_string())?;
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => build::<f32>(&device, &config.into(), sounding),
+        SampleFormat::I16 => build::<i16>(&device, &config.into(), sounding),
+        SampleFormat::U16 => build::<u16>(&device, &config.into(), sounding),
+        format => return Err(format!("Unsupported sample format {}", format)),
+    }?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    sounding: Arc<AtomicBool>,
+) -> Result<Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let sample_rate = config.sample_rate.0;
+    let mut buzzer = Buzzer::default();
+    let mut samples = Vec::new();
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                samples.resize(data.len(), 0.0);
+                let on = sounding.load(Ordering::Relaxed);
+                buzzer.fill_channels(&mut samples, channels, sample_rate, on);
+                for (out, &sample) in data.iter_mut().zip(samples.iter()) {
+                    *out = T::from_sample(sample);
+                }
+            },
+            |e| eprintln!("Audio: {}", e),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
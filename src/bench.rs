@@ -0,0 +1,134 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::{Error, Keyboard, CPU};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: emu_rs bench <rom> [cycles] [options]
+
+Runs a ROM headless with every interpreter and reports their throughput.
+Only instructions which ran count, the cached interpreter skips the rest
+of a frame at a jump to itself. Busy ROMs like tests/fixtures/busy-alu.ch8
+show the speed of the interpreters themselves.
+
+Options:
+  --cycles <n>   Instructions to run (default 10000000)";
+
+// How long `cycles` cycles took and how many instructions ran in them
+fn measure(rom: &[u8], cycles: u64, cached: bool) -> Result<(Duration, u64), Error> {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.seed(0);
+    cpu.load_program(rom)?;
+
+    let mut executed = 0;
+    let start = Instant::now();
+
+    // Keep the timers moving like the 540 Hz / 60 Hz main loop does
+    for _ in 0..cycles / 9 {
+        if cached {
+            executed += cpu.execute_cached_n(9)? as u64;
+        } else {
+            for _ in 0..9 {
+                cpu.execute()?;
+            }
+            executed += 9;
+        }
+
+        cpu.tick();
+    }
+
+    Ok((start.elapsed(), executed))
+}
+
+// Entry point of the bench subcommand, `args` starts after "bench"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let rom_path = args.positional("rom to benchmark");
+    let mut cycles = 10_000_000;
+
+    while let Some(arg) = args.next_arg() {
+        match arg {
+            "--cycles" => cycles = args.number(arg),
+            // The cycle count used to be the only, positional, option
+            _ => {
+                cycles = arg
+                    .parse()
+                    .unwrap_or_else(|_| args.fail(&format!("Unknown option {}", arg)))
+            }
+        }
+    }
+
+    if cycles < 9 {
+        args.fail("Benchmarks run at least one frame of 9 cycles");
+    }
+
+    if let Err(e) = compare(&cli::load_rom(rom_path).program, cycles) {
+        cli::error(&e.to_string());
+    }
+}
+
+fn compare(rom: &[u8], cycles: u64) -> Result<(), Error> {
+    let cycles = cycles - cycles % 9;
+    let plain = measure(rom, cycles, false)?;
+    let cached = measure(rom, cycles, true)?;
+
+    let mips =
+        |(time, executed): (Duration, u64)| executed as f64 / time.as_secs_f64() / 1_000_000.0;
+    let report = |name: &str, result: (Duration, u64)| {
+        println!(
+            "{:<12} {:>10.2?} {:>10} instructions {:>10.2} MIPS",
+            name,
+            result.0,
+            result.1,
+            mips(result)
+        );
+    };
+
+    report("interpreter", plain);
+    report("cached", cached);
+    // Instructions per second mean little next to skipped ones
+    if cached.1 == cycles {
+        println!("speedup      {:>10.2}x", mips(cached) / mips(plain));
+    } else {
+        println!(
+            "The cached interpreter skipped {} cycles in idle loops, so it took {:.2}x less time.",
+            cycles - cached.1,
+            plain.0.as_secs_f64() / cached.0.as_secs_f64()
+        );
+        println!("Busy ROMs without idle loops compare the interpreters themselves.");
+    }
+
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    {
+        let jit = measure_jit(rom, cycles)?;
+        report("jit", jit);
+        println!("speedup      {:>10.2}x", mips(jit) / mips(plain));
+    }
+
+    Ok(())
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+fn measure_jit(rom: &[u8], cycles: u64) -> Result<(Duration, u64), Error> {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.seed(0);
+    cpu.load_program(rom)?;
+
+    let mut jit = emu_rs::emu::arch::chip8::Jit::new();
+    let mut executed = 0;
+    let start = Instant::now();
+
+    for _ in 0..cycles / 9 {
+        executed += jit.run(&mut cpu, 9)? as u64;
+        cpu.tick();
+    }
+
+    Ok((start.elapsed(), executed))
+}
@@ -0,0 +1,110 @@
+use emu_rs::emu::arch::chip8::database::Database;
+use emu_rs::emu::arch::chip8::loader::{self, Rom};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+// Argument handling shared by the subcommands. Usage errors print the
+// subcommand's usage and exit with 2, other errors exit with 1.
+pub struct Args<'a> {
+    iter: std::slice::Iter<'a, String>,
+    usage: &'static str,
+}
+
+impl<'a> Args<'a> {
+    // `--help` as the first argument prints the usage and exits
+    pub fn new(args: &'a [String], usage: &'static str) -> Self {
+        if let Some("--help") | Some("-h") = args.first().map(String::as_str) {
+            println!("{}", usage);
+            process::exit(0);
+        }
+
+        Self {
+            iter: args.iter(),
+            usage,
+        }
+    }
+
+    pub fn next_arg(&mut self) -> Option<&'a str> {
+        self.iter.next().map(String::as_str)
+    }
+
+    pub fn fail(&self, message: &str) -> ! {
+        eprintln!("{}\n\n{}", message, self.usage);
+        process::exit(2);
+    }
+
+    // The value following `flag`
+    pub fn value(&mut self, flag: &str) -> &'a str {
+        match self.next_arg() {
+            Some(value) => value,
+            None => self.fail(&format!("Missing value for {}", flag)),
+        }
+    }
+
+    pub fn number<T: FromStr>(&mut self, flag: &str) -> T {
+        let text = self.value(flag);
+        text.parse()
+            .unwrap_or_else(|_| self.fail(&format!("Invalid number for {}: {}", flag, text)))
+    }
+
+    // A leading positional argument such as the ROM
+    pub fn positional(&mut self, what: &str) -> &'a str {
+        match self.next_arg() {
+            Some(arg) if !arg.starts_with("--") => arg,
+            _ => self.fail(&format!("Please specify a {}", what)),
+        }
+    }
+}
+
+pub fn error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+// A ROM file, archive or cartridge, "-" reads it from stdin
+pub fn load_rom(path: &str) -> Rom {
+    loader::load(path).unwrap_or_else(|e| error(&e))
+}
+
+pub fn read_to_string(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| error(&format!("Can't read {}: {}", path, e)))
+}
+
+pub fn write(path: &str, contents: impl AsRef<[u8]>) {
+    fs::write(path, contents).unwrap_or_else(|e| error(&format!("Can't write {}: {}", path, e)))
+}
+
+// $XDG_CONFIG_HOME/emu_rs or ~/.config/emu_rs
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("emu_rs"))
+}
+
+// The built in ROM database plus the entries from `path`, or from
+// database.json in the config directory if it exists
+pub fn database(path: Option<&str>) -> Database {
+    let mut database = Database::builtin();
+    let (path, required) = match (path, config_dir()) {
+        (Some(path), _) => (PathBuf::from(path), true),
+        (None, Some(dir)) => (dir.join("database.json"), false),
+        (None, None) => return database,
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(text) => database.extend(
+            Database::parse(&text).unwrap_or_else(|e| error(&format!("{}: {}", path.display(), e))),
+        ),
+        Err(e) if !required && e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => error(&format!("Can't read {}: {}", path.display(), e)),
+    }
+
+    database
+}
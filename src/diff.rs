@@ -0,0 +1,84 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::lockstep::{self, Options, Side};
+use emu_rs::emu::arch::chip8::Movie;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "Usage: emu_rs diff <rom> [options]
+
+Runs two emulator instances in lockstep and reports the first instruction
+after which they disagree.
+
+Options:
+  --movie <file>    Replay keypad input from a movie file
+  --frames <n>      Number of 60 Hz frames to run (default 600)
+  --seed <n>        Seed for CXNN (default 0)
+  -a <side>         First instance (default interpreter:default)
+  -b <side>         Second instance (default cached:default)
+  --record <file>   Write a trace of side a instead of comparing
+  --replay <file>   Compare side a against a recorded trace
+
+A side is <backend>[:<quirk preset>] with backend interpreter, cached or jit.";
+
+// Entry point of the diff subcommand, `args` starts after "diff"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let rom_path = args.positional("rom to load");
+
+    let mut options = Options::default();
+    let mut movie = Movie::new();
+    let mut a = Side::parse("interpreter").unwrap();
+    let mut b = Side::parse("cached").unwrap();
+    let mut record = None;
+    let mut replay = None;
+
+    while let Some(flag) = args.next_arg() {
+        match flag {
+            "--movie" => {
+                let text = cli::read_to_string(args.value(flag));
+                movie = Movie::parse(&text).unwrap_or_else(|e| cli::error(&e));
+            }
+            "--frames" => options.frames = args.number(flag),
+            "--seed" => options.seed = args.number(flag),
+            "-a" => a = Side::parse(args.value(flag)).unwrap_or_else(|e| args.fail(&e)),
+            "-b" => b = Side::parse(args.value(flag)).unwrap_or_else(|e| args.fail(&e)),
+            "--record" => record = Some(args.value(flag)),
+            "--replay" => replay = Some(args.value(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    let rom = cli::load_rom(rom_path).program;
+
+    if let Some(path) = record {
+        let mut out = BufWriter::new(
+            File::create(path)
+                .unwrap_or_else(|e| cli::error(&format!("Can't create {}: {}", path, e))),
+        );
+        let cycles = lockstep::record(&rom, &movie, a, &options, &mut out)
+            .and_then(|cycles| out.flush().map(|_| cycles))
+            .unwrap_or_else(|e| cli::error(&format!("Can't write {}: {}", path, e)));
+        println!("Recorded {} cycles to {}", cycles, path);
+        return;
+    }
+
+    let result = if let Some(path) = replay {
+        let mut trace = BufReader::new(
+            File::open(path).unwrap_or_else(|e| cli::error(&format!("Can't open {}: {}", path, e))),
+        );
+        lockstep::replay(&rom, &movie, a, &options, &mut trace)
+            .unwrap_or_else(|e| cli::error(&format!("Can't read {}: {}", path, e)))
+    } else {
+        lockstep::run(&rom, &movie, a, b, &options).unwrap_or_else(|e| cli::error(&e.to_string()))
+    };
+
+    match result {
+        Ok(cycles) => println!("No divergence in {} cycles", cycles),
+        Err(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8;
+
+const USAGE: &str = "Usage: emu_rs disasm <rom> [-o <file>]
+
+Disassembles a ROM into source `emu_rs asm` assembles back into the same
+bytes. Code and data aren't told apart, every word is listed as the
+instruction it decodes to.
+
+Options:
+  -o <file>   Write the listing to a file instead of stdout";
+
+// Entry point of the disasm subcommand, `args` starts after "disasm"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let rom_path = args.positional("rom to disassemble");
+    let mut output = None;
+
+    while let Some(flag) = args.next_arg() {
+        match flag {
+            "-o" => output = Some(args.value(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    let listing = chip8::disassemble(&cli::load_rom(rom_path).program);
+    match output {
+        Some(path) => cli::write(path, listing),
+        None => print!("{}", listing),
+    }
+}
@@ -0,0 +1,365 @@
+use super::cpu::*;
+use super::quirks::*;
+
+use std::collections::BTreeSet;
+
+// Static analysis guessing the quirks a ROM was written for, used for ROMs
+// the ROM database doesn't know. Only code reachable from the entry point
+// is looked at, so data which happens to look like instructions doesn't
+// count. Every finding adds weight to the presets it agrees with, the
+// confidence is the share of the total weight the suggestion gets.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    // Bytes of program which fit between the entry point and the end of the
+    // platform's memory. XO-CHIP's 64 KiB are capped by `CPU::memory`.
+    pub fn max_rom_size(self) -> usize {
+        let memory = match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0xFFFF,
+        };
+        memory - PROGRAM_ENTRY as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub platform: Platform,
+    // Suggested preset, see `Quirks::preset`
+    pub preset: &'static str,
+    // 0 without any findings, 1 when all of them agree
+    pub confidence: f64,
+    pub reasons: Vec<String>,
+}
+
+impl Analysis {
+    pub fn quirks(&self) -> Quirks {
+        Quirks::preset(self.preset).unwrap()
+    }
+}
+
+// Findings naming more instructions only list the first few
+const EXAMPLES: usize = 3;
+
+struct Finding {
+    presets: &'static [&'static str],
+    weight: u32,
+    reason: String,
+}
+
+struct Rom<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Rom<'a> {
+    fn opcode(&self, address: usize) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_ENTRY as usize)?;
+        let bytes = self.bytes.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Start addresses of the instructions reachable from the entry point.
+    // BNNN and unknown opcodes end a path, their targets can't be known.
+    fn reachable(&self) -> BTreeSet<usize> {
+        let mut code = BTreeSet::new();
+        let mut pending = vec![PROGRAM_ENTRY as usize];
+
+        while let Some(address) = pending.pop() {
+            let opcode = match self.opcode(address) {
+                Some(opcode) if code.insert(address) => opcode,
+                _ => continue,
+            };
+
+            let next = address + 2;
+            let nnn = (opcode & 0xFFF) as usize;
+            match (opcode >> 12, opcode & 0xFF) {
+                (0x0, 0xEE) | (0x0, 0xFD) | (0xB, _) => {}
+                (0x1, _) => pending.push(nnn),
+                (0x2, _) => pending.extend_from_slice(&[nnn, next]),
+                (0x3, _) | (0x4, _) | (0x5, _) | (0x9, _) | (0xE, 0x9E) | (0xE, 0xA1) => {
+                    // A skipped XO-CHIP F000 NNNN is four bytes long
+                    let skipped = if self.opcode(next) == Some(0xF000) {
+                        4
+                    } else {
+                        2
+                    };
+                    pending.extend_from_slice(&[next, next + skipped]);
+                }
+                (0xF, _) if opcode == 0xF000 => pending.push(address + 4),
+                _ if is_known(opcode) => pending.push(next),
+                _ => {}
+            }
+        }
+
+        code
+    }
+}
+
+fn is_schip(opcode: u16) -> bool {
+    match opcode >> 12 {
+        // 00CN scroll down, 00FB-00FF scroll sideways, exit, resolution
+        0x0 => {
+            (opcode & 0xFFF0 == 0x00C0 && opcode != 0x00C0) || (0x00FB..=0x00FF).contains(&opcode)
+        }
+        // 16x16 sprites
+        0xD => opcode & 0xF == 0,
+        // Big font, flag registers
+        0xF => [0x30, 0x75, 0x85].contains(&(opcode & 0xFF)),
+        _ => false,
+    }
+}
+
+fn is_xo(opcode: u16) -> bool {
+    match opcode >> 12 {
+        // 00DN scroll up
+        0x0 => opcode & 0xFFF0 == 0x00D0,
+        // Save and load register ranges
+        0x5 => opcode & 0xF == 0x2 || opcode & 0xF == 0x3,
+        // Long I, audio pattern, planes, pitch
+        0xF => {
+            opcode == 0xF000 || opcode == 0xF002 || opcode & 0xFF == 0x01 || opcode & 0xFF == 0x3A
+        }
+        _ => false,
+    }
+}
+
+fn is_known(opcode: u16) -> bool {
+    let low = opcode & 0xFF;
+    let n = opcode & 0xF;
+    let standard = match opcode >> 12 {
+        0x0 => opcode == 0x00E0 || opcode == 0x00EE,
+        0x5 | 0x9 => n == 0,
+        0x8 => n <= 7 || n == 0xE,
+        0xE => low == 0x9E || low == 0xA1,
+        0xF => [0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65].contains(&low),
+        _ => true,
+    };
+    standard || is_schip(opcode) || is_xo(opcode)
+}
+
+// Registers an instruction writes, VF as a side effect not included
+fn writes(opcode: u16) -> u16 {
+    let x = 1 << ((opcode >> 8) & 0xF);
+    match (opcode >> 12, opcode & 0xFF) {
+        (0x6, _) | (0x7, _) | (0x8, _) | (0xC, _) => x,
+        (0xF, 0x07) | (0xF, 0x0A) => x,
+        // V0 to VX
+        (0xF, 0x65) | (0xF, 0x85) => (x << 1) - 1,
+        _ => 0,
+    }
+}
+
+fn list(addresses: &[(usize, u16)]) -> String {
+    let mut examples: Vec<String> = addresses
+        .iter()
+        .take(EXAMPLES)
+        .map(|(address, opcode)| format!("{:04X} at {:04X}", opcode, address))
+        .collect();
+    if addresses.len() > EXAMPLES {
+        examples.push(format!("{} more", addresses.len() - EXAMPLES));
+    }
+    examples.join(", ")
+}
+
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let rom = Rom { bytes: rom };
+    let code = rom.reachable();
+    let instructions: Vec<(usize, u16)> = code
+        .iter()
+        .map(|&address| (address, rom.opcode(address).unwrap()))
+        .collect();
+
+    let mut findings = Vec::new();
+    let mut platform = Platform::Chip8;
+
+    let xo: Vec<(usize, u16)> = instructions
+        .iter()
+        .copied()
+        .filter(|&(_, opcode)| is_xo(opcode))
+        .collect();
+    let schip: Vec<(usize, u16)> = instructions
+        .iter()
+        .copied()
+        .filter(|&(_, opcode)| is_schip(opcode))
+        .collect();
+    if !xo.is_empty() {
+        platform = Platform::XoChip;
+        findings.push(Finding {
+            presets: &["default"],
+            weight: 10,
+            reason: format!(
+                "XO-CHIP instructions, which emu_rs doesn't run: {}",
+                list(&xo)
+            ),
+        });
+    } else if !schip.is_empty() {
+        platform = Platform::SuperChip;
+        findings.push(Finding {
+            presets: &["schip"],
+            weight: 10,
+            reason: format!("SUPER-CHIP instructions: {}", list(&schip)),
+        });
+    }
+
+    let written = instructions
+        .iter()
+        .fold(0, |written, &(_, opcode)| written | writes(opcode));
+
+    // With VX only shifts VY doesn't matter, with shift_vy it's the source
+    for &(address, opcode) in &instructions {
+        let (x, y) = ((opcode >> 8) & 0xF, (opcode >> 4) & 0xF);
+        let shift = opcode >> 12 == 0x8 && (opcode & 0xF == 0x6 || opcode & 0xF == 0xE);
+        if !shift || x == y {
+            continue;
+        }
+
+        let loads_vy = code.contains(&(address - 2))
+            && rom
+                .opcode(address - 2)
+                .is_some_and(|previous| writes(previous) & 1 << y != 0);
+        if written & 1 << y == 0 {
+            findings.push(Finding {
+                presets: &["default", "schip"],
+                weight: 2,
+                reason: format!(
+                    "{:04X} at {:04X} shifts V{:X} while V{:X} is never set",
+                    opcode, address, x, y
+                ),
+            });
+        } else if loads_vy {
+            findings.push(Finding {
+                presets: &["chip8"],
+                weight: 2,
+                reason: format!(
+                    "{:04X} at {:04X} shifts V{:X} right after setting V{:X}",
+                    opcode, address, x, y
+                ),
+            });
+        }
+    }
+
+    // Loops storing or loading registers without ever touching I only make
+    // sense when FX55/FX65 advance I
+    let mut looped = BTreeSet::new();
+    for &(end, opcode) in &instructions {
+        let start = (opcode & 0xFFF) as usize;
+        if opcode >> 12 != 0x1 || start > end {
+            continue;
+        }
+
+        let body: Vec<(usize, u16)> = instructions
+            .iter()
+            .copied()
+            .filter(|&(address, _)| start <= address && address <= end)
+            .collect();
+        let sets_i = body.iter().any(|&(_, opcode)| {
+            opcode >> 12 == 0xA || (opcode >> 12 == 0xF && opcode & 0xFF == 0x1E)
+        });
+        if sets_i {
+            continue;
+        }
+
+        for &(address, opcode) in &body {
+            let low = opcode & 0xFF;
+            if opcode >> 12 == 0xF && (low == 0x55 || low == 0x65) && looped.insert(address) {
+                findings.push(Finding {
+                    presets: &["chip8"],
+                    weight: 3,
+                    reason: format!(
+                        "{:04X} at {:04X} runs in the loop {:04X}-{:04X}, which relies on I \
+                         advancing",
+                        opcode, address, start, end
+                    ),
+                });
+            }
+        }
+    }
+
+    // Constant coordinates are only followed within straight line code
+    let targets: BTreeSet<usize> = instructions
+        .iter()
+        .flat_map(|&(address, opcode)| match opcode >> 12 {
+            0x1 | 0x2 => vec![(opcode & 0xFFF) as usize],
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => vec![address + 4],
+            _ => vec![],
+        })
+        .collect();
+    let mut values: [Option<u8>; 16] = [None; 16];
+    let mut edges = Vec::new();
+    for &(address, opcode) in &instructions {
+        if targets.contains(&address) || !code.contains(&(address - 2)) {
+            values = [None; 16];
+        }
+
+        let (x, y, n) = (
+            ((opcode >> 8) & 0xF) as usize,
+            ((opcode >> 4) & 0xF) as usize,
+            (opcode & 0xF) as u32,
+        );
+        if opcode >> 12 == 0xD && n > 0 {
+            if let (Some(vx), Some(vy)) = (values[x], values[y]) {
+                let (left, top) = (vx as u32 % 64, vy as u32 % 32);
+                if left + 8 > 64 || top + n > 32 {
+                    edges.push((address, opcode));
+                }
+            }
+        }
+
+        let written = writes(opcode);
+        for (register, value) in values.iter_mut().enumerate() {
+            if written & 1 << register != 0 {
+                *value = None;
+            }
+        }
+        if opcode >> 12 == 0x6 {
+            values[x] = Some(opcode as u8);
+        }
+    }
+    if !edges.is_empty() {
+        findings.push(Finding {
+            presets: &[],
+            weight: 0,
+            reason: format!(
+                "Sprites cross the screen edge, wrap_sprites decides whether they wrap: {}",
+                list(&edges)
+            ),
+        });
+    }
+
+    let total: u32 = findings.iter().map(|finding| finding.weight).sum();
+    let mut preset = "default";
+    let mut best = 0;
+    for candidate in QUIRK_PRESETS.iter() {
+        let score: u32 = findings
+            .iter()
+            .filter(|finding| finding.presets.contains(candidate))
+            .map(|finding| finding.weight)
+            .sum();
+        if score > best {
+            preset = candidate;
+            best = score;
+        }
+    }
+
+    let mut reasons: Vec<String> = findings.into_iter().map(|finding| finding.reason).collect();
+    if total == 0 {
+        reasons.insert(0, "No quirk dependent code found".to_string());
+    }
+
+    Analysis {
+        platform,
+        preset,
+        confidence: if total == 0 {
+            0.0
+        } else {
+            best as f64 / total as f64
+        },
+        reasons,
+    }
+}
@@ -0,0 +1,348 @@
+use super::cpu::*;
+use super::instruction::*;
+use super::opcode::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+// Two pass assembler for the Cowgod mnemonics `Instruction` disassembles to,
+// so disassembled ROMs assemble back to the same bytes. On top of those:
+//
+//   loop:            labels, usable wherever an address or byte goes
+//   ; comment
+//   DB 1, 0x02, #03  raw bytes
+//   DW 0x1234        raw big endian words
+//
+// Numbers are decimal, 0x/# prefixed hex or 0b prefixed binary.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// A source line split into its parts, all optional
+struct Line<'a> {
+    label: Option<&'a str>,
+    mnemonic: Option<String>,
+    operands: Vec<&'a str>,
+}
+
+fn split(line: &str) -> Line<'_> {
+    let code = line.split(';').next().unwrap_or_default().trim();
+
+    let (label, code) = match code.find(':') {
+        Some(end) => (Some(code[..end].trim()), code[end + 1..].trim()),
+        None => (None, code),
+    };
+
+    let mut parts = code.splitn(2, char::is_whitespace);
+    let mnemonic = parts
+        .next()
+        .filter(|mnemonic| !mnemonic.is_empty())
+        .map(|mnemonic| mnemonic.to_ascii_uppercase());
+    let operands = match parts.next().map(str::trim) {
+        Some(operands) if !operands.is_empty() => operands.split(',').map(str::trim).collect(),
+        _ => Vec::new(),
+    };
+
+    Line {
+        label,
+        mnemonic,
+        operands,
+    }
+}
+
+// Bytes a line assembles to, known before labels are
+fn size(line: &Line) -> usize {
+    match line.mnemonic.as_deref() {
+        None => 0,
+        Some("DB") => line.operands.len(),
+        Some("DW") => line.operands.len() * 2,
+        Some(_) => 2,
+    }
+}
+
+fn number(text: &str) -> Option<u32> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+struct Assembler<'a> {
+    labels: &'a HashMap<String, u32>,
+    operands: &'a [&'a str],
+}
+
+impl<'a> Assembler<'a> {
+    fn operand(&self, index: usize) -> Result<&'a str, String> {
+        self.operands
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("Missing operand {}", index + 1))
+    }
+
+    fn expect(&self, count: usize) -> Result<(), String> {
+        if self.operands.len() != count {
+            return Err(format!(
+                "Expected {} operands, got {}",
+                count,
+                self.operands.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn register(&self, index: usize) -> Result<u16, String> {
+        let text = self.operand(index)?;
+        register(text).ok_or_else(|| format!("Expected a register, got '{}'", text))
+    }
+
+    // A number or label no larger than `max`
+    fn value(&self, index: usize, max: u32) -> Result<u16, String> {
+        let text = self.operand(index)?;
+        let value = number(text)
+            .or_else(|| self.labels.get(&text.to_ascii_lowercase()).copied())
+            .ok_or_else(|| format!("Unknown value '{}'", text))?;
+
+        if value > max {
+            return Err(format!("'{}' doesn't fit into {:X}", text, max));
+        }
+        Ok(value as u16)
+    }
+
+    fn is(&self, index: usize, name: &str) -> bool {
+        self.operands
+            .get(index)
+            .is_some_and(|operand| operand.eq_ignore_ascii_case(name))
+    }
+
+    fn is_register(&self, index: usize) -> bool {
+        self.operands
+            .get(index)
+            .is_some_and(|operand| register(operand).is_some())
+    }
+
+    // Opcode with X and Y from the first two operands
+    fn xy(&self, opcode: u16) -> Result<u16, String> {
+        self.expect(2)?;
+        Ok(opcode | self.register(0)? << 8 | self.register(1)? << 4)
+    }
+
+    // Opcode with X from the first operand and NN or Y from the second
+    fn x_nn_or_y(&self, nn: u16, y: u16) -> Result<u16, String> {
+        self.expect(2)?;
+        let x = self.register(0)? << 8;
+        if self.is_register(1) {
+            Ok(y | x | self.register(1)? << 4)
+        } else {
+            Ok(nn | x | self.value(1, 0xFF)?)
+        }
+    }
+
+    fn x(&self, opcode: u16) -> Result<u16, String> {
+        self.expect(1)?;
+        Ok(opcode | self.register(0)? << 8)
+    }
+
+    fn instruction(&self, mnemonic: &str) -> Result<u16, String> {
+        match mnemonic {
+            "CLS" => self.expect(0).map(|_| 0x00E0),
+            "RET" => self.expect(0).map(|_| 0x00EE),
+            "JP" if self.operands.len() == 2 => {
+                if !self.is(0, "V0") {
+                    return Err("Offset jumps are relative to V0".to_string());
+                }
+                Ok(0xB000 | self.value(1, 0xFFF)?)
+            }
+            "JP" => {
+                self.expect(1)?;
+                Ok(0x1000 | self.value(0, 0xFFF)?)
+            }
+            "CALL" => {
+                self.expect(1)?;
+                Ok(0x2000 | self.value(0, 0xFFF)?)
+            }
+            "SE" => self.x_nn_or_y(0x3000, 0x5000),
+            "SNE" => self.x_nn_or_y(0x4000, 0x9000),
+            "ADD" if self.is(0, "I") => {
+                self.expect(2)?;
+                Ok(0xF01E | self.register(1)? << 8)
+            }
+            "ADD" => self.x_nn_or_y(0x7000, 0x8004),
+            "OR" => self.xy(0x8001),
+            "AND" => self.xy(0x8002),
+            "XOR" => self.xy(0x8003),
+            "SUB" => self.xy(0x8005),
+            "SUBN" => self.xy(0x8007),
+            // The Y operand only matters with the shift_vy quirk
+            "SHR" | "SHL" => {
+                let opcode = if mnemonic == "SHR" { 0x8006 } else { 0x800E };
+                if self.operands.len() == 1 {
+                    Ok(opcode | self.register(0)? << 8 | self.register(0)? << 4)
+                } else {
+                    self.xy(opcode)
+                }
+            }
+            "RND" => {
+                self.expect(2)?;
+                Ok(0xC000 | self.register(0)? << 8 | self.value(1, 0xFF)?)
+            }
+            "DRW" => {
+                self.expect(3)?;
+                Ok(
+                    0xD000
+                        | self.register(0)? << 8
+                        | self.register(1)? << 4
+                        | self.value(2, 0xF)?,
+                )
+            }
+            "SKP" => self.x(0xE09E),
+            "SKNP" => self.x(0xE0A1),
+            "LD" => self.load(),
+            _ => Err(format!("Unknown instruction '{}'", mnemonic)),
+        }
+    }
+
+    fn load(&self) -> Result<u16, String> {
+        self.expect(2)?;
+
+        let source = |opcode: u16| Ok(opcode | self.register(1)? << 8);
+        if self.is(0, "I") {
+            Ok(0xA000 | self.value(1, 0xFFF)?)
+        } else if self.is(0, "DT") {
+            source(0xF015)
+        } else if self.is(0, "ST") {
+            source(0xF018)
+        } else if self.is(0, "F") {
+            source(0xF029)
+        } else if self.is(0, "B") {
+            source(0xF033)
+        } else if self.is(0, "[I]") {
+            source(0xF055)
+        } else if self.is(1, "DT") {
+            Ok(0xF007 | self.register(0)? << 8)
+        } else if self.is(1, "K") {
+            Ok(0xF00A | self.register(0)? << 8)
+        } else if self.is(1, "[I]") {
+            Ok(0xF065 | self.register(0)? << 8)
+        } else {
+            self.x_nn_or_y(0x6000, 0x8000)
+        }
+    }
+
+    fn encode(&self, mnemonic: &str) -> Result<Vec<u8>, String> {
+        match mnemonic {
+            "DB" => self
+                .operands
+                .iter()
+                .enumerate()
+                .map(|(i, _)| self.value(i, 0xFF).map(|byte| byte as u8))
+                .collect(),
+            "DW" => {
+                let mut bytes = Vec::new();
+                for i in 0..self.operands.len() {
+                    bytes.extend_from_slice(&self.value(i, 0xFFFF)?.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            _ => self
+                .instruction(mnemonic)
+                .map(|opcode| opcode.to_be_bytes().to_vec()),
+        }
+    }
+}
+
+fn register(text: &str) -> Option<u16> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|digit| digit as u16)
+        }
+        _ => None,
+    }
+}
+
+// Assembles a program loaded at `PROGRAM_ENTRY`
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let lines: Vec<Line> = source.lines().map(split).collect();
+    let error = |line: usize, message: String| AsmError {
+        line: line + 1,
+        message,
+    };
+
+    let mut labels = HashMap::new();
+    let mut address = PROGRAM_ENTRY as u32;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(label) = line.label {
+            let valid = label.chars().next().is_some_and(char::is_alphabetic)
+                && label.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !valid || register(label).is_some() {
+                return Err(error(i, format!("Invalid label '{}'", label)));
+            }
+            if labels.insert(label.to_ascii_lowercase(), address).is_some() {
+                return Err(error(i, format!("Duplicate label '{}'", label)));
+            }
+        }
+        address += size(line) as u32;
+    }
+
+    let mut program = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(mnemonic) = &line.mnemonic {
+            let assembler = Assembler {
+                labels: &labels,
+                operands: &line.operands,
+            };
+            program.extend(assembler.encode(mnemonic).map_err(|e| error(i, e))?);
+        }
+    }
+
+    Ok(program)
+}
+
+// Listing of a program loaded at `PROGRAM_ENTRY` which `assemble` turns back
+// into the same bytes. Each line carries its address and opcode as a comment.
+pub fn disassemble(program: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (i, bytes) in program.chunks(2).enumerate() {
+        let address = PROGRAM_ENTRY as usize + i * 2;
+        let line = match *bytes {
+            [high, low] => {
+                let value = u16::from_be_bytes([high, low]);
+                // The decoder ignores some nibbles, e.g. 01E0 runs as CLS.
+                // Those stay raw words so the bytes survive.
+                let mut text = Instruction::decode(&Opcode { value }).to_string();
+                if assemble(&text).ok().as_deref() != Some(bytes) {
+                    text = format!("DW 0x{:04X}", value);
+                }
+                format!("{:<20}; {:04X}  {:04X}", text, address, value)
+            }
+            [byte] => format!(
+                "{:<20}; {:04X}  {:02X}",
+                format!("DB 0x{:02X}", byte),
+                address,
+                byte
+            ),
+            _ => unreachable!(),
+        };
+        let _ = writeln!(out, "{}", line);
+    }
+
+    out
+}
@@ -0,0 +1,361 @@
+use super::cpu::*;
+
+use std::fmt;
+use std::fmt::Write;
+
+// Cheat engine. Searches narrow down the registers and memory bytes holding
+// a value, e.g. the lives counter, by comparing them with a snapshot taken at
+// the last search. Codes then freeze what was found or patch the program.
+// A ROM's codes are kept in a text file, one per line:
+//
+//   # Infinite lives
+//   freeze 02F4 03
+//   freeze V7 FF
+//   # Skip the title screen
+//   patch 0204 1240
+//
+// Addresses, values and bytes are hex. The commands of `Cheats::command` are
+// what GDB's `monitor` sends.
+
+// Search results listed by `results`
+const LISTED_RESULTS: usize = 16;
+
+// Bytes of `CPU::memory`
+const MEMORY_SIZE: usize = 0xFFFF;
+
+pub const HELP: &str = "\
+search              Start a search over V0-VF and memory
+search <value>      Keep what holds the hex value now
+search changed      Keep what changed since the last search, same for
+                    unchanged, increased and decreased
+results             List what the search kept
+freeze <at> [value] Rewrite V0-VF or a memory byte every frame, with its
+                    current value if none is given
+patch <at> <bytes>  Write hex bytes to memory now and whenever the ROM loads
+list                List the codes
+remove <n>          Remove the code numbered n by list
+help                This text
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    Register(u8),
+    Memory(u16),
+}
+
+impl Target {
+    // "V3" or a hex address inside memory
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut chars = text.chars();
+        if let (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) =
+            (chars.next(), chars.next(), chars.next())
+        {
+            return digit.to_digit(16).map(|x| Target::Register(x as u8));
+        }
+
+        let address = u16::from_str_radix(hex_digits(text), 16).ok()?;
+        if address as usize >= MEMORY_SIZE {
+            return None;
+        }
+        Some(Target::Memory(address))
+    }
+
+    pub fn read(self, cpu: &CPU) -> u8 {
+        match self {
+            Target::Register(x) => cpu.regs[x as usize],
+            Target::Memory(address) => cpu.memory[address as usize],
+        }
+    }
+
+    pub fn write(self, cpu: &mut CPU, value: u8) {
+        match self {
+            Target::Register(x) => cpu.regs[x as usize] = value,
+            Target::Memory(address) => cpu.write_memory(address as usize, value),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Register(x) => write!(f, "V{:X}", x),
+            Target::Memory(address) => write!(f, "{:04X}", address),
+        }
+    }
+}
+
+fn hex_digits(text: &str) -> &str {
+    text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text)
+}
+
+fn parse_byte(text: &str) -> Option<u8> {
+    u8::from_str_radix(hex_digits(text), 16).ok()
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    let hex = hex_digits(text);
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "changed" => Some(Filter::Changed),
+            "unchanged" => Some(Filter::Unchanged),
+            "increased" => Some(Filter::Increased),
+            "decreased" => Some(Filter::Decreased),
+            _ => parse_byte(text).map(Filter::Equal),
+        }
+    }
+
+    fn keeps(self, before: u8, now: u8) -> bool {
+        match self {
+            Filter::Equal(value) => now == value,
+            Filter::Changed => now != before,
+            Filter::Unchanged => now == before,
+            Filter::Increased => now > before,
+            Filter::Decreased => now < before,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Search {
+    // What's left, with the value at the last search
+    pub results: Vec<(Target, u8)>,
+}
+
+impl Search {
+    // Everything, V0-VF first
+    pub fn new(cpu: &CPU) -> Self {
+        let registers = (0..16).map(Target::Register);
+        let memory = (0..MEMORY_SIZE as u16).map(Target::Memory);
+        Self {
+            results: registers
+                .chain(memory)
+                .map(|target| (target, target.read(cpu)))
+                .collect(),
+        }
+    }
+
+    // Keeps the results the filter matches and takes a new snapshot of them
+    pub fn filter(&mut self, cpu: &CPU, filter: Filter) {
+        self.results = self
+            .results
+            .iter()
+            .map(|&(target, before)| (target, before, target.read(cpu)))
+            .filter(|&(_, before, now)| filter.keeps(before, now))
+            .map(|(target, _, now)| (target, now))
+            .collect();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Code {
+    // Rewritten at the start of every frame
+    Freeze { target: Target, value: u8 },
+    // Written when the ROM is loaded
+    Patch { address: u16, bytes: Vec<u8> },
+}
+
+impl Code {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let target = |text: &str| {
+            Target::parse(text).ok_or_else(|| format!("Invalid register or address '{}'", text))
+        };
+
+        match words.as_slice() {
+            ["freeze", at, value] => Ok(Code::Freeze {
+                target: target(at)?,
+                value: parse_byte(value).ok_or_else(|| format!("Invalid value '{}'", value))?,
+            }),
+            ["patch", at, bytes] => {
+                let address = match target(at)? {
+                    Target::Memory(address) => address,
+                    Target::Register(_) => return Err("Only memory can be patched".to_string()),
+                };
+                let bytes =
+                    parse_bytes(bytes).ok_or_else(|| format!("Invalid bytes '{}'", bytes))?;
+                if address as usize + bytes.len() > MEMORY_SIZE {
+                    return Err("The patch runs past the end of memory".to_string());
+                }
+                Ok(Code::Patch { address, bytes })
+            }
+            _ => Err(format!("Invalid code '{}'", line)),
+        }
+    }
+
+    pub fn apply(&self, cpu: &mut CPU) {
+        match self {
+            Code::Freeze { target, value } => {
+                // Rewriting memory every frame would keep dropping cached
+                // instructions
+                if target.read(cpu) != *value {
+                    target.write(cpu, *value);
+                }
+            }
+            Code::Patch { address, bytes } => {
+                for (i, &byte) in bytes.iter().enumerate() {
+                    cpu.write_memory(*address as usize + i, byte);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Code::Freeze { target, value } => write!(f, "freeze {} {:02X}", target, value),
+            Code::Patch { address, bytes } => {
+                let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                write!(f, "patch {:04X} {}", address, hex)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cheats {
+    pub codes: Vec<Code>,
+    pub search: Option<Search>,
+}
+
+impl Cheats {
+    // Codes from a cheat file, '#' starts a comment
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut codes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if !line.is_empty() {
+                codes.push(Code::parse(line).map_err(|e| format!("Line {}: {}", number + 1, e))?);
+            }
+        }
+
+        Ok(Self {
+            codes,
+            search: None,
+        })
+    }
+
+    // Applies the patches, after the ROM has been loaded
+    pub fn patch(&self, cpu: &mut CPU) {
+        for code in &self.codes {
+            if let Code::Patch { .. } = code {
+                code.apply(cpu);
+            }
+        }
+    }
+
+    // Applies the freezes, before each frame
+    pub fn freeze(&self, cpu: &mut CPU) {
+        for code in &self.codes {
+            if let Code::Freeze { .. } = code {
+                code.apply(cpu);
+            }
+        }
+    }
+
+    // Runs a command, see `HELP`, and returns its output
+    pub fn command(&mut self, cpu: &mut CPU, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match self.run(cpu, &words) {
+            Ok(output) => output,
+            Err(e) => format!("{}\n", e),
+        }
+    }
+
+    fn run(&mut self, cpu: &mut CPU, words: &[&str]) -> Result<String, String> {
+        match words {
+            ["search"] => {
+                let search = Search::new(cpu);
+                let count = search.results.len();
+                self.search = Some(search);
+                Ok(format!("Searching {} registers and bytes\n", count))
+            }
+            ["search", filter] => {
+                let filter =
+                    Filter::parse(filter).ok_or_else(|| format!("Invalid search '{}'", filter))?;
+                let search = self.search.get_or_insert_with(|| Search::new(cpu));
+                search.filter(cpu, filter);
+                Ok(format!("{} left\n", search.results.len()))
+            }
+            ["results"] => {
+                let search = self.search.as_ref().ok_or("No search running")?;
+                let mut out = String::new();
+                for (target, value) in search.results.iter().take(LISTED_RESULTS) {
+                    let _ = writeln!(out, "{} {:02X}", target, value);
+                }
+                if search.results.len() > LISTED_RESULTS {
+                    let _ = writeln!(out, "{} more", search.results.len() - LISTED_RESULTS);
+                }
+                if search.results.is_empty() {
+                    out.push_str("Nothing left\n");
+                }
+                Ok(out)
+            }
+            ["freeze", at] => {
+                let target = Target::parse(at)
+                    .ok_or_else(|| format!("Invalid register or address '{}'", at))?;
+                let value = format!("{:02X}", target.read(cpu));
+                self.run(cpu, &["freeze", at, &value])
+            }
+            ["freeze", ..] | ["patch", ..] => {
+                let code = Code::parse(&words.join(" "))?;
+                code.apply(cpu);
+                let output = format!("{}: {}\n", self.codes.len() + 1, code);
+                self.codes.push(code);
+                Ok(output)
+            }
+            ["list"] => {
+                let mut out = String::new();
+                for (i, code) in self.codes.iter().enumerate() {
+                    let _ = writeln!(out, "{}: {}", i + 1, code);
+                }
+                if self.codes.is_empty() {
+                    out.push_str("No codes\n");
+                }
+                Ok(out)
+            }
+            ["remove", n] => {
+                let index = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| 1 <= n && n <= self.codes.len())
+                    .ok_or_else(|| format!("No code {}", n))?;
+                let code = self.codes.remove(index - 1);
+                Ok(format!("Removed {}\n", code))
+            }
+            ["help"] | [] => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command '{}', try help", words.join(" "))),
+        }
+    }
+}
+
+// The cheat file a ROM's codes are saved to
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for code in &self.codes {
+            writeln!(f, "{}", code)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// User configuration. A TOML file with settings for every ROM under
+// [default] and sections for single ROMs keyed by the ROM's SHA-1:
+//
+//   [default]
+//   clock = 540
+//   palette = "amber"
+//
+//   [rom.0123456789abcdef0123456789abcdef01234567]
+//   name = "pong.ch8"
+//   quirks = "chip8"
+//   keys = { up = "5", down = "8" }
+//
+// A ROM's section overrides what the ROM database recommends, which
+// overrides [default], which overrides the built in defaults. Values are
+// checked where they're applied, not here.
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // Informative only, the file the section was saved for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // Instructions per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock: Option<u32>,
+    // Quirk preset or list of quirks, see `Quirks::parse`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<String>,
+    // "epx" or "none"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scaler: Option<String>,
+    // Named palette or "RRGGBB,RRGGBB"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    // Window pixels per CHIP-8 pixel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    // "integer" or "fit", how the image fills a resized window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_mode: Option<String>,
+    // Host key names to keypad keys as hex digits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<BTreeMap<String, String>>,
+}
+
+impl Settings {
+    // Takes over everything `other` sets. Key mappings are added to the
+    // existing ones instead of replacing them all.
+    pub fn merge(&mut self, other: &Settings) {
+        fn take<T: Clone>(value: &mut Option<T>, other: &Option<T>) {
+            if other.is_some() {
+                *value = other.clone();
+            }
+        }
+
+        take(&mut self.name, &other.name);
+        take(&mut self.clock, &other.clock);
+        take(&mut self.quirks, &other.quirks);
+        take(&mut self.scaler, &other.scaler);
+        take(&mut self.palette, &other.palette);
+        take(&mut self.scale, &other.scale);
+        take(&mut self.scale_mode, &other.scale_mode);
+        if let Some(keys) = &other.keys {
+            self.keys
+                .get_or_insert_with(BTreeMap::new)
+                .extend(keys.iter().map(|(host, key)| (host.clone(), key.clone())));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub default: Settings,
+    // By lower case hex SHA-1
+    pub rom: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn to_toml(&self) -> String {
+        // Only fails on types TOML can't represent, which `Config` has none of
+        toml::to_string_pretty(self).unwrap()
+    }
+
+    // Settings for the ROM with the given hash, before CLI overrides.
+    // `known` is what the ROM database recommends, it goes between [default]
+    // and the ROM's own section.
+    pub fn settings(&self, hash: &str, known: &Settings) -> Settings {
+        let mut settings = self.default.clone();
+        settings.merge(known);
+        if let Some(rom) = self.rom.get(hash) {
+            settings.merge(rom);
+        }
+        settings
+    }
+}
+
+// Whether `text` is a color as RRGGBB, with or without a leading '#'
+pub fn is_color(text: &str) -> bool {
+    let hex = text.trim().trim_start_matches('#');
+    hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// Key of a ROM's section
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
@@ -1,12 +1,18 @@
 extern crate rand;
 
 use rand::prelude::*;
-use std::sync::{Mutex, Arc};
+use rand_chacha::ChaCha20Rng;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
 
 use super::super::super::core::FrameBuffer;
+use super::database::{Database, Program};
+use super::error::*;
 use super::font::*;
-use super::opcode::*;
+use super::instruction::*;
 use super::keyboard::*;
+use super::opcode::*;
+use super::quirks::*;
 
 // General constants
 pub const PROGRAM_ENTRY: u16 = 0x200;
@@ -41,15 +47,38 @@ pub struct CPU {
     pub i: u16,
     pub timers: [u8; 2],
     // Internal memory
-    pub frame_buf: Arc<Mutex<FrameBuffer<u8>>>,
+    pub frame_buf: FrameBuffer<u8>,
     pub memory: [u8; 0xFFFF],
     pub stack: [u16; 64],
     // User input
     pub keyboard: Arc<Mutex<Keyboard>>,
+    // Interpreter compatibility
+    pub quirks: Quirks,
+    // Predecoded instructions by address, used by `execute_cached`. One
+    // entry for every value of `pc`, which saves the bounds check.
+    decoded: Box<[Option<Instruction>; 0x10000]>,
+    // Counts the changes to memory announced through `write_memory` and
+    // `invalidate_cache`, so other caches of the code can tell they're stale
+    generation: u64,
+    // ChaCha20, the same generator as rand's StdRng, which can be rewound
+    // for save states
+    rng: ChaCha20Rng,
+    seed: u64,
+    // Numbers drawn since seeding, each one 32 bit word of the stream
+    draws: u64,
 }
 
 impl CPU {
-    pub fn new(frame_buf: Arc<Mutex<FrameBuffer<u8>>>, keyboard: Arc<Mutex<Keyboard>>) -> Self {
+    pub fn new(frame_buf: FrameBuffer<u8>, keyboard: Arc<Mutex<Keyboard>>) -> Self {
+        Self::with_seed(frame_buf, keyboard, rand::random())
+    }
+
+    // Doesn't ask the OS for a seed, wasm32-unknown-unknown has none to give
+    pub fn with_seed(
+        frame_buf: FrameBuffer<u8>,
+        keyboard: Arc<Mutex<Keyboard>>,
+        seed: u64,
+    ) -> Self {
         Self {
             regs: [0; 16],
             sp: 0,
@@ -60,309 +89,431 @@ impl CPU {
             memory: [0; 0xFFFF],
             stack: [0; 64],
             keyboard,
+            quirks: Quirks::default(),
+            decoded: vec![None; 0x10000].into_boxed_slice().try_into().unwrap(),
+            generation: 0,
+            rng: ChaCha20Rng::seed_from_u64(seed),
+            seed,
+            draws: 0,
         }
     }
 
-    pub fn load_program(&mut self, binary: &[u8]) {
-        // Load font map
-        for i in 0..16 {
-            self.memory[i] = FONT_CHARMAP[i];
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha20Rng::seed_from_u64(seed);
+        self.seed = seed;
+        self.draws = 0;
+    }
+
+    // Seed and position of the CXNN generator, for save states
+    pub fn rng_state(&self) -> (u64, u64) {
+        (self.seed, self.draws)
+    }
+
+    pub fn set_rng_state(&mut self, seed: u64, draws: u64) {
+        self.seed(seed);
+        self.rng.set_word_pos(draws as u128);
+        self.draws = draws;
+    }
+
+    pub fn load_program(&mut self, binary: &[u8]) -> Result<(), Error> {
+        let entry = PROGRAM_ENTRY as usize;
+        let max = self.memory.len() - entry;
+        if binary.len() > max {
+            return Err(Error::RomTooLarge {
+                size: binary.len(),
+                max,
+            });
         }
+
+        // Load font map
+        self.memory[..FONT_CHARMAP.len()].copy_from_slice(&FONT_CHARMAP);
         // Load program
-        let mut address = PROGRAM_ENTRY;
-        for byte in binary {
-            self.memory[address as usize] = *byte;
-            address += 1;
+        self.memory[entry..entry + binary.len()].copy_from_slice(binary);
+
+        self.invalidate_cache();
+        Ok(())
+    }
+
+    // Like `load_program`, ROMs `database` knows also switch to the quirks
+    // they were made for. Their entry has the rest of their settings.
+    pub fn load_known_program<'a>(
+        &mut self,
+        binary: &[u8],
+        database: &'a Database,
+    ) -> Result<Option<&'a Program>, Error> {
+        self.load_program(binary)?;
+        if let Some(quirks) = database.quirks(binary) {
+            self.quirks = quirks;
         }
+        Ok(database.get(binary))
     }
 
-    fn read_opcode(&self) -> Opcode {
-        let high = self.memory[self.pc as usize] as u16;
-        let low = self.memory[(self.pc + 1) as usize] as u16;
+    // Must be called after writing to `memory` directly, otherwise
+    // `execute_cached` may keep running the old code
+    pub fn invalidate_cache(&mut self) {
+        for entry in self.decoded.iter_mut() {
+            *entry = None;
+        }
+        self.generation += 1;
+    }
 
-        Opcode {
-            value: (high << 8) | low,
+    // Writes a byte, dropping the cached instructions it is part of
+    pub fn write_memory(&mut self, address: usize, value: u8) {
+        // Rewriting the same value keeps the cached instructions
+        if self.memory[address] == value {
+            return;
+        }
+        self.memory[address] = value;
+        self.generation += 1;
+
+        // An instruction starting one byte earlier overlaps this address too
+        self.decoded[address] = None;
+        if address > 0 {
+            self.decoded[address - 1] = None;
         }
     }
 
-    pub fn execute(&mut self) {
-        let opcode = self.read_opcode();
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 
-        let vx = opcode.x() as usize;
-        let vy = opcode.y() as usize;
-        let nn = opcode.nn() as u8;
+    // Fails unless `len` bytes starting at `address` are inside memory
+    fn check_range(&self, address: usize, len: usize) -> Result<(), Error> {
+        if address + len > self.memory.len() {
+            Err(Error::InvalidAddress {
+                pc: self.pc,
+                address: address.max(self.memory.len()),
+            })
+        } else {
+            Ok(())
+        }
+    }
 
-        //println!("({:04X}) Executing opcode: {:04X}", self.pc, opcode.value);
+    fn read_opcode(&self) -> Result<Opcode, Error> {
+        self.check_range(self.pc as usize, 2)?;
 
-        let mut rng = rand::thread_rng();
+        let high = self.memory[self.pc as usize] as u16;
+        let low = self.memory[self.pc as usize + 1] as u16;
 
-        match opcode.first() {
-            0x0 => match opcode.nn() {
-                0xE0 => {
-                    // Clear display
-                    self.frame_buf.lock().unwrap().clear(0);
-                    self.pc += 2;
+        Ok(Opcode {
+            value: (high << 8) | low,
+        })
+    }
+
+    pub fn execute(&mut self) -> Result<(), Error> {
+        let instruction = Instruction::decode(&self.read_opcode()?);
+        self.run(instruction)
+    }
+
+    // Same as `execute`, but decodes every address only once until the
+    // memory behind it changes
+    pub fn execute_cached(&mut self) -> Result<(), Error> {
+        self.execute_cached_n(1).map(|_| ())
+    }
+
+    // Runs `cycles` instructions back to back through the decoded cache.
+    // Batching avoids the call overhead which dominates for simple opcodes.
+    // Returns how many instructions actually ran, fewer than `cycles` when
+    // the batch ended in an idle loop.
+    pub fn execute_cached_n(&mut self, cycles: usize) -> Result<usize, Error> {
+        for executed in 0..cycles {
+            let pc = self.pc as usize;
+            let instruction = match self.decoded[pc] {
+                Some(instruction) => instruction,
+                None => {
+                    let instruction = Instruction::decode(&self.read_opcode()?);
+                    self.decoded[pc] = Some(instruction);
+                    instruction
                 }
-                0xEE => {
-                    // Return from subroutine
-                    self.sp -= 1;
-                    let return_address = self.stack[self.sp as usize];
-                    self.pc = return_address;
+            };
+
+            // A jump to itself spins until the timers move, which they don't
+            // within a batch. The remaining cycles would change nothing.
+            if instruction == Instruction::Jump(pc as u16) {
+                return Ok(executed);
+            }
+
+            self.run(instruction)?;
+        }
+
+        Ok(cycles)
+    }
+
+    #[inline(always)]
+    fn run(&mut self, instruction: Instruction) -> Result<(), Error> {
+        match instruction {
+            Instruction::Clear => {
+                // Clear display
+                self.frame_buf.clear(0);
+                self.frame_buf.request_draw();
+                self.pc += 2;
+            }
+            Instruction::Return => {
+                // Return from subroutine
+                if self.sp == 0 {
+                    return Err(Error::StackUnderflow { pc: self.pc });
                 }
-                val => unimplemented!("Unknown opcode ({:04X})", val),
-            },
-            0x1 => {
+                self.sp -= 1;
+                let return_address = self.stack[self.sp as usize];
+                self.pc = return_address;
+            }
+            Instruction::Jump(nnn) => {
                 // Goto NNN
-                self.pc = opcode.nnn();
+                self.pc = nnn;
             }
-            0x2 => {
+            Instruction::Call(nnn) => {
                 // Call NNN
+                if self.sp as usize >= self.stack.len() {
+                    return Err(Error::StackOverflow { pc: self.pc });
+                }
                 self.stack[self.sp as usize] = self.pc + 2;
                 self.sp += 1;
-                self.pc = opcode.nnn();
+                self.pc = nnn;
             }
-            0x3 => {
+            Instruction::SkipEq(vx, nn) => {
                 // Skip next instruction if VX == NN
-                if self.regs[vx] == nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[vx as usize] == nn);
             }
-            0x4 => {
+            Instruction::SkipNe(vx, nn) => {
                 // Skip next instruction if VX != NN
-                if self.regs[vx] != nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[vx as usize] != nn);
             }
-            0x5 => {
+            Instruction::SkipEqReg(vx, vy) => {
                 // Skip next instruction if VX == VY
-                if self.regs[vx] == self.regs[vy] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[vx as usize] == self.regs[vy as usize]);
             }
-            0x6 => {
+            Instruction::Load(vx, nn) => {
                 // VX = NN
-                self.regs[vx] = nn;
+                self.regs[vx as usize] = nn;
                 self.pc += 2;
             }
-            0x7 => {
+            Instruction::Add(vx, nn) => {
                 // VX += NN
-                self.regs[vx] = match self.regs[vx].overflowing_add(nn) {
-                    (res, _) => res,
-                };
-
+                self.regs[vx as usize] = self.regs[vx as usize].wrapping_add(nn);
                 self.pc += 2;
             }
-            0x8 => match opcode.last() {
-                0x0 => {
-                    // VX = VY
-                    self.regs[vx] = self.regs[vy];
-                    self.pc += 2;
-                }
-                0x1 => {
-                    // VX |= VY
-                    self.regs[vx] |= self.regs[vy];
-                    self.pc += 2;
-                }
-                0x2 => {
-                    // VX &= VY
-                    self.regs[vx] &= self.regs[vy];
-                    self.pc += 2;
-                }
-                0x3 => {
-                    // VX ^= VY
-                    self.regs[vx] ^= self.regs[vy];
-                    self.pc += 2;
-                }
-                0x4 => {
-                    // VX += VY
-                    match self.regs[vx].overflowing_add(self.regs[vy]) {
-                        (result, overflow) => {
-                            self.regs[vx] = result;
-                            self.regs[VF] = overflow as u8;
-                        }
-                    }
-                    self.pc += 2;
+            Instruction::Move(vx, vy) => {
+                // VX = VY
+                self.regs[vx as usize] = self.regs[vy as usize];
+                self.pc += 2;
+            }
+            Instruction::Or(vx, vy) => {
+                // VX |= VY
+                self.regs[vx as usize] |= self.regs[vy as usize];
+                if self.quirks.vf_reset {
+                    self.regs[VF] = 0;
                 }
-                0x5 => {
-                    // VX -= VY
-                    match self.regs[vx].overflowing_sub(self.regs[vy]) {
-                        (result, overflow) => {
-                            self.regs[vx] = result;
-                            self.regs[VF] = overflow as u8;
-                        }
-                    }
-                    self.pc += 2;
+                self.pc += 2;
+            }
+            Instruction::And(vx, vy) => {
+                // VX &= VY
+                self.regs[vx as usize] &= self.regs[vy as usize];
+                if self.quirks.vf_reset {
+                    self.regs[VF] = 0;
                 }
-                0x6 => {
-                    // VX >>= 1
-                    self.regs[VF] = self.regs[vx] & 0x1;
-                    self.regs[vx] >>= 1;
-                    self.pc += 2;
+                self.pc += 2;
+            }
+            Instruction::Xor(vx, vy) => {
+                // VX ^= VY
+                self.regs[vx as usize] ^= self.regs[vy as usize];
+                if self.quirks.vf_reset {
+                    self.regs[VF] = 0;
                 }
-                0x7 => {
-                    // VX = VY - VX
-                    match self.regs[vy].overflowing_sub(self.regs[vx]) {
-                        (result, overflow) => {
-                            self.regs[vx] = result;
-                            self.regs[VF] = overflow as u8;
-                        }
-                    }
-                    self.pc += 2;
+                self.pc += 2;
+            }
+            Instruction::AddReg(vx, vy) => {
+                // VX += VY
+                let (result, overflow) =
+                    self.regs[vx as usize].overflowing_add(self.regs[vy as usize]);
+                self.regs[vx as usize] = result;
+                self.regs[VF] = overflow as u8;
+                self.pc += 2;
+            }
+            Instruction::Sub(vx, vy) => {
+                // VX -= VY
+                let (result, borrow) =
+                    self.regs[vx as usize].overflowing_sub(self.regs[vy as usize]);
+                self.regs[vx as usize] = result;
+                self.regs[VF] = !borrow as u8;
+                self.pc += 2;
+            }
+            Instruction::ShiftRight(vx, vy) => {
+                // VX >>= 1
+                if self.quirks.shift_vy {
+                    self.regs[vx as usize] = self.regs[vy as usize];
                 }
-                0xE => {
-                    // VX <<= 1
-                    self.regs[VF] = self.regs[vx] >> 7 & 0x1;
-                    self.regs[vx] <<= 1;
-                    self.pc += 2;
+                let flag = self.regs[vx as usize] & 0x1;
+                self.regs[vx as usize] >>= 1;
+                self.regs[VF] = flag;
+                self.pc += 2;
+            }
+            Instruction::SubReverse(vx, vy) => {
+                // VX = VY - VX
+                let (result, borrow) =
+                    self.regs[vy as usize].overflowing_sub(self.regs[vx as usize]);
+                self.regs[vx as usize] = result;
+                self.regs[VF] = !borrow as u8;
+                self.pc += 2;
+            }
+            Instruction::ShiftLeft(vx, vy) => {
+                // VX <<= 1
+                if self.quirks.shift_vy {
+                    self.regs[vx as usize] = self.regs[vy as usize];
                 }
-                _ => unimplemented!("Unknown opcode: ({:04X})", opcode.value),
-            },
-            0x9 => {
+                let flag = (self.regs[vx as usize] >> 7) & 0x1;
+                self.regs[vx as usize] <<= 1;
+                self.regs[VF] = flag;
+                self.pc += 2;
+            }
+            Instruction::SkipNeReg(vx, vy) => {
                 // Skip next instruction if VX != VY
-                if self.regs[vx] != self.regs[vy] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[vx as usize] != self.regs[vy as usize]);
             }
-            0xA => {
+            Instruction::LoadI(nnn) => {
                 // I = NNN
-                self.i = opcode.nnn();
+                self.i = nnn;
                 self.pc += 2;
             }
-            0xB => {
+            Instruction::JumpOffset(nnn, vx) => {
                 // Goto NNN + V0
-                self.pc = opcode.nnn() + self.regs[V0] as u16
+                let offset = if self.quirks.jump_vx { vx as usize } else { V0 };
+                self.pc = nnn + self.regs[offset] as u16;
             }
-            0xC => {
+            Instruction::Random(vx, nn) => {
                 // VX = rand() & NN
-                let rand: u8 = rng.gen();
-                self.regs[vx] = rand & nn;
+                let rand: u8 = self.rng.gen();
+                self.draws += 1;
+                self.regs[vx as usize] = rand & nn;
                 self.pc += 2;
             }
-            0xD => {
+            Instruction::Draw(vx, vy, n) => {
                 // Draw sprite
-                let flipped = self.draw_sprite(self.regs[vx], self.regs[vy], opcode.last() as u8);
+                self.check_range(self.i as usize, n as usize)?;
+                let flipped = self.draw_sprite(self.regs[vx as usize], self.regs[vy as usize], n);
                 self.regs[VF] = flipped as u8;
                 self.pc += 2;
             }
-            0xE => match opcode.nn() {
-                0x9E => {
-                    // Skip next instruction if key[VX] is pressed
-                    if self.keyboard.lock().unwrap().state[self.regs[vx] as usize] {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
-                    }
-                }
-                0xA1 => {
-                    // Skip next instruction if key[VX] is not pressed
-                    if !self.keyboard.lock().unwrap().state[self.regs[vx] as usize] {
-                        self.pc += 4;
-                    } else {
-                        self.pc += 2;
-                    }
-                }
-                _ => unimplemented!("Unknown opcode: ({:04X})", opcode.value),
-            },
-            0xF => match opcode.nn() {
-                0x07 => {
-                    // VX = delay
-                    self.regs[vx] = self.timers[DELAY] as u8;
-                    self.pc += 2;
-                }
-                0x0A => {
-                    // Wait for keypress
-
-                    let mut kb = self.keyboard.lock().unwrap();
-                    if kb.wait_for_key && kb.key_received {
-                        kb.wait_for_key = false;
-                        kb.key_received = false;
-
-                        self.regs[vx] = kb.key;
-                        self.pc += 2;
-                    } else {
-                        kb.wait_for_key = true;
-                    }
-                }
-                0x15 => {
-                    // delay = VX
-                    self.timers[DELAY] = self.regs[vx];
-                    self.pc += 2;
-                }
-                0x18 => {
-                    // sound = VX
-                    self.timers[SOUND] = self.regs[vx];
-                    self.pc += 2;
-                }
-                0x1E => {
-                    // I += VX
-                    let res = self.i + self.regs[vx] as u16;
-                    let overflow = (res > 0xFFF) as u8;
-                    self.i = res;
-                    self.regs[VF] = overflow;
+            Instruction::SkipKey(vx) => {
+                // Skip next instruction if key[VX] is pressed
+                let pressed = self.key_state(vx)?;
+                self.skip_if(pressed);
+            }
+            Instruction::SkipNotKey(vx) => {
+                // Skip next instruction if key[VX] is not pressed
+                let pressed = self.key_state(vx)?;
+                self.skip_if(!pressed);
+            }
+            Instruction::LoadDelay(vx) => {
+                // VX = delay
+                self.regs[vx as usize] = self.timers[DELAY];
+                self.pc += 2;
+            }
+            Instruction::WaitKey(vx) => {
+                // Wait for keypress
+                let mut kb = self.keyboard.lock().unwrap();
+                if kb.wait_for_key && kb.key_received {
+                    kb.wait_for_key = false;
+                    kb.key_received = false;
+
+                    self.regs[vx as usize] = kb.key;
                     self.pc += 2;
+                } else {
+                    kb.wait_for_key = true;
                 }
-                0x29 => {
-                    // I = sprite_addr[VX]
-                    self.i = (self.regs[vx] * 5) as u16;
-                    self.pc += 2;
+            }
+            Instruction::SetDelay(vx) => {
+                // delay = VX
+                self.timers[DELAY] = self.regs[vx as usize];
+                self.pc += 2;
+            }
+            Instruction::SetSound(vx) => {
+                // sound = VX
+                self.timers[SOUND] = self.regs[vx as usize];
+                self.pc += 2;
+            }
+            Instruction::AddI(vx) => {
+                // I += VX
+                let res = self.i as u32 + self.regs[vx as usize] as u32;
+                let overflow = (res > 0xFFF) as u8;
+                self.i = res as u16;
+                self.regs[VF] = overflow;
+                self.pc += 2;
+            }
+            Instruction::LoadFont(vx) => {
+                // I = sprite_addr[VX], only the low nibble selects the glyph
+                self.i = (self.regs[vx as usize] & 0xF) as u16 * 5;
+                self.pc += 2;
+            }
+            Instruction::StoreBcd(vx) => {
+                // Store BCD(VX) at I
+                let address = self.i as usize;
+                let val = self.regs[vx as usize];
+                self.check_range(address, 3)?;
+
+                self.write_memory(address, val / 100);
+                self.write_memory(address + 1, val / 10 % 10);
+                self.write_memory(address + 2, val % 10);
+                self.pc += 2;
+            }
+            Instruction::StoreRegs(vx) => {
+                // Dump V0-VX at I
+                let mut address = self.i as usize;
+                self.check_range(address, vx as usize + 1)?;
+
+                for i in 0..=vx as usize {
+                    self.write_memory(address, self.regs[i]);
+                    address += 1;
                 }
-                0x33 => {
-                    // Store BCD(VX) at I
-                    let mut offset = self.i;
-                    let mut val = self.regs[vx];
-
-                    if val % 100 > 0 {
-                        self.memory[offset as usize] = val / 100;
-                        val = val - (val / 100) * 100;
-                        offset += 1;
-                    }
-                    if val % 10 > 0 {
-                        self.memory[offset as usize] = val / 10;
-                        val = val - (val / 10) * 10;
-                        offset += 1;
-                    }
-                    self.memory[offset as usize] = val;
-                    self.pc += 2;
+
+                if self.quirks.load_store_inc_i {
+                    self.i = address as u16;
                 }
-                0x55 => {
-                    // Dump V0-VX at I
-                    let mut address = self.i as usize;
 
-                    for i in 0..(vx + 1) {
-                        self.memory[address] = self.regs[i];
-                        address += 1;
-                    }
+                self.pc += 2;
+            }
+            Instruction::LoadRegs(vx) => {
+                // Read V0-VX from I
+                let mut address = self.i as usize;
+                self.check_range(address, vx as usize + 1)?;
+
+                for i in 0..=vx as usize {
+                    self.regs[i] = self.memory[address];
+                    address += 1;
+                }
 
-                    self.pc += 2;
+                if self.quirks.load_store_inc_i {
+                    self.i = address as u16;
                 }
-                0x65 => {
-                    // Read V0-VX from I
-                    let mut address = self.i as usize;
 
-                    for i in 0..(vx + 1) {
-                        self.regs[i] = self.memory[address];
-                        address += 1;
-                    }
+                self.pc += 2;
+            }
+            Instruction::Unknown(opcode) => {
+                return Err(Error::UnknownOpcode {
+                    pc: self.pc,
+                    opcode,
+                })
+            }
+        }
 
-                    self.pc += 2;
-                }
-                _ => unimplemented!("Unknown opcode: ({:04X})", opcode.value),
-            },
-            _ => unimplemented!("Unknown opcode: ({:04X})", opcode.value),
+        Ok(())
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc += 2;
         }
+    }
+
+    fn key_state(&self, vx: u8) -> Result<bool, Error> {
+        let key = self.regs[vx as usize];
+        let keyboard = self.keyboard.lock().unwrap();
 
-        /*if self.draw_flag {
-            self.draw_flag = false;
-            self.debug_draw();
-        }*/
+        match keyboard.state.get(key as usize) {
+            Some(&pressed) => Ok(pressed),
+            None => Err(Error::InvalidKey { pc: self.pc, key }),
+        }
     }
 
     pub fn tick(&mut self) {
@@ -376,37 +527,39 @@ impl CPU {
     }
 
     fn draw_sprite(&mut self, x: u8, y: u8, height: u8) -> bool {
-        let buf = [0u8; 8];
         let mut ret = false;
-        let mut frame_buf = self.frame_buf.lock().unwrap();
+        let frame_buf = &mut self.frame_buf;
+        let (width, rows) = (frame_buf.width(), frame_buf.height());
+
+        // The starting position always wraps, the sprite itself only if the quirk is set
+        let (x, y) = (x as u32 % width, y as u32 % rows);
 
-        for i in 0..height {
+        for i in 0..height as u32 {
             // u8 line containing 8 pixels bit encoded
-            let line = self.memory[(self.i + i as u16) as usize];
+            let line = self.memory[self.i as usize + i as usize];
 
             for j in 0..8 {
-                let mut pixel = 0;
-                let mask = (1 << (7 - j));
+                let mask = 1 << (7 - j);
 
-                if line & mask != 0 {
-                    pixel = 255;
+                if line & mask == 0 {
+                    continue;
                 }
 
-                let c_x = match x.overflowing_add(j) {
-                    (res, _) => res as u32,
-                };
-
-                let c_y = match y.overflowing_add(i) {
-                    (res, _) => res as u32,
-                };
-
-                if pixel == 0xFF {
-                    if frame_buf.read(c_x, c_y) == 0x00 {
-                        frame_buf.write(c_x, c_y, 0xFF);
-                        ret = true;
-                    } else {
-                        frame_buf.write(c_x, c_y, 0x00);
-                    }
+                let (mut c_x, mut c_y) = (x + j, y + i);
+
+                if self.quirks.wrap_sprites {
+                    c_x %= width;
+                    c_y %= rows;
+                } else if c_x >= width || c_y >= rows {
+                    continue;
+                }
+
+                // Erasing a lit pixel counts as a collision
+                if frame_buf.read(c_x, c_y) == 0x00 {
+                    frame_buf.write(c_x, c_y, 0xFF);
+                } else {
+                    frame_buf.write(c_x, c_y, 0x00);
+                    ret = true;
                 }
             }
         }
@@ -0,0 +1,246 @@
+use super::config::{is_color, rom_hash, Settings};
+use super::quirks::*;
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// ROM metadata keyed by the ROM's SHA-1, in the spirit of the community
+// CHIP-8 database:
+//
+//   {
+//     "0123456789abcdef0123456789abcdef01234567": {
+//       "title": "Pong",
+//       "author": "Paul Vervalin",
+//       "platform": "originalChip8",
+//       "tickrate": 15,
+//       "keys": { "up": 1, "down": 4 },
+//       "colors": { "foreground": "FFFFFF", "background": "000000" }
+//     }
+//   }
+//
+// Database files may also be the community database's programs.json
+// (https://github.com/chip-8/chip-8-database), which lists programs with
+// their ROMs by SHA-1. The built in database only knows the test ROMs in
+// tests/fixtures, more entries come from database files.
+
+const BUILTIN: &str = include_str!("database.json");
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Colors {
+    // RRGGBB
+    pub foreground: String,
+    pub background: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    // originalChip8, modernChip8 or superchip
+    #[serde(default)]
+    pub platform: Option<String>,
+    // Instructions per 60 Hz frame
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    // Quirk preset for ROMs which don't match their platform's
+    #[serde(default)]
+    pub quirks: Option<String>,
+    // What the keypad keys do, e.g. "up" or "fire"
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub colors: Option<Colors>,
+}
+
+impl Program {
+    // Quirk preset the program expects
+    pub fn quirk_preset(&self) -> Option<&str> {
+        if let Some(quirks) = &self.quirks {
+            return Some(quirks);
+        }
+
+        platform_preset(self.platform.as_deref()?)
+    }
+
+    // Recommended settings, below the ROM's own section of the config. The
+    // arrow keys take over key hints named after directions.
+    pub fn settings(&self) -> Settings {
+        let keys: BTreeMap<String, String> = self
+            .keys
+            .iter()
+            .filter(|(hint, _)| ["up", "down", "left", "right"].contains(&hint.as_str()))
+            .map(|(hint, key)| (hint.clone(), format!("{:X}", key)))
+            .collect();
+
+        Settings {
+            clock: self.tickrate.and_then(|tickrate| tickrate.checked_mul(60)),
+            quirks: self.quirk_preset().map(str::to_string),
+            palette: self
+                .colors
+                .as_ref()
+                .map(|colors| format!("{},{}", colors.foreground, colors.background)),
+            keys: Some(keys).filter(|keys| !keys.is_empty()),
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Database {
+    pub programs: BTreeMap<String, Program>,
+}
+
+impl Database {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).unwrap()
+    }
+
+    // Either format, the community database's is an array
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let programs: BTreeMap<String, Program> = match value {
+            Value::Array(programs) => programs.iter().flat_map(community_roms).collect(),
+            value => serde_json::from_value(value).map_err(|e| e.to_string())?,
+        };
+
+        for (hash, program) in &programs {
+            if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("Invalid SHA-1 '{}'", hash));
+            }
+            if let Some(preset) = &program.quirks {
+                Quirks::preset(preset).ok_or_else(|| {
+                    format!("Unknown quirk preset '{}' for {}", preset, program.title)
+                })?;
+            }
+            if program.keys.values().any(|&key| key > 0xF) {
+                return Err(format!("Invalid keypad key for {}", program.title));
+            }
+            // The clock is the tickrate times 60 Hz
+            if let Some(tickrate) = program.tickrate {
+                if tickrate == 0 {
+                    return Err(format!("Tickrate 0 for {}", program.title));
+                }
+                tickrate
+                    .checked_mul(60)
+                    .ok_or_else(|| format!("Tickrate too high for {}", program.title))?;
+            }
+            if let Some(colors) = &program.colors {
+                if !is_color(&colors.foreground) || !is_color(&colors.background) {
+                    return Err(format!("Invalid colors for {}", program.title));
+                }
+            }
+        }
+
+        Ok(Self {
+            programs: programs
+                .into_iter()
+                .map(|(hash, program)| (hash.to_ascii_lowercase(), program))
+                .collect(),
+        })
+    }
+
+    // Entries of `other` replace ours
+    pub fn extend(&mut self, other: Database) {
+        self.programs.extend(other.programs);
+    }
+
+    pub fn get(&self, rom: &[u8]) -> Option<&Program> {
+        self.programs.get(&rom_hash(rom))
+    }
+
+    // The quirks a known ROM expects
+    pub fn quirks(&self, rom: &[u8]) -> Option<Quirks> {
+        Quirks::preset(self.get(rom)?.quirk_preset()?)
+    }
+}
+
+// Quirk preset of a platform as the community database names them
+fn platform_preset(platform: &str) -> Option<&'static str> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some("chip8"),
+        "modernChip8" => Some("default"),
+        "chip48" | "superchip1" | "superchip" => Some("schip"),
+        _ => None,
+    }
+}
+
+// The ROMs of one program from the community database. Its schema keeps
+// growing, so only the fields emu_rs uses are read and values of an
+// unexpected type are left out instead of failing the whole file.
+fn community_roms(program: &Value) -> Vec<(String, Program)> {
+    let title = match program["title"].as_str() {
+        Some(title) => title,
+        None => return Vec::new(),
+    };
+    let authors: Vec<&str> = program["authors"]
+        .as_array()
+        .map(|authors| authors.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let roms = match program["roms"].as_object() {
+        Some(roms) => roms,
+        None => return Vec::new(),
+    };
+
+    roms.iter()
+        .map(|(hash, rom)| {
+            let platforms: Vec<&str> = rom["platforms"]
+                .as_array()
+                .map(|platforms| platforms.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            // Platforms are listed best first, prefer one with a preset
+            let platform = platforms
+                .iter()
+                .find(|&&platform| platform_preset(platform).is_some())
+                .or_else(|| platforms.first());
+
+            let keys = rom["keys"]
+                .as_object()
+                .map(|keys| {
+                    keys.iter()
+                        .filter_map(|(hint, key)| {
+                            let key = key.as_u64().filter(|&key| key <= 0xF)?;
+                            Some((hint.clone(), key as u8))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            // Background first, then the color of lit pixels
+            let pixels: Vec<&str> = rom["colors"]["pixels"]
+                .as_array()
+                .map(|pixels| pixels.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            let color = |index: usize| {
+                let color = pixels.get(index)?.trim_start_matches('#');
+                Some(color.to_ascii_uppercase()).filter(|color| {
+                    color.len() == 6 && color.bytes().all(|b| b.is_ascii_hexdigit())
+                })
+            };
+            let colors = match (color(1), color(0)) {
+                (Some(foreground), Some(background)) => Some(Colors {
+                    foreground,
+                    background,
+                }),
+                _ => None,
+            };
+
+            let program = Program {
+                title: title.to_string(),
+                author: Some(authors.join(", ")).filter(|authors| !authors.is_empty()),
+                platform: platform.map(|platform| platform.to_string()),
+                tickrate: rom["tickrate"]
+                    .as_u64()
+                    .filter(|&tickrate| tickrate > 0 && tickrate <= (u32::MAX / 60) as u64)
+                    .map(|tickrate| tickrate as u32),
+                quirks: None,
+                keys,
+                colors,
+            };
+            (hash.clone(), program)
+        })
+        .collect()
+}
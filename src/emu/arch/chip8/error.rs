@@ -0,0 +1,41 @@
+use std::fmt;
+
+// Faults a malformed ROM can trigger. The faulting instruction is not
+// executed, so the CPU is left in the state from right before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // 2NNN with a full stack
+    StackOverflow { pc: u16 },
+    // 00EE with an empty stack
+    StackUnderflow { pc: u16 },
+    // Access past the end of `CPU::memory`
+    InvalidAddress { pc: u16, address: usize },
+    // EX9E/EXA1 with VX not naming one of the 16 keys
+    InvalidKey { pc: u16, key: u8 },
+    // Program doesn't fit between the entry point and the end of memory
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:04X}", opcode, pc)
+            }
+            Error::StackOverflow { pc } => write!(f, "Stack overflow at {:04X}", pc),
+            Error::StackUnderflow { pc } => write!(f, "Stack underflow at {:04X}", pc),
+            Error::InvalidAddress { pc, address } => {
+                write!(f, "Invalid memory access to {:04X} at {:04X}", address, pc)
+            }
+            Error::InvalidKey { pc, key } => write!(f, "Invalid key {:02X} at {:04X}", key, pc),
+            Error::RomTooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes, but at most {} bytes fit into memory",
+                size, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,52 @@
+use super::error::*;
+use super::lockstep::{Backend, Machine, Options, Side};
+use super::movie::*;
+use super::quirks::*;
+
+// Frames each fuzz input runs for, short enough to keep the fuzzer fast
+pub const FUZZ_FRAMES: u64 = 120;
+
+// Splits raw fuzzer data into a ROM, key input and quirks:
+//
+//   byte 0          quirk bits in declaration order of `Quirks`
+//   byte 1          number of key events N
+//   2 * N bytes     frame, then key in the low nibble and bit 4 set for a press
+//   remaining bytes the ROM
+pub fn decode_input(data: &[u8]) -> (Vec<u8>, Movie, Quirks) {
+    let bits = data.first().copied().unwrap_or(0);
+    let quirks = Quirks {
+        shift_vy: bits & 0x01 != 0,
+        load_store_inc_i: bits & 0x02 != 0,
+        jump_vx: bits & 0x04 != 0,
+        vf_reset: bits & 0x08 != 0,
+        wrap_sprites: bits & 0x10 != 0,
+    };
+
+    let count = data.get(1).copied().unwrap_or(0) as usize;
+    let events = data.get(2..).unwrap_or_default();
+    let count = count.min(events.len() / 2);
+
+    let mut movie = Movie::new();
+    for event in events[..count * 2].chunks(2) {
+        movie.push(event[0] as u64, event[1] & 0xF, event[1] & 0x10 != 0);
+    }
+
+    (events[count * 2..].to_vec(), movie, quirks)
+}
+
+// Runs a fuzz input headless on the given backend until it faults or runs
+// out of frames. Returns the number of executed cycles.
+pub fn run(data: &[u8], backend: Backend) -> Result<u64, Error> {
+    let (rom, movie, quirks) = decode_input(data);
+    let options = Options {
+        frames: FUZZ_FRAMES,
+        ..Options::default()
+    };
+    let mut machine = Machine::new(&rom, Side { backend, quirks }, &movie, &options)?;
+
+    while machine.frame() < options.frames {
+        machine.step(options.cycles_per_frame)?;
+    }
+
+    Ok(machine.cycle)
+}
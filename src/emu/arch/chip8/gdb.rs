@@ -0,0 +1,650 @@
+use super::cpu::*;
+use super::error::*;
+
+use std::collections::HashSet;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+// GDB remote serial protocol stub. `listen` spawns a thread which accepts one
+// debugger connection at a time and forwards its requests to the returned
+// `Debugger`, which the thread owning the CPU polls before every instruction.
+//
+//   emu_rs rom.ch8 --gdb 1234
+//   gdb -ex 'target remote :1234'
+//
+// Breakpoints are kept in a set instead of being patched into memory, so the
+// ROM never sees them. `monitor` commands go to the handler given to
+// `poll_with`, which is how the cheat engine is driven.
+
+// How long a paused CPU thread or a running session blocks before checking
+// for something else to do
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Register sizes in bytes, in the order of `TARGET_XML`
+pub const REGISTER_SIZES: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1,
+];
+
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.emu_rs.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // Halted for a newly attached debugger
+    Attach,
+    Breakpoint,
+    Step,
+    Interrupt,
+    Fault(Error),
+}
+
+impl StopReason {
+    // Unix signal number reported to GDB
+    pub fn signal(&self) -> u8 {
+        match *self {
+            StopReason::Interrupt => 2,
+            StopReason::Fault(Error::UnknownOpcode { .. }) => 4,
+            StopReason::Fault(_) => 11,
+            _ => 5,
+        }
+    }
+}
+
+enum Request {
+    Attach,
+    Detach,
+    Status,
+    ReadRegisters,
+    WriteRegisters(Vec<u8>),
+    ReadMemory(usize, usize),
+    WriteMemory(usize, Vec<u8>),
+    InsertBreakpoint(u16),
+    RemoveBreakpoint(u16),
+    Step,
+    Continue,
+    Interrupt,
+    Monitor(String),
+}
+
+enum Reply {
+    Ok,
+    Error,
+    Data(Vec<u8>),
+    // Answers status requests, and resume requests once the CPU stops again
+    Stopped(StopReason),
+}
+
+// V0-VF, I, PC, SP, DT and ST, multi byte registers little endian
+pub fn read_registers(cpu: &CPU) -> Vec<u8> {
+    let mut data = cpu.regs.to_vec();
+    data.extend_from_slice(&cpu.i.to_le_bytes());
+    data.extend_from_slice(&cpu.pc.to_le_bytes());
+    data.extend_from_slice(&cpu.sp.to_le_bytes());
+    data.push(cpu.timers[DELAY]);
+    data.push(cpu.timers[SOUND]);
+    data
+}
+
+// Inverse of `read_registers`, rejects short data and a SP past the stack
+pub fn write_registers(cpu: &mut CPU, data: &[u8]) -> bool {
+    if data.len() < REGISTER_SIZES.iter().sum() {
+        return false;
+    }
+
+    let sp = u16::from_le_bytes([data[20], data[21]]);
+    if sp as usize > cpu.stack.len() {
+        return false;
+    }
+
+    cpu.regs.copy_from_slice(&data[..16]);
+    cpu.i = u16::from_le_bytes([data[16], data[17]]);
+    cpu.pc = u16::from_le_bytes([data[18], data[19]]);
+    cpu.sp = sp;
+    cpu.timers[DELAY] = data[22];
+    cpu.timers[SOUND] = data[23];
+    true
+}
+
+// CPU side of the stub, owned by the thread which runs the CPU
+pub struct Debugger {
+    requests: Receiver<Request>,
+    replies: Sender<Reply>,
+    local_addr: SocketAddr,
+    pub breakpoints: HashSet<u16>,
+    paused: bool,
+    stepping: bool,
+    // Resuming from a breakpoint must not hit it again right away
+    resume_pc: Option<u16>,
+    last_stop: StopReason,
+}
+
+// Binds the listening socket and starts the server thread. The CPU starts
+// out paused until a debugger attaches and resumes it.
+pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Debugger> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let (request_tx, request_rx) = channel();
+    let (reply_tx, reply_rx) = channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            // Drop stops which happened while nobody was connected
+            while reply_rx.try_recv().is_ok() {}
+
+            let mut session = match Session::new(stream, &request_tx, &reply_rx) {
+                Ok(session) => session,
+                Err(_) => continue,
+            };
+            let _ = session.run();
+
+            if request_tx.send(Request::Detach).is_err() {
+                // The CPU thread is gone
+                break;
+            }
+        }
+    });
+
+    Ok(Debugger {
+        requests: request_rx,
+        replies: reply_tx,
+        local_addr,
+        breakpoints: HashSet::new(),
+        paused: true,
+        stepping: false,
+        resume_pc: None,
+        last_stop: StopReason::Attach,
+    })
+}
+
+impl Debugger {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Handles pending requests, then returns whether the CPU may execute the
+    // next instruction. While paused this waits up to `POLL_INTERVAL` for a
+    // request, so the caller gets to check for shutdown in between.
+    pub fn poll(&mut self, cpu: &mut CPU) -> bool {
+        self.poll_with(cpu, &mut |_, _| {
+            "Monitor commands aren't supported\n".to_string()
+        })
+    }
+
+    // Like `poll`, with `monitor` running `monitor` commands and returning
+    // their output
+    pub fn poll_with(
+        &mut self,
+        cpu: &mut CPU,
+        monitor: &mut dyn FnMut(&mut CPU, &str) -> String,
+    ) -> bool {
+        loop {
+            let request = if self.paused {
+                match self.requests.recv_timeout(POLL_INTERVAL) {
+                    Ok(request) => request,
+                    Err(RecvTimeoutError::Timeout) => return false,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        self.paused = false;
+                        break;
+                    }
+                }
+            } else {
+                match self.requests.try_recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                }
+            };
+
+            self.handle(cpu, request, monitor);
+        }
+
+        if self.paused {
+            return false;
+        }
+
+        if self.breakpoints.contains(&cpu.pc) && self.resume_pc != Some(cpu.pc) {
+            self.stop(StopReason::Breakpoint);
+            return false;
+        }
+
+        self.resume_pc = None;
+        true
+    }
+
+    // Called after an instruction `poll` allowed completed
+    pub fn executed(&mut self) {
+        if self.stepping {
+            self.stop(StopReason::Step);
+        }
+    }
+
+    // Called instead of `executed` when the instruction faulted, the CPU
+    // stays paused on it
+    pub fn fault(&mut self, error: Error) {
+        self.stop(StopReason::Fault(error));
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.paused = true;
+        self.stepping = false;
+        self.last_stop = reason;
+        let _ = self.replies.send(Reply::Stopped(reason));
+    }
+
+    fn resume(&mut self, cpu: &CPU, stepping: bool) {
+        self.paused = false;
+        self.stepping = stepping;
+        self.resume_pc = Some(cpu.pc);
+    }
+
+    fn reply(&self, reply: Reply) {
+        let _ = self.replies.send(reply);
+    }
+
+    fn handle(
+        &mut self,
+        cpu: &mut CPU,
+        request: Request,
+        monitor: &mut dyn FnMut(&mut CPU, &str) -> String,
+    ) {
+        match request {
+            Request::Attach => {
+                if !self.paused {
+                    self.paused = true;
+                    self.stepping = false;
+                    self.last_stop = StopReason::Attach;
+                }
+                self.reply(Reply::Stopped(self.last_stop));
+            }
+            Request::Detach => {
+                self.breakpoints.clear();
+                self.resume(cpu, false);
+            }
+            Request::Status => self.reply(Reply::Stopped(self.last_stop)),
+            Request::ReadRegisters => self.reply(Reply::Data(read_registers(cpu))),
+            Request::WriteRegisters(data) => {
+                if write_registers(cpu, &data) {
+                    self.reply(Reply::Ok);
+                } else {
+                    self.reply(Reply::Error);
+                }
+            }
+            Request::ReadMemory(address, len) => {
+                // Reads past the end are cut short, GDB asks again for the rest
+                let start = address.min(cpu.memory.len());
+                let end = address.saturating_add(len).min(cpu.memory.len());
+                if start == end && len > 0 {
+                    self.reply(Reply::Error);
+                } else {
+                    self.reply(Reply::Data(cpu.memory[start..end].to_vec()));
+                }
+            }
+            Request::WriteMemory(address, data) => {
+                match cpu
+                    .memory
+                    .get_mut(address..address.saturating_add(data.len()))
+                {
+                    Some(memory) => {
+                        memory.copy_from_slice(&data);
+                        cpu.invalidate_cache();
+                        self.reply(Reply::Ok);
+                    }
+                    None => self.reply(Reply::Error),
+                }
+            }
+            Request::InsertBreakpoint(address) => {
+                self.breakpoints.insert(address);
+                self.reply(Reply::Ok);
+            }
+            Request::RemoveBreakpoint(address) => {
+                self.breakpoints.remove(&address);
+                self.reply(Reply::Ok);
+            }
+            Request::Step => self.resume(cpu, true),
+            Request::Continue => self.resume(cpu, false),
+            Request::Interrupt => {
+                if !self.paused {
+                    self.stop(StopReason::Interrupt);
+                }
+            }
+            Request::Monitor(command) => {
+                let output = monitor(cpu, &command);
+                self.reply(Reply::Data(output.into_bytes()));
+            }
+        }
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "addr,len" as used by m, M and Z packets
+fn parse_pair(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let a = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let b = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((a, b))
+}
+
+fn stop_packet(reason: StopReason) -> String {
+    format!("S{:02x}", reason.signal())
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "CPU thread stopped")
+}
+
+// One debugger connection on the server thread
+struct Session<'a> {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    requests: &'a Sender<Request>,
+    replies: &'a Receiver<Reply>,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        stream: TcpStream,
+        requests: &'a Sender<Request>,
+        replies: &'a Receiver<Reply>,
+    ) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            requests,
+            replies,
+        })
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.request(Request::Attach)?;
+
+        loop {
+            let packet = self.read_packet()?;
+            match self.handle(&packet)? {
+                Some(response) => self.write_packet(&response)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    // Reads up to the next valid packet, acknowledging it. Acks and stray
+    // interrupts in between are skipped.
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            if self.read_byte()? != b'$' {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(expected);
+
+            if !valid {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+
+            self.writer.write_all(b"+")?;
+            return Ok(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            if let b'#' | b'$' | b'}' | b'*' = byte {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let checksum = packet[1..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        self.writer.write_all(&packet)
+    }
+
+    fn request(&mut self, request: Request) -> io::Result<Reply> {
+        self.requests.send(request).map_err(|_| disconnected())?;
+        self.replies.recv().map_err(|_| disconnected())
+    }
+
+    // Lets the CPU run until it stops again, forwarding Ctrl-C from GDB
+    fn resume(&mut self, request: Request) -> io::Result<String> {
+        self.requests.send(request).map_err(|_| disconnected())?;
+        self.writer.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let reason = loop {
+            match self.replies.recv_timeout(POLL_INTERVAL) {
+                Ok(Reply::Stopped(reason)) => break reason,
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(disconnected()),
+            }
+
+            match self.read_byte() {
+                Ok(0x03) => {
+                    self.requests
+                        .send(Request::Interrupt)
+                        .map_err(|_| disconnected())?;
+                }
+                Ok(_) => {}
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        };
+
+        self.writer.set_read_timeout(None)?;
+        Ok(stop_packet(reason))
+    }
+
+    fn simple(&mut self, request: Request) -> io::Result<String> {
+        Ok(match self.request(request)? {
+            Reply::Ok => "OK".to_string(),
+            Reply::Data(data) => hex_encode(&data),
+            Reply::Stopped(reason) => stop_packet(reason),
+            Reply::Error => "E01".to_string(),
+        })
+    }
+
+    fn registers(&mut self) -> io::Result<Vec<u8>> {
+        match self.request(Request::ReadRegisters)? {
+            Reply::Data(data) => Ok(data),
+            _ => Err(disconnected()),
+        }
+    }
+
+    // Returns the response, or None to close the connection
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let response = match command {
+            "?" => self.simple(Request::Status)?,
+            "g" => self.simple(Request::ReadRegisters)?,
+            "G" => match hex_decode(args) {
+                Some(data) => self.simple(Request::WriteRegisters(data))?,
+                None => "E01".to_string(),
+            },
+            "p" => {
+                let registers = self.registers()?;
+                match usize::from_str_radix(args, 16) {
+                    Ok(n) if n < REGISTER_SIZES.len() => {
+                        let offset: usize = REGISTER_SIZES[..n].iter().sum();
+                        hex_encode(&registers[offset..offset + REGISTER_SIZES[n]])
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let mut registers = self.registers()?;
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                let value = parts.next().and_then(hex_decode);
+
+                match (n, value) {
+                    (Some(n), Some(value))
+                        if n < REGISTER_SIZES.len() && value.len() == REGISTER_SIZES[n] =>
+                    {
+                        let offset: usize = REGISTER_SIZES[..n].iter().sum();
+                        registers[offset..offset + value.len()].copy_from_slice(&value);
+                        self.simple(Request::WriteRegisters(registers))?
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_pair(args) {
+                Some((address, len)) => self.simple(Request::ReadMemory(address, len))?,
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_pair);
+                let data = parts.next().and_then(hex_decode);
+
+                match (range, data) {
+                    (Some((address, len)), Some(data)) if data.len() == len => {
+                        self.simple(Request::WriteMemory(address, data))?
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            // Software and hardware breakpoints are the same thing here
+            "Z" | "z" if args.starts_with('0') || args.starts_with('1') => {
+                match args.get(2..).and_then(parse_pair) {
+                    Some((address, _)) if address <= 0xFFFF => {
+                        let address = address as u16;
+                        if command == "Z" {
+                            self.simple(Request::InsertBreakpoint(address))?
+                        } else {
+                            self.simple(Request::RemoveBreakpoint(address))?
+                        }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "s" => self.resume(Request::Step)?,
+            "c" => self.resume(Request::Continue)?,
+            "D" => {
+                self.write_packet("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            "H" => "OK".to_string(),
+            // The output goes back hex encoded in one packet
+            "q" if args.starts_with("Rcmd,") => {
+                let command =
+                    hex_decode(&args[5..]).and_then(|bytes| String::from_utf8(bytes).ok());
+                match command {
+                    Some(command) => match self.request(Request::Monitor(command))? {
+                        Reply::Data(output) if output.is_empty() => "OK".to_string(),
+                        Reply::Data(output) => hex_encode(&output),
+                        _ => "E01".to_string(),
+                    },
+                    None => "E01".to_string(),
+                }
+            }
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+
+        Ok(Some(response))
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+".to_string()
+        } else if query == "Attached" {
+            "1".to_string()
+        } else if query == "fThreadInfo" {
+            "m1".to_string()
+        } else if query == "sThreadInfo" {
+            "l".to_string()
+        } else if query == "C" {
+            "QC1".to_string()
+        } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            match parse_pair(range) {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = offset.saturating_add(len).min(xml.len());
+                    let marker = if end == xml.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end]))
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use super::opcode::*;
+
+use std::fmt;
+
+// An opcode with its operands already extracted. Register operands are stored
+// as indices into `CPU::regs`, kept small so a decoded cache stays compact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Clear,
+    Return,
+    Jump(u16),
+    Call(u16),
+    SkipEq(u8, u8),
+    SkipNe(u8, u8),
+    SkipEqReg(u8, u8),
+    SkipNeReg(u8, u8),
+    Load(u8, u8),
+    Add(u8, u8),
+    Move(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubReverse(u8, u8),
+    ShiftLeft(u8, u8),
+    LoadI(u16),
+    JumpOffset(u16, u8),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipKey(u8),
+    SkipNotKey(u8),
+    LoadDelay(u8),
+    WaitKey(u8),
+    SetDelay(u8),
+    SetSound(u8),
+    AddI(u8),
+    LoadFont(u8),
+    StoreBcd(u8),
+    StoreRegs(u8),
+    LoadRegs(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: &Opcode) -> Self {
+        let vx = opcode.x() as u8;
+        let vy = opcode.y() as u8;
+        let nn = opcode.nn() as u8;
+        let nnn = opcode.nnn();
+
+        match opcode.first() {
+            0x0 => match nn {
+                0xE0 => Instruction::Clear,
+                0xEE => Instruction::Return,
+                _ => Instruction::Unknown(opcode.value),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipEq(vx, nn),
+            0x4 => Instruction::SkipNe(vx, nn),
+            0x5 => Instruction::SkipEqReg(vx, vy),
+            0x6 => Instruction::Load(vx, nn),
+            0x7 => Instruction::Add(vx, nn),
+            0x8 => match opcode.last() {
+                0x0 => Instruction::Move(vx, vy),
+                0x1 => Instruction::Or(vx, vy),
+                0x2 => Instruction::And(vx, vy),
+                0x3 => Instruction::Xor(vx, vy),
+                0x4 => Instruction::AddReg(vx, vy),
+                0x5 => Instruction::Sub(vx, vy),
+                0x6 => Instruction::ShiftRight(vx, vy),
+                0x7 => Instruction::SubReverse(vx, vy),
+                0xE => Instruction::ShiftLeft(vx, vy),
+                _ => Instruction::Unknown(opcode.value),
+            },
+            0x9 => Instruction::SkipNeReg(vx, vy),
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpOffset(nnn, vx),
+            0xC => Instruction::Random(vx, nn),
+            0xD => Instruction::Draw(vx, vy, opcode.last() as u8),
+            0xE => match nn {
+                0x9E => Instruction::SkipKey(vx),
+                0xA1 => Instruction::SkipNotKey(vx),
+                _ => Instruction::Unknown(opcode.value),
+            },
+            0xF => match nn {
+                0x07 => Instruction::LoadDelay(vx),
+                0x0A => Instruction::WaitKey(vx),
+                0x15 => Instruction::SetDelay(vx),
+                0x18 => Instruction::SetSound(vx),
+                0x1E => Instruction::AddI(vx),
+                0x29 => Instruction::LoadFont(vx),
+                0x33 => Instruction::StoreBcd(vx),
+                0x55 => Instruction::StoreRegs(vx),
+                0x65 => Instruction::LoadRegs(vx),
+                _ => Instruction::Unknown(opcode.value),
+            },
+            _ => Instruction::Unknown(opcode.value),
+        }
+    }
+}
+
+// Disassembly in the common Cowgod mnemonics, unknown opcodes as raw words
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEq(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNe(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::Add(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpOffset(nnn, _) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegs(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegs(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown(value) => write!(f, "DW 0x{:04X}", value),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+extern crate dynasmrt;
+
+use dynasmrt::x64::Assembler;
+use dynasmrt::{dynasm, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+
+use super::cpu::*;
+use super::error::*;
+use super::instruction::*;
+use super::opcode::*;
+use super::quirks::*;
+
+// Native blocks are called with a pointer to `CPU::regs`, one to `CPU::i` and
+// the cycle budget. They return the new PC in the low 16 bits and the number
+// of executed instructions above.
+type BlockFn = extern "sysv64" fn(regs: *mut u8, i: *mut u16, budget: u64) -> u64;
+
+// Longest run of instructions compiled into a single block
+const MAX_BLOCK_LEN: usize = 64;
+
+struct Block {
+    // Keeps the native code mapped
+    _code: ExecutableBuffer,
+    entry: BlockFn,
+    start: u16,
+    len: usize,
+}
+
+impl Block {
+    fn end(&self) -> u16 {
+        self.start + 2 * self.len as u16
+    }
+}
+
+enum Entry {
+    Unknown,
+    // Nothing at this address can be compiled
+    Interpret,
+    Native(Box<Block>),
+}
+
+// Translates straight-line runs of register/ALU instructions into x86-64.
+// Everything else (control flow, memory, timers, input and drawing) is left
+// to the cached interpreter, so a block always ends right before such an
+// instruction.
+pub struct Jit {
+    // One entry per start address
+    entries: Vec<Entry>,
+    // Quirks the blocks were compiled for
+    quirks: Quirks,
+    // `CPU::generation` of the memory the blocks were compiled from
+    generation: u64,
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        let mut entries = Vec::new();
+        entries.resize_with(0x10000, || Entry::Unknown);
+
+        Self {
+            entries,
+            quirks: Quirks::default(),
+            generation: 0,
+        }
+    }
+
+    // Must be called after writing to `CPU::memory` directly. Changes made
+    // through `CPU::write_memory`, `CPU::load_program` or a state restore
+    // are noticed on the next step.
+    pub fn invalidate(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = Entry::Unknown;
+        }
+    }
+
+    fn invalidate_range(&mut self, start: u16, end: u16) {
+        // A block covering `start` may begin up to a full block earlier
+        let first = start.saturating_sub(2 * MAX_BLOCK_LEN as u16);
+
+        for address in first..end {
+            let entry = &mut self.entries[address as usize];
+            let overlaps = match entry {
+                Entry::Unknown => false,
+                Entry::Interpret => address + 2 > start,
+                Entry::Native(block) => block.end() > start,
+            };
+
+            if overlaps {
+                *entry = Entry::Unknown;
+            }
+        }
+    }
+
+    // Executes at most `cycles` instructions, returns how many were executed
+    pub fn run(&mut self, cpu: &mut CPU, cycles: usize) -> Result<usize, Error> {
+        let mut executed = 0;
+
+        while executed < cycles {
+            executed += self.step(cpu, cycles - executed)?;
+        }
+
+        Ok(executed)
+    }
+
+    // Executes one native block if it fits into `budget` instructions, and
+    // a single interpreted instruction otherwise. Returns the instruction count.
+    pub fn step(&mut self, cpu: &mut CPU, budget: usize) -> Result<usize, Error> {
+        if cpu.quirks != self.quirks || cpu.generation() != self.generation {
+            self.invalidate();
+            self.quirks = cpu.quirks;
+            self.generation = cpu.generation();
+        }
+
+        let pc = cpu.pc;
+        let entry = &mut self.entries[pc as usize];
+        if let Entry::Unknown = entry {
+            *entry = match compile(cpu, pc) {
+                Some(block) => Entry::Native(Box::new(block)),
+                None => Entry::Interpret,
+            };
+        }
+
+        if let Entry::Native(block) = entry {
+            if block.len <= budget {
+                let result = (block.entry)(cpu.regs.as_mut_ptr(), &mut cpu.i, budget as u64);
+                cpu.pc = result as u16;
+                return Ok((result >> 16) as usize);
+            }
+        }
+
+        // Stores are the only instructions able to modify code. Without an
+        // opcode at pc the interpreter reports the invalid address.
+        let written = match cpu.memory.get(pc as usize..pc as usize + 2) {
+            Some(&[high, 0x33]) if high >> 4 == 0xF => Some((cpu.i, 3)),
+            Some(&[high, 0x55]) if high >> 4 == 0xF => Some((cpu.i, (high & 0xF) as u16 + 1)),
+            _ => None,
+        };
+
+        cpu.execute_cached()?;
+
+        if let Some((address, len)) = written {
+            self.invalidate_range(address, address.saturating_add(len));
+        }
+        self.generation = cpu.generation();
+
+        Ok(1)
+    }
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// None past the end of memory
+fn decode_at(cpu: &CPU, address: u16) -> Option<Instruction> {
+    let bytes = cpu.memory.get(address as usize..address as usize + 2)?;
+
+    Some(Instruction::decode(&Opcode {
+        value: (bytes[0] as u16) << 8 | bytes[1] as u16,
+    }))
+}
+
+fn compile(cpu: &CPU, start: u16) -> Option<Block> {
+    let mut ops = Assembler::new().unwrap();
+    let entry = ops.offset();
+    let mut len = 0;
+
+    // r8 counts the executed instructions
+    dynasm!(ops
+        ; .arch x64
+        ; xor r8d, r8d
+        ; top:
+    );
+    let mut exited = false;
+
+    while len < MAX_BLOCK_LEN {
+        let address = start + 2 * len as u16;
+        let instruction = match decode_at(cpu, address) {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        if emit(&mut ops, instruction, &cpu.quirks) {
+            len += 1;
+        } else {
+            if emit_exit(&mut ops, instruction, address) {
+                len += 1;
+                exited = true;
+            }
+            break;
+        }
+    }
+
+    if len == 0 {
+        return None;
+    }
+
+    if !exited {
+        let next = start + 2 * len as u16;
+        dynasm!(ops
+            ; .arch x64
+            ; mov eax, next as i32
+        );
+    }
+
+    // Loops back to the start as long as the budget allows another iteration,
+    // which keeps tight loops from bouncing between native code and `step`
+    dynasm!(ops
+        ; .arch x64
+        ; add r8, len as i32
+        ; cmp eax, start as i32
+        ; jne >done
+        ; lea rcx, [r8 + len as i32]
+        ; cmp rcx, rdx
+        ; jbe <top
+        ; done:
+        ; shl r8, 16
+        ; or rax, r8
+        ; ret
+    );
+
+    let code = ops.finalize().unwrap();
+    let entry: BlockFn = unsafe { std::mem::transmute(code.ptr(entry)) };
+
+    Some(Block {
+        _code: code,
+        entry,
+        start,
+        len,
+    })
+}
+
+// Emits native code for a single instruction operating on the register file
+// in rdi and I in rsi. Returns false if the instruction can't be compiled.
+fn emit(ops: &mut Assembler, instruction: Instruction, quirks: &Quirks) -> bool {
+    let flag = VF as i32;
+
+    match instruction {
+        Instruction::Load(vx, nn) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov BYTE [rdi + vx as i32], nn as i8
+            );
+        }
+        Instruction::Add(vx, nn) => {
+            dynasm!(ops
+                ; .arch x64
+                ; add BYTE [rdi + vx as i32], nn as i8
+            );
+        }
+        Instruction::Move(vx, vy) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + vy as i32]
+                ; mov BYTE [rdi + vx as i32], al
+            );
+        }
+        Instruction::Or(vx, vy) | Instruction::And(vx, vy) | Instruction::Xor(vx, vy) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + vy as i32]
+            );
+            match instruction {
+                Instruction::Or(..) => dynasm!(ops ; .arch x64 ; or BYTE [rdi + vx as i32], al),
+                Instruction::And(..) => dynasm!(ops ; .arch x64 ; and BYTE [rdi + vx as i32], al),
+                _ => dynasm!(ops ; .arch x64 ; xor BYTE [rdi + vx as i32], al),
+            }
+            if quirks.vf_reset {
+                dynasm!(ops
+                    ; .arch x64
+                    ; mov BYTE [rdi + flag], 0
+                );
+            }
+        }
+        Instruction::AddReg(vx, vy) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + vx as i32]
+                ; add al, BYTE [rdi + vy as i32]
+                ; setc cl
+                ; mov BYTE [rdi + vx as i32], al
+                ; mov BYTE [rdi + flag], cl
+            );
+        }
+        Instruction::Sub(vx, vy) | Instruction::SubReverse(vx, vy) => {
+            let (lhs, rhs) = match instruction {
+                Instruction::Sub(..) => (vx, vy),
+                _ => (vy, vx),
+            };
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + lhs as i32]
+                ; sub al, BYTE [rdi + rhs as i32]
+                ; setnc cl
+                ; mov BYTE [rdi + vx as i32], al
+                ; mov BYTE [rdi + flag], cl
+            );
+        }
+        Instruction::ShiftRight(vx, vy) => {
+            let src = if quirks.shift_vy { vy } else { vx };
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + src as i32]
+                ; mov cl, al
+                ; and cl, 1
+                ; shr al, 1
+                ; mov BYTE [rdi + vx as i32], al
+                ; mov BYTE [rdi + flag], cl
+            );
+        }
+        Instruction::ShiftLeft(vx, vy) => {
+            let src = if quirks.shift_vy { vy } else { vx };
+            dynasm!(ops
+                ; .arch x64
+                ; mov al, BYTE [rdi + src as i32]
+                ; mov cl, al
+                ; shr cl, 7
+                ; shl al, 1
+                ; mov BYTE [rdi + vx as i32], al
+                ; mov BYTE [rdi + flag], cl
+            );
+        }
+        Instruction::LoadI(nnn) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov WORD [rsi], nnn as i16
+            );
+        }
+        Instruction::AddI(vx) => {
+            dynasm!(ops
+                ; .arch x64
+                ; movzx eax, BYTE [rdi + vx as i32]
+                ; movzx ecx, WORD [rsi]
+                ; add eax, ecx
+                ; mov WORD [rsi], ax
+                ; cmp eax, 0xFFF
+                ; seta cl
+                ; mov BYTE [rdi + flag], cl
+            );
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+// Emits a jump or skip ending the block at `address`, leaving the new PC in
+// eax. Returns false if the instruction can't be compiled.
+fn emit_exit(ops: &mut Assembler, instruction: Instruction, address: u16) -> bool {
+    let next = address.wrapping_add(2) as i32;
+    let skip = address.wrapping_add(4) as i32;
+
+    match instruction {
+        Instruction::Jump(nnn) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov eax, nnn as i32
+            );
+            return true;
+        }
+        Instruction::SkipEq(vx, nn) | Instruction::SkipNe(vx, nn) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov eax, next
+                ; mov ecx, skip
+                ; cmp BYTE [rdi + vx as i32], nn as i8
+            );
+        }
+        Instruction::SkipEqReg(vx, vy) | Instruction::SkipNeReg(vx, vy) => {
+            dynasm!(ops
+                ; .arch x64
+                ; mov eax, next
+                ; mov ecx, skip
+                ; mov r9b, BYTE [rdi + vy as i32]
+                ; cmp BYTE [rdi + vx as i32], r9b
+            );
+        }
+        _ => return false,
+    }
+
+    match instruction {
+        Instruction::SkipEq(..) | Instruction::SkipEqReg(..) => {
+            dynasm!(ops ; .arch x64 ; cmove eax, ecx)
+        }
+        _ => dynasm!(ops ; .arch x64 ; cmovne eax, ecx),
+    }
+
+    true
+}
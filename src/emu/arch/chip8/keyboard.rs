@@ -27,4 +27,10 @@ impl Keyboard {
     pub fn release_key(&mut self, key: u8) {
         self.state[key as usize] = false;
     }
-}
\ No newline at end of file
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,298 @@
+use super::analysis::Platform;
+use super::config::{is_color, rom_hash, Settings};
+use super::error::Error;
+use super::octo;
+use super::quirks::*;
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+// Loads programs from plain ROM files, gzip and zip archives and Octo
+// cartridges, from a file or from stdin as "-". The format is told by the
+// content, not the file name.
+//
+// Octo cartridges are GIFs whose pixels carry the Octo source and the options
+// of the program. Each pixel's palette index holds two bits, four pixels make
+// a byte, highest bits first, over all frames in order. The bytes are a big
+// endian 32 bit length followed by that many bytes of UTF-8 JSON:
+//
+//   { "program": "<Octo source>", "options": { "tickrate": 20, ... } }
+
+// Unpacked files past this are no ROM, and may be a zip bomb
+const MAX_UNPACKED: usize = 0x10000;
+
+// Extensions picked from archives holding more than one file
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    Raw,
+    Gzip,
+    // With the name of the file taken from the archive
+    Zip(String),
+    OctoCartridge,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Raw => write!(f, "raw"),
+            Format::Gzip => write!(f, "gzip"),
+            Format::Zip(name) => write!(f, "zip, {}", name),
+            Format::OctoCartridge => write!(f, "Octo cartridge"),
+        }
+    }
+}
+
+// The options Octo saves with a program which emu_rs has a use for. XO-CHIP
+// plane and buzzer colors, screen rotation, touch input and font style are
+// ignored, as are the VF order and vblank quirks emu_rs doesn't emulate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Options {
+    // Instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+    // #RRGGBB
+    pub fill_color: Option<String>,
+    pub background_color: Option<String>,
+    // Shifts leave VY alone
+    pub shift_quirks: Option<bool>,
+    // FX55/FX65 leave I alone
+    pub load_store_quirks: Option<bool>,
+    // BNNN jumps to NNN + VX
+    pub jump_quirks: Option<bool>,
+    // 8XY1/8XY2/8XY3 reset VF
+    pub logic_quirks: Option<bool>,
+    // Sprites are clipped at the screen edges
+    pub clip_quirks: Option<bool>,
+}
+
+// Octo's defaults for colors a cartridge leaves out
+const OCTO_FILL_COLOR: &str = "#FFCC00";
+const OCTO_BACKGROUND_COLOR: &str = "#996600";
+
+impl Options {
+    // Fails on options the emulator can't run with
+    pub fn check(&self) -> Result<(), String> {
+        // The clock is the tickrate times 60 Hz
+        if let Some(tickrate) = self.tickrate {
+            if tickrate == 0 {
+                return Err("tickrate 0 is too low".to_string());
+            }
+            if tickrate.checked_mul(60).is_none() {
+                return Err(format!("tickrate {} is too high", tickrate));
+            }
+        }
+
+        let colors = [
+            ("fillColor", &self.fill_color),
+            ("backgroundColor", &self.background_color),
+        ];
+        for (name, color) in colors.iter() {
+            if let Some(color) = color {
+                if !is_color(color) {
+                    return Err(format!("{} '{}' isn't a #RRGGBB color", name, color));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // What the program was set up to run with, below the ROM's own section
+    // of the config. Quirks the cartridge leaves out are Octo's defaults.
+    pub fn settings(&self) -> Settings {
+        let flags = [
+            self.shift_quirks,
+            self.load_store_quirks,
+            self.jump_quirks,
+            self.logic_quirks,
+            self.clip_quirks,
+        ];
+        let quirks = if flags.iter().any(Option::is_some) {
+            let quirks = Quirks {
+                shift_vy: !self.shift_quirks.unwrap_or(false),
+                load_store_inc_i: !self.load_store_quirks.unwrap_or(false),
+                jump_vx: self.jump_quirks.unwrap_or(false),
+                vf_reset: self.logic_quirks.unwrap_or(false),
+                wrap_sprites: !self.clip_quirks.unwrap_or(false),
+            };
+            Some(quirks.to_string())
+        } else {
+            None
+        };
+
+        let palette = match (&self.fill_color, &self.background_color) {
+            (None, None) => None,
+            (fill, background) => Some(format!(
+                "{},{}",
+                fill.as_deref().unwrap_or(OCTO_FILL_COLOR),
+                background.as_deref().unwrap_or(OCTO_BACKGROUND_COLOR)
+            )),
+        };
+
+        Settings {
+            clock: self.tickrate.and_then(|tickrate| tickrate.checked_mul(60)),
+            quirks,
+            palette,
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Cartridge {
+    // Octo source the program was compiled from
+    pub program: String,
+    #[serde(default)]
+    pub options: Options,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rom {
+    pub program: Vec<u8>,
+    pub format: Format,
+    // SHA-1 of the program, not the file it came in
+    pub hash: String,
+    pub cartridge: Option<Cartridge>,
+}
+
+impl Rom {
+    // Fails unless the program fits into the platform's memory
+    pub fn check_size(&self, platform: Platform) -> Result<(), Error> {
+        let max = platform.max_rom_size();
+        if self.program.len() > max {
+            return Err(Error::RomTooLarge {
+                size: self.program.len(),
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+// Reads `path`, or stdin if it is "-"
+pub fn load(path: &str) -> Result<Rom, String> {
+    let bytes = if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Can't read stdin: {}", e))?;
+        bytes
+    } else {
+        fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?
+    };
+
+    decode(bytes).map_err(|e| {
+        let name = if path == "-" { "stdin" } else { path };
+        format!("{}: {}", name, e)
+    })
+}
+
+pub fn decode(bytes: Vec<u8>) -> Result<Rom, String> {
+    let (program, format, cartridge) = if bytes.starts_with(&[0x1F, 0x8B]) {
+        (unpack(GzDecoder::new(&bytes[..]))?, Format::Gzip, None)
+    } else if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+        // The second is an empty archive
+        let (name, program) = unzip(&bytes)?;
+        (program, Format::Zip(name), None)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        let cartridge = cartridge(&bytes)?;
+        let program = octo::compile(&cartridge.program)
+            .map_err(|e| format!("Can't compile the cartridge's program: {}", e))?;
+        (program, Format::OctoCartridge, Some(cartridge))
+    } else {
+        (bytes, Format::Raw, None)
+    };
+
+    Ok(Rom {
+        hash: rom_hash(&program),
+        program,
+        format,
+        cartridge,
+    })
+}
+
+fn unpack(reader: impl Read) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_UNPACKED as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Can't unpack: {}", e))?;
+    if bytes.len() > MAX_UNPACKED {
+        return Err(format!("Unpacks to more than {} bytes", MAX_UNPACKED));
+    }
+    Ok(bytes)
+}
+
+// The archive's only file, or its only file which looks like a ROM
+fn unzip(bytes: &[u8]) -> Result<(String, Vec<u8>), String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let mut files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    files.sort();
+
+    let roms: Vec<&str> = files
+        .iter()
+        .map(String::as_str)
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+                })
+        })
+        .collect();
+    let name = match (files.as_slice(), roms.as_slice()) {
+        ([], _) => return Err("The archive is empty".to_string()),
+        ([name], _) => name.clone(),
+        (_, [name]) => name.to_string(),
+        (_, []) => return Err(format!("No ROM among {}", files.join(", "))),
+        (_, roms) => return Err(format!("More than one ROM: {}", roms.join(", "))),
+    };
+
+    let file = archive.by_name(&name).map_err(|e| e.to_string())?;
+    Ok((name, unpack(file)?))
+}
+
+pub fn cartridge(gif: &[u8]) -> Result<Cartridge, String> {
+    let error = |e: gif::DecodingError| format!("Not a valid GIF: {}", e);
+    let mut reader = gif::Decoder::new(gif).read_info().map_err(error)?;
+
+    let mut bytes = Vec::new();
+    let mut byte = 0;
+    let mut pixels = 0;
+    while let Some(frame) = reader.read_next_frame().map_err(error)? {
+        for &index in frame.buffer.iter() {
+            byte = byte << 2 | index & 3;
+            pixels += 1;
+            if pixels % 4 == 0 {
+                bytes.push(byte);
+            }
+        }
+    }
+
+    let invalid = || "Not an Octo cartridge".to_string();
+    let length = bytes
+        .get(..4)
+        .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+        .ok_or_else(invalid)?;
+    let json = bytes
+        .get(4..)
+        .and_then(|rest| rest.get(..length))
+        .ok_or_else(invalid)?;
+    let cartridge: Cartridge =
+        serde_json::from_slice(json).map_err(|e| format!("Invalid cartridge data: {}", e))?;
+    cartridge
+        .options
+        .check()
+        .map_err(|e| format!("Invalid cartridge data: {}", e))?;
+    Ok(cartridge)
+}
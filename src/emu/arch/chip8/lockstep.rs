@@ -0,0 +1,549 @@
+use super::super::super::core::FrameBuffer;
+use super::cpu::*;
+use super::error::*;
+use super::instruction::*;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+use super::jit::*;
+use super::keyboard::*;
+use super::movie::*;
+use super::opcode::*;
+use super::quirks::*;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Cached,
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    Jit,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "interpreter" => Some(Backend::Interpreter),
+            "cached" => Some(Backend::Cached),
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            "jit" => Some(Backend::Jit),
+            _ => None,
+        }
+    }
+}
+
+// One side of a comparison, written as "<backend>[:<quirk preset>]"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Side {
+    pub backend: Backend,
+    pub quirks: Quirks,
+}
+
+impl Side {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(2, ':');
+        let backend = parts.next().unwrap_or_default();
+        let backend =
+            Backend::parse(backend).ok_or_else(|| format!("Unknown backend '{}'", backend))?;
+        let quirks = match parts.next() {
+            Some(preset) => Quirks::preset(preset)
+                .ok_or_else(|| format!("Unknown quirk preset '{}'", preset))?,
+            None => Quirks::default(),
+        };
+
+        Ok(Self { backend, quirks })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub frames: u64,
+    // 540 Hz CPU against 60 Hz timers like the main loop
+    pub cycles_per_frame: u64,
+    pub seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            frames: 600,
+            cycles_per_frame: 9,
+            seed: 0,
+        }
+    }
+}
+
+// A headless emulator instance replaying a movie
+pub struct Machine<'a> {
+    pub cpu: CPU,
+    pub cycle: u64,
+    backend: Backend,
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    jit: Jit,
+    movie: &'a Movie,
+    cycles_per_frame: u64,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(rom: &[u8], side: Side, movie: &'a Movie, options: &Options) -> Result<Self, Error> {
+        let mut cpu = CPU::new(
+            FrameBuffer::new(64, 32, 0u8),
+            Arc::new(Mutex::new(Keyboard::new())),
+        );
+        cpu.quirks = side.quirks;
+        cpu.seed(options.seed);
+        cpu.load_program(rom)?;
+
+        Ok(Self {
+            cpu,
+            cycle: 0,
+            backend: side.backend,
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            jit: Jit::new(),
+            movie,
+            cycles_per_frame: options.cycles_per_frame,
+        })
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.cycle / self.cycles_per_frame
+    }
+
+    // Executes one instruction, or one native block of at most `budget`
+    // instructions on the JIT, and returns the instruction count. Steps end at
+    // frame boundaries: input for a frame is applied before its first
+    // instruction and the timers tick after its last one.
+    pub fn step(&mut self, budget: u64) -> Result<u64, Error> {
+        if self.cycle.is_multiple_of(self.cycles_per_frame) {
+            let mut keyboard = self.cpu.keyboard.lock().unwrap();
+            self.movie.apply(self.frame(), &mut keyboard);
+        }
+        let budget = budget.min(self.cycles_per_frame - self.cycle % self.cycles_per_frame);
+        #[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+        let _ = budget;
+
+        let executed = match self.backend {
+            Backend::Interpreter => self.cpu.execute().map(|_| 1)?,
+            Backend::Cached => self.cpu.execute_cached().map(|_| 1)?,
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            Backend::Jit => self.jit.step(&mut self.cpu, budget as usize)? as u64,
+        };
+
+        self.cycle += executed;
+        if self.cycle.is_multiple_of(self.cycles_per_frame) {
+            self.cpu.tick();
+        }
+
+        Ok(executed)
+    }
+
+    // Executes exactly `cycles` instructions in as few steps as possible
+    pub fn advance(&mut self, cycles: u64) -> Result<(), Error> {
+        let mut executed = 0;
+        while executed < cycles {
+            executed += self.step(cycles - executed)?;
+        }
+
+        Ok(())
+    }
+
+    // Whether a step may run more than one instruction
+    fn runs_blocks(&self) -> bool {
+        match self.backend {
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            Backend::Jit => true,
+            _ => false,
+        }
+    }
+
+    fn opcode(&self) -> u16 {
+        let pc = self.cpu.pc as usize;
+        let byte = |address: usize| self.cpu.memory.get(address).copied().unwrap_or(0) as u16;
+        byte(pc) << 8 | byte(pc + 1)
+    }
+}
+
+// Everything visible about a CPU except its memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub regs: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub timers: [u8; 2],
+    pub stack: Vec<u16>,
+    pub width: u32,
+    pub screen: Vec<bool>,
+}
+
+const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+
+impl State {
+    pub fn capture(cpu: &CPU) -> Self {
+        let depth = (cpu.sp as usize).min(cpu.stack.len());
+
+        Self {
+            regs: cpu.regs,
+            i: cpu.i,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            timers: cpu.timers,
+            stack: cpu.stack[..depth].to_vec(),
+            width: cpu.frame_buf.width(),
+            screen: cpu.frame_buf.frame().iter().map(|&p| p != 0).collect(),
+        }
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.regs)?;
+        out.write_all(&self.i.to_le_bytes())?;
+        out.write_all(&self.pc.to_le_bytes())?;
+        out.write_all(&self.sp.to_le_bytes())?;
+        out.write_all(&self.timers)?;
+        out.write_all(&[self.stack.len() as u8])?;
+        for address in &self.stack {
+            out.write_all(&address.to_le_bytes())?;
+        }
+
+        // One bit per pixel
+        let mut packed = vec![0u8; self.screen.len().div_ceil(8)];
+        for (index, &lit) in self.screen.iter().enumerate() {
+            packed[index / 8] |= (lit as u8) << (index % 8);
+        }
+        out.write_all(&packed)
+    }
+
+    // Returns None at the end of the trace
+    fn read(input: &mut dyn Read, width: u32, height: u32) -> io::Result<Option<Self>> {
+        let mut regs = [0u8; 16];
+        match input.read_exact(&mut regs) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+
+        let mut words = [0u8; 6];
+        input.read_exact(&mut words)?;
+        let mut timers = [0u8; 2];
+        input.read_exact(&mut timers)?;
+        let mut depth = [0u8; 1];
+        input.read_exact(&mut depth)?;
+        let mut stack = vec![0u8; depth[0] as usize * 2];
+        input.read_exact(&mut stack)?;
+        let pixels = (width * height) as usize;
+        let mut packed = vec![0u8; pixels.div_ceil(8)];
+        input.read_exact(&mut packed)?;
+
+        Ok(Some(Self {
+            regs,
+            i: u16::from_le_bytes([words[0], words[1]]),
+            pc: u16::from_le_bytes([words[2], words[3]]),
+            sp: u16::from_le_bytes([words[4], words[5]]),
+            timers,
+            stack: stack
+                .chunks(2)
+                .map(|word| u16::from_le_bytes([word[0], word[1]]))
+                .collect(),
+            width,
+            screen: (0..pixels)
+                .map(|index| packed[index / 8] & (1 << (index % 8)) != 0)
+                .collect(),
+        }))
+    }
+
+    fn register_diff(&self, other: &Self) -> Vec<String> {
+        let mut diff = Vec::new();
+
+        for (index, (a, b)) in self.regs.iter().zip(other.regs.iter()).enumerate() {
+            if a != b {
+                diff.push(format!("V{:X}: {:02X} != {:02X}", index, a, b));
+            }
+        }
+        if self.i != other.i {
+            diff.push(format!("I: {:04X} != {:04X}", self.i, other.i));
+        }
+        if self.pc != other.pc {
+            diff.push(format!("PC: {:04X} != {:04X}", self.pc, other.pc));
+        }
+        if self.sp != other.sp {
+            diff.push(format!("SP: {:04X} != {:04X}", self.sp, other.sp));
+        }
+        if self.timers[DELAY] != other.timers[DELAY] {
+            diff.push(format!(
+                "DT: {:02X} != {:02X}",
+                self.timers[DELAY], other.timers[DELAY]
+            ));
+        }
+        if self.timers[SOUND] != other.timers[SOUND] {
+            diff.push(format!(
+                "ST: {:02X} != {:02X}",
+                self.timers[SOUND], other.timers[SOUND]
+            ));
+        }
+        if self.stack != other.stack {
+            diff.push(format!(
+                "stack: {:04X?} != {:04X?}",
+                self.stack, other.stack
+            ));
+        }
+
+        diff
+    }
+
+    // Map of the screen with 'a' and 'b' marking pixels lit on one side only
+    fn screen_diff(&self, other: &Self) -> Option<String> {
+        if self.screen == other.screen {
+            return None;
+        }
+
+        let mut map = String::new();
+        for (row_a, row_b) in self
+            .screen
+            .chunks(self.width as usize)
+            .zip(other.screen.chunks(self.width as usize))
+        {
+            for (&a, &b) in row_a.iter().zip(row_b.iter()) {
+                map.push(match (a, b) {
+                    (true, true) => '#',
+                    (true, false) => 'a',
+                    (false, true) => 'b',
+                    (false, false) => '.',
+                });
+            }
+            map.push('\n');
+        }
+
+        Some(map)
+    }
+}
+
+// The first step after which both sides disagree
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub frame: u64,
+    // Address and opcode of the instruction both sides just executed, the
+    // first of the block if the JIT ran one
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: Vec<String>,
+    // Differing addresses, empty when comparing against a trace
+    pub memory: Vec<usize>,
+    pub screen: Option<String>,
+    // Set if only one side faulted, or both in different ways
+    pub error: Option<String>,
+}
+
+impl Divergence {
+    fn new(machine: &Machine, pc: u16, opcode: u16, a: &State, b: &State) -> Self {
+        Self {
+            cycle: machine.cycle,
+            frame: machine.frame(),
+            pc,
+            opcode,
+            registers: a.register_diff(b),
+            memory: Vec::new(),
+            screen: a.screen_diff(b),
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = Instruction::decode(&Opcode { value: self.opcode });
+        writeln!(
+            f,
+            "Diverged at cycle {} (frame {}) after {:04X} {:?} at {:04X}",
+            self.cycle, self.frame, self.opcode, instruction, self.pc
+        )?;
+
+        if let Some(error) = &self.error {
+            writeln!(f, "  {}", error)?;
+        }
+
+        for line in &self.registers {
+            writeln!(f, "  {}", line)?;
+        }
+
+        if !self.memory.is_empty() {
+            let shown: Vec<String> = self
+                .memory
+                .iter()
+                .take(8)
+                .map(|address| format!("{:04X}", address))
+                .collect();
+            write!(f, "  memory differs at {}", shown.join(", "))?;
+            if self.memory.len() > shown.len() {
+                write!(f, " (+{} more)", self.memory.len() - shown.len())?;
+            }
+            writeln!(f)?;
+        }
+
+        if let Some(screen) = &self.screen {
+            writeln!(f, "  screen differs ('a'/'b' lit on one side only):")?;
+            write!(f, "{}", screen)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_rom(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+fn outcome(result: &Result<(), Error>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(error) => error.to_string(),
+    }
+}
+
+fn same(a: &CPU, b: &CPU) -> bool {
+    a.regs == b.regs
+        && a.i == b.i
+        && a.pc == b.pc
+        && a.sp == b.sp
+        && a.timers == b.timers
+        && a.stack[..] == b.stack[..]
+        && a.frame_buf.frame() == b.frame_buf.frame()
+        && a.memory[..] == b.memory[..]
+}
+
+// Runs two instances side by side and stops at the first step after which
+// their state differs. A JIT side runs whole native blocks and the other side
+// follows by as many instructions, so state is compared at block boundaries.
+// Returns the number of executed cycles otherwise, which is less than
+// requested if both sides faulted the same way.
+pub fn run(
+    rom: &[u8],
+    movie: &Movie,
+    a: Side,
+    b: Side,
+    options: &Options,
+) -> Result<Result<u64, Divergence>, Error> {
+    let mut machine_a = Machine::new(rom, a, movie, options)?;
+    let mut machine_b = Machine::new(rom, b, movie, options)?;
+
+    while machine_a.frame() < options.frames {
+        let pc = machine_a.cpu.pc;
+        let opcode = machine_a.opcode();
+
+        let (result_a, result_b) = if machine_b.runs_blocks() && !machine_a.runs_blocks() {
+            let result_b = machine_b.step(options.cycles_per_frame);
+            let result_a = machine_a.advance(*result_b.as_ref().unwrap_or(&1));
+            (result_a, result_b.map(|_| ()))
+        } else {
+            let result_a = machine_a.step(options.cycles_per_frame);
+            let result_b = machine_b.advance(*result_a.as_ref().unwrap_or(&1));
+            (result_a.map(|_| ()), result_b)
+        };
+
+        if result_a != result_b || !same(&machine_a.cpu, &machine_b.cpu) {
+            let state_a = State::capture(&machine_a.cpu);
+            let state_b = State::capture(&machine_b.cpu);
+            let mut divergence = Divergence::new(&machine_a, pc, opcode, &state_a, &state_b);
+            divergence.memory = (0..machine_a.cpu.memory.len())
+                .filter(|&address| machine_a.cpu.memory[address] != machine_b.cpu.memory[address])
+                .collect();
+            if result_a != result_b {
+                divergence.error = Some(format!(
+                    "result: {} != {}",
+                    outcome(&result_a),
+                    outcome(&result_b)
+                ));
+            }
+
+            return Ok(Err(divergence));
+        }
+
+        if result_a.is_err() {
+            break;
+        }
+    }
+
+    Ok(Ok(machine_a.cycle))
+}
+
+// Writes the state after every instruction so a later build can be compared
+// against this one with `replay`, the JIT included. The trace ends at the
+// first fault.
+pub fn record(
+    rom: &[u8],
+    movie: &Movie,
+    side: Side,
+    options: &Options,
+    out: &mut dyn Write,
+) -> io::Result<u64> {
+    let mut machine = Machine::new(rom, side, movie, options).map_err(invalid_rom)?;
+
+    out.write_all(TRACE_MAGIC)?;
+    out.write_all(&(machine.cpu.frame_buf.width() as u16).to_le_bytes())?;
+    out.write_all(&(machine.cpu.frame_buf.height() as u16).to_le_bytes())?;
+
+    while machine.frame() < options.frames {
+        if machine.step(1).is_err() {
+            break;
+        }
+        State::capture(&machine.cpu).write(out)?;
+    }
+
+    Ok(machine.cycle)
+}
+
+// Runs the ROM against a trace written by `record`, up to the end of the
+// trace. After a native block the state is compared with the trace's state
+// for the block's last instruction.
+pub fn replay(
+    rom: &[u8],
+    movie: &Movie,
+    side: Side,
+    options: &Options,
+    trace: &mut dyn Read,
+) -> io::Result<Result<u64, Divergence>> {
+    let mut machine = Machine::new(rom, side, movie, options).map_err(invalid_rom)?;
+
+    let mut header = [0u8; 8];
+    trace.read_exact(&mut header)?;
+    if &header[..4] != TRACE_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a lockstep trace",
+        ));
+    }
+    let width = u16::from_le_bytes([header[4], header[5]]) as u32;
+    let height = u16::from_le_bytes([header[6], header[7]]) as u32;
+
+    let mut checked = 0;
+    while machine.frame() < options.frames {
+        let pc = machine.cpu.pc;
+        let opcode = machine.opcode();
+        let result = machine.step(options.cycles_per_frame);
+
+        let mut expected = None;
+        for _ in 0..*result.as_ref().unwrap_or(&1) {
+            expected = State::read(trace, width, height)?;
+            if expected.is_none() {
+                break;
+            }
+        }
+        let expected = match expected {
+            Some(state) => state,
+            None => break,
+        };
+
+        let state = State::capture(&machine.cpu);
+        if result.is_err() || state != expected {
+            let mut divergence = Divergence::new(&machine, pc, opcode, &expected, &state);
+            if let Err(error) = result {
+                divergence.error = Some(format!("result: ok != {}", error));
+            }
+
+            return Ok(Err(divergence));
+        }
+        checked = machine.cycle;
+    }
+
+    Ok(Ok(checked))
+}
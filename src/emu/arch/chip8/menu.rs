@@ -0,0 +1,351 @@
+use super::super::super::core::{draw_text, FrameBuffer, CELL_HEIGHT, CELL_WIDTH};
+use super::overlay::{render_lines, Line};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Settings menu drawn over the game image, opened with Esc. The window
+// thread owns the `Menu`, feeds it keys and carries out the `Command`s it
+// returns. Values apply as soon as they change, the other entries on Enter.
+
+pub const MENU_COLUMNS: u32 = 32;
+// Title, the entries and the key help
+pub const MENU_ROWS: u32 = ENTRIES.len() as u32 + 4;
+pub const NOTIFICATION_COLUMNS: u32 = 32;
+// How long notifications stay on screen
+pub const NOTIFICATION_TIME: Duration = Duration::from_secs(2);
+
+// What the menu cycles through, named like the config file names them
+pub const QUIRK_PRESETS: [&str; 3] = ["default", "chip8", "schip"];
+pub const PALETTES: [&str; 4] = ["white", "amber", "green", "lcd"];
+pub const SLOTS: u8 = 9;
+// Files the ROM picker lists
+pub const ROM_EXTENSIONS: [&str; 7] = ["ch8", "c8", "sc8", "xo8", "zip", "gz", "gif"];
+
+// Speed steps in Hz, one instruction per frame each
+const CLOCK_STEP: u32 = 60;
+const MAX_CLOCK: u32 = 6000;
+// Where values start, after the entry names
+const VALUE_COLUMN: usize = 10;
+const LOCKED: &str = "Not with netplay, gdb, rpc or movies";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    Rom,
+    Reset,
+    Speed,
+    Quirks,
+    Scaler,
+    Palette,
+    Slot,
+    SaveState,
+    LoadState,
+}
+
+pub const ENTRIES: [Entry; 9] = [
+    Entry::Rom,
+    Entry::Reset,
+    Entry::Speed,
+    Entry::Quirks,
+    Entry::Scaler,
+    Entry::Palette,
+    Entry::Slot,
+    Entry::SaveState,
+    Entry::LoadState,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    LoadRom(PathBuf),
+    Reset,
+    // In Hz
+    SetClock(u32),
+    SetQuirks(&'static str),
+    // Whether EPX smoothing is on
+    SetScaler(bool),
+    SetPalette(&'static str),
+    SaveState(u8),
+    LoadState(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Menu {
+    pub open: bool,
+    // Index into `ENTRIES`
+    pub selected: usize,
+    // The picker's ROMs and the one it shows
+    pub roms: Vec<PathBuf>,
+    pub rom: usize,
+    pub clock: u32,
+    // Indices into `QUIRK_PRESETS` and `PALETTES`, None for settings the
+    // menu has no name for
+    pub quirks: Option<usize>,
+    pub palette: Option<usize>,
+    pub epx: bool,
+    // 1 to `SLOTS`
+    pub slot: u8,
+    // Set while netplay, a debugger or a movie follows the run. Only the
+    // scaler, the palette and saving states work then.
+    pub locked: bool,
+    notification: Option<(String, Instant)>,
+}
+
+impl Menu {
+    // Starts with the settings the emulator runs with, `quirks` and
+    // `palette` as the config file has them
+    pub fn new(clock: u32, quirks: &str, epx: bool, palette: &str) -> Self {
+        let mut menu = Self {
+            open: false,
+            selected: 0,
+            roms: Vec::new(),
+            rom: 0,
+            clock,
+            quirks: None,
+            palette: None,
+            epx,
+            slot: 1,
+            locked: false,
+            notification: None,
+        };
+        menu.set_settings(clock, quirks, epx, palette);
+        menu
+    }
+
+    // Shows the settings of another ROM, like `new`
+    pub fn set_settings(&mut self, clock: u32, quirks: &str, epx: bool, palette: &str) {
+        self.clock = clock;
+        self.quirks = QUIRK_PRESETS.iter().position(|&name| name == quirks);
+        self.palette = PALETTES.iter().position(|&name| name == palette);
+        self.epx = epx;
+    }
+
+    // Fills the picker, showing the ROM named like `current` if it's among
+    // `roms`
+    pub fn set_roms(&mut self, roms: Vec<PathBuf>, current: Option<&Path>) {
+        self.rom = current
+            .and_then(|current| {
+                roms.iter()
+                    .position(|rom| rom.file_name() == current.file_name())
+            })
+            .unwrap_or(0);
+        self.roms = roms;
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    // Handles a key pressed while the menu is open. Commands which start
+    // the game over or load something close the menu.
+    pub fn key(&mut self, key: MenuKey) -> Option<Command> {
+        let entry = ENTRIES[self.selected];
+        let changes_run = matches!(
+            (entry, key),
+            (Entry::Rom | Entry::Reset | Entry::LoadState, MenuKey::Enter)
+                | (Entry::Speed | Entry::Quirks, MenuKey::Left | MenuKey::Right)
+        );
+        if self.locked && changes_run {
+            self.notify(LOCKED);
+            return None;
+        }
+
+        let command = match key {
+            MenuKey::Up => {
+                self.selected = (self.selected + ENTRIES.len() - 1) % ENTRIES.len();
+                None
+            }
+            MenuKey::Down => {
+                self.selected = (self.selected + 1) % ENTRIES.len();
+                None
+            }
+            MenuKey::Left => self.change(entry, -1),
+            MenuKey::Right => self.change(entry, 1),
+            MenuKey::Enter => match entry {
+                Entry::Rom => self.roms.get(self.rom).cloned().map(Command::LoadRom),
+                Entry::Reset => Some(Command::Reset),
+                Entry::SaveState => Some(Command::SaveState(self.slot)),
+                Entry::LoadState => Some(Command::LoadState(self.slot)),
+                Entry::Scaler => self.change(entry, 1),
+                _ => None,
+            },
+        };
+
+        if let Some(
+            Command::LoadRom(_) | Command::Reset | Command::SaveState(_) | Command::LoadState(_),
+        ) = command
+        {
+            self.open = false;
+        }
+        command
+    }
+
+    // Steps the entry's value back or forth
+    fn change(&mut self, entry: Entry, step: i32) -> Option<Command> {
+        match entry {
+            Entry::Rom => {
+                self.rom = wrap(Some(self.rom), self.roms.len(), step)?;
+                None
+            }
+            Entry::Speed => {
+                let clock = (self.clock as i64 + (step * CLOCK_STEP as i32) as i64)
+                    .clamp(CLOCK_STEP as i64, MAX_CLOCK as i64) as u32;
+                if clock == self.clock {
+                    return None;
+                }
+                self.clock = clock;
+                Some(Command::SetClock(clock))
+            }
+            Entry::Quirks => {
+                let quirks = wrap(self.quirks, QUIRK_PRESETS.len(), step)?;
+                self.quirks = Some(quirks);
+                Some(Command::SetQuirks(QUIRK_PRESETS[quirks]))
+            }
+            Entry::Scaler => {
+                self.epx = !self.epx;
+                Some(Command::SetScaler(self.epx))
+            }
+            Entry::Palette => {
+                let palette = wrap(self.palette, PALETTES.len(), step)?;
+                self.palette = Some(palette);
+                Some(Command::SetPalette(PALETTES[palette]))
+            }
+            Entry::Slot => {
+                self.slot = wrap(Some(self.slot as usize - 1), SLOTS as usize, step)? as u8 + 1;
+                None
+            }
+            Entry::Reset | Entry::SaveState | Entry::LoadState => None,
+        }
+    }
+
+    // Shows `text` for `NOTIFICATION_TIME`, menu open or not
+    pub fn notify(&mut self, text: impl Into<String>) {
+        self.notification = Some((text.into(), Instant::now()));
+    }
+
+    pub fn notification(&self) -> Option<&str> {
+        match &self.notification {
+            Some((text, shown)) if shown.elapsed() < NOTIFICATION_TIME => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        let mut lines = vec![Line::new("Menu".to_string()), Line::new(String::new())];
+
+        for (index, &entry) in ENTRIES.iter().enumerate() {
+            let (name, value) = match entry {
+                Entry::Rom => (
+                    "ROM",
+                    Some(match self.roms.get(self.rom) {
+                        Some(rom) => rom
+                            .file_name()
+                            .map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+                        None => "none found".to_string(),
+                    }),
+                ),
+                Entry::Reset => ("Reset", None),
+                Entry::Speed => ("Speed", Some(format!("{} Hz", self.clock))),
+                Entry::Quirks => ("Quirks", Some(name(&QUIRK_PRESETS, self.quirks))),
+                Entry::Scaler => (
+                    "Scaler",
+                    Some(if self.epx { "epx" } else { "none" }.to_string()),
+                ),
+                Entry::Palette => ("Palette", Some(name(&PALETTES, self.palette))),
+                Entry::Slot => ("Slot", Some(self.slot.to_string())),
+                Entry::SaveState => ("Save state", None),
+                Entry::LoadState => ("Load state", None),
+            };
+
+            let mut text = match value {
+                // Long file names lose their end
+                Some(mut value) => {
+                    value.truncate(MENU_COLUMNS as usize - VALUE_COLUMN - 4);
+                    format!("{:width$}< {} >", name, value, width = VALUE_COLUMN)
+                }
+                None => name.to_string(),
+            };
+            text.truncate(MENU_COLUMNS as usize);
+
+            let mut line = Line::new(text);
+            if index == self.selected {
+                line.highlight = Some((0, MENU_COLUMNS as usize));
+            }
+            lines.push(line);
+        }
+
+        lines.push(Line::new(String::new()));
+        lines.push(Line::new("Arrows, Enter, Esc closes".to_string()));
+        lines
+    }
+
+    // Draws the menu into `buf`, which should be `menu_buffer()` sized
+    pub fn render(&self, buf: &mut FrameBuffer<u8>) {
+        render_lines(buf, &self.lines());
+    }
+
+    // Draws the notification into `buf`, which should be
+    // `notification_buffer()` sized
+    pub fn render_notification(&self, buf: &mut FrameBuffer<u8>) {
+        buf.clear(0);
+        if let Some(text) = self.notification() {
+            draw_text(buf, 0, 0, text, 255, false);
+        }
+        buf.request_draw();
+    }
+}
+
+// `index` moved by `step` with wrap around, None without anything to pick.
+// Without a current index stepping forth picks the first one and stepping
+// back the last.
+fn wrap(index: Option<usize>, len: usize, step: i32) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(match index {
+        Some(index) => (index as i64 + step as i64).rem_euclid(len as i64) as usize,
+        None if step > 0 => 0,
+        None => len - 1,
+    })
+}
+
+fn name(names: &[&str], index: Option<usize>) -> String {
+    index.map_or("custom", |index| names[index]).to_string()
+}
+
+pub fn menu_buffer() -> FrameBuffer<u8> {
+    FrameBuffer::new(MENU_COLUMNS * CELL_WIDTH, MENU_ROWS * CELL_HEIGHT, 0u8)
+}
+
+pub fn notification_buffer() -> FrameBuffer<u8> {
+    FrameBuffer::new(NOTIFICATION_COLUMNS * CELL_WIDTH, CELL_HEIGHT, 0u8)
+}
+
+// The ROMs in `dir` by name, for the picker
+pub fn list_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+                    })
+        })
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
@@ -1,9 +1,46 @@
+pub mod analysis;
+mod asm;
+pub mod cheats;
+pub mod config;
 mod cpu;
+pub mod database;
+mod error;
 mod font;
-mod opcode;
+pub mod fuzz;
+#[cfg(feature = "native")]
+pub mod gdb;
+mod instruction;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+mod jit;
 mod keyboard;
+pub mod loader;
+pub mod lockstep;
+pub mod menu;
+mod movie;
+#[cfg(feature = "native")]
+pub mod netplay;
+pub mod octo;
+mod opcode;
+pub mod overlay;
+pub mod profile;
+mod quirks;
+#[cfg(feature = "native")]
+pub mod rpc;
+#[cfg(feature = "native")]
+pub mod script;
+mod state;
+pub mod trace;
+pub mod wasm;
 
+pub use asm::*;
 pub use cpu::*;
+pub use error::*;
 pub use font::*;
+pub use instruction::*;
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+pub use jit::*;
 pub use keyboard::*;
+pub use movie::*;
 pub use opcode::*;
+pub use quirks::*;
+pub use state::*;
@@ -0,0 +1,110 @@
+use super::keyboard::*;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    // Applied before the first instruction of this frame
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Recorded keypad input, replayed frame by frame so a run can be reproduced.
+//
+// The text format has one event per line, "<frame> +<key>" for a press and
+// "<frame> -<key>" for a release, with the key as a hex digit. Lines starting
+// with '#' are comments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Self::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || format!("Invalid movie event on line {}: {}", number + 1, line);
+            let mut parts = line.split_whitespace();
+            let frame = parts
+                .next()
+                .and_then(|frame| frame.parse().ok())
+                .ok_or_else(error)?;
+            let event = parts.next().ok_or_else(error)?;
+            if parts.next().is_some() {
+                return Err(error());
+            }
+
+            let (pressed, key) = if let Some(key) = event.strip_prefix('+') {
+                (true, key)
+            } else if let Some(key) = event.strip_prefix('-') {
+                (false, key)
+            } else {
+                return Err(error());
+            };
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(error)?;
+
+            movie.push(frame, key, pressed);
+        }
+
+        Ok(movie)
+    }
+
+    // Keeps the events sorted by frame, events within a frame keep their order
+    pub fn push(&mut self, frame: u64, key: u8, pressed: bool) {
+        let index = self.events.partition_point(|event| event.frame <= frame);
+        self.events.insert(
+            index,
+            KeyEvent {
+                frame,
+                key,
+                pressed,
+            },
+        );
+    }
+
+    pub fn apply(&self, frame: u64, keyboard: &mut Keyboard) {
+        let start = self.events.partition_point(|event| event.frame < frame);
+
+        for event in self.events[start..].iter().take_while(|e| e.frame == frame) {
+            if event.pressed {
+                keyboard.press_key(event.key);
+            } else {
+                keyboard.release_key(event.key);
+            }
+        }
+    }
+
+    // Frame of the last event, runs usually continue a little past it
+    pub fn len(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.frame + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            let sign = if event.pressed { '+' } else { '-' };
+            writeln!(f, "{} {}{:X}", event.frame, sign, event.key)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+use super::cpu::*;
+use super::keyboard::*;
+use super::state::*;
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+// Two player netplay over UDP with rollback. Each player owns some of the
+// keypad's keys, the peer owns the rest. Every frame both sides send their
+// keys, and until the peer's keys for a frame arrive they're predicted to be
+// the same as in the last frame that did arrive. When a prediction turns
+// out wrong, the CPU goes back to the save state from the start of that
+// frame and runs the frames since again with the right keys. Both sides
+// need the same ROM, quirks, clock and CXNN seed.
+//
+// Packets carry, integers little endian:
+//
+//   "C8NP", version, game ID, the sender's key mask, the first frame of
+//   its keys, their count and the key masks, how many of the receiver's
+//   frames it has, then a checksum frame and checksum or u64::MAX twice
+//
+// Keys are resent until the peer has them, so lost packets don't matter.
+// The checksums cover the save states at the start of every
+// `CHECK_INTERVAL`th frame once both sides' keys before it are known, a
+// mismatch means the peers desynced.
+
+const MAGIC: &[u8; 4] = b"C8NP";
+const VERSION: u8 = 1;
+
+// How far ahead of the peer's keys the CPU may run before it waits for them
+pub const MAX_ROLLBACK: u64 = 8;
+// Frames between desync checks
+pub const CHECK_INTERVAL: u64 = 30;
+// How long waiting for the peer blocks before the caller gets control back
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Most keys sent in one packet
+const MAX_INPUTS: usize = 255;
+// Checksums kept around to compare with the peer's
+const MAX_CHECKS: usize = 16;
+
+pub struct Session {
+    socket: UdpSocket,
+    game: u64,
+    // Keys this side owns
+    mask: u16,
+    // Keys pressed on this side and the peer's, by frame
+    local: Vec<u16>,
+    remote: Vec<u16>,
+    // Keys the CPU ran each frame with, predictions included
+    inputs: Vec<u16>,
+    // States at the start of the last frames, oldest first
+    states: VecDeque<(u64, SaveState)>,
+    // Earliest frame which ran with a wrong prediction
+    rollback: Option<u64>,
+    // How many of our frames the peer has
+    acked: u64,
+    next_check: u64,
+    checks: VecDeque<(u64, u64)>,
+    remote_checks: VecDeque<(u64, u64)>,
+    // Frames run again after wrong predictions
+    pub rollbacks: u64,
+}
+
+// A session identifier for the ROM and everything else which has to match
+pub fn game_id(program: &[u8], settings: &str) -> u64 {
+    fnv(program) ^ fnv(settings.as_bytes()).rotate_left(1)
+}
+
+// Keypad keys as a mask, key 0 in the lowest bit
+pub fn key_mask(keys: &[bool; 16]) -> u16 {
+    (0..16).filter(|&key| keys[key]).map(|key| 1 << key).sum()
+}
+
+// FNV-1a
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01B3)
+    })
+}
+
+// Presses and releases keys until the keyboard matches `input`
+fn apply(keyboard: &mut Keyboard, input: u16) {
+    for key in 0..16 {
+        let pressed = input & (1 << key) != 0;
+        if pressed && !keyboard.state[key as usize] {
+            keyboard.press_key(key);
+        } else if !pressed && keyboard.state[key as usize] {
+            keyboard.release_key(key);
+        }
+    }
+}
+
+impl Session {
+    // Binds `local`, e.g. "0.0.0.0:7000", and talks to `peer` only. `mask`
+    // has a bit set for each key this side owns.
+    pub fn connect(local: &str, peer: &str, mask: u16, game: u64) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+
+        Ok(Self {
+            socket,
+            game,
+            mask,
+            local: Vec::new(),
+            remote: Vec::new(),
+            inputs: Vec::new(),
+            states: VecDeque::new(),
+            rollback: None,
+            acked: 0,
+            next_check: 0,
+            checks: VecDeque::new(),
+            remote_checks: VecDeque::new(),
+            rollbacks: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        Ok(self.socket.local_addr()?.to_string())
+    }
+
+    // Frames started so far
+    pub fn frame(&self) -> u64 {
+        self.local.len() as u64
+    }
+
+    // Frames the peer's keys are known for
+    pub fn confirmed(&self) -> u64 {
+        self.remote.len() as u64
+    }
+
+    // Sets up the keypad for the next frame, right before its first
+    // instruction. `keys` are this side's keys, the peer's are masked out.
+    // `run_frame` runs a whole frame, it's called to run frames again after
+    // wrong predictions. Returns false without starting the frame when the
+    // CPU is too far ahead of the peer, after waiting up to `POLL_INTERVAL`
+    // for it.
+    pub fn start_frame<F>(
+        &mut self,
+        cpu: &mut CPU,
+        keys: &[bool; 16],
+        run_frame: F,
+    ) -> Result<bool, String>
+    where
+        F: FnMut(&mut CPU) -> Result<(), String>,
+    {
+        self.receive()?;
+        if self.frame() >= self.confirmed() + MAX_ROLLBACK {
+            self.send();
+            self.wait()?;
+            return Ok(false);
+        }
+        self.roll_back(cpu, run_frame)?;
+
+        let frame = self.frame();
+        self.local.push(key_mask(keys) & self.mask);
+        let input = self.input(frame);
+        self.inputs.push(input);
+        self.save(frame, cpu);
+        apply(&mut cpu.keyboard.lock().unwrap(), input);
+
+        self.check()?;
+        self.send();
+        Ok(true)
+    }
+
+    // Waits until both sides have each other's keys for all frames started
+    // so far and runs frames again where predictions were wrong, so both
+    // end in the same state. Fails if the peer doesn't answer in `timeout`.
+    pub fn finish<F>(
+        &mut self,
+        cpu: &mut CPU,
+        run_frame: F,
+        timeout: Duration,
+    ) -> Result<(), String>
+    where
+        F: FnMut(&mut CPU) -> Result<(), String>,
+    {
+        let deadline = Instant::now() + timeout;
+        while self.confirmed() < self.frame() || self.acked < self.frame() {
+            if Instant::now() >= deadline {
+                return Err("The peer stopped answering".to_string());
+            }
+            self.send();
+            self.wait()?;
+        }
+        // The peer may still be waiting for the acknowledgement
+        self.send();
+
+        self.roll_back(cpu, run_frame)?;
+        self.check()
+    }
+
+    // The peer's keys are predicted to stay the same
+    fn input(&self, frame: u64) -> u16 {
+        let remote = match self.remote.get(frame as usize) {
+            Some(&input) => input,
+            None => self.remote.last().copied().unwrap_or(0),
+        };
+        self.local[frame as usize] | remote
+    }
+
+    fn save(&mut self, frame: u64, cpu: &CPU) {
+        // Frames run again replace the states after the one rolled back to
+        while matches!(self.states.back(), Some(&(last, _)) if last >= frame) {
+            self.states.pop_back();
+        }
+        self.states.push_back((frame, SaveState::capture(cpu)));
+        // The next desync check needs its state even past the rollback window
+        while matches!(self.states.front(), Some(&(oldest, _))
+            if oldest + MAX_ROLLBACK < frame && oldest < self.next_check)
+        {
+            self.states.pop_front();
+        }
+    }
+
+    fn roll_back<F>(&mut self, cpu: &mut CPU, mut run_frame: F) -> Result<(), String>
+    where
+        F: FnMut(&mut CPU) -> Result<(), String>,
+    {
+        let start = match self.rollback.take() {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let state = match self.states.iter().find(|(frame, _)| *frame == start) {
+            Some((_, state)) => state.clone(),
+            None => return Err(format!("No save state to roll back to frame {}", start)),
+        };
+
+        // The keys are held like they were at the time, without pressing
+        // them again
+        state.restore(cpu);
+        let previous = match start {
+            0 => 0,
+            _ => self.inputs[start as usize - 1],
+        };
+        let mut keyboard = cpu.keyboard.lock().unwrap();
+        for key in 0..16 {
+            keyboard.state[key] = previous & (1 << key) != 0;
+        }
+        drop(keyboard);
+
+        for frame in start..self.frame() {
+            if frame > start {
+                self.save(frame, cpu);
+            }
+            let input = self.input(frame);
+            self.inputs[frame as usize] = input;
+            apply(&mut cpu.keyboard.lock().unwrap(), input);
+            run_frame(cpu)?;
+            self.rollbacks += 1;
+        }
+        Ok(())
+    }
+
+    // Checksums the states both sides' keys are known for and compares them
+    // with the peer's
+    fn check(&mut self) -> Result<(), String> {
+        let last = self.confirmed().min(self.frame().saturating_sub(1));
+        while self.next_check <= last && self.next_check < self.frame() {
+            let frame = self.next_check;
+            self.next_check += CHECK_INTERVAL;
+
+            let state = match self.states.iter().find(|(saved, _)| *saved == frame) {
+                Some((_, state)) => state,
+                None => return Err(format!("No save state to check frame {}", frame)),
+            };
+            self.checks.push_back((frame, fnv(&state.to_bytes())));
+            if self.checks.len() > MAX_CHECKS {
+                self.checks.pop_front();
+            }
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<(), String> {
+        while let Some(&(frame, checksum)) = self.remote_checks.front() {
+            match self.checks.iter().find(|(checked, _)| *checked == frame) {
+                Some(&(_, local)) if local != checksum => {
+                    return Err(format!(
+                        "Desync at frame {}, the peers' states differ",
+                        frame
+                    ))
+                }
+                Some(_) => {}
+                // Not checked here yet
+                None if frame >= self.next_check => break,
+                // Skipping it would hide a desync from both sides
+                None => {
+                    return Err(format!(
+                        "No checksum of frame {} to compare with the peer's",
+                        frame
+                    ))
+                }
+            }
+            self.remote_checks.pop_front();
+        }
+        Ok(())
+    }
+
+    fn send(&self) {
+        let first = self.acked.min(self.frame());
+        let inputs = &self.local[first as usize..];
+        let inputs = &inputs[..inputs.len().min(MAX_INPUTS)];
+
+        let mut packet = MAGIC.to_vec();
+        packet.push(VERSION);
+        packet.extend_from_slice(&self.game.to_le_bytes());
+        packet.extend_from_slice(&self.mask.to_le_bytes());
+        packet.extend_from_slice(&first.to_le_bytes());
+        packet.push(inputs.len() as u8);
+        for input in inputs {
+            packet.extend_from_slice(&input.to_le_bytes());
+        }
+        packet.extend_from_slice(&self.confirmed().to_le_bytes());
+        let (frame, checksum) = self.checks.back().copied().unwrap_or((u64::MAX, u64::MAX));
+        packet.extend_from_slice(&frame.to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+
+        // Lost packets are made up for by the next ones
+        let _ = self.socket.send(&packet);
+    }
+
+    // Handles the packets which arrived
+    fn receive(&mut self) -> Result<(), String> {
+        self.socket
+            .set_nonblocking(true)
+            .map_err(|e| e.to_string())?;
+        let mut buf = [0; 1024];
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => self.handle(&buf[..len])?,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // Nobody listening on the peer's port yet
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    // Blocks up to `POLL_INTERVAL` for a packet, then handles all that arrived
+    fn wait(&mut self) -> Result<(), String> {
+        self.socket
+            .set_nonblocking(false)
+            .and_then(|()| self.socket.set_read_timeout(Some(POLL_INTERVAL)))
+            .map_err(|e| e.to_string())?;
+        let mut buf = [0; 1024];
+        match self.socket.recv(&mut buf) {
+            Ok(len) => self.handle(&buf[..len])?,
+            Err(e) => match e.kind() {
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ConnectionRefused => {}
+                _ => return Err(e.to_string()),
+            },
+        }
+        self.receive()
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Result<(), String> {
+        let mut input = Input { bytes: packet };
+        let packet = match input.packet() {
+            Some(packet) => packet,
+            // Not from a peer
+            None => return Ok(()),
+        };
+
+        if packet.game != self.game {
+            return Err("The peer runs a different ROM or different settings".to_string());
+        }
+        if packet.mask & self.mask != 0 {
+            let key = (packet.mask & self.mask).trailing_zeros();
+            return Err(format!("Both players own key {:X}", key));
+        }
+
+        for (index, &keys) in packet.inputs.iter().enumerate() {
+            let frame = packet.first + index as u64;
+            if frame != self.confirmed() {
+                continue;
+            }
+            let keys = keys & !self.mask;
+            if frame < self.frame() && self.inputs[frame as usize] & !self.mask != keys {
+                self.rollback = Some(self.rollback.map_or(frame, |start| start.min(frame)));
+            }
+            self.remote.push(keys);
+        }
+        self.acked = self.acked.max(packet.acked);
+
+        if packet.check.0 != u64::MAX && self.remote_checks.back() != Some(&packet.check) {
+            self.remote_checks.push_back(packet.check);
+            if self.remote_checks.len() > MAX_CHECKS {
+                self.remote_checks.pop_front();
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Packet {
+    game: u64,
+    mask: u16,
+    first: u64,
+    inputs: Vec<u16>,
+    acked: u64,
+    check: (u64, u64),
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Some(u16::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn packet(&mut self) -> Option<Packet> {
+        if self.take(4)? != MAGIC || self.take(1)? != [VERSION] {
+            return None;
+        }
+        let (game, mask, first) = (self.u64()?, self.u16()?, self.u64()?);
+        let count = self.take(1)?[0];
+        let inputs = (0..count).map(|_| self.u16()).collect::<Option<Vec<_>>>()?;
+        let acked = self.u64()?;
+        let check = (self.u64()?, self.u64()?);
+        if !self.bytes.is_empty() {
+            return None;
+        }
+
+        Some(Packet {
+            game,
+            mask,
+            first,
+            inputs,
+            acked,
+            check,
+        })
+    }
+}
@@ -0,0 +1,869 @@
+use super::asm::AsmError;
+use super::cpu::*;
+
+use std::collections::{HashMap, VecDeque};
+
+// Compiler for Octo, the assembly language CHIP-8 programs are usually
+// written in nowadays and which Octo cartridges carry. Covers the statements,
+// `if`/`loop` control flow, labels, :const, :alias, :unpack, :next, :org,
+// :byte, :call, :macro, :calc and :assert. Like Octo, the program starts
+// with a jump to the `main` label.
+//
+// Expressions in `{ }` after :calc, :byte and :assert know Octo's operators
+// except `strlen`, and like Octo they have no precedence and group from the
+// right: `2 * 3 + 1` is 8. Labels have to be defined before they are used
+// in expressions. :stringmode and other directives fail to compile with
+// "Unsupported directive".
+
+// Macros expanding macros could go on forever
+const MAX_EXPANSIONS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// How an address not known yet goes into the program once it is
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    // Low 12 bits of the instruction
+    Nnn,
+    // The word after F000
+    Long,
+    // :unpack's V0 byte, the nibble and the address' high nibble
+    High(u8),
+    // :unpack's V1 byte
+    Low,
+}
+
+struct Fixup {
+    address: usize,
+    name: String,
+    patch: Patch,
+    line: usize,
+}
+
+enum Value {
+    Known(i32),
+    Label(String),
+}
+
+enum Block {
+    // Jump to the end of the `if` or `else` branch
+    Branch(usize),
+    // Start of the loop and the jumps of its `while`s
+    Loop(usize, Vec<usize>),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    program: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i32>,
+    // :calc results before rounding, for later expressions
+    calculated: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, usize)>,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        tokens.extend(code.split_whitespace().map(|text| Token {
+            text: text.to_string(),
+            line: i + 1,
+        }));
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn unary(operator: &str) -> Option<fn(f64) -> f64> {
+    Some(match operator {
+        "-" => |a| -a,
+        "~" => |a| !(a as i64) as f64,
+        "!" => |a| (a == 0.0) as i32 as f64,
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" => f64::tan,
+        "exp" => f64::exp,
+        "log" => f64::ln,
+        "abs" => f64::abs,
+        "sqrt" => f64::sqrt,
+        "sign" => |a| if a == 0.0 { 0.0 } else { a.signum() },
+        "ceil" => f64::ceil,
+        "floor" => f64::floor,
+        _ => return None,
+    })
+}
+
+fn binary(operator: &str) -> Option<fn(f64, f64) -> f64> {
+    Some(match operator {
+        "+" => |a, b| a + b,
+        "-" => |a, b| a - b,
+        "*" => |a, b| a * b,
+        "/" => |a, b| a / b,
+        "%" => |a, b| a % b,
+        "&" => |a, b| (a as i64 & b as i64) as f64,
+        "|" => |a, b| (a as i64 | b as i64) as f64,
+        "^" => |a, b| (a as i64 ^ b as i64) as f64,
+        "<<" => |a, b| (a as i64).wrapping_shl(b as u32) as f64,
+        ">>" => |a, b| (a as i64).wrapping_shr(b as u32) as f64,
+        "pow" => f64::powf,
+        "min" => f64::min,
+        "max" => f64::max,
+        "<" => |a, b| (a < b) as i32 as f64,
+        "<=" => |a, b| (a <= b) as i32 as f64,
+        "==" => |a, b| (a == b) as i32 as f64,
+        "!=" => |a, b| (a != b) as i32 as f64,
+        ">=" => |a, b| (a >= b) as i32 as f64,
+        ">" => |a, b| (a > b) as i32 as f64,
+        _ => return None,
+    })
+}
+
+// Whole numbers for constants and bytes, rounded towards zero
+fn integer(value: f64) -> Result<i32, String> {
+    if !value.is_finite() || value.abs() >= 0x8000_0000u32 as f64 {
+        return Err(format!("{} is out of range", value));
+    }
+    Ok(value as i32)
+}
+
+fn register(text: &str) -> Option<u16> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|digit| digit as u16)
+        }
+        _ => None,
+    }
+}
+
+impl Compiler {
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| "Unexpected end of the program".to_string())?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("Expected '{}', got '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) {
+        let offset = self.here - PROGRAM_ENTRY as usize;
+        if self.program.len() <= offset {
+            self.program.resize(offset + 1, 0);
+        }
+        self.program[offset] = byte;
+        self.here += 1;
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        for &byte in &word.to_be_bytes() {
+            self.emit(byte);
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.next()?;
+        if number(&name).is_some() || register(&name).is_some() || name.starts_with(':') {
+            return Err(format!("Invalid name '{}'", name));
+        }
+        Ok(name)
+    }
+
+    fn define(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.labels.insert(name.clone(), address as u16).is_some() {
+            return Err(format!("Duplicate label '{}'", name));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u16, String> {
+        let text = self.next()?;
+        self.to_register(&text)
+            .ok_or_else(|| format!("Expected a register, got '{}'", text))
+    }
+
+    fn to_register(&self, text: &str) -> Option<u16> {
+        register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let text = self.next()?;
+        if let Some(value) = number(&text).or_else(|| self.constants.get(&text).copied()) {
+            return Ok(Value::Known(value));
+        }
+        match self.labels.get(&text) {
+            Some(&address) => Ok(Value::Known(address as i32)),
+            None if self.to_register(&text).is_none() && !text.starts_with(':') => {
+                Ok(Value::Label(text))
+            }
+            None => Err(format!("Expected a value, got '{}'", text)),
+        }
+    }
+
+    // A value which has to be known already, in `min..=max`
+    fn known(&mut self, min: i32, max: i32) -> Result<i32, String> {
+        match self.value()? {
+            Value::Known(value) if (min..=max).contains(&value) => Ok(value),
+            Value::Known(value) => Err(format!("{} is out of range", value)),
+            Value::Label(name) => Err(format!("Unknown value '{}'", name)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, String> {
+        Ok(self.known(-128, 0xFF)? as u8 as u16)
+    }
+
+    // Emits `opcode` with an address, patched later if not known yet
+    fn emit_address(&mut self, opcode: u16, patch: Patch) -> Result<(), String> {
+        let long = matches!(patch, Patch::Long);
+        let max = if long { 0xFFFF } else { 0xFFF };
+        let address = match self.value()? {
+            Value::Known(value) if (0..=max).contains(&value) => value as u16,
+            Value::Known(value) => return Err(format!("Address {:X} is out of range", value)),
+            Value::Label(name) => {
+                self.fixups.push(Fixup {
+                    address: self.here,
+                    name,
+                    patch,
+                    line: self.line,
+                });
+                0
+            }
+        };
+
+        if long {
+            self.emit_word(opcode);
+            self.emit_word(address);
+        } else {
+            self.emit_word(opcode | address);
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self, at: usize, patch: Patch, address: u16) -> Result<(), String> {
+        let offset = at - PROGRAM_ENTRY as usize;
+        match patch {
+            Patch::Nnn => {
+                if address > 0xFFF {
+                    return Err(format!("Address {:X} is out of range", address));
+                }
+                self.program[offset] |= (address >> 8) as u8;
+                self.program[offset + 1] = address as u8;
+            }
+            Patch::Long => {
+                self.program[offset + 2..offset + 4].copy_from_slice(&address.to_be_bytes())
+            }
+            Patch::High(nibble) => {
+                self.program[offset + 1] = nibble << 4 | (address >> 8) as u8 & 0xF
+            }
+            Patch::Low => self.program[offset + 1] = address as u8,
+        }
+        Ok(())
+    }
+
+    fn jump_to(&mut self, address: usize) {
+        self.emit_word(0x1000 | address as u16);
+    }
+
+    // Emits instructions after which the next one only runs if the condition
+    // holds, or only if it doesn't with `negate`
+    fn condition(&mut self, negate: bool) -> Result<(), String> {
+        let x = self.register()?;
+        let mut operator = self.next()?;
+        if negate {
+            operator = match operator.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                "<=" => ">",
+                _ => return Err(format!("Unknown comparison '{}'", operator)),
+            }
+            .to_string();
+        }
+
+        if operator == "key" || operator == "-key" {
+            let opcode = if operator == "key" { 0xE0A1 } else { 0xE09E };
+            self.emit_word(opcode | x << 8);
+            return Ok(());
+        }
+
+        let y = self.peek().and_then(|text| self.to_register(text));
+        if y.is_some() {
+            self.next()?;
+        }
+        let rhs = match y {
+            Some(y) => Err(y),
+            None => Ok(self.byte()?),
+        };
+
+        match (operator.as_str(), rhs) {
+            ("==", Ok(nn)) => self.emit_word(0x4000 | x << 8 | nn),
+            ("==", Err(y)) => self.emit_word(0x9000 | x << 8 | y << 4),
+            ("!=", Ok(nn)) => self.emit_word(0x3000 | x << 8 | nn),
+            ("!=", Err(y)) => self.emit_word(0x5000 | x << 8 | y << 4),
+            ("<", _) | (">", _) | ("<=", _) | (">=", _) => {
+                // VF := right hand side, then subtract and test the flag
+                match rhs {
+                    Ok(nn) => self.emit_word(0x6F00 | nn),
+                    Err(y) => self.emit_word(0x8F00 | y << 4),
+                }
+                let (subtract, skip) = match operator.as_str() {
+                    // VF = VX - rhs, flag set if VX >= rhs
+                    "<" => (0x8F07, 0x3F01),
+                    ">=" => (0x8F07, 0x4F01),
+                    // VF = rhs - VX, flag set if rhs >= VX
+                    ">" => (0x8F05, 0x3F01),
+                    _ => (0x8F05, 0x4F01),
+                };
+                self.emit_word(subtract | x << 4);
+                self.emit_word(skip);
+            }
+            _ => return Err(format!("Unknown comparison '{}'", operator)),
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let y = self.peek().and_then(|text| self.to_register(text));
+        if let Some(y) = y {
+            self.next()?;
+            let n = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(format!("Unknown operator '{}'", operator)),
+            };
+            self.emit_word(0x8000 | x << 8 | y << 4 | n);
+            return Ok(());
+        }
+
+        match (operator.as_str(), self.peek()) {
+            (":=", Some("key")) => {
+                self.next()?;
+                self.emit_word(0xF00A | x << 8);
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                self.emit_word(0xF007 | x << 8);
+            }
+            (":=", Some("random")) => {
+                self.next()?;
+                let nn = self.byte()?;
+                self.emit_word(0xC000 | x << 8 | nn);
+            }
+            (":=", _) => {
+                let nn = self.byte()?;
+                self.emit_word(0x6000 | x << 8 | nn);
+            }
+            ("+=", _) => {
+                let nn = self.byte()?;
+                self.emit_word(0x7000 | x << 8 | nn);
+            }
+            ("-=", _) => {
+                let nn = self.byte()?;
+                self.emit_word(0x7000 | x << 8 | (0x100 - nn) & 0xFF);
+            }
+            _ => return Err(format!("Unknown operator '{}'", operator)),
+        }
+        Ok(())
+    }
+
+    fn assign_i(&mut self) -> Result<(), String> {
+        match self.next()?.as_str() {
+            "+=" => {
+                let x = self.register()?;
+                self.emit_word(0xF01E | x << 8);
+            }
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit_word(0xF029 | x << 8);
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.emit_word(0xF030 | x << 8);
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit_address(0xF000, Patch::Long)?;
+                }
+                _ => self.emit_address(0xA000, Patch::Nnn)?,
+            },
+            operator => return Err(format!("Unknown operator '{}'", operator)),
+        }
+        Ok(())
+    }
+
+    // `save vx` or `save vx - vy`
+    fn range(&mut self, single: u16, range: u16) -> Result<(), String> {
+        let x = self.register()?;
+        if self.peek() == Some("-") {
+            self.next()?;
+            let y = self.register()?;
+            self.emit_word(range | x << 8 | y << 4);
+        } else {
+            self.emit_word(single | x << 8);
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            match self.next()?.as_str() {
+                "{" => break,
+                arg => args.push(arg.to_string()),
+            }
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| format!("Macro '{}' is missing its '}}'", name))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("Too many expansions of macro '{}'", name));
+        }
+
+        let count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for i in 0..count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[i].clone(), value);
+        }
+
+        let line = self.line;
+        for token in self.macros[name].body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text);
+            self.tokens.push_front(Token {
+                text: text.clone(),
+                line,
+            });
+        }
+        Ok(())
+    }
+
+    // A string in quotes, which may have spaces but no '#'
+    fn string(&mut self) -> Result<String, String> {
+        let mut string = self.next()?;
+        if !string.starts_with('"') {
+            return Err(format!("Expected a string, got '{}'", string));
+        }
+        while string.len() < 2 || !string.ends_with('"') {
+            string.push(' ');
+            string.push_str(&self.next()?);
+        }
+        Ok(string[1..string.len() - 1].to_string())
+    }
+
+    // `{ expression }`
+    fn calc(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let left = self.term()?;
+        match self.peek().and_then(binary) {
+            Some(operator) => {
+                self.next()?;
+                Ok(operator(left, self.expression()?))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        if token == "(" {
+            let value = self.expression()?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+        if token == "@" {
+            // A byte compiled so far
+            let address = self.term()? as usize;
+            let byte = address
+                .checked_sub(PROGRAM_ENTRY as usize)
+                .and_then(|offset| self.program.get(offset));
+            return Ok(byte.copied().unwrap_or(0) as f64);
+        }
+        if let Some(operator) = unary(&token) {
+            return Ok(operator(self.term()?));
+        }
+
+        let value = match token.as_str() {
+            "HERE" => Some(self.here as f64),
+            "PI" => Some(std::f64::consts::PI),
+            "E" => Some(std::f64::consts::E),
+            _ => number(&token)
+                .map(f64::from)
+                .or_else(|| token.parse().ok().filter(|value: &f64| value.is_finite()))
+                .or_else(|| self.calculated.get(&token).copied())
+                .or_else(|| self.constants.get(&token).map(|&value| value as f64))
+                .or_else(|| self.labels.get(&token).map(|&address| address as f64)),
+        };
+        value.ok_or_else(|| format!("Unknown value '{}'", token))
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), String> {
+        match directive {
+            ":" => {
+                let name = self.name()?;
+                self.define(name, self.here)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.known(-0x8000, 0xFFFF)?;
+                self.calculated.remove(&name);
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name.clone(), integer(value)?);
+                self.calculated.insert(name, value);
+            }
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(token) if token.starts_with('"') => Some(self.string()?),
+                    _ => None,
+                };
+                if self.calc()? == 0.0 {
+                    return Err(match message {
+                        Some(message) => format!("Assertion failed: {}", message),
+                        None => "Assertion failed".to_string(),
+                    });
+                }
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+            }
+            ":unpack" => {
+                // V0 := nibble and high address bits, V1 := low address bits
+                let nibble = self.known(0, 0xF)? as u8;
+                let at = self.here;
+                self.emit_word(0x6000);
+                self.emit_word(0x6100);
+                match self.value()? {
+                    Value::Known(address) if (0..=0xFFF).contains(&address) => {
+                        self.patch(at, Patch::High(nibble), address as u16)?;
+                        self.patch(at + 2, Patch::Low, address as u16)?;
+                    }
+                    Value::Known(address) => {
+                        return Err(format!("Address {:X} is out of range", address))
+                    }
+                    Value::Label(name) => {
+                        for &(address, patch) in &[(at, Patch::High(nibble)), (at + 2, Patch::Low)]
+                        {
+                            self.fixups.push(Fixup {
+                                address,
+                                name: name.clone(),
+                                patch,
+                                line: self.line,
+                            });
+                        }
+                    }
+                }
+            }
+            ":next" => {
+                // Names the second byte of the next instruction
+                let name = self.name()?;
+                self.define(name, self.here + 1)?;
+            }
+            ":org" => self.here = self.known(PROGRAM_ENTRY as i32, 0xFFFF)? as usize,
+            ":byte" => {
+                let byte = if self.peek() == Some("{") {
+                    let value = integer(self.calc()?)?;
+                    if !(-128..=0xFF).contains(&value) {
+                        return Err(format!("{} doesn't fit into a byte", value));
+                    }
+                    value as u8 as u16
+                } else {
+                    self.byte()?
+                };
+                self.emit(byte as u8);
+            }
+            ":call" => self.emit_address(0x2000, Patch::Nnn)?,
+            ":macro" => self.define_macro()?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            _ => return Err(format!("Unsupported directive '{}'", directive)),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        match token {
+            "clear" => self.emit_word(0x00E0),
+            "return" | ";" => self.emit_word(0x00EE),
+            "exit" => self.emit_word(0x00FD),
+            "lores" => self.emit_word(0x00FE),
+            "hires" => self.emit_word(0x00FF),
+            "scroll-down" => {
+                let n = self.known(0, 0xF)? as u16;
+                self.emit_word(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.known(0, 0xF)? as u16;
+                self.emit_word(0x00D0 | n);
+            }
+            "scroll-right" => self.emit_word(0x00FB),
+            "scroll-left" => self.emit_word(0x00FC),
+            "audio" => self.emit_word(0xF002),
+            "plane" => {
+                let n = self.known(0, 0xF)? as u16;
+                self.emit_word(0xF001 | n << 8);
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit_word(0xF033 | x << 8);
+            }
+            "save" => self.range(0xF055, 0x5002)?,
+            "load" => self.range(0xF065, 0x5003)?,
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit_word(0xF075 | x << 8);
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit_word(0xF085 | x << 8);
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.known(0, 0xF)? as u16;
+                self.emit_word(0xD000 | x << 8 | y << 4 | n);
+            }
+            "jump" => self.emit_address(0x1000, Patch::Nnn)?,
+            "jump0" => self.emit_address(0xB000, Patch::Nnn)?,
+            "native" => self.emit_address(0x0000, Patch::Nnn)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match token {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit_word(opcode | x << 8);
+            }
+            "i" => self.assign_i()?,
+            "if" => {
+                // Looking ahead for `then` or `begin` decides how the
+                // condition compiles
+                let begin = self
+                    .tokens
+                    .iter()
+                    .take(4)
+                    .find(|token| token.text == "then" || token.text == "begin")
+                    .map(|token| token.text == "begin")
+                    .ok_or_else(|| "Expected 'then' or 'begin' after 'if'".to_string())?;
+                self.condition(begin)?;
+                self.next()?;
+                if begin {
+                    self.blocks.push((Block::Branch(self.here), self.line));
+                    self.emit_word(0x1000);
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::Branch(at), line)) => {
+                    self.blocks.push((Block::Branch(self.here), line));
+                    self.emit_word(0x1000);
+                    self.patch(at, Patch::Nnn, self.here as u16)?;
+                }
+                _ => return Err("'else' without 'if ... begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::Branch(at), _)) => self.patch(at, Patch::Nnn, self.here as u16)?,
+                _ => return Err("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => self
+                .blocks
+                .push((Block::Loop(self.here, Vec::new()), self.line)),
+            "while" => {
+                self.condition(true)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find_map(|(block, _)| match block {
+                        Block::Loop(_, whiles) => Some(whiles),
+                        _ => None,
+                    }) {
+                    Some(whiles) => whiles.push(self.here),
+                    None => return Err("'while' outside of a loop".to_string()),
+                }
+                self.emit_word(0x1000);
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, whiles), _)) => {
+                    self.jump_to(start);
+                    for at in whiles {
+                        self.patch(at, Patch::Nnn, self.here as u16)?;
+                    }
+                }
+                _ => return Err("'again' without 'loop'".to_string()),
+            },
+            _ if token.starts_with(':') => self.directive(token)?,
+            _ if self.macros.contains_key(token) => self.expand(token)?,
+            _ => {
+                if let Some(x) = self.to_register(token) {
+                    return self.assign(x);
+                }
+                // Numbers are data, other names are calls
+                match number(token).or_else(|| self.constants.get(token).copied()) {
+                    Some(value) if (-128..=0xFF).contains(&value) => self.emit(value as u8),
+                    Some(value) => return Err(format!("{} doesn't fit into a byte", value)),
+                    None => {
+                        self.tokens.push_front(Token {
+                            text: token.to_string(),
+                            line: self.line,
+                        });
+                        self.emit_address(0x2000, Patch::Nnn)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Compiles an Octo program loaded at `PROGRAM_ENTRY`
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source),
+        line: 1,
+        program: Vec::new(),
+        here: PROGRAM_ENTRY as usize,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        calculated: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: vec![Fixup {
+            address: PROGRAM_ENTRY as usize,
+            name: "main".to_string(),
+            patch: Patch::Nnn,
+            line: 1,
+        }],
+        blocks: Vec::new(),
+    };
+    compiler.emit_word(0x1000);
+
+    let error = |line: usize, message: String| AsmError { line, message };
+    while !compiler.tokens.is_empty() {
+        let token = compiler.next().unwrap();
+        compiler
+            .statement(&token)
+            .map_err(|e| error(compiler.line, e))?;
+    }
+
+    if let Some((block, line)) = compiler.blocks.last() {
+        let message = match block {
+            Block::Branch(_) => "'if ... begin' without 'end'",
+            Block::Loop(..) => "'loop' without 'again'",
+        };
+        return Err(error(*line, message.to_string()));
+    }
+
+    for fixup in std::mem::take(&mut compiler.fixups) {
+        let address = *compiler.labels.get(&fixup.name).ok_or_else(|| {
+            let message = match fixup.name.as_str() {
+                "main" => "The program has no 'main' label".to_string(),
+                name => format!("Unknown label '{}'", name),
+            };
+            error(fixup.line, message)
+        })?;
+        compiler
+            .patch(fixup.address, fixup.patch, address)
+            .map_err(|e| error(fixup.line, e))?;
+    }
+
+    Ok(compiler.program)
+}
@@ -0,0 +1,206 @@
+use super::super::super::core::{draw_text, FrameBuffer, CELL_HEIGHT, CELL_WIDTH};
+use super::cpu::*;
+use super::instruction::*;
+use super::opcode::*;
+
+// Debug overlay drawn next to the game image. The CPU thread captures a
+// `Snapshot` once per frame, the window thread renders it into a frame
+// buffer with the built in debug font.
+
+pub const OVERLAY_COLUMNS: u32 = 32;
+pub const OVERLAY_ROWS: u32 = 24;
+
+// Bytes around I in the hex view and instructions around PC in the
+// disassembly
+pub const MEMORY_ROWS: usize = 6;
+pub const CODE_ROWS: usize = 9;
+
+// Stack entries shown, most recent first
+const STACK_ENTRIES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub regs: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub stack: Vec<u16>,
+    pub timers: [u8; 2],
+    pub keys: [bool; 16],
+    pub wait_for_key: bool,
+    // `memory` holds the bytes from `memory_start` on, same for `code`
+    pub memory_start: usize,
+    pub memory: Vec<u8>,
+    pub code_start: usize,
+    pub code: Vec<u8>,
+}
+
+// A row of overlay text, the highlighted columns are drawn inverted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub text: String,
+    pub highlight: Option<(usize, usize)>,
+}
+
+impl Line {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            highlight: None,
+        }
+    }
+}
+
+// Start of a `len` byte window around `address`, kept inside memory
+fn window(address: usize, before: usize, len: usize, memory_len: usize) -> usize {
+    address.saturating_sub(before).min(memory_len - len)
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &CPU) -> Self {
+        let keyboard = cpu.keyboard.lock().unwrap();
+
+        // Two rows before the one holding I
+        let memory_len = MEMORY_ROWS * 8;
+        let memory_start = window((cpu.i & !7) as usize, 16, memory_len, cpu.memory.len());
+
+        // Four instructions before PC
+        let code_len = CODE_ROWS * 2;
+        let code_start = window(cpu.pc as usize, 8, code_len, cpu.memory.len());
+
+        Self {
+            regs: cpu.regs,
+            i: cpu.i,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            stack: cpu.stack[..cpu.sp as usize].to_vec(),
+            timers: cpu.timers,
+            keys: keyboard.state,
+            wait_for_key: keyboard.wait_for_key,
+            memory_start,
+            memory: cpu.memory[memory_start..memory_start + memory_len].to_vec(),
+            code_start,
+            code: cpu.memory[code_start..code_start + code_len].to_vec(),
+        }
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+
+        lines.push(Line::new(format!(
+            "PC {:04X} I {:04X} SP {:02X}",
+            self.pc, self.i, self.sp
+        )));
+        for (row, regs) in self.regs.chunks(4).enumerate() {
+            let text: Vec<String> = regs
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+                .collect();
+            lines.push(Line::new(text.join(" ")));
+        }
+        lines.push(Line::new(format!(
+            "DT {:02X} ST {:02X}",
+            self.timers[DELAY], self.timers[SOUND]
+        )));
+
+        let keys: String = self
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(key, &pressed)| {
+                if pressed {
+                    std::char::from_digit(key as u32, 16).unwrap()
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let wait = if self.wait_for_key { " WAIT" } else { "" };
+        lines.push(Line::new(format!("KEYS {}{}", keys.to_uppercase(), wait)));
+
+        let stack: Vec<String> = self
+            .stack
+            .iter()
+            .rev()
+            .take(STACK_ENTRIES)
+            .map(|address| format!("{:04X}", address))
+            .collect();
+        if stack.is_empty() {
+            lines.push(Line::new("STACK -".to_string()));
+            lines.push(Line::new(String::new()));
+        } else {
+            let half = STACK_ENTRIES / 2;
+            lines.push(Line::new(format!(
+                "STACK {}",
+                stack[..stack.len().min(half)].join(" ")
+            )));
+            lines.push(Line::new(format!(
+                "      {}",
+                stack[stack.len().min(half)..].join(" ")
+            )));
+        }
+
+        for (row, bytes) in self.memory.chunks(8).enumerate() {
+            let address = self.memory_start + row * 8;
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let mut line = Line::new(format!("{:04X} {}", address, hex.join(" ")));
+
+            let i = self.i as usize;
+            if address <= i && i < address + 8 {
+                line.highlight = Some((5 + (i - address) * 3, 2));
+            }
+            lines.push(line);
+        }
+
+        for row in 0..CODE_ROWS {
+            let address = self.code_start + row * 2;
+            let value = (self.code[row * 2] as u16) << 8 | self.code[row * 2 + 1] as u16;
+            let instruction = Instruction::decode(&Opcode { value });
+            let mut text = format!("{:04X} {:04X} {}", address, value, instruction);
+            text.truncate(OVERLAY_COLUMNS as usize);
+
+            let mut line = Line::new(text);
+            if address == self.pc as usize {
+                line.highlight = Some((0, OVERLAY_COLUMNS as usize));
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    // Draws the overlay into `buf`, which should be `overlay_buffer()` sized
+    pub fn render(&self, buf: &mut FrameBuffer<u8>) {
+        render_lines(buf, &self.lines());
+    }
+}
+
+// Draws one line per text row, replacing what `buf` held
+pub fn render_lines(buf: &mut FrameBuffer<u8>, lines: &[Line]) {
+    buf.clear(0);
+
+    for (row, line) in lines.iter().enumerate() {
+        let row = row as u32;
+        draw_text(buf, 0, row, &line.text, 255, false);
+
+        if let Some((start, len)) = line.highlight {
+            let text: String = format!("{:width$}", line.text, width = start + len)
+                .chars()
+                .skip(start)
+                .take(len)
+                .collect();
+            draw_text(buf, start as u32, row, &text, 255, true);
+        }
+    }
+
+    buf.request_draw();
+}
+
+pub fn overlay_buffer() -> FrameBuffer<u8> {
+    FrameBuffer::new(
+        OVERLAY_COLUMNS * CELL_WIDTH,
+        OVERLAY_ROWS * CELL_HEIGHT,
+        0u8,
+    )
+}
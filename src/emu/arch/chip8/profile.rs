@@ -0,0 +1,269 @@
+use super::cpu::*;
+use super::error::*;
+use super::instruction::*;
+use super::opcode::*;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Execution profiler. `Profiler::execute` stands in for `CPU::execute` and
+// counts executions per PC, cycles spent in every 2NNN target and DXYN draws
+// per frame. The report lists the hot spots, the annotated disassembly shows
+// which bytes of the ROM never ran.
+
+// Rows in the hot spot and subroutine tables of the report
+pub const REPORT_ROWS: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub calls: u64,
+    // Cycles from the first instruction to the matching 00EE, callees included
+    pub inclusive: u64,
+    // Cycles spent in the subroutine itself
+    pub exclusive: u64,
+}
+
+// A call which hasn't returned yet
+struct Frame {
+    target: u16,
+    start: u64,
+}
+
+pub struct Profiler {
+    pub counts: Vec<u64>,
+    pub subroutines: HashMap<u16, Subroutine>,
+    // Draws in every finished frame
+    pub draws: Vec<u32>,
+    pub cycle: u64,
+    calls: Vec<Frame>,
+    frame_draws: u32,
+    rom_len: usize,
+}
+
+impl Profiler {
+    // `rom_len` bounds the coverage to the loaded program
+    pub fn new(rom_len: usize) -> Self {
+        Self {
+            counts: vec![0; 0x10000],
+            subroutines: HashMap::new(),
+            draws: Vec::new(),
+            cycle: 0,
+            calls: Vec::new(),
+            frame_draws: 0,
+            rom_len,
+        }
+    }
+
+    // Executes one instruction like `CPU::execute` and profiles it. Faulting
+    // instructions don't count.
+    pub fn execute(&mut self, cpu: &mut CPU) -> Result<(), Error> {
+        self.execute_with(cpu, CPU::execute)
+    }
+
+    // Same with a different way to execute, e.g. through a `Tracer`
+    pub fn execute_with<F>(&mut self, cpu: &mut CPU, execute: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut CPU) -> Result<(), Error>,
+    {
+        let pc = cpu.pc;
+        let instruction = match cpu.memory.get(pc as usize..pc as usize + 2) {
+            Some(bytes) => Instruction::decode(&Opcode {
+                value: u16::from_be_bytes([bytes[0], bytes[1]]),
+            }),
+            None => Instruction::Unknown(0),
+        };
+
+        execute(cpu)?;
+
+        self.counts[pc as usize] += 1;
+        if let Some(frame) = self.calls.last() {
+            self.subroutines.entry(frame.target).or_default().exclusive += 1;
+        }
+        self.cycle += 1;
+
+        match instruction {
+            Instruction::Call(target) => {
+                self.subroutines.entry(target).or_default().calls += 1;
+                self.calls.push(Frame {
+                    target,
+                    start: self.cycle,
+                });
+            }
+            Instruction::Return => {
+                if let Some(frame) = self.calls.pop() {
+                    self.subroutines.entry(frame.target).or_default().inclusive +=
+                        self.cycle - frame.start;
+                }
+            }
+            Instruction::Draw(..) => self.frame_draws += 1,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Called along with `CPU::tick` to close the current frame
+    pub fn tick(&mut self) {
+        self.draws.push(self.frame_draws);
+        self.frame_draws = 0;
+    }
+
+    // Subroutine totals, calls which are still running count up to now
+    pub fn subroutines(&self) -> HashMap<u16, Subroutine> {
+        let mut subroutines = self.subroutines.clone();
+        for frame in &self.calls {
+            subroutines.entry(frame.target).or_default().inclusive += self.cycle - frame.start;
+        }
+        subroutines
+    }
+
+    fn rom(&self) -> std::ops::Range<usize> {
+        let start = PROGRAM_ENTRY as usize;
+        start..(start + self.rom_len).min(self.counts.len())
+    }
+
+    // Whether `address` was part of an executed instruction
+    pub fn covered(&self, address: usize) -> bool {
+        self.counts.get(address).copied().unwrap_or(0) > 0
+            || (address > 0 && self.counts[address - 1] > 0)
+    }
+
+    // Executed and total bytes of the ROM
+    pub fn coverage(&self) -> (usize, usize) {
+        let rom = self.rom();
+        let covered = rom.clone().filter(|&address| self.covered(address)).count();
+        (covered, rom.len())
+    }
+
+    pub fn report(&self, memory: &[u8]) -> String {
+        let mut out = String::new();
+        let percent = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                part as f64 * 100.0 / total as f64
+            }
+        };
+
+        let _ = writeln!(out, "{} cycles in {} frames", self.cycle, self.draws.len());
+        let (covered, total) = self.coverage();
+        let _ = writeln!(
+            out,
+            "Coverage: {} of {} ROM bytes executed ({:.1}%)",
+            covered,
+            total,
+            percent(covered as u64, total as u64)
+        );
+
+        let mut hot: Vec<(usize, u64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(pc, &count)| (pc, count))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let _ = writeln!(out, "\nHot spots\n   count      %  addr  instruction");
+        for &(pc, count) in hot.iter().take(REPORT_ROWS) {
+            let _ = writeln!(
+                out,
+                "{:8} {:5.1}%  {:04X}  {}",
+                count,
+                percent(count, self.cycle),
+                pc,
+                disassemble(memory, pc)
+            );
+        }
+
+        let mut subroutines: Vec<(u16, Subroutine)> = self.subroutines().into_iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+
+        let _ = writeln!(
+            out,
+            "\nSubroutines\n   calls  inclusive      %  exclusive      %  target"
+        );
+        for (target, subroutine) in subroutines.iter().take(REPORT_ROWS) {
+            let _ = writeln!(
+                out,
+                "{:8} {:10} {:5.1}% {:10} {:5.1}%  {:04X}",
+                subroutine.calls,
+                subroutine.inclusive,
+                percent(subroutine.inclusive, self.cycle),
+                subroutine.exclusive,
+                percent(subroutine.exclusive, self.cycle),
+                target
+            );
+        }
+
+        let total: u64 = self.draws.iter().map(|&draws| draws as u64).sum();
+        let _ = write!(out, "\nDraws: {} total", total);
+        if let Some((frame, max)) = self
+            .draws
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+        {
+            let _ = write!(
+                out,
+                ", {:.1} per frame, at most {} in frame {}",
+                total as f64 / self.draws.len() as f64,
+                max,
+                frame
+            );
+        }
+        out.push('\n');
+
+        out
+    }
+
+    // Disassembly of the ROM with execution counts. Runs of bytes which never
+    // executed are listed as data, they are either data or dead code.
+    pub fn annotate(&self, memory: &[u8]) -> String {
+        let mut out = String::new();
+        let rom = self.rom();
+        let mut address = rom.start;
+
+        while address < rom.end {
+            let count = self.counts[address];
+            if count > 0 {
+                let _ = writeln!(
+                    out,
+                    "{:8}  {:04X}  {:02X}{:02X}  {}",
+                    count,
+                    address,
+                    memory.get(address).copied().unwrap_or(0),
+                    memory.get(address + 1).copied().unwrap_or(0),
+                    disassemble(memory, address)
+                );
+                address += 2;
+                continue;
+            }
+
+            let start = address;
+            while address < rom.end && address < start + 8 && !self.covered(address) {
+                address += 1;
+            }
+            // The second byte of an instruction at an odd address
+            if address == start {
+                address += 1;
+                continue;
+            }
+
+            let bytes: Vec<String> = memory[start..address]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            let _ = writeln!(out, "       -  {:04X}  {}", start, bytes.join(" "));
+        }
+
+        out
+    }
+}
+
+fn disassemble(memory: &[u8], address: usize) -> Instruction {
+    let byte = |address: usize| memory.get(address).copied().unwrap_or(0) as u16;
+    Instruction::decode(&Opcode {
+        value: byte(address) << 8 | byte(address + 1),
+    })
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+// Behavioural differences between CHIP-8 interpreters that ROMs rely on.
+// All quirks disabled matches what this emulator always did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_vy: bool,
+    // FX55/FX65 leave I pointing past the last register accessed
+    pub load_store_inc_i: bool,
+    // BNNN jumps to NNN + VX, X being the highest nibble of NNN
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // Sprites wrap around the screen edges instead of being clipped
+    pub wrap_sprites: bool,
+}
+
+pub const QUIRK_PRESETS: [&str; 3] = ["default", "chip8", "schip"];
+
+// In the order of the fields
+pub const QUIRK_NAMES: [&str; 5] = [
+    "shift_vy",
+    "load_store_inc_i",
+    "jump_vx",
+    "vf_reset",
+    "wrap_sprites",
+];
+
+impl Quirks {
+    // Original COSMAC VIP interpreter
+    pub fn chip8() -> Self {
+        Self {
+            shift_vy: true,
+            load_store_inc_i: true,
+            jump_vx: false,
+            vf_reset: true,
+            wrap_sprites: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Self {
+        Self {
+            shift_vy: false,
+            load_store_inc_i: false,
+            jump_vx: true,
+            vf_reset: false,
+            wrap_sprites: false,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "chip8" => Some(Self::chip8()),
+            "schip" => Some(Self::schip()),
+            _ => None,
+        }
+    }
+
+    // A preset, or the quirks to enable as a comma separated list of names
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(quirks) = Self::preset(text) {
+            return Some(quirks);
+        }
+
+        let mut quirks = Self::default();
+        for name in text.split(',').map(str::trim) {
+            *quirks.flag(name)? = true;
+        }
+        Some(quirks)
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift_vy" => Some(&mut self.shift_vy),
+            "load_store_inc_i" => Some(&mut self.load_store_inc_i),
+            "jump_vx" => Some(&mut self.jump_vx),
+            "vf_reset" => Some(&mut self.vf_reset),
+            "wrap_sprites" => Some(&mut self.wrap_sprites),
+            _ => None,
+        }
+    }
+}
+
+// The preset's name, or the enabled quirks the way `parse` reads them
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(preset) = QUIRK_PRESETS
+            .iter()
+            .find(|preset| Self::preset(preset) == Some(*self))
+        {
+            return write!(f, "{}", preset);
+        }
+
+        let mut quirks = *self;
+        let enabled: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .copied()
+            .filter(|name| *quirks.flag(name).unwrap())
+            .collect();
+        write!(f, "{}", enabled.join(","))
+    }
+}
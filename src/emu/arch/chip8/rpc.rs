@@ -0,0 +1,519 @@
+use super::super::super::core::FrameBuffer;
+use super::cpu::*;
+use super::database::Database;
+use super::error::*;
+use super::loader;
+use super::state::*;
+
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+// JSON-RPC 2.0 remote control server for test tooling. `listen` spawns a
+// thread which accepts connections on a TCP address or, given as
+// "unix:<path>", a Unix socket. Each connection gets a thread which reads one
+// request per line and writes one response per line, forwarding the calls to
+// the returned `Server`, which the thread owning the CPU polls before every
+// instruction like the GDB stub.
+//
+//   -> {"jsonrpc": "2.0", "id": 1, "method": "runFrames", "params": {"count": 60}}
+//   <- {"jsonrpc": "2.0", "id": 1, "result": {"frame": 60, "paused": true, "pc": 530}}
+//
+// Methods and their params:
+//
+//   status                        {paused, frame, pc}
+//   pause, resume                 Answer with the status
+//   step {count = 1}              Runs instructions, pauses, answers with
+//                                 the status
+//   runFrames {count = 1}         The same until that many frames ended
+//   reset                         Reloads the ROM
+//   loadRom {path} or {data}      Loads a ROM file or base64 ROM and resets,
+//                                 known ROMs get their quirks
+//   setKey {key, pressed}
+//   readMemory {address, length}  {data} in base64
+//   writeMemory {address, data}
+//   getRegisters                  {v, i, pc, sp, dt, st, stack}
+//   getFramebuffer                {width, height, data}, one base64 byte per
+//                                 pixel, 0 when unset
+//   saveState                     {state}, a base64 `SaveState`
+//   loadState {state}
+//   quit                          Ends the run
+//
+// The CPU starts out paused. Calls are answered between instructions, so
+// the window keeps rendering while a client waits for frames to run.
+
+// How long a paused CPU thread blocks before checking for something else to do
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+enum Call {
+    Status,
+    Pause,
+    Resume,
+    Step(u64),
+    RunFrames(u64),
+    Reset,
+    LoadRom(Vec<u8>),
+    SetKey(u8, bool),
+    ReadMemory(usize, usize),
+    WriteMemory(usize, Vec<u8>),
+    GetRegisters,
+    GetFramebuffer,
+    SaveState,
+    LoadState(Box<SaveState>),
+    Quit,
+}
+
+type Reply = Result<Value, String>;
+
+struct Request {
+    call: Call,
+    reply: Sender<Reply>,
+}
+
+enum Budget {
+    Steps(u64),
+    Frames(u64),
+}
+
+// CPU side of the server, owned by the thread which runs the CPU
+pub struct Server {
+    requests: Receiver<Request>,
+    address: String,
+    // Removed again when the server goes away
+    socket: Option<PathBuf>,
+    // What `reset` loads
+    program: Vec<u8>,
+    // Where loadRom looks up quirks
+    database: Database,
+    paused: bool,
+    // The step or runFrames call to answer once its budget is used up
+    running: Option<(Budget, Sender<Reply>)>,
+    quit: bool,
+}
+
+// Binds the socket and starts the server thread
+pub fn listen(addr: &str, program: &[u8]) -> io::Result<Server> {
+    let (request_tx, request_rx) = channel();
+
+    let (address, socket) = match addr.strip_prefix("unix:") {
+        Some(path) => (bind_unix(path, request_tx)?, Some(PathBuf::from(path))),
+        None => {
+            let listener = TcpListener::bind(addr)?;
+            let address = listener.local_addr()?.to_string();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = stream.set_nodelay(true);
+                    if let Ok(reader) = stream.try_clone() {
+                        session(reader, stream, request_tx.clone());
+                    }
+                }
+            });
+            (address, None)
+        }
+    };
+
+    Ok(Server {
+        requests: request_rx,
+        address,
+        socket,
+        program: program.to_vec(),
+        database: Database::builtin(),
+        paused: true,
+        running: None,
+        quit: false,
+    })
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, requests: Sender<Request>) -> io::Result<String> {
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Ok(reader) = stream.try_clone() {
+                session(reader, stream, requests.clone());
+            }
+        }
+    });
+    Ok(format!("unix:{}", path))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_: &str, _: Sender<Request>) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Unix sockets aren't supported on this platform",
+    ))
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.socket {
+            let _ = fs::remove_file(socket);
+        }
+    }
+}
+
+impl Server {
+    // The bound address, with the port picked for port 0
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Replaces the built in ROM database
+    pub fn set_database(&mut self, database: Database) {
+        self.database = database;
+    }
+
+    // Whether a client called quit
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    // Handles pending calls, then returns whether the CPU may execute the
+    // next instruction. While paused this waits up to `POLL_INTERVAL` for a
+    // call, so the caller gets to check for shutdown in between.
+    pub fn poll(&mut self, cpu: &mut CPU, frame: u64) -> bool {
+        while !self.quit {
+            let request = if self.paused {
+                match self.requests.recv_timeout(POLL_INTERVAL) {
+                    Ok(request) => request,
+                    Err(RecvTimeoutError::Timeout) => return false,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match self.requests.try_recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(reply) = self.handle(cpu, frame, request.call, &request.reply) {
+                let _ = request.reply.send(reply);
+            }
+        }
+
+        !self.paused && !self.quit
+    }
+
+    // Called after an instruction `poll` allowed completed, `frame` counts
+    // the frames which ended so far
+    pub fn executed(&mut self, cpu: &CPU, frame: u64, frame_ended: bool) {
+        let done = match &mut self.running {
+            Some((Budget::Steps(count), _)) => {
+                *count -= 1;
+                *count == 0
+            }
+            Some((Budget::Frames(count), _)) if frame_ended => {
+                *count -= 1;
+                *count == 0
+            }
+            _ => false,
+        };
+
+        if done {
+            self.paused = true;
+            if let Some((_, reply)) = self.running.take() {
+                let _ = reply.send(Ok(self.status(cpu, frame)));
+            }
+        }
+    }
+
+    // Called instead of `executed` when the instruction faulted, the CPU
+    // stays paused on it
+    pub fn fault(&mut self, error: Error) {
+        self.paused = true;
+        if let Some((_, reply)) = self.running.take() {
+            let _ = reply.send(Err(error.to_string()));
+        }
+    }
+
+    fn status(&self, cpu: &CPU, frame: u64) -> Value {
+        json!({ "paused": self.paused, "frame": frame, "pc": cpu.pc })
+    }
+
+    // Answers a pending step or runFrames call which another call cut short
+    fn interrupt(&mut self) {
+        if let Some((_, reply)) = self.running.take() {
+            let _ = reply.send(Err("Interrupted by another call".to_string()));
+        }
+    }
+
+    // A fresh CPU with the same keypad, quirks and seed
+    fn reset(&self, cpu: &mut CPU) -> Result<(), String> {
+        let mut fresh = CPU::new(FrameBuffer::new(64, 32, 0), cpu.keyboard.clone());
+        fresh.quirks = cpu.quirks;
+        fresh.seed(cpu.rng_state().0);
+        fresh
+            .load_program(&self.program)
+            .map_err(|e| e.to_string())?;
+        fresh.frame_buf.request_draw();
+        *cpu = fresh;
+
+        let mut keyboard = cpu.keyboard.lock().unwrap();
+        keyboard.wait_for_key = false;
+        keyboard.key_received = false;
+        Ok(())
+    }
+
+    // Returns None for calls answered later
+    fn handle(
+        &mut self,
+        cpu: &mut CPU,
+        frame: u64,
+        call: Call,
+        reply: &Sender<Reply>,
+    ) -> Option<Reply> {
+        let result = match call {
+            Call::Status => Ok(self.status(cpu, frame)),
+            Call::Pause | Call::Resume => {
+                self.interrupt();
+                self.paused = matches!(call, Call::Pause);
+                Ok(self.status(cpu, frame))
+            }
+            Call::Step(count) | Call::RunFrames(count) => {
+                self.interrupt();
+                if count == 0 {
+                    return Some(Ok(self.status(cpu, frame)));
+                }
+                let budget = match call {
+                    Call::Step(_) => Budget::Steps(count),
+                    _ => Budget::Frames(count),
+                };
+                self.running = Some((budget, reply.clone()));
+                self.paused = false;
+                return None;
+            }
+            Call::Reset => self.reset(cpu).map(|()| Value::Null),
+            Call::LoadRom(program) => {
+                let quirks = cpu.quirks;
+                if let Some(known) = self.database.quirks(&program) {
+                    cpu.quirks = known;
+                }
+                let previous = std::mem::replace(&mut self.program, program);
+                let result = self.reset(cpu);
+                if result.is_err() {
+                    self.program = previous;
+                    cpu.quirks = quirks;
+                }
+                result.map(|()| Value::Null)
+            }
+            Call::SetKey(key, pressed) => {
+                let mut keyboard = cpu.keyboard.lock().unwrap();
+                if pressed {
+                    keyboard.press_key(key);
+                } else {
+                    keyboard.release_key(key);
+                }
+                Ok(Value::Null)
+            }
+            Call::ReadMemory(address, len) => {
+                match cpu.memory.get(address..address.saturating_add(len)) {
+                    Some(data) => Ok(json!({ "data": base64::encode(data) })),
+                    None => Err("Outside of memory".to_string()),
+                }
+            }
+            Call::WriteMemory(address, data) => {
+                if address.saturating_add(data.len()) > cpu.memory.len() {
+                    Err("Outside of memory".to_string())
+                } else {
+                    for (offset, &byte) in data.iter().enumerate() {
+                        cpu.write_memory(address + offset, byte);
+                    }
+                    Ok(Value::Null)
+                }
+            }
+            Call::GetRegisters => {
+                let depth = (cpu.sp as usize).min(cpu.stack.len());
+                Ok(json!({
+                    "v": cpu.regs,
+                    "i": cpu.i,
+                    "pc": cpu.pc,
+                    "sp": cpu.sp,
+                    "dt": cpu.timers[DELAY],
+                    "st": cpu.timers[SOUND],
+                    "stack": &cpu.stack[..depth],
+                }))
+            }
+            Call::GetFramebuffer => Ok(json!({
+                "width": cpu.frame_buf.width(),
+                "height": cpu.frame_buf.height(),
+                "data": base64::encode(cpu.frame_buf.frame()),
+            })),
+            Call::SaveState => {
+                let state = SaveState::capture(cpu).to_bytes();
+                Ok(json!({ "state": base64::encode(state) }))
+            }
+            Call::LoadState(state) => {
+                state.restore(cpu);
+                Ok(Value::Null)
+            }
+            Call::Quit => {
+                self.interrupt();
+                self.quit = true;
+                Ok(Value::Null)
+            }
+        };
+
+        Some(result)
+    }
+}
+
+// Serves one connection on its own thread
+fn session(
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write + Send + 'static,
+    requests: Sender<Request>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = respond(&line, &requests) {
+                if writeln!(writer, "{}", response).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+// Runs one request, returns None for notifications
+fn respond(line: &str, requests: &Sender<Request>) -> Option<Value> {
+    let request: Map<String, Value> = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+    };
+
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str);
+    let method = match method {
+        Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => method,
+        _ => {
+            let id = id.unwrap_or(Value::Null);
+            return Some(error(id, INVALID_REQUEST, "Not a JSON-RPC 2.0 request"));
+        }
+    };
+
+    let empty = Map::new();
+    let params = match request.get("params") {
+        None => Ok(&empty),
+        Some(Value::Object(params)) => Ok(params),
+        Some(_) => Err((INVALID_PARAMS, "Params must be an object".to_string())),
+    };
+
+    let result = params
+        .and_then(|params| parse(method, params))
+        .and_then(|call| {
+            let (reply_tx, reply_rx) = channel();
+            let request = Request {
+                call,
+                reply: reply_tx,
+            };
+            let stopped = || (SERVER_ERROR, "The emulator stopped".to_string());
+            requests.send(request).map_err(|_| stopped())?;
+            reply_rx
+                .recv()
+                .map_err(|_| stopped())?
+                .map_err(|e| (SERVER_ERROR, e))
+        });
+
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+// Reads the params of a call, and the ROM to load, off the CPU thread
+fn parse(method: &str, params: &Map<String, Value>) -> Result<Call, (i64, String)> {
+    let invalid = |message: String| (INVALID_PARAMS, message);
+    let number = |name: &str, default: Option<u64>| match params.get(name) {
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| invalid(format!("{} must be a non-negative integer", name))),
+        None => default.ok_or_else(|| invalid(format!("Missing {}", name))),
+    };
+    let string = |name: &str| match params.get(name) {
+        Some(value) => value
+            .as_str()
+            .ok_or_else(|| invalid(format!("{} must be a string", name))),
+        None => Err(invalid(format!("Missing {}", name))),
+    };
+    let data = |name: &str| {
+        base64::decode(string(name)?).map_err(|e| invalid(format!("Invalid {}: {}", name, e)))
+    };
+
+    Ok(match method {
+        "status" => Call::Status,
+        "pause" => Call::Pause,
+        "resume" => Call::Resume,
+        "step" => Call::Step(number("count", Some(1))?),
+        "runFrames" => Call::RunFrames(number("count", Some(1))?),
+        "reset" => Call::Reset,
+        "loadRom" => {
+            let rom = match (params.contains_key("path"), params.contains_key("data")) {
+                (true, false) => loader::load(string("path")?),
+                (false, true) => loader::decode(data("data")?),
+                _ => return Err(invalid("Give either path or data".to_string())),
+            };
+            Call::LoadRom(rom.map_err(|e| (SERVER_ERROR, e))?.program)
+        }
+        "setKey" => {
+            let key = number("key", None)?;
+            if key > 0xF {
+                return Err(invalid(format!("Invalid key {}", key)));
+            }
+            let pressed = params
+                .get("pressed")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| invalid("pressed must be a boolean".to_string()))?;
+            Call::SetKey(key as u8, pressed)
+        }
+        "readMemory" => Call::ReadMemory(
+            number("address", None)? as usize,
+            number("length", None)? as usize,
+        ),
+        "writeMemory" => Call::WriteMemory(number("address", None)? as usize, data("data")?),
+        "getRegisters" => Call::GetRegisters,
+        "getFramebuffer" => Call::GetFramebuffer,
+        "saveState" => Call::SaveState,
+        "loadState" => {
+            let state = SaveState::from_bytes(&data("state")?).map_err(invalid)?;
+            Call::LoadState(Box::new(state))
+        }
+        "quit" => Call::Quit,
+        _ => return Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+    })
+}
@@ -0,0 +1,337 @@
+use super::super::super::core::FrameBuffer;
+use super::cpu::*;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+// Rhai scripts driving the emulator, for bots and automated test scenarios.
+// The script runs once before the first instruction to set up callbacks,
+// which then run on the CPU thread:
+//
+//   on_frame(|frame| if frame == 60 { press(5) });
+//   on_pc(0x2F0, || print(`score ${peek(0x300)}`));
+//   on_write(0x300, |address, old, value| print(`${old} -> ${value}`));
+//   after(600, || { screenshot("end.pbm"); stop(); });
+//
+// Functions:
+//
+//   peek(address), poke(address, value)    Memory
+//   reg(x), set_reg(x, value)              V0-VF
+//   pc(), set_pc(address), i(), set_i(address)
+//   press(key), release(key)               Keypad keys 0-F
+//   frame()                                60 Hz frames run so far
+//   pixel(x, y), screenshot(path)          The screen, saved as PBM
+//   stop()                                 Ends the run
+//   on_frame(fn)                           fn(frame) before every frame
+//   after(frames, fn)                      fn() once, frames from now
+//   on_pc(address, fn)                     fn() before the instruction runs
+//   on_write(address, fn)                  fn(address, old, value) after an
+//                                          instruction changed the byte
+//
+// Callbacks see a copy of the machine taken right before they run, their
+// changes are written back afterwards. Errors, including `throw`, end the
+// run as a failure.
+
+type Result<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+// Bytes of `CPU::memory`
+const MEMORY_SIZE: i64 = 0xFFFF;
+
+// The machine as callbacks see it
+struct Machine {
+    regs: [u8; 16],
+    pc: u16,
+    i: u16,
+    memory: Vec<u8>,
+    screen: FrameBuffer<u8>,
+    frame: u64,
+    // Changes to write back
+    writes: Vec<(usize, u8)>,
+    keys: Vec<(u8, bool)>,
+}
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    // With the frame to run at
+    after: Vec<(u64, FnPtr)>,
+    pc: HashMap<u16, Vec<FnPtr>>,
+    // With the byte's value after the last instruction
+    write: BTreeMap<u16, (u8, Vec<FnPtr>)>,
+}
+
+struct Shared {
+    machine: Machine,
+    hooks: Hooks,
+    stopped: bool,
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    shared: Arc<Mutex<Shared>>,
+}
+
+// Fails unless 0 <= value < end
+fn check(value: i64, end: i64, what: &str) -> Result<usize> {
+    if (0..end).contains(&value) {
+        Ok(value as usize)
+    } else {
+        Err(format!("Invalid {} {}", what, value).into())
+    }
+}
+
+impl Script {
+    pub fn new(source: &str) -> std::result::Result<Self, String> {
+        let shared = Arc::new(Mutex::new(Shared {
+            machine: Machine {
+                regs: [0; 16],
+                pc: 0,
+                i: 0,
+                memory: vec![0; MEMORY_SIZE as usize],
+                screen: FrameBuffer::new(64, 32, 0),
+                frame: 0,
+                writes: Vec::new(),
+                keys: Vec::new(),
+            },
+            hooks: Hooks::default(),
+            stopped: false,
+        }));
+
+        let mut engine = Engine::new();
+        register(&mut engine, &shared);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            engine,
+            ast,
+            shared,
+        })
+    }
+
+    // Whether the script called `stop`
+    pub fn stopped(&self) -> bool {
+        self.shared.lock().unwrap().stopped
+    }
+
+    // Runs the script itself, after the ROM has been loaded
+    pub fn start(&mut self, cpu: &mut CPU) -> std::result::Result<(), String> {
+        self.load(cpu, 0);
+        let result = self.engine.run_ast(&self.ast);
+        self.store(cpu);
+        result.map_err(|e| e.to_string())
+    }
+
+    // Before the first instruction of a frame
+    pub fn frame(&mut self, cpu: &mut CPU, frame: u64) -> std::result::Result<(), String> {
+        let calls = {
+            let mut shared = self.shared.lock().unwrap();
+            let hooks = &mut shared.hooks;
+            let mut calls: Vec<(FnPtr, Vec<Dynamic>)> = hooks
+                .frame
+                .iter()
+                .map(|f| (f.clone(), vec![Dynamic::from(frame as i64)]))
+                .collect();
+
+            let (due, later) = hooks.after.drain(..).partition(|&(at, _)| at <= frame);
+            hooks.after = later;
+            calls.extend(due.into_iter().map(|(_, f): (u64, FnPtr)| (f, vec![])));
+            calls
+        };
+        self.call(cpu, frame, calls)
+    }
+
+    // Before each instruction
+    pub fn before(&mut self, cpu: &mut CPU, frame: u64) -> std::result::Result<(), String> {
+        let calls = match self.shared.lock().unwrap().hooks.pc.get(&cpu.pc) {
+            Some(hooks) => hooks.iter().map(|f| (f.clone(), vec![])).collect(),
+            None => return Ok(()),
+        };
+        self.call(cpu, frame, calls)
+    }
+
+    // After each instruction
+    pub fn after(&mut self, cpu: &mut CPU, frame: u64) -> std::result::Result<(), String> {
+        let mut calls = Vec::new();
+        for (&address, (last, hooks)) in self.shared.lock().unwrap().hooks.write.iter_mut() {
+            let now = cpu.memory[address as usize];
+            if now != *last {
+                let args = [address as i64, *last as i64, now as i64].map(Dynamic::from);
+                calls.extend(hooks.iter().map(|f| (f.clone(), args.to_vec())));
+                *last = now;
+            }
+        }
+        self.call(cpu, frame, calls)
+    }
+
+    fn call(
+        &mut self,
+        cpu: &mut CPU,
+        frame: u64,
+        calls: Vec<(FnPtr, Vec<Dynamic>)>,
+    ) -> std::result::Result<(), String> {
+        if calls.is_empty() {
+            return Ok(());
+        }
+
+        self.load(cpu, frame);
+        let result = calls
+            .into_iter()
+            .try_for_each(|(f, args)| f.call::<Dynamic>(&self.engine, &self.ast, args).map(drop));
+        self.store(cpu);
+        result.map_err(|e| e.to_string())
+    }
+
+    fn load(&self, cpu: &CPU, frame: u64) {
+        let machine = &mut self.shared.lock().unwrap().machine;
+        machine.regs = cpu.regs;
+        machine.pc = cpu.pc;
+        machine.i = cpu.i;
+        machine.memory.copy_from_slice(&cpu.memory);
+        machine.screen.copy_from(&cpu.frame_buf);
+        machine.frame = frame;
+    }
+
+    fn store(&self, cpu: &mut CPU) {
+        let mut shared = self.shared.lock().unwrap();
+        let Shared { machine, hooks, .. } = &mut *shared;
+        cpu.regs = machine.regs;
+        cpu.pc = machine.pc;
+        cpu.i = machine.i;
+        for (address, value) in machine.writes.drain(..) {
+            cpu.write_memory(address, value);
+        }
+
+        let mut keyboard = cpu.keyboard.lock().unwrap();
+        for (key, pressed) in machine.keys.drain(..) {
+            if pressed {
+                keyboard.press_key(key);
+            } else {
+                keyboard.release_key(key);
+            }
+        }
+
+        // The script's own writes don't count as changes
+        for (&address, (last, _)) in hooks.write.iter_mut() {
+            *last = cpu.memory[address as usize];
+        }
+    }
+}
+
+fn register(engine: &mut Engine, shared: &Arc<Mutex<Shared>>) {
+    let s = shared.clone();
+    engine.register_fn("peek", move |address: i64| -> Result<i64> {
+        let address = check(address, MEMORY_SIZE, "address")?;
+        Ok(s.lock().unwrap().machine.memory[address] as i64)
+    });
+    let s = shared.clone();
+    engine.register_fn("poke", move |address: i64, value: i64| -> Result<()> {
+        let address = check(address, MEMORY_SIZE, "address")?;
+        let value = check(value, 0x100, "byte")? as u8;
+        let machine = &mut s.lock().unwrap().machine;
+        machine.memory[address] = value;
+        machine.writes.push((address, value));
+        Ok(())
+    });
+
+    let s = shared.clone();
+    engine.register_fn("reg", move |x: i64| -> Result<i64> {
+        let x = check(x, 16, "register")?;
+        Ok(s.lock().unwrap().machine.regs[x] as i64)
+    });
+    let s = shared.clone();
+    engine.register_fn("set_reg", move |x: i64, value: i64| -> Result<()> {
+        let x = check(x, 16, "register")?;
+        s.lock().unwrap().machine.regs[x] = check(value, 0x100, "byte")? as u8;
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("pc", move || s.lock().unwrap().machine.pc as i64);
+    let s = shared.clone();
+    engine.register_fn("set_pc", move |address: i64| -> Result<()> {
+        s.lock().unwrap().machine.pc = check(address, 0x10000, "address")? as u16;
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("i", move || s.lock().unwrap().machine.i as i64);
+    let s = shared.clone();
+    engine.register_fn("set_i", move |address: i64| -> Result<()> {
+        s.lock().unwrap().machine.i = check(address, 0x10000, "address")? as u16;
+        Ok(())
+    });
+
+    for (name, pressed) in [("press", true), ("release", false)] {
+        let s = shared.clone();
+        engine.register_fn(name, move |key: i64| -> Result<()> {
+            let key = check(key, 16, "key")? as u8;
+            s.lock().unwrap().machine.keys.push((key, pressed));
+            Ok(())
+        });
+    }
+
+    let s = shared.clone();
+    engine.register_fn("frame", move || s.lock().unwrap().machine.frame as i64);
+    let s = shared.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64| -> Result<bool> {
+        let screen = &s.lock().unwrap().machine.screen;
+        let x = check(x, screen.width() as i64, "x")?;
+        let y = check(y, screen.height() as i64, "y")?;
+        Ok(screen.read(x as u32, y as u32) != 0)
+    });
+    let s = shared.clone();
+    engine.register_fn("screenshot", move |path: &str| -> Result<()> {
+        let pbm = pbm(&s.lock().unwrap().machine.screen);
+        fs::write(path, pbm).map_err(|e| format!("Can't write {}: {}", path, e).into())
+    });
+    let s = shared.clone();
+    engine.register_fn("stop", move || s.lock().unwrap().stopped = true);
+
+    let s = shared.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| {
+        s.lock().unwrap().hooks.frame.push(f);
+    });
+    let s = shared.clone();
+    engine.register_fn("after", move |frames: i64, f: FnPtr| -> Result<()> {
+        let frames = check(frames, i64::MAX, "frame count")? as u64;
+        let mut shared = s.lock().unwrap();
+        let at = shared.machine.frame + frames;
+        shared.hooks.after.push((at, f));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("on_pc", move |address: i64, f: FnPtr| -> Result<()> {
+        let address = check(address, 0x10000, "address")? as u16;
+        s.lock()
+            .unwrap()
+            .hooks
+            .pc
+            .entry(address)
+            .or_default()
+            .push(f);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("on_write", move |address: i64, f: FnPtr| -> Result<()> {
+        let address = check(address, MEMORY_SIZE, "address")?;
+        let mut shared = s.lock().unwrap();
+        let value = shared.machine.memory[address];
+        let hooks = shared.hooks.write.entry(address as u16);
+        hooks.or_insert_with(|| (value, Vec::new())).1.push(f);
+        Ok(())
+    });
+}
+
+// Plain PBM, set pixels are black
+pub fn pbm(screen: &FrameBuffer<u8>) -> String {
+    let mut out = format!("P1\n{} {}\n", screen.width(), screen.height());
+    for y in 0..screen.height() {
+        let row: Vec<&str> = (0..screen.width())
+            .map(|x| if screen.read(x, y) != 0 { "1" } else { "0" })
+            .collect();
+        let _ = writeln!(out, "{}", row.join(" "));
+    }
+    out
+}
@@ -0,0 +1,201 @@
+use super::super::super::core::FrameBuffer;
+use super::cpu::*;
+
+// Save states: everything needed to resume a CPU exactly where it was,
+// including the position of its random number generator. The keypad isn't
+// saved since it's input, neither are the quirks. Binary format, integers
+// little endian:
+//
+//   "C8SV", version, V0-VF, I, PC, SP, DT, ST, the 64 stack entries,
+//   memory length and memory, screen width, height and pixels, RNG seed and
+//   numbers drawn, then FX0A's wait flag, received flag and key
+
+const MAGIC: &[u8; 4] = b"C8SV";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub regs: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub timers: [u8; 2],
+    pub stack: Vec<u16>,
+    pub memory: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub screen: Vec<u8>,
+    pub seed: u64,
+    pub draws: u64,
+    // FX0A waiting for a key
+    pub wait_for_key: bool,
+    pub key_received: bool,
+    pub key: u8,
+}
+
+impl SaveState {
+    pub fn capture(cpu: &CPU) -> Self {
+        let keyboard = cpu.keyboard.lock().unwrap();
+        let (seed, draws) = cpu.rng_state();
+
+        Self {
+            regs: cpu.regs,
+            i: cpu.i,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            timers: cpu.timers,
+            stack: cpu.stack.to_vec(),
+            memory: cpu.memory.to_vec(),
+            width: cpu.frame_buf.width(),
+            height: cpu.frame_buf.height(),
+            screen: cpu.frame_buf.frame().to_vec(),
+            seed,
+            draws,
+            wait_for_key: keyboard.wait_for_key,
+            key_received: keyboard.key_received,
+            key: keyboard.key,
+        }
+    }
+
+    pub fn restore(&self, cpu: &mut CPU) {
+        cpu.regs = self.regs;
+        cpu.i = self.i;
+        cpu.pc = self.pc;
+        cpu.sp = self.sp;
+        cpu.timers = self.timers;
+        cpu.stack.copy_from_slice(&self.stack);
+        cpu.memory.copy_from_slice(&self.memory);
+        cpu.invalidate_cache();
+        cpu.set_rng_state(self.seed, self.draws);
+
+        let mut screen = FrameBuffer::new(self.width, self.height, 0);
+        for (index, &pixel) in self.screen.iter().enumerate() {
+            let index = index as u32;
+            screen.write(index % self.width, index / self.width, pixel);
+        }
+        screen.request_draw();
+        cpu.frame_buf = screen;
+
+        let mut keyboard = cpu.keyboard.lock().unwrap();
+        keyboard.wait_for_key = self.wait_for_key;
+        keyboard.key_received = self.key_received;
+        keyboard.key = self.key;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.regs);
+        for value in [self.i, self.pc, self.sp] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&self.timers);
+        for address in &self.stack {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.width.to_le_bytes());
+        out.extend_from_slice(&self.height.to_le_bytes());
+        out.extend_from_slice(&self.screen);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.draws.to_le_bytes());
+        out.extend_from_slice(&[self.wait_for_key as u8, self.key_received as u8, self.key]);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut input = Input { bytes };
+        if input.take(4)? != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let version = input.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported save state version {}", version));
+        }
+
+        let mut regs = [0; 16];
+        regs.copy_from_slice(input.take(16)?);
+        let (i, pc, sp) = (input.u16()?, input.u16()?, input.u16()?);
+        let timers = [input.u8()?, input.u8()?];
+        let stack = (0..64)
+            .map(|_| input.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        if sp as usize > stack.len() {
+            return Err(format!("Invalid stack pointer {}", sp));
+        }
+
+        let memory_len = input.u32()? as usize;
+        if memory_len != 0xFFFF {
+            return Err(format!("Invalid memory size {}", memory_len));
+        }
+        let memory = input.take(memory_len)?.to_vec();
+
+        let (width, height) = (input.u32()?, input.u32()?);
+        if width == 0 || height == 0 || width > 256 || height > 256 {
+            return Err(format!("Invalid screen size {}x{}", width, height));
+        }
+        let screen = input.take((width * height) as usize)?.to_vec();
+
+        let seed = u64::from_le_bytes(input.array()?);
+        let draws = u64::from_le_bytes(input.array()?);
+        let (wait_for_key, key_received, key) = (input.u8()? != 0, input.u8()? != 0, input.u8()?);
+        if key > 0xF {
+            return Err(format!("Invalid key {}", key));
+        }
+        if !input.bytes.is_empty() {
+            return Err("Trailing data after the save state".to_string());
+        }
+
+        Ok(Self {
+            regs,
+            i,
+            pc,
+            sp,
+            timers,
+            stack,
+            memory,
+            width,
+            height,
+            screen,
+            seed,
+            draws,
+            wait_for_key,
+            key_received,
+            key,
+        })
+    }
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("Truncated save state".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.array().map(u32::from_le_bytes)
+    }
+}
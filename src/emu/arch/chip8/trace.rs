@@ -0,0 +1,377 @@
+use super::cpu::*;
+use super::error::*;
+use super::instruction::*;
+use super::opcode::*;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+// Per instruction execution trace. `Tracer::execute` stands in for
+// `CPU::execute` and records the PC, opcode and every register the
+// instruction changed, either as text lines or as compact binary records:
+//
+//   "C8TL", then per entry
+//   u64 cycle, u32 frame, u16 pc, u16 opcode, u8 change count,
+//   per change u8 register and u16 new value, all little endian
+
+pub const TRACE_MAGIC: &[u8; 4] = b"C8TL";
+
+// Registers an entry can list as changed, PC isn't since it always does
+pub const REGISTER_NAMES: [&str; 20] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "SP", "DT", "ST",
+];
+
+fn registers(cpu: &CPU) -> [u16; 20] {
+    let mut values = [0; 20];
+    for (value, reg) in values.iter_mut().zip(cpu.regs.iter()) {
+        *value = *reg as u16;
+    }
+    values[16] = cpu.i;
+    values[17] = cpu.sp;
+    values[18] = cpu.timers[DELAY] as u16;
+    values[19] = cpu.timers[SOUND] as u16;
+    values
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub cycle: u64,
+    pub frame: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    // Register index into `REGISTER_NAMES` and its value afterwards
+    pub changes: Vec<(u8, u16)>,
+}
+
+impl Entry {
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.cycle.to_le_bytes())?;
+        out.write_all(&(self.frame as u32).to_le_bytes())?;
+        out.write_all(&self.pc.to_le_bytes())?;
+        out.write_all(&self.opcode.to_le_bytes())?;
+        out.write_all(&[self.changes.len() as u8])?;
+        for (register, value) in &self.changes {
+            out.write_all(&[*register])?;
+            out.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Returns None at the end of the input
+    pub fn read(input: &mut dyn Read) -> io::Result<Option<Self>> {
+        let mut header = [0; 17];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut changes = Vec::with_capacity(header[16] as usize);
+        for _ in 0..header[16] {
+            let mut change = [0; 3];
+            input.read_exact(&mut change)?;
+            if change[0] as usize >= REGISTER_NAMES.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid register in trace",
+                ));
+            }
+            changes.push((change[0], u16::from_le_bytes([change[1], change[2]])));
+        }
+
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&header[..8]);
+        Ok(Some(Self {
+            cycle: u64::from_le_bytes(cycle),
+            frame: u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as u64,
+            pc: u16::from_le_bytes([header[12], header[13]]),
+            opcode: u16::from_le_bytes([header[14], header[15]]),
+            changes,
+        }))
+    }
+}
+
+// "cycle frame pc opcode disassembly changes", e.g.
+//   "      12      1 0202 7001 ADD V0, 0x01       V0=06"
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = Instruction::decode(&Opcode { value: self.opcode });
+        let mut line = format!(
+            "{:8} {:6} {:04X} {:04X} {:<18}",
+            self.cycle,
+            self.frame,
+            self.pc,
+            self.opcode,
+            instruction.to_string()
+        );
+        for (register, value) in &self.changes {
+            let name = REGISTER_NAMES[*register as usize];
+            if name == "I" {
+                line.push_str(&format!(" {}={:04X}", name, value));
+            } else {
+                line.push_str(&format!(" {}={:02X}", name, value));
+            }
+        }
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+// Reads a whole binary trace
+pub fn read(input: &mut dyn Read) -> io::Result<Vec<Entry>> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != TRACE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a trace"));
+    }
+
+    let mut entries = Vec::new();
+    while let Some(entry) = Entry::read(input)? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Inclusive ranges, parsed from "<start>-<end>" or a single value
+fn parse_range(spec: &str, radix: u32) -> Result<(u64, u64), String> {
+    let mut parts = spec.splitn(2, '-');
+    let start = parts.next().unwrap_or_default();
+    let end = parts.next().unwrap_or(start);
+    let parse = |value: &str| {
+        u64::from_str_radix(value.trim_start_matches("0x"), radix)
+            .map_err(|_| format!("Invalid range '{}'", spec))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+
+    if start > end {
+        return Err(format!("Invalid range '{}'", spec));
+    }
+    Ok((start, end))
+}
+
+// Which instructions get traced, everything by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub addresses: Option<(u16, u16)>,
+    // Bit N set traces opcodes NXXX
+    pub classes: Option<u16>,
+    pub frames: Option<(u64, u64)>,
+}
+
+impl Filter {
+    // Hex addresses, e.g. "200-2FF"
+    pub fn parse_addresses(spec: &str) -> Result<(u16, u16), String> {
+        let (start, end) = parse_range(spec, 16)?;
+        if end > 0xFFFF {
+            return Err(format!("Invalid range '{}'", spec));
+        }
+        Ok((start as u16, end as u16))
+    }
+
+    // Comma separated first opcode nibbles, e.g. "8,D" for ALU and draws
+    pub fn parse_classes(spec: &str) -> Result<u16, String> {
+        let mut classes = 0;
+        for class in spec.split(',') {
+            match u8::from_str_radix(class.trim(), 16) {
+                Ok(class) if class < 16 => classes |= 1 << class,
+                _ => return Err(format!("Invalid opcode class '{}'", class)),
+            }
+        }
+        Ok(classes)
+    }
+
+    // Decimal frames, e.g. "60-120"
+    pub fn parse_frames(spec: &str) -> Result<(u64, u64), String> {
+        parse_range(spec, 10)
+    }
+
+    pub fn matches(&self, frame: u64, pc: u16, opcode: u16) -> bool {
+        let inside = |range: Option<(u64, u64)>, value: u64| match range {
+            Some((start, end)) => start <= value && value <= end,
+            None => true,
+        };
+
+        inside(self.frames, frame)
+            && inside(
+                self.addresses
+                    .map(|(start, end)| (start as u64, end as u64)),
+                pc as u64,
+            )
+            && self
+                .classes
+                .is_none_or(|classes| classes & (1 << (opcode >> 12)) != 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "binary" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub format: Format,
+    pub filter: Filter,
+    // Keep only the last N entries and write them out when the CPU faults
+    pub ring: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            format: Format::Text,
+            filter: Filter::default(),
+            ring: None,
+        }
+    }
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    options: Options,
+    ring: VecDeque<Entry>,
+    // The first write error, tracing stops after it
+    error: Option<io::Error>,
+    pub cycle: u64,
+    pub frame: u64,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut out: W, options: Options) -> io::Result<Self> {
+        if options.format == Format::Binary {
+            out.write_all(TRACE_MAGIC)?;
+        }
+
+        Ok(Self {
+            out,
+            options,
+            ring: VecDeque::new(),
+            error: None,
+            cycle: 0,
+            frame: 0,
+        })
+    }
+
+    // Executes one instruction like `CPU::execute` and traces it
+    pub fn execute(&mut self, cpu: &mut CPU) -> Result<(), Error> {
+        let pc = cpu.pc;
+        let opcode = match cpu.memory.get(pc as usize..pc as usize + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        };
+
+        let traced = self.error.is_none() && self.options.filter.matches(self.frame, pc, opcode);
+        let before = registers(cpu);
+
+        let result = cpu.execute();
+        if let Err(error) = result {
+            self.fault(error);
+            return result;
+        }
+
+        if traced {
+            let after = registers(cpu);
+            let changes = (0..after.len())
+                .filter(|&register| before[register] != after[register])
+                .map(|register| (register as u8, after[register]))
+                .collect();
+
+            self.record(Entry {
+                cycle: self.cycle,
+                frame: self.frame,
+                pc,
+                opcode,
+                changes,
+            });
+        }
+
+        self.cycle += 1;
+        Ok(())
+    }
+
+    // Called along with `CPU::tick` to advance the frame count
+    pub fn tick(&mut self) {
+        self.frame += 1;
+    }
+
+    fn record(&mut self, entry: Entry) {
+        match self.options.ring {
+            Some(capacity) => {
+                if self.ring.len() == capacity {
+                    self.ring.pop_front();
+                }
+                if capacity > 0 {
+                    self.ring.push_back(entry);
+                }
+            }
+            None => {
+                let result = self.write(&entry);
+                self.check(result);
+            }
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        match self.options.format {
+            Format::Text => writeln!(self.out, "{}", entry),
+            Format::Binary => entry.write(&mut self.out),
+        }
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            if self.error.is_none() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    // Writes out the ring, then the fault itself in text traces
+    fn fault(&mut self, error: Error) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = self.dump();
+        self.check(result);
+
+        if self.options.format == Format::Text {
+            let result = writeln!(self.out, "! {}", error);
+            self.check(result);
+        }
+
+        let result = self.out.flush();
+        self.check(result);
+    }
+
+    // Writes the entries held back in ring mode
+    pub fn dump(&mut self) -> io::Result<()> {
+        while let Some(entry) = self.ring.pop_front() {
+            self.write(&entry)?;
+        }
+        Ok(())
+    }
+
+    // Flushes the output and hands it back, or reports the first write error
+    pub fn finish(mut self) -> io::Result<W> {
+        let result = self.out.flush();
+        self.check(result);
+
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self.out),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use super::super::super::core::{EpxGPU, FrameBuffer, GPU};
+use super::cpu::*;
+use super::database::Database;
+use super::keyboard::*;
+use super::quirks::*;
+
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// The emulator as web pages embed it, built for wasm32-unknown-unknown with
+// the wasm feature. Nothing in here needs threads, the page runs a frame 60
+// times a second and draws the RGBA image into a canvas:
+//
+//   const emulator = new Emulator(seed);
+//   emulator.load_rom(new Uint8Array(await response.arrayBuffer()));
+//   setInterval(() => {
+//       emulator.run_frame();
+//       const pixels = new Uint8ClampedArray(emulator.rgba());
+//       const image = new ImageData(pixels, emulator.width(), emulator.height());
+//       context.putImageData(image, 0, 0);
+//   }, 1000 / 60);
+//
+// wasm-bindgen's exports need its JS glue. Hosts without JS, like wasmtime,
+// use the chip8_* exports at the end instead, one emulator per instance.
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct Emulator {
+    cpu: CPU,
+    // What `reset` loads again
+    program: Vec<u8>,
+    // The ROM database's title for it
+    title: Option<String>,
+    seed: u64,
+    quirks: Quirks,
+    cycles_per_frame: u32,
+    gpu: EpxGPU,
+    // The scaler's output, twice the CHIP-8 resolution
+    scaled: FrameBuffer<u8>,
+    // Background and foreground as RGBA
+    palette: [[u8; 4]; 2],
+    rgba: Vec<u8>,
+    // Why the CPU stopped, every frame after that fails with it
+    fault: Option<String>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Emulator {
+    // `seed` seeds CXNN, there's no OS to ask for one
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(seed: u32) -> Self {
+        let mut emulator = Self {
+            cpu: Self::cpu(seed as u64, Quirks::default()),
+            program: Vec::new(),
+            title: None,
+            seed: seed as u64,
+            quirks: Quirks::default(),
+            cycles_per_frame: 9,
+            gpu: EpxGPU::new(),
+            scaled: FrameBuffer::new(128, 64, 0),
+            palette: [[0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]],
+            rgba: Vec::new(),
+            fault: None,
+        };
+        emulator.render();
+        emulator
+    }
+
+    // Loads a ROM into a fresh CPU. ROMs the built in database knows get the
+    // quirks, speed and colors they were made for.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        let mut cpu = Self::cpu(self.seed, self.quirks);
+        let database = Database::builtin();
+        let program = cpu
+            .load_known_program(rom, &database)
+            .map_err(|e| e.to_string())?;
+
+        self.title = program.map(|program| program.title.clone());
+        if let Some(program) = program {
+            self.quirks = cpu.quirks;
+            if let Some(tickrate) = program.tickrate {
+                self.cycles_per_frame = tickrate;
+            }
+            if let Some(colors) = &program.colors {
+                if let (Some(foreground), Some(background)) =
+                    (rgb(&colors.foreground), rgb(&colors.background))
+                {
+                    self.palette = [rgba(background), rgba(foreground)];
+                }
+            }
+        }
+        self.start(cpu, rom.to_vec());
+        Ok(())
+    }
+
+    // Starts the ROM over, with the same random numbers and settings
+    pub fn reset(&mut self) {
+        let program = std::mem::take(&mut self.program);
+        let mut cpu = Self::cpu(self.seed, self.quirks);
+        // It loaded before
+        let _ = cpu.load_program(&program);
+        self.start(cpu, program);
+    }
+
+    // A quirk preset like schip, or the quirks to enable like
+    // shift_vy,vf_reset. Applies right away.
+    pub fn set_quirks(&mut self, quirks: &str) -> Result<(), String> {
+        self.quirks =
+            Quirks::parse(quirks).ok_or_else(|| format!("Unknown quirks '{}'", quirks))?;
+        self.cpu.quirks = self.quirks;
+        Ok(())
+    }
+
+    // Instructions per second, rounded to a multiple of 60
+    pub fn set_clock(&mut self, hz: u32) -> Result<(), String> {
+        if hz < 60 {
+            return Err("The clock has to be at least 60 Hz".to_string());
+        }
+        self.cycles_per_frame = (hz + 30) / 60;
+        Ok(())
+    }
+
+    // EPX smoothing, or plain pixel doubling
+    pub fn set_scaler(&mut self, epx: bool) {
+        self.gpu.enabled = epx;
+        self.render();
+    }
+
+    // Colors as 0xRRGGBB
+    pub fn set_palette(&mut self, foreground: u32, background: u32) {
+        self.palette = [rgba(background), rgba(foreground)];
+        self.render();
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) -> Result<(), String> {
+        if key > 0xF {
+            return Err(format!("Invalid key {}", key));
+        }
+        let mut keyboard = self.cpu.keyboard.lock().unwrap();
+        if pressed {
+            keyboard.press_key(key);
+        } else {
+            keyboard.release_key(key);
+        }
+        Ok(())
+    }
+
+    // Runs one 60 Hz frame. After a fault the CPU stays where it was and
+    // every frame fails with the fault.
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        if let Some(fault) = &self.fault {
+            return Err(fault.clone());
+        }
+
+        for _ in 0..self.cycles_per_frame {
+            if let Err(e) = self.cpu.execute() {
+                self.fault = Some(e.to_string());
+                break;
+            }
+        }
+        if self.fault.is_none() {
+            self.cpu.tick();
+        }
+        if self.cpu.frame_buf.handle_draw() {
+            self.render();
+        }
+
+        match &self.fault {
+            Some(fault) => Err(fault.clone()),
+            None => Ok(()),
+        }
+    }
+
+    // The title of ROMs the database knows
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    // Whether the buzzer sounds
+    pub fn sound(&self) -> bool {
+        self.cpu.timers[SOUND] > 0
+    }
+
+    pub fn width(&self) -> u32 {
+        self.scaled.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.scaled.height()
+    }
+
+    // The scaled image, four bytes per pixel, row by row
+    pub fn rgba(&self) -> Vec<u8> {
+        self.rgba.clone()
+    }
+
+    // Where `rgba` is in memory, valid until the next call
+    pub fn rgba_ptr(&self) -> *const u8 {
+        self.rgba.as_ptr()
+    }
+}
+
+impl Emulator {
+    fn cpu(seed: u64, quirks: Quirks) -> CPU {
+        let keyboard = Arc::new(Mutex::new(Keyboard::new()));
+        let mut cpu = CPU::with_seed(FrameBuffer::new(64, 32, 0), keyboard, seed);
+        cpu.quirks = quirks;
+        cpu
+    }
+
+    fn start(&mut self, cpu: CPU, program: Vec<u8>) {
+        self.cpu = cpu;
+        self.program = program;
+        self.fault = None;
+        self.render();
+    }
+
+    fn render(&mut self) {
+        let screen = &self.cpu.frame_buf;
+        if self.scaled.width() != screen.width() * 2 || self.scaled.height() != screen.height() * 2
+        {
+            self.scaled = FrameBuffer::new(screen.width() * 2, screen.height() * 2, 0);
+        }
+        self.gpu.process(screen, &mut self.scaled);
+        self.scaled.handle_draw();
+
+        self.rgba.clear();
+        for &pixel in self.scaled.frame() {
+            self.rgba
+                .extend_from_slice(&self.palette[(pixel != 0) as usize]);
+        }
+    }
+}
+
+fn rgba(color: u32) -> [u8; 4] {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b, 0xFF]
+}
+
+// RRGGBB as the ROM database has it
+fn rgb(text: &str) -> Option<u32> {
+    u32::from_str_radix(text.trim().trim_start_matches('#'), 16).ok()
+}
+
+// Plain exports for hosts without JS
+#[cfg(feature = "wasm")]
+mod exports {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static EMULATOR: RefCell<Emulator> = RefCell::new(Emulator::default());
+        // Where hosts copy ROMs to before loading them
+        static ROM: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // A buffer of `len` bytes in the module's memory for the next ROM
+    #[no_mangle]
+    pub extern "C" fn chip8_rom_buffer(len: u32) -> *mut u8 {
+        ROM.with(|rom| {
+            let mut rom = rom.borrow_mut();
+            rom.resize(len as usize, 0);
+            rom.as_mut_ptr()
+        })
+    }
+
+    // Starts a new emulator with the ROM from the buffer
+    #[no_mangle]
+    pub extern "C" fn chip8_load_rom(seed: u32) -> bool {
+        let mut emulator = Emulator::new(seed);
+        let loaded = ROM.with(|rom| emulator.load_rom(&rom.borrow()).is_ok());
+        EMULATOR.with(|current| *current.borrow_mut() = emulator);
+        loaded
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_set_key(key: u32, pressed: bool) -> bool {
+        let key = key.min(0xFF) as u8;
+        EMULATOR.with(|emulator| emulator.borrow_mut().set_key(key, pressed).is_ok())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_run_frame() -> bool {
+        EMULATOR.with(|emulator| emulator.borrow_mut().run_frame().is_ok())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_sound() -> bool {
+        EMULATOR.with(|emulator| emulator.borrow().sound())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_width() -> u32 {
+        EMULATOR.with(|emulator| emulator.borrow().width())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn chip8_height() -> u32 {
+        EMULATOR.with(|emulator| emulator.borrow().height())
+    }
+
+    // The RGBA image, valid until the next call
+    #[no_mangle]
+    pub extern "C" fn chip8_rgba() -> *const u8 {
+        EMULATOR.with(|emulator| emulator.borrow().rgba_ptr())
+    }
+}
@@ -0,0 +1,55 @@
+// Square wave for the CHIP-8 buzzer, which sounds while the sound timer is
+// above zero. The emulator switches it on and off, an audio callback pulls
+// the samples.
+
+pub const BUZZER_FREQUENCY: f32 = 440.0; // Hz
+pub const BUZZER_VOLUME: f32 = 0.2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buzzer {
+    pub frequency: f32,
+    pub volume: f32,
+    // Position within the current period, from 0 to 1
+    phase: f32,
+}
+
+impl Buzzer {
+    pub fn new(frequency: f32, volume: f32) -> Self {
+        Self {
+            frequency,
+            volume,
+            phase: 0.0,
+        }
+    }
+
+    // Fills `out` with mono samples at `sample_rate`, silence while off. The
+    // wave picks up where it stopped so switching doesn't click mid-period.
+    pub fn fill(&mut self, out: &mut [f32], sample_rate: u32, on: bool) {
+        self.fill_channels(out, 1, sample_rate, on);
+    }
+
+    // Like `fill`, with `channels` interleaved samples of the same wave per
+    // frame, as audio devices take them
+    pub fn fill_channels(&mut self, out: &mut [f32], channels: usize, sample_rate: u32, on: bool) {
+        if !on {
+            out.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        let step = self.frequency / sample_rate as f32;
+        for frame in out.chunks_mut(channels) {
+            frame.fill(if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            });
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+impl Default for Buzzer {
+    fn default() -> Self {
+        Self::new(BUZZER_FREQUENCY, BUZZER_VOLUME)
+    }
+}
@@ -1,6 +1,6 @@
+use std::thread::sleep;
 use std::time::Instant;
 use std::time::*;
-use std::thread::sleep;
 
 pub struct Clock {
     last_tick: Instant,
@@ -15,6 +15,11 @@ impl Clock {
         }
     }
 
+    // Starts counting from now, so time spent paused isn't caught up on
+    pub fn reset(&mut self) {
+        self.last_tick = Instant::now();
+    }
+
     pub fn tick(&mut self, sync: bool) -> bool {
         let diff = self.last_tick.elapsed();
 
@@ -31,4 +36,4 @@ impl Clock {
             }
         }
     }
-}
\ No newline at end of file
+}
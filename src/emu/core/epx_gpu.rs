@@ -11,6 +11,12 @@ impl EpxGPU {
     }
 }
 
+impl Default for EpxGPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone + Copy + Eq + PartialEq> GPU<T> for EpxGPU {
     fn process(&self, input: &FrameBuffer<T>, output: &mut FrameBuffer<T>) {
         let multiplier = output.width() / input.width();
@@ -28,25 +34,25 @@ impl<T: Clone + Copy + Eq + PartialEq> GPU<T> for EpxGPU {
                 let origin = input.read(input_x, input_y);
 
                 let top = if y_min {
-                    origin.clone()
+                    origin
                 } else {
                     input.read(input_x, input_y - 1)
                 };
 
                 let right = if x_max {
-                    origin.clone()
+                    origin
                 } else {
                     input.read(input_x + 1, input_y)
                 };
 
                 let left = if x_min {
-                    origin.clone()
+                    origin
                 } else {
                     input.read(input_x - 1, input_y)
                 };
 
                 let bottom = if y_max {
-                    origin.clone()
+                    origin
                 } else {
                     input.read(input_x, input_y + 1)
                 };
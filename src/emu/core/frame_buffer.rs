@@ -32,11 +32,7 @@ impl<T: Copy + Clone + Eq + PartialEq> FrameBuffer<T> {
     }
 
     pub fn write(&mut self, x: u32, y: u32, val: T) {
-        if self.completed {
-            eprintln!("Warning: Writing to framebuffer that hasn't been handled yet");
-        }
-
-        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+        if x < self.width && y < self.height {
             self.buf[(y * self.width + x) as usize] = val;
         } else {
             println!("Ignoring pixel out of bounds");
@@ -47,6 +43,12 @@ impl<T: Copy + Clone + Eq + PartialEq> FrameBuffer<T> {
         self.buf = vec![init; (self.width * self.height) as usize];
     }
 
+    pub fn copy_from(&mut self, other: &FrameBuffer<T>) {
+        self.buf.clone_from(&other.buf);
+        self.width = other.width;
+        self.height = other.height;
+    }
+
     pub fn request_draw(&mut self) {
         self.completed = true;
     }
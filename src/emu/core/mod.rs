@@ -1,13 +1,27 @@
+mod buzzer;
+#[cfg(feature = "native")]
 mod clock;
-mod context;
 mod cpu;
 mod epx_gpu;
 mod frame_buffer;
 mod gpu;
+#[cfg(feature = "native")]
+mod run_control;
+#[cfg(feature = "native")]
+mod swap_chain;
+mod text;
+mod viewport;
 
+pub use buzzer::*;
+#[cfg(feature = "native")]
 pub use clock::*;
-pub use context::*;
 pub use cpu::*;
 pub use epx_gpu::*;
 pub use frame_buffer::*;
 pub use gpu::*;
+#[cfg(feature = "native")]
+pub use run_control::*;
+#[cfg(feature = "native")]
+pub use swap_chain::*;
+pub use text::*;
+pub use viewport::*;
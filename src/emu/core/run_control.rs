@@ -0,0 +1,171 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Pause, frame advance, single step and fast forward for the CPU thread.
+// Emulated time only advances in whole instructions and timers tick once per
+// emulated frame, so running faster or slower than real time never changes
+// what a ROM sees. The window thread changes the state through a shared
+// `RunControl`, the CPU thread asks it what to do next.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    Normal,
+    FastForward(u32),
+    // As fast as the host allows
+    Uncapped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Paused, check back later
+    Wait,
+    // Execute a single instruction
+    Step,
+    // Run to the end of the current frame, then pace at the given speed
+    Frame(Speed),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunControl {
+    pub paused: bool,
+    // Held down fast forward key
+    pub fast_forward: bool,
+    pub multiplier: u32,
+    pub uncapped: bool,
+    frames: u32,
+    steps: u32,
+}
+
+impl RunControl {
+    pub fn new(multiplier: u32) -> Self {
+        Self {
+            paused: false,
+            fast_forward: false,
+            multiplier: multiplier.max(1),
+            uncapped: false,
+            frames: 0,
+            steps: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.frames = 0;
+        self.steps = 0;
+    }
+
+    // Pauses if running, then lets exactly one more frame run
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.frames += 1;
+    }
+
+    // Pauses if running, then lets exactly one more instruction run
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.steps += 1;
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.uncapped {
+            Speed::Uncapped
+        } else if self.fast_forward && self.multiplier > 1 {
+            Speed::FastForward(self.multiplier)
+        } else {
+            Speed::Normal
+        }
+    }
+
+    // Sound is muted unless running at normal speed, pitching a square wave
+    // up by the fast forward factor just makes for noise
+    pub fn muted(&self) -> bool {
+        self.paused || self.speed() != Speed::Normal
+    }
+
+    // Whether the buzzer plays, CHIP-8 sounds while the sound timer runs
+    pub fn buzzer(&self, sound_timer: u8) -> bool {
+        sound_timer > 0 && !self.muted()
+    }
+
+    pub fn next_action(&mut self) -> Action {
+        if !self.paused {
+            Action::Frame(self.speed())
+        } else if self.steps > 0 {
+            self.steps -= 1;
+            Action::Step
+        } else if self.frames > 0 {
+            self.frames -= 1;
+            Action::Frame(Speed::Uncapped)
+        } else {
+            Action::Wait
+        }
+    }
+
+    // Short description for the window title, empty at normal speed
+    pub fn status(&self) -> String {
+        if self.paused {
+            return "paused".to_string();
+        }
+
+        match self.speed() {
+            Speed::Normal => String::new(),
+            Speed::FastForward(multiplier) => format!("{}x", multiplier),
+            Speed::Uncapped => "uncapped".to_string(),
+        }
+    }
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+// Paces emulated frames against the wall clock
+pub struct Pacer {
+    frame: Duration,
+    deadline: Instant,
+}
+
+// A host this far behind gives up on catching up instead of running the
+// missed frames in one burst
+const MAX_LAG_FRAMES: u32 = 4;
+
+impl Pacer {
+    pub fn new(frequency: u32) -> Self {
+        Self {
+            frame: Duration::from_secs(1) / frequency,
+            deadline: Instant::now(),
+        }
+    }
+
+    // Wall clock time of a frame at normal speed
+    pub fn frame(&self) -> Duration {
+        self.frame
+    }
+
+    // Starts counting from now, e.g. after a pause
+    pub fn reset(&mut self) {
+        self.deadline = Instant::now();
+    }
+
+    // Sleeps until the next frame is due at `speed`
+    pub fn wait(&mut self, speed: Speed) {
+        let frame = match speed {
+            Speed::Normal => self.frame,
+            Speed::FastForward(multiplier) => self.frame / multiplier.max(1),
+            Speed::Uncapped => {
+                self.reset();
+                return;
+            }
+        };
+
+        self.deadline += frame;
+        let now = Instant::now();
+        if self.deadline > now {
+            sleep(self.deadline - now);
+        } else if now - self.deadline > frame * MAX_LAG_FRAMES {
+            self.deadline = now;
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use super::frame_buffer::*;
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// The shared slot holds the index of the buffer that sits between writer and
+// reader, plus a flag telling whether it contains a frame the reader hasn't seen.
+const INDEX_MASK: u8 = 0b011;
+const FRESH: u8 = 0b100;
+
+struct Shared<T> {
+    buffers: [UnsafeCell<FrameBuffer<T>>; 3],
+    middle: AtomicU8,
+}
+
+// Each buffer is only ever accessed by whoever currently owns its index, and
+// ownership is transferred exclusively through the atomic `middle` slot.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Producer half of a triple buffer. Frames are drawn elsewhere and copied in
+/// as a whole by `publish`, so the reader never observes a partial frame.
+pub struct FrameWriter<T> {
+    shared: Arc<Shared<T>>,
+    back: u8,
+}
+
+/// Consumer half of a triple buffer. `update` latches the newest published
+/// frame without ever blocking the writer.
+pub struct FrameReader<T> {
+    shared: Arc<Shared<T>>,
+    front: u8,
+}
+
+pub fn swap_chain<T: Copy + Eq>(
+    width: u32,
+    height: u32,
+    init: T,
+) -> (FrameWriter<T>, FrameReader<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [
+            UnsafeCell::new(FrameBuffer::new(width, height, init)),
+            UnsafeCell::new(FrameBuffer::new(width, height, init)),
+            UnsafeCell::new(FrameBuffer::new(width, height, init)),
+        ],
+        middle: AtomicU8::new(1),
+    });
+
+    (
+        FrameWriter {
+            shared: shared.clone(),
+            back: 0,
+        },
+        FrameReader { shared, front: 2 },
+    )
+}
+
+impl<T: Copy + Eq> FrameWriter<T> {
+    pub fn publish(&mut self, frame: &FrameBuffer<T>) {
+        // Safety: the back buffer is owned exclusively by the writer
+        let back = unsafe { &mut *self.shared.buffers[self.back as usize].get() };
+        back.copy_from(frame);
+
+        let prev = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = prev & INDEX_MASK;
+    }
+}
+
+impl<T: Copy + Eq> FrameReader<T> {
+    /// Returns true if a new frame was latched since the last call.
+    pub fn update(&mut self) -> bool {
+        // Only the writer touches the slot besides us and it always sets FRESH,
+        // so the flag can't disappear between the load and the swap
+        if self.shared.middle.load(Ordering::Acquire) & FRESH == 0 {
+            return false;
+        }
+
+        let prev = self.shared.middle.swap(self.front, Ordering::AcqRel);
+        self.front = prev & INDEX_MASK;
+        true
+    }
+
+    pub fn frame(&self) -> &FrameBuffer<T> {
+        // Safety: the front buffer is owned exclusively by the reader
+        unsafe { &*self.shared.buffers[self.front as usize].get() }
+    }
+}
@@ -0,0 +1,61 @@
+// How the emulated screen is fit into a window of any size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    // Whole multiples of the image's pixels, so every pixel is the same size
+    Integer,
+    // As large as fits, keeping the aspect ratio
+    Fit,
+}
+
+impl ScaleMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "integer" => Some(ScaleMode::Integer),
+            "fit" => Some(ScaleMode::Fit),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+        }
+    }
+}
+
+// Where the image goes in the window, the bars around it are letterboxing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    // Window pixels per image pixel
+    pub scale: f64,
+}
+
+impl Viewport {
+    // Centers an image of `image` pixels in an area of `area` window pixels.
+    // Integer scaling fits like `Fit` in areas smaller than the image.
+    pub fn new(image: (u32, u32), area: (f64, f64), mode: ScaleMode) -> Self {
+        let (image_width, image_height) = (image.0.max(1) as f64, image.1.max(1) as f64);
+        let (width, height) = (area.0.max(0.0), area.1.max(0.0));
+
+        let fit = (width / image_width).min(height / image_height);
+        let scale = match mode {
+            ScaleMode::Integer if fit >= 1.0 => fit.floor(),
+            _ => fit,
+        };
+
+        let (scaled_width, scaled_height) = (image_width * scale, image_height * scale);
+        Self {
+            // Whole pixels keep integer scaled images sharp
+            x: ((width - scaled_width) / 2.0).floor(),
+            y: ((height - scaled_height) / 2.0).floor(),
+            width: scaled_width,
+            height: scaled_height,
+            scale,
+        }
+    }
+}
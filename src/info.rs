@@ -0,0 +1,145 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::analysis::{self, Analysis, Platform};
+use emu_rs::emu::arch::chip8::database::Program;
+use emu_rs::emu::arch::chip8::loader::Options;
+use emu_rs::emu::arch::chip8::{Instruction, Opcode, PROGRAM_ENTRY};
+
+const USAGE: &str = "Usage: emu_rs info <rom> [options]
+
+Prints the format, size and load address of a ROM, what the ROM database
+knows about it, the quirks an analysis of its code suggests and a linear
+sweep over its words.
+
+Options:
+  --database <file>   ROM database to add to the built in one, in emu_rs's
+                      format or the community database's programs.json
+                      (default ~/.config/emu_rs/database.json)";
+
+// Entry point of the info subcommand, `args` starts after "info"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let rom_path = args.positional("rom to inspect");
+    let mut database = None;
+
+    while let Some(flag) = args.next_arg() {
+        match flag {
+            "--database" => database = Some(args.value(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    let loaded = cli::load_rom(rom_path);
+    let rom = &loaded.program;
+    let database = cli::database(database);
+    let analysis = analysis::analyze(rom);
+    let start = PROGRAM_ENTRY as usize;
+
+    println!("File:     {}", rom_path);
+    println!("Format:   {}", loaded.format);
+    println!("Size:     {} bytes", rom.len());
+    if rom.is_empty() {
+        println!("Loads at: nothing to load");
+    } else {
+        println!("Loads at: {:04X}-{:04X}", start, start + rom.len() - 1);
+    }
+    if let Err(e) = loaded.check_size(analysis.platform) {
+        println!("          {}", e);
+    }
+    println!("SHA-1:    {}", loaded.hash);
+
+    match database.get(rom) {
+        Some(program) => print_program(program),
+        None => println!("Title:    unknown, not in the ROM database"),
+    }
+    if let Some(cartridge) = &loaded.cartridge {
+        print_options(&cartridge.options);
+    }
+    print_analysis(&analysis);
+
+    let mut unknown = 0;
+    let mut calls = 0;
+    let mut draws = 0;
+    for word in rom.chunks_exact(2) {
+        let value = u16::from_be_bytes([word[0], word[1]]);
+        match Instruction::decode(&Opcode { value }) {
+            Instruction::Unknown(_) => unknown += 1,
+            Instruction::Call(_) => calls += 1,
+            Instruction::Draw(..) => draws += 1,
+            _ => {}
+        }
+    }
+
+    // Data decodes to instructions just as well, so this is only a rough hint
+    let words = rom.len() / 2;
+    println!(
+        "Words:    {} ({} don't decode, {} calls, {} draws)",
+        words, unknown, calls, draws
+    );
+}
+
+fn print_program(program: &Program) {
+    println!("Title:    {}", program.title);
+    if let Some(author) = &program.author {
+        println!("Author:   {}", author);
+    }
+    if let Some(platform) = &program.platform {
+        println!("Platform: {}", platform);
+    }
+    if let Some(tickrate) = program.tickrate {
+        println!(
+            "Speed:    {} instructions per frame ({} Hz)",
+            tickrate,
+            tickrate * 60
+        );
+    }
+    if let Some(preset) = program.quirk_preset() {
+        println!("Quirks:   {}", preset);
+    }
+    if !program.keys.is_empty() {
+        let keys: Vec<String> = program
+            .keys
+            .iter()
+            .map(|(hint, key)| format!("{:X} {}", key, hint))
+            .collect();
+        println!("Keys:     {}", keys.join(", "));
+    }
+    if let Some(colors) = &program.colors {
+        println!("Colors:   {} on {}", colors.foreground, colors.background);
+    }
+}
+
+// What the cartridge sets up, on one line
+fn print_options(options: &Options) {
+    let settings = options.settings();
+    let mut parts = Vec::new();
+    if let Some(tickrate) = options.tickrate {
+        parts.push(format!("{} instructions per frame", tickrate));
+    }
+    if let Some(quirks) = settings.quirks {
+        parts.push(format!("quirks {}", quirks));
+    }
+    if let Some(palette) = settings.palette {
+        parts.push(format!("colors {}", palette.replace(',', " on ")));
+    }
+    if parts.is_empty() {
+        parts.push("no options".to_string());
+    }
+    println!("Octo:     {}", parts.join(", "));
+}
+
+fn print_analysis(analysis: &Analysis) {
+    let platform = match analysis.platform {
+        Platform::Chip8 => "CHIP-8",
+        Platform::SuperChip => "SUPER-CHIP",
+        Platform::XoChip => "XO-CHIP",
+    };
+    println!("Analysis: {} code", platform);
+    println!(
+        "          {} quirks suggested ({:.0}% confidence)",
+        analysis.preset,
+        analysis.confidence * 100.0
+    );
+    for reason in &analysis.reasons {
+        println!("          - {}", reason);
+    }
+}
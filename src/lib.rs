@@ -0,0 +1,4 @@
+pub mod emu;
+
+#[cfg(test)]
+mod test;
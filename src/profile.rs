@@ -0,0 +1,78 @@
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::profile::Profiler;
+use emu_rs::emu::arch::chip8::{self, Movie};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "Usage: emu_rs profile <rom> [options]
+
+Runs a ROM headless and prints where its cycles went.
+
+Options:
+  --movie <file>      Replay keypad input from a movie file
+  --frames <n>        Number of 60 Hz frames to run (default 600)
+  --quirks <preset>   Quirk preset (default default)
+  --annotate <file>   Write the disassembly with execution counts";
+
+// 540 Hz CPU against 60 Hz timers like the main loop
+const CYCLES_PER_FRAME: u64 = 9;
+
+// Entry point of the profile subcommand, `args` starts after "profile"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, USAGE);
+    let rom_path = args.positional("rom to load");
+
+    let mut movie = Movie::new();
+    let mut frames = 600;
+    let mut quirks = chip8::Quirks::default();
+    let mut annotate = None;
+
+    while let Some(flag) = args.next_arg() {
+        match flag {
+            "--movie" => {
+                let text = cli::read_to_string(args.value(flag));
+                movie = Movie::parse(&text).unwrap_or_else(|e| cli::error(&e));
+            }
+            "--frames" => frames = args.number(flag),
+            "--quirks" => {
+                let preset = args.value(flag);
+                quirks = chip8::Quirks::preset(preset)
+                    .unwrap_or_else(|| args.fail(&format!("Unknown quirk preset '{}'", preset)));
+            }
+            "--annotate" => annotate = Some(args.value(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    let rom = cli::load_rom(rom_path).program;
+
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.quirks = quirks;
+    cpu.load_program(&rom)
+        .unwrap_or_else(|e| cli::error(&e.to_string()));
+
+    let mut profiler = Profiler::new(rom.len());
+    'frames: for frame in 0..frames {
+        movie.apply(frame, &mut cpu.keyboard.lock().unwrap());
+
+        for _ in 0..CYCLES_PER_FRAME {
+            if let Err(e) = profiler.execute(&mut cpu) {
+                eprintln!("{}", e);
+                break 'frames;
+            }
+        }
+
+        cpu.tick();
+        profiler.tick();
+    }
+
+    print!("{}", profiler.report(&cpu.memory));
+
+    if let Some(path) = annotate {
+        cli::write(path, profiler.annotate(&cpu.memory));
+    }
+}
@@ -0,0 +1,134 @@
+// The run subcommand. `options` parses the command line, `settings` merges
+// it with the config file and the ROM database, `runner` drives the CPU and
+// `window` shows it.
+
+mod options;
+mod runner;
+mod settings;
+mod window;
+
+use self::runner::{CheatFile, Runner, Stop};
+use self::settings::Sources;
+use self::window::{window, CurrentRom};
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::analysis;
+use emu_rs::emu::arch::chip8::loader::{self, Rom};
+use emu_rs::emu::arch::chip8::Keyboard;
+
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+// Entry point of the run subcommand, `args` starts after "run"
+pub fn run(args: &[String]) {
+    let mut args = Args::new(args, options::USAGE);
+    let rom_path = args.positional("rom to load");
+    let mut options = options::parse(&mut args);
+
+    let loaded = cli::load_rom(rom_path);
+    loaded
+        .check_size(analysis::analyze(&loaded.program).platform)
+        .unwrap_or_else(|e| cli::error(&format!("{}: {}", rom_path, e)));
+
+    let sources = Sources {
+        database: cli::database(options.database.as_deref()),
+        config_path: options
+            .config
+            .clone()
+            .or_else(|| cli::config_dir().map(|dir| dir.join("config.toml"))),
+        overrides: options.overrides.clone(),
+    };
+    let settings = sources
+        .settings(&loaded, rom_path)
+        .unwrap_or_else(|e| cli::error(&e));
+    let (rom, hash) = (loaded.program.as_slice(), loaded.hash.clone());
+
+    let cheat_path = options.cheats.clone().or_else(|| default_cheat_path(&hash));
+    let cheats = CheatFile::load(cheat_path.clone())
+        .unwrap_or_else(|e| cli::error(&format!("{}: {}", cheat_path.unwrap().display(), e)));
+
+    let keyboard = Arc::new(Mutex::new(Keyboard::new()));
+    let runner = Runner::new(
+        rom,
+        &mut options,
+        &settings.resolved,
+        cheats,
+        &sources.database,
+        keyboard.clone(),
+    );
+
+    let halted = if options.headless {
+        // Clients decide when the run ends
+        let frames = match options.rpc {
+            Some(_) => u64::MAX,
+            None => options.frames,
+        };
+        headless(runner, frames)
+    } else {
+        let current = CurrentRom {
+            path: Some(PathBuf::from(rom_path)).filter(|_| rom_path != "-"),
+            hash,
+        };
+        window(runner, &options, &sources, settings, current, keyboard)
+    };
+
+    if halted {
+        process::exit(1);
+    }
+}
+
+// ~/.config/emu_rs/cheats/<sha1>.txt
+pub fn default_cheat_path(hash: &str) -> Option<PathBuf> {
+    cli::config_dir().map(|dir| dir.join("cheats").join(format!("{}.txt", hash)))
+}
+
+// ~/.config/emu_rs/states/<sha1>/<slot>.state
+pub fn state_path(hash: &str, slot: u8) -> Option<PathBuf> {
+    cli::config_dir().map(|dir| {
+        dir.join("states")
+            .join(hash)
+            .join(format!("{}.state", slot))
+    })
+}
+
+// Reads a ROM the menu picked, checked like the one on the command line
+pub fn load_picked(path: &Path) -> Result<Rom, String> {
+    let rom = loader::load(&path.to_string_lossy())?;
+    rom.check_size(analysis::analyze(&rom.program).platform)
+        .map_err(|e| e.to_string())?;
+    Ok(rom)
+}
+
+// Runs `frames` frames and prints the screen, returns whether the CPU faulted
+fn headless(mut runner: Runner, frames: u64) -> bool {
+    let mut halted = false;
+
+    while runner.frame < frames {
+        match runner.run(runner.remaining()) {
+            Ok(_) | Err(Stop::Paused) => {}
+            Err(Stop::Halted) => {
+                halted = true;
+                break;
+            }
+            Err(Stop::Finished) => break,
+        }
+    }
+
+    if !halted {
+        if let Err(e) = runner.sync() {
+            eprintln!("Netplay: {}", e);
+            halted = true;
+        }
+    }
+
+    let screen = &runner.cpu.frame_buf;
+    for y in 0..screen.height() {
+        let row: String = (0..screen.width())
+            .map(|x| if screen.read(x, y) != 0 { '#' } else { '.' })
+            .collect();
+        println!("{}", row);
+    }
+
+    runner.finish();
+    halted
+}
@@ -0,0 +1,231 @@
+use super::settings::{parse_keymap, resolve};
+use crate::cli::{self, Args};
+use emu_rs::emu::arch::chip8::config::Settings;
+use emu_rs::emu::arch::chip8::lockstep::Backend;
+use emu_rs::emu::arch::chip8::{trace, Movie};
+use emu_rs::emu::core::RunControl;
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: emu_rs run <rom> [options]
+
+Runs a ROM in a window, or without one with --headless. The ROM may be a
+.zip or .gz archive or an Octo cartridge GIF, \"-\" reads it from stdin.
+
+Options:
+  --clock <hz>           Instructions per second (default 540)
+  --quirks <quirks>      default, chip8, schip or a list like shift_vy,vf_reset
+  --scaler <name>        epx or none (default epx)
+  --palette <colors>     white, amber, green, lcd or <fg>,<bg> as RRGGBB
+  --scale <n>            Window pixels per CHIP-8 pixel (default 10)
+  --scale-mode <mode>    integer or fit (default integer)
+  --fullscreen           Start in fullscreen
+  --keymap <file>        \"<host key> <keypad key>\" pairs, one per line
+  --config <file>        Config file (default ~/.config/emu_rs/config.toml)
+  --database <file>      ROM database to add to the built in one
+  --cheats <file>        Cheat codes (default ~/.config/emu_rs/cheats/<sha1>)
+  --seed <n>             Seed for CXNN (default random)
+  --backend <name>       interpreter, cached or jit (default cached)
+  --headless             Run without a window and print the last screen
+  --frames <n>           60 Hz frames to run headless (default 600)
+  --record <file>        Record keypad input into a movie
+  --play <file>          Replay keypad input from a movie
+  --fast-forward <n>     Speed while Tab is held (default 4)
+  --script <file>        Run a Rhai script along with the ROM
+  --gdb <port>           Wait for GDB to attach
+  --rpc <address>        Serve JSON-RPC on host:port or unix:<path>
+  --netplay <address>    Play against a peer over UDP
+  --netplay-port <n>     Local UDP port for netplay (default 7000)
+  --netplay-keys <keys>  Keypad keys this side owns, e.g. 14
+  --profile <file>       Write a profile on exit
+  --trace <file>         Write an execution trace
+  --trace-format <fmt>   text or binary (default text)
+  --trace-range <range>  Only trace PCs in a hex range, e.g. 200-2FF
+  --trace-class <list>   Only trace opcode classes, e.g. 8,D
+  --trace-frames <range> Only trace frames in a range, e.g. 60-120
+  --trace-ring <n>       Keep the last n entries, written on a fault
+
+Keys:
+  1234 QWER ASDF YXCV    Keypad
+  P / N / B              Pause, next frame, single step
+  U / Tab                Uncapped speed, fast forward while held
+  O / G                  Debug overlay, toggle the scaler
+  F11 / M                Fullscreen, toggle the scale mode
+  Esc                    Menu
+  F5                     Save the current settings for this ROM
+
+The config file holds defaults under [default] and settings for single ROMs
+under [rom.<sha1>].";
+
+pub struct Options {
+    // Config file settings given on the command line
+    pub overrides: Settings,
+    pub config: Option<PathBuf>,
+    pub database: Option<String>,
+    pub cheats: Option<PathBuf>,
+    pub fullscreen: bool,
+    pub seed: Option<u64>,
+    pub backend: Backend,
+    pub headless: bool,
+    pub frames: u64,
+    pub record: Option<String>,
+    pub play: Option<Movie>,
+    pub fast_forward: u32,
+    pub script: Option<String>,
+    pub gdb_port: Option<u16>,
+    pub rpc: Option<String>,
+    // Peer address, local port and the keys this side owns
+    pub netplay: Option<String>,
+    pub netplay_port: u16,
+    pub netplay_keys: Option<u16>,
+    pub profile: Option<String>,
+    pub trace: Option<String>,
+    pub trace_options: trace::Options,
+}
+
+pub fn parse(args: &mut Args) -> Options {
+    let mut options = Options {
+        overrides: Settings::default(),
+        config: None,
+        database: None,
+        cheats: None,
+        fullscreen: false,
+        seed: None,
+        backend: Backend::Cached,
+        headless: false,
+        frames: 600,
+        record: None,
+        play: None,
+        fast_forward: RunControl::default().multiplier,
+        script: None,
+        gdb_port: None,
+        rpc: None,
+        netplay: None,
+        netplay_port: 7000,
+        netplay_keys: None,
+        profile: None,
+        trace: None,
+        trace_options: trace::Options::default(),
+    };
+
+    while let Some(flag) = args.next_arg() {
+        let overrides = &mut options.overrides;
+        match flag {
+            "--clock" => overrides.clock = Some(args.number(flag)),
+            "--quirks" => overrides.quirks = Some(args.value(flag).to_string()),
+            "--scaler" => overrides.scaler = Some(args.value(flag).to_string()),
+            "--palette" => overrides.palette = Some(args.value(flag).to_string()),
+            "--scale" => overrides.scale = Some(args.number(flag)),
+            "--scale-mode" => overrides.scale_mode = Some(args.value(flag).to_string()),
+            "--keymap" => {
+                let path = args.value(flag);
+                let keys = parse_keymap(&cli::read_to_string(path))
+                    .unwrap_or_else(|e| cli::error(&format!("{}: {}", path, e)));
+                overrides.keys = Some(keys);
+            }
+            "--config" => options.config = Some(PathBuf::from(args.value(flag))),
+            "--database" => options.database = Some(args.value(flag).to_string()),
+            "--cheats" => options.cheats = Some(PathBuf::from(args.value(flag))),
+            "--fullscreen" => options.fullscreen = true,
+            "--seed" => options.seed = Some(args.number(flag)),
+            "--backend" => {
+                let name = args.value(flag);
+                options.backend = Backend::parse(name)
+                    .unwrap_or_else(|| args.fail(&format!("Unknown backend '{}'", name)));
+            }
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = args.number(flag),
+            "--record" => options.record = Some(args.value(flag).to_string()),
+            "--play" => {
+                let path = args.value(flag);
+                let movie = Movie::parse(&cli::read_to_string(path))
+                    .unwrap_or_else(|e| cli::error(&format!("{}: {}", path, e)));
+                options.play = Some(movie);
+            }
+            "--fast-forward" => {
+                options.fast_forward = args.number(flag);
+                if options.fast_forward == 0 {
+                    args.fail("The fast forward multiplier has to be at least 1");
+                }
+            }
+            "--script" => options.script = Some(args.value(flag).to_string()),
+            "--gdb" => options.gdb_port = Some(args.number(flag)),
+            "--rpc" => options.rpc = Some(args.value(flag).to_string()),
+            "--netplay" => options.netplay = Some(args.value(flag).to_string()),
+            "--netplay-port" => options.netplay_port = args.number(flag),
+            "--netplay-keys" => {
+                let keys = args.value(flag);
+                let mask = parse_keys(keys).unwrap_or_else(|e| args.fail(&e));
+                options.netplay_keys = Some(mask);
+            }
+            "--profile" => options.profile = Some(args.value(flag).to_string()),
+            "--trace" => options.trace = Some(args.value(flag).to_string()),
+            "--trace-format" => {
+                let format = args.value(flag);
+                options.trace_options.format = trace::Format::parse(format)
+                    .unwrap_or_else(|| args.fail(&format!("Unknown trace format '{}'", format)));
+            }
+            "--trace-range" => {
+                let range = trace::Filter::parse_addresses(args.value(flag));
+                options.trace_options.filter.addresses =
+                    Some(range.unwrap_or_else(|e| args.fail(&e)));
+            }
+            "--trace-class" => {
+                let classes = trace::Filter::parse_classes(args.value(flag));
+                options.trace_options.filter.classes =
+                    Some(classes.unwrap_or_else(|e| args.fail(&e)));
+            }
+            "--trace-frames" => {
+                let range = trace::Filter::parse_frames(args.value(flag));
+                options.trace_options.filter.frames = Some(range.unwrap_or_else(|e| args.fail(&e)));
+            }
+            "--trace-ring" => options.trace_options.ring = Some(args.number(flag)),
+            _ => args.fail(&format!("Unknown option {}", flag)),
+        }
+    }
+
+    // Bad values on the command line are usage errors, bad values in the
+    // config file are reported with the file's name later on
+    if let Err(e) = resolve(&options.overrides) {
+        args.fail(&e);
+    }
+    if options.record.is_some() && options.play.is_some() {
+        args.fail("--record and --play can't be combined");
+    }
+    if options.record.is_some() && options.headless {
+        args.fail("There's no input to record without a window");
+    }
+    if options.netplay.is_some() {
+        if options.netplay_keys.is_none() {
+            args.fail("--netplay needs --netplay-keys");
+        }
+        // Rolling back would run these twice
+        if options.gdb_port.is_some()
+            || options.rpc.is_some()
+            || options.script.is_some()
+            || options.record.is_some()
+        {
+            args.fail("--netplay can't be combined with --gdb, --rpc, --script or --record");
+        }
+        // Both sides need the same random numbers
+        options.seed.get_or_insert(0);
+    }
+
+    options
+}
+
+// Keypad keys given as hex digits, e.g. "14", as a mask
+fn parse_keys(keys: &str) -> Result<u16, String> {
+    if keys.is_empty() {
+        return Err("No keypad keys given".to_string());
+    }
+    keys.chars().try_fold(0u16, |mask, c| {
+        let key = c
+            .to_digit(16)
+            .ok_or_else(|| format!("Invalid keypad keys '{}'", keys))?;
+        if mask & 1 << key != 0 {
+            return Err(format!("Keypad key {} is given twice in '{}'", c, keys));
+        }
+        Ok(mask | 1 << key)
+    })
+}
@@ -0,0 +1,587 @@
+use super::default_cheat_path;
+use super::options::Options;
+use super::settings::Resolved;
+use crate::cli;
+use emu_rs::emu::arch::chip8::cheats::Cheats;
+use emu_rs::emu::arch::chip8::database::Database;
+use emu_rs::emu::arch::chip8::lockstep::Backend;
+use emu_rs::emu::arch::chip8::script::Script;
+use emu_rs::emu::arch::chip8::{
+    self, gdb, netplay, profile::Profiler, rpc, trace, Keyboard, Movie, SaveState,
+};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// How long headless netplay waits for the peer to catch up at the end
+const NETPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A ROM's cheat codes and the file they're saved to when they change
+pub struct CheatFile {
+    pub cheats: Cheats,
+    pub path: Option<PathBuf>,
+}
+
+impl CheatFile {
+    // A missing file has no codes
+    pub fn load(path: Option<PathBuf>) -> Result<Self, String> {
+        let cheats = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => Cheats::parse(&text)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Cheats::default(),
+                Err(e) => return Err(e.to_string()),
+            },
+            None => Cheats::default(),
+        };
+        Ok(Self { cheats, path })
+    }
+
+    // Runs a cheat command from the debugger, saving the codes if it
+    // changed them
+    fn command(&mut self, cpu: &mut chip8::CPU, line: &str) -> String {
+        let codes = self.cheats.codes.clone();
+        let mut output = self.cheats.command(cpu, line);
+        if self.cheats.codes != codes {
+            if let Err(e) = self.save() {
+                output.push_str(&format!("Can't save the codes: {}\n", e));
+            }
+        }
+        output
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.cheats.to_string()).map_err(|e| e.to_string())
+    }
+}
+
+// Why `Runner::run` stopped early
+pub enum Stop {
+    // The debugger holds the CPU, try again later
+    Paused,
+    // The CPU faulted, or the script failed
+    Halted,
+    // The script or a JSON-RPC client ended the run
+    Finished,
+}
+
+struct Recording {
+    movie: Movie,
+    keys: [bool; 16],
+    path: String,
+}
+
+struct Netplay {
+    session: netplay::Session,
+    // The host's keys, the CPU's keypad gets them through the session
+    keyboard: Arc<Mutex<Keyboard>>,
+}
+
+// What the menu has the CPU thread do
+pub enum MenuRequest {
+    // With the ROM's quirks and instructions per frame
+    LoadRom {
+        program: Vec<u8>,
+        hash: String,
+        name: String,
+        quirks: chip8::Quirks,
+        cycles_per_frame: u64,
+    },
+    Reset,
+    // Instructions per frame
+    Clock(u64),
+    Quirks(chip8::Quirks),
+    // The slot's file and number
+    SaveState(PathBuf, u8),
+    LoadState(PathBuf, u8),
+}
+
+// Everything the CPU thread runs, shared by windowed and headless runs
+pub struct Runner {
+    pub cpu: chip8::CPU,
+    // What a reset loads again
+    program: Vec<u8>,
+    cycles_per_frame: u64,
+    // Instructions executed in the current frame
+    cycle: u64,
+    pub frame: u64,
+    frame_started: bool,
+    backend: Backend,
+    // Created on first use. It notices changes to memory by itself, see
+    // `CPU::generation`.
+    #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+    jit: Option<chip8::Jit>,
+    debugger: Option<gdb::Debugger>,
+    rpc: Option<rpc::Server>,
+    cheats: CheatFile,
+    script: Option<(Script, String)>,
+    tracer: Option<trace::Tracer<BufWriter<File>>>,
+    profiler: Option<(Profiler, String)>,
+    pub playback: Option<Movie>,
+    recording: Option<Recording>,
+    netplay: Option<Netplay>,
+}
+
+impl Runner {
+    pub fn new(
+        rom: &[u8],
+        options: &mut Options,
+        resolved: &Resolved,
+        cheats: CheatFile,
+        database: &Database,
+        keyboard: Arc<Mutex<Keyboard>>,
+    ) -> Self {
+        let netplay = options.netplay.as_ref().map(|peer| {
+            let settings = format!(
+                "{:?} {} {:?}",
+                resolved.quirks, resolved.cycles_per_frame, options.seed
+            );
+            let local = format!("0.0.0.0:{}", options.netplay_port);
+            let keys = options.netplay_keys.unwrap();
+            let session =
+                netplay::Session::connect(&local, peer, keys, netplay::game_id(rom, &settings))
+                    .unwrap_or_else(|e| cli::error(&format!("Can't play with {}: {}", peer, e)));
+            eprintln!("Netplay on port {} with {}", options.netplay_port, peer);
+            Netplay {
+                session,
+                keyboard: keyboard.clone(),
+            }
+        });
+        let keyboard = match netplay {
+            Some(_) => Arc::new(Mutex::new(Keyboard::new())),
+            None => keyboard,
+        };
+
+        let mut cpu = chip8::CPU::new(FrameBuffer::new(64, 32, 0u8), keyboard);
+        cpu.quirks = resolved.quirks;
+        if let Some(seed) = options.seed {
+            cpu.seed(seed);
+        }
+        cpu.load_program(rom)
+            .unwrap_or_else(|e| cli::error(&e.to_string()));
+        cheats.cheats.patch(&mut cpu);
+
+        let script = options.script.take().map(|path| {
+            let mut script = Script::new(&cli::read_to_string(&path))
+                .unwrap_or_else(|e| cli::error(&format!("{}: {}", path, e)));
+            script
+                .start(&mut cpu)
+                .unwrap_or_else(|e| cli::error(&format!("{}: {}", path, e)));
+            (script, path)
+        });
+
+        // The CPU waits for the debugger to attach and resume it
+        let debugger = options.gdb_port.map(|port| {
+            let debugger = gdb::listen(("127.0.0.1", port))
+                .unwrap_or_else(|e| cli::error(&format!("Can't listen on port {}: {}", port, e)));
+            eprintln!("Waiting for GDB on {}", debugger.local_addr());
+            debugger
+        });
+
+        // Like the debugger, clients start out with the CPU paused
+        let rpc = options.rpc.as_ref().map(|address| {
+            let mut server = rpc::listen(address, rom)
+                .unwrap_or_else(|e| cli::error(&format!("Can't listen on {}: {}", address, e)));
+            server.set_database(database.clone());
+            eprintln!("Serving JSON-RPC on {}", server.address());
+            server
+        });
+
+        let tracer = options.trace.as_ref().map(|path| {
+            File::create(path)
+                .and_then(|f| trace::Tracer::new(BufWriter::new(f), options.trace_options))
+                .unwrap_or_else(|e| cli::error(&format!("Can't write trace {}: {}", path, e)))
+        });
+
+        Self {
+            cpu,
+            program: rom.to_vec(),
+            cycles_per_frame: resolved.cycles_per_frame,
+            cycle: 0,
+            frame: 0,
+            frame_started: false,
+            backend: options.backend,
+            #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+            jit: None,
+            debugger,
+            rpc,
+            cheats,
+            script,
+            tracer,
+            profiler: options
+                .profile
+                .take()
+                .map(|path| (Profiler::new(rom.len()), path)),
+            playback: options.play.take(),
+            recording: options.record.take().map(|path| Recording {
+                movie: Movie::new(),
+                keys: [false; 16],
+                path,
+            }),
+            netplay,
+        }
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.cycles_per_frame - self.cycle
+    }
+
+    // Whether netplay, a debugger, a JSON-RPC client or a movie follows the
+    // run, which the menu mustn't change under them
+    pub fn followed(&self) -> bool {
+        self.netplay.is_some()
+            || self.debugger.is_some()
+            || self.rpc.is_some()
+            || self.playback.is_some()
+            || self.recording.is_some()
+    }
+
+    // A fresh CPU running `program` with the same keypad, quirks and seed
+    fn restart(&mut self, program: &[u8]) -> Result<(), String> {
+        let mut cpu = chip8::CPU::new(FrameBuffer::new(64, 32, 0u8), self.cpu.keyboard.clone());
+        cpu.quirks = self.cpu.quirks;
+        cpu.seed(self.cpu.rng_state().0);
+        cpu.load_program(program).map_err(|e| e.to_string())?;
+        self.cheats.cheats.patch(&mut cpu);
+        cpu.frame_buf.request_draw();
+        self.cpu = cpu;
+
+        let mut keyboard = self.cpu.keyboard.lock().unwrap();
+        keyboard.wait_for_key = false;
+        keyboard.key_received = false;
+        Ok(())
+    }
+
+    // Carries out what the menu asked for, returns the notification to show
+    pub fn menu(&mut self, request: MenuRequest) -> Result<Option<String>, String> {
+        match request {
+            MenuRequest::LoadRom {
+                program,
+                hash,
+                name,
+                quirks,
+                cycles_per_frame,
+            } => {
+                let cheats = CheatFile::load(default_cheat_path(&hash)).unwrap_or_else(|e| {
+                    eprintln!("Can't load the cheat codes for {}: {}", name, e);
+                    CheatFile {
+                        cheats: Cheats::default(),
+                        path: None,
+                    }
+                });
+                let previous = std::mem::replace(&mut self.cheats, cheats);
+                let previous_quirks = std::mem::replace(&mut self.cpu.quirks, quirks);
+                if let Err(e) = self.restart(&program) {
+                    self.cheats = previous;
+                    self.cpu.quirks = previous_quirks;
+                    return Err(e);
+                }
+                self.program = program;
+                self.cycles_per_frame = cycles_per_frame;
+                self.cycle = self.cycle.min(cycles_per_frame - 1);
+                Ok(Some(format!("Loaded {}", name)))
+            }
+            MenuRequest::Reset => {
+                let program = std::mem::take(&mut self.program);
+                let result = self.restart(&program);
+                self.program = program;
+                result.map(|()| Some("Reset".to_string()))
+            }
+            MenuRequest::Clock(cycles) => {
+                self.cycles_per_frame = cycles;
+                self.cycle = self.cycle.min(cycles - 1);
+                Ok(None)
+            }
+            MenuRequest::Quirks(quirks) => {
+                self.cpu.quirks = quirks;
+                Ok(None)
+            }
+            MenuRequest::SaveState(path, slot) => {
+                let error = |e: io::Error| format!("Can't save to slot {}: {}", slot, e);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(error)?;
+                }
+                fs::write(&path, SaveState::capture(&self.cpu).to_bytes()).map_err(error)?;
+                Ok(Some(format!("State saved to slot {}", slot)))
+            }
+            MenuRequest::LoadState(path, slot) => {
+                let bytes = match fs::read(&path) {
+                    Ok(bytes) => bytes,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        return Err(format!("Slot {} is empty", slot))
+                    }
+                    Err(e) => return Err(format!("Can't load slot {}: {}", slot, e)),
+                };
+                let state = SaveState::from_bytes(&bytes)
+                    .map_err(|e| format!("Can't load slot {}: {}", slot, e))?;
+                state.restore(&mut self.cpu);
+                Ok(Some(format!("State loaded from slot {}", slot)))
+            }
+        }
+    }
+
+    // Keypad input is replayed and recorded right before a frame's first
+    // instruction, like the movie format expects
+    fn start_frame(&mut self) -> Result<(), Stop> {
+        // With netplay the movie holds this side's keys
+        if let Some(netplay) = &mut self.netplay {
+            let keys = {
+                let mut keyboard = netplay.keyboard.lock().unwrap();
+                if let Some(movie) = &self.playback {
+                    movie.apply(self.frame, &mut keyboard);
+                }
+                keyboard.state
+            };
+            let run_frame = replay_frame(&self.cheats.cheats, self.cycles_per_frame);
+            match netplay.session.start_frame(&mut self.cpu, &keys, run_frame) {
+                Ok(true) => {}
+                // Too far ahead of the peer
+                Ok(false) => return Err(Stop::Paused),
+                Err(e) => {
+                    eprintln!("Netplay: {}", e);
+                    return Err(Stop::Halted);
+                }
+            }
+        }
+
+        self.cheats.cheats.freeze(&mut self.cpu);
+        if let Some((script, path)) = &mut self.script {
+            script_result(script.frame(&mut self.cpu, self.frame), script, path)?;
+        }
+        let mut keyboard = self.cpu.keyboard.lock().unwrap();
+
+        if let (Some(movie), None) = (&self.playback, &self.netplay) {
+            movie.apply(self.frame, &mut keyboard);
+        }
+
+        if let Some(recording) = &mut self.recording {
+            for key in 0..16 {
+                let pressed = keyboard.state[key];
+                if pressed != recording.keys[key] {
+                    recording.movie.push(self.frame, key as u8, pressed);
+                }
+            }
+            recording.keys = keyboard.state;
+        }
+
+        self.frame_started = true;
+        Ok(())
+    }
+
+    // Executes up to `cycles` instructions and returns whether the screen
+    // changed
+    pub fn run(&mut self, cycles: u64) -> Result<bool, Stop> {
+        let mut drawn = false;
+        let mut executed = 0;
+
+        while executed < cycles {
+            let started = if self.frame_started {
+                Ok(())
+            } else {
+                self.start_frame()
+            };
+
+            // Without anything looking at single instructions the rest of
+            // the frame runs as one batch
+            let batch = if self.watched() {
+                1
+            } else {
+                (cycles - executed).min(self.remaining())
+            };
+
+            if let Err(stop) = started.and_then(|()| self.execute(batch)) {
+                // Keep it for the next publish
+                if drawn {
+                    self.cpu.frame_buf.request_draw();
+                }
+                return Err(stop);
+            }
+
+            drawn |= self.cpu.frame_buf.handle_draw();
+
+            // Timers follow emulated time, not the wall clock
+            executed += batch;
+            self.cycle += batch;
+            let frame_ended = self.cycle == self.cycles_per_frame;
+            if frame_ended {
+                self.cycle = 0;
+                self.frame += 1;
+                self.frame_started = false;
+                self.cpu.tick();
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.tick();
+                }
+                if let Some((profiler, _)) = &mut self.profiler {
+                    profiler.tick();
+                }
+            }
+            if let Some(server) = &mut self.rpc {
+                server.executed(&self.cpu, self.frame, frame_ended);
+            }
+        }
+
+        Ok(drawn)
+    }
+
+    // Whether a debugger, JSON-RPC server, script, tracer or profiler needs
+    // to see every instruction
+    fn watched(&self) -> bool {
+        self.debugger.is_some()
+            || self.rpc.is_some()
+            || self.script.is_some()
+            || self.tracer.is_some()
+            || self.profiler.is_some()
+    }
+
+    // Executes `cycles` instructions, more than one only while not `watched`
+    fn execute(&mut self, cycles: u64) -> Result<(), Stop> {
+        let cpu = &mut self.cpu;
+
+        // Timers stop too while a client or the debugger has the CPU paused.
+        // The debugger's `monitor` commands drive the cheat engine.
+        if let Some(server) = &mut self.rpc {
+            if !server.poll(cpu, self.frame) {
+                return Err(if server.quit() {
+                    Stop::Finished
+                } else {
+                    Stop::Paused
+                });
+            }
+        }
+        if let Some(debugger) = &mut self.debugger {
+            let cheats = &mut self.cheats;
+            if !debugger.poll_with(cpu, &mut |cpu, line| cheats.command(cpu, line)) {
+                return Err(Stop::Paused);
+            }
+        }
+
+        if let Some((script, path)) = &mut self.script {
+            script_result(script.before(cpu, self.frame), script, path)?;
+        }
+
+        // Halt on faults, the last frame stays on screen. With a debugger or
+        // a JSON-RPC server attached, clients get to inspect the faulting
+        // state.
+        let result = match (&mut self.profiler, &mut self.tracer) {
+            (Some((profiler, _)), Some(tracer)) => {
+                profiler.execute_with(cpu, |cpu| tracer.execute(cpu))
+            }
+            (Some((profiler, _)), None) => profiler.execute(cpu),
+            (None, Some(tracer)) => tracer.execute(cpu),
+            (None, None) => match self.backend {
+                Backend::Interpreter => (0..cycles).try_for_each(|_| cpu.execute()),
+                Backend::Cached => cpu.execute_cached_n(cycles as usize).map(|_| ()),
+                #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+                Backend::Jit => self
+                    .jit
+                    .get_or_insert_with(chip8::Jit::new)
+                    .run(cpu, cycles as usize)
+                    .map(|_| ()),
+            },
+        };
+
+        if let (Ok(()), Some((script, path))) = (&result, &mut self.script) {
+            script_result(script.after(cpu, self.frame), script, path)?;
+        }
+
+        let error = match result {
+            Ok(()) => {
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.executed();
+                }
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        eprintln!("{}", error);
+        if let Some(server) = &mut self.rpc {
+            server.fault(error);
+        }
+        match &mut self.debugger {
+            Some(debugger) => {
+                debugger.fault(error);
+                Err(Stop::Paused)
+            }
+            None if self.rpc.is_some() => Err(Stop::Paused),
+            None => Err(Stop::Halted),
+        }
+    }
+
+    // Waits for the peer's keys up to the current frame, so both sides end
+    // in the same state
+    pub fn sync(&mut self) -> Result<(), String> {
+        let netplay = match &mut self.netplay {
+            Some(netplay) => netplay,
+            None => return Ok(()),
+        };
+        let run_frame = replay_frame(&self.cheats.cheats, self.cycles_per_frame);
+        netplay
+            .session
+            .finish(&mut self.cpu, run_frame, NETPLAY_TIMEOUT)
+    }
+
+    // Writes out the trace, profile and recorded movie
+    pub fn finish(self) {
+        if let Some(tracer) = self.tracer {
+            if let Err(e) = tracer.finish() {
+                eprintln!("Can't write trace: {}", e);
+            }
+        }
+
+        if let Some((profiler, path)) = self.profiler {
+            let report = format!(
+                "{}\n{}",
+                profiler.report(&self.cpu.memory),
+                profiler.annotate(&self.cpu.memory)
+            );
+            if let Err(e) = std::fs::write(&path, report) {
+                eprintln!("Can't write profile {}: {}", path, e);
+            }
+        }
+
+        if let Some(recording) = self.recording {
+            if let Err(e) = std::fs::write(&recording.path, recording.movie.to_string()) {
+                eprintln!("Can't write movie {}: {}", recording.path, e);
+            }
+        }
+    }
+}
+
+// Runs a frame again after netplay rolled back, without the tracer and
+// profiler since they saw it already
+fn replay_frame(
+    cheats: &Cheats,
+    cycles: u64,
+) -> impl FnMut(&mut chip8::CPU) -> Result<(), String> + '_ {
+    move |cpu| {
+        cheats.freeze(cpu);
+        for _ in 0..cycles {
+            cpu.execute().map_err(|e| e.to_string())?;
+        }
+        cpu.tick();
+        Ok(())
+    }
+}
+
+// Script errors halt the emulator, `stop` ends the run
+fn script_result(result: Result<(), String>, script: &Script, path: &str) -> Result<(), Stop> {
+    match result {
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            Err(Stop::Halted)
+        }
+        Ok(()) if script.stopped() => Err(Stop::Finished),
+        Ok(()) => Ok(()),
+    }
+}
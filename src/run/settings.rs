@@ -0,0 +1,318 @@
+use emu_rs::emu::arch::chip8::analysis::{self, Analysis};
+use emu_rs::emu::arch::chip8::{
+    self,
+    config::{Config, Settings},
+    database::Database,
+    loader::Rom,
+};
+use emu_rs::emu::core::ScaleMode;
+use piston_window::Key;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Below this the analyzer's suggestion isn't applied
+const MIN_CONFIDENCE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub foreground: [f32; 4],
+    pub background: [f32; 4],
+}
+
+impl Palette {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (foreground, background) = match text {
+            "white" => ("FFFFFF", "000000"),
+            "amber" => ("FFB000", "000000"),
+            "green" => ("33FF66", "0A1A0A"),
+            "lcd" => ("0F380F", "8BAC0F"),
+            _ => {
+                let mut colors = text.splitn(2, ',');
+                (colors.next()?, colors.next()?)
+            }
+        };
+
+        Some(Self {
+            foreground: color(foreground)?,
+            background: color(background)?,
+        })
+    }
+}
+
+fn color(hex: &str) -> Option<[f32; 4]> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| ((value >> shift) & 0xFF) as f32 / 255.0;
+    Some([channel(16), channel(8), channel(0), 1.0])
+}
+
+// Host key names and keypad keys from a key map file, checked by `Keymap::new`
+pub fn parse_keymap(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut keys = BTreeMap::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[..] {
+            [host, key] => keys.insert(host.to_ascii_lowercase(), key.to_string()),
+            _ => {
+                return Err(format!(
+                    "Invalid key mapping on line {}: {}",
+                    number + 1,
+                    line
+                ))
+            }
+        };
+    }
+
+    Ok(keys)
+}
+
+// Keypad keys 0-F in rows of four on the left of a QWERTZ keyboard
+fn default_keys() -> BTreeMap<String, String> {
+    "1234qwerasdfyxcv"
+        .chars()
+        .enumerate()
+        .map(|(key, host)| (host.to_string(), format!("{:X}", key)))
+        .collect()
+}
+
+pub struct Keymap {
+    pub keys: HashMap<Key, u8>,
+}
+
+impl Keymap {
+    fn new(names: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+
+        for (host, key) in names {
+            let host = host_key(host).ok_or_else(|| format!("Unknown host key '{}'", host))?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| format!("Invalid keypad key '{}'", key))?;
+            keys.insert(host, key);
+        }
+
+        Ok(Self { keys })
+    }
+}
+
+fn host_key(name: &str) -> Option<Key> {
+    let name = name.to_ascii_lowercase();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        // Letters and digits have their ASCII codes as key codes
+        (Some(c), None) if c.is_ascii_alphanumeric() => Some(Key::from(c as u32)),
+        _ => match name.as_str() {
+            "space" => Some(Key::Space),
+            "return" | "enter" => Some(Key::Return),
+            "up" => Some(Key::Up),
+            "down" => Some(Key::Down),
+            "left" => Some(Key::Left),
+            "right" => Some(Key::Right),
+            _ => None,
+        },
+    }
+}
+
+// Settings the config file can hold, once merged and checked
+pub struct Resolved {
+    pub cycles_per_frame: u64,
+    pub quirks: chip8::Quirks,
+    pub epx: bool,
+    pub palette: Palette,
+    pub scale: f64,
+    pub scale_mode: ScaleMode,
+    pub keymap: Keymap,
+    // As the settings name them, for the menu
+    pub quirks_name: String,
+    pub palette_name: String,
+}
+
+fn defaults() -> Settings {
+    Settings {
+        name: None,
+        clock: Some(540),
+        quirks: Some("default".to_string()),
+        scaler: Some("epx".to_string()),
+        palette: Some("white".to_string()),
+        scale: Some(10),
+        scale_mode: Some("integer".to_string()),
+        keys: Some(default_keys()),
+    }
+}
+
+// Checks `settings` on top of the defaults
+pub fn resolve(settings: &Settings) -> Result<Resolved, String> {
+    let mut merged = defaults();
+    merged.merge(settings);
+    let settings = merged;
+
+    let clock = settings.clock.unwrap();
+    if clock < 60 {
+        return Err("The clock has to be at least 60 Hz".to_string());
+    }
+
+    let quirks = settings.quirks.unwrap();
+    let scaler = settings.scaler.unwrap();
+    let palette = settings.palette.unwrap();
+    let scale = settings.scale.unwrap();
+    if scale == 0 {
+        return Err("The scale has to be at least 1".to_string());
+    }
+    let scale_mode = settings.scale_mode.unwrap();
+
+    Ok(Resolved {
+        cycles_per_frame: (clock as u64 + 30) / 60,
+        quirks: chip8::Quirks::parse(&quirks)
+            .ok_or_else(|| format!("Unknown quirks '{}'", quirks))?,
+        epx: match scaler.as_str() {
+            "epx" => true,
+            "none" => false,
+            _ => return Err(format!("Unknown scaler '{}'", scaler)),
+        },
+        palette: Palette::parse(&palette)
+            .ok_or_else(|| format!("Invalid palette '{}'", palette))?,
+        scale: scale as f64,
+        scale_mode: ScaleMode::parse(&scale_mode)
+            .ok_or_else(|| format!("Unknown scale mode '{}'", scale_mode))?,
+        keymap: Keymap::new(&settings.keys.unwrap())?,
+        quirks_name: quirks,
+        palette_name: palette,
+    })
+}
+
+// Checks the settings from one source on top of the defaults, so errors
+// name where the bad value came from
+fn check(settings: &Settings, source: &str) -> Result<(), String> {
+    resolve(settings)
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", source, e))
+}
+
+// Unknown ROMs get the quirks the analyzer suggests, if it's confident enough
+fn detect_quirks(analysis: &Analysis) -> Settings {
+    if analysis.confidence < MIN_CONFIDENCE {
+        return Settings::default();
+    }
+
+    eprintln!(
+        "Detected quirk preset {} ({:.0}% confidence), see `emu_rs info` for why",
+        analysis.preset,
+        analysis.confidence * 100.0
+    );
+    Settings {
+        quirks: Some(analysis.preset.to_string()),
+        ..Settings::default()
+    }
+}
+
+// Where ROMs get their settings from, the one on the command line as well
+// as the ones the menu loads
+pub struct Sources {
+    pub database: Database,
+    pub config_path: Option<PathBuf>,
+    // From the command line
+    pub overrides: Settings,
+}
+
+// A ROM's settings from all sources
+pub struct RomSettings {
+    // Unchecked, what saving the ROM's settings starts from
+    pub settings: Settings,
+    pub resolved: Resolved,
+    // The ROM database's title for it
+    pub title: Option<String>,
+}
+
+impl Sources {
+    // Known ROMs get the quirks, speed and colors they were made for, from
+    // the ROM database and the options of the cartridge they came in. The
+    // command line takes precedence over the ROM's section of the config,
+    // those and the config file's defaults, in that order. `path` names
+    // the ROM in errors.
+    pub fn settings(&self, rom: &Rom, path: &str) -> Result<RomSettings, String> {
+        let program = self.database.get(&rom.program);
+        let mut known = match program {
+            Some(program) => {
+                let settings = program.settings();
+                check(
+                    &settings,
+                    &format!("ROM database entry for {}", program.title),
+                )?;
+                settings
+            }
+            None if rom.cartridge.is_none() => detect_quirks(&analysis::analyze(&rom.program)),
+            None => Settings::default(),
+        };
+        if let Some(cartridge) = &rom.cartridge {
+            let settings = cartridge.options.settings();
+            check(&settings, path)?;
+            known.merge(&settings);
+        }
+
+        // The file is read again for every ROM, it may have changed
+        let mut settings = match &self.config_path {
+            Some(config_path) => {
+                let source = config_path.display().to_string();
+                let config = load_config(config_path).map_err(|e| format!("{}: {}", source, e))?;
+                check(&config.settings(&rom.hash, &Settings::default()), &source)?;
+                config.settings(&rom.hash, &known)
+            }
+            None => known,
+        };
+        settings.merge(&self.overrides);
+
+        Ok(RomSettings {
+            // Every source was checked on its own, which leaves clashes
+            // between them
+            resolved: resolve(&settings)?,
+            settings,
+            title: program.map(|program| program.title.clone()),
+        })
+    }
+}
+
+// A missing file is an empty config
+pub fn load_config(path: &Path) -> Result<Config, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Config::parse(&text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// What the window needs to write the current settings back for this ROM
+pub struct SaveTarget {
+    pub path: PathBuf,
+    pub hash: String,
+    pub settings: Settings,
+}
+
+impl SaveTarget {
+    // Reloads the file first, it may have changed since the start.
+    // `current` holds what changed while running.
+    pub fn save(&self, current: &Settings) -> Result<(), String> {
+        let mut config = load_config(&self.path)?;
+        let mut settings = self.settings.clone();
+        settings.merge(current);
+        config.rom.insert(self.hash.clone(), settings);
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(&self.path, config.to_toml()).map_err(|e| e.to_string())
+    }
+}
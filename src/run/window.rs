@@ -0,0 +1,701 @@
+use super::options::Options;
+use super::runner::{MenuRequest, Runner, Stop};
+use super::settings::{Palette, RomSettings, SaveTarget, Sources};
+use super::{load_picked, state_path};
+#[cfg(feature = "audio")]
+use crate::audio;
+use crate::cli;
+use emu_rs::emu::arch::chip8::config::Settings;
+use emu_rs::emu::arch::chip8::menu::{self, Menu, MenuKey};
+use emu_rs::emu::arch::chip8::{self, overlay, Keyboard};
+use emu_rs::emu::core::{
+    Action, EpxGPU, FrameBuffer, FrameReader, FrameWriter, Pacer, RunControl, ScaleMode, Speed,
+    Viewport, CELL_HEIGHT, CELL_WIDTH, GPU,
+};
+use piston_window::*;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WINDOW_TITLE: &str = "Chip8 Emulator";
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// The debug overlay goes right of the game image
+const OVERLAY_ZOOM: f64 = 2.0;
+const OVERLAY_MARGIN: f64 = 8.0;
+// Around the image when the window's aspect ratio differs
+const LETTERBOX: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+// Behind the menu and notifications, with the border around their text in
+// text pixels
+const MENU_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.85];
+const MENU_BORDER: u32 = 4;
+const NO_STATE_DIR: &str = "No config directory for save states";
+
+// The ROM the window runs, the menu may swap it for another
+pub struct CurrentRom {
+    // None for stdin
+    pub path: Option<PathBuf>,
+    pub hash: String,
+}
+
+// What the CPU thread shares with the window thread
+struct CpuLink {
+    active: Arc<AtomicBool>,
+    // Set while the sound timer runs and the speed is normal
+    sounding: Arc<AtomicBool>,
+    control: Arc<Mutex<RunControl>>,
+    // Snapshots are only captured while the overlay is shown
+    overlay_enabled: Arc<AtomicBool>,
+    snapshot: Arc<Mutex<Option<overlay::Snapshot>>>,
+    // Wakes up the GPU thread
+    frame_tx: Sender<()>,
+    menu_rx: Receiver<MenuRequest>,
+    notification_tx: Sender<Result<Option<String>, String>>,
+}
+
+// How the window shows the game, changed by keys and the menu
+struct View {
+    palette: Palette,
+    scale_mode: ScaleMode,
+    fullscreen: bool,
+    // The overlay takes this much of the window's right side
+    overlay_width: f64,
+}
+
+#[derive(Default)]
+struct Textures {
+    game: Option<G2dTexture>,
+    overlay: Option<G2dTexture>,
+    menu: Option<G2dTexture>,
+    notification: Option<G2dTexture>,
+}
+
+pub fn window(
+    runner: Runner,
+    options: &Options,
+    sources: &Sources,
+    rom: RomSettings,
+    mut current: CurrentRom,
+    keyboard: Arc<Mutex<Keyboard>>,
+) -> bool {
+    let (mut base_title, mut save) = rom_target(sources, &current, &rom);
+    let mut resolved = rom.resolved;
+    let window_size = (64.0 * resolved.scale, 32.0 * resolved.scale);
+    let mut view = View {
+        palette: resolved.palette,
+        scale_mode: resolved.scale_mode,
+        fullscreen: options.fullscreen,
+        overlay_width: (overlay::OVERLAY_COLUMNS * CELL_WIDTH) as f64 * OVERLAY_ZOOM
+            + OVERLAY_MARGIN * 2.0,
+    };
+    let playing = runner.playback.is_some();
+
+    let (cpu_tx, cpu_rx) = channel();
+
+    let (cpu_writer, cpu_reader) = emu_rs::emu::core::swap_chain(64, 32, 0u8);
+    let (epx_writer, mut epx_reader) = emu_rs::emu::core::swap_chain(128, 64, 0u8);
+
+    let mut settings = WindowSettings::new(title(&base_title, &RunControl::default()), window_size);
+    settings.set_vsync(true);
+    settings.set_fullscreen(options.fullscreen);
+    settings.set_resizable(true);
+    let mut window = open_window(settings)
+        .unwrap_or_else(|e| cli::error(&format!("Can't open a window: {}", e)));
+    let mut texture_ctx = window.create_texture_context();
+    let mut textures = Textures::default();
+
+    let gpu = Arc::new(Mutex::new(EpxGPU::new()));
+    gpu.lock().unwrap().enabled = resolved.epx;
+    let cpu_active = Arc::new(AtomicBool::new(true));
+    let gpu_active = Arc::new(AtomicBool::new(true));
+
+    let overlay_enabled = Arc::new(AtomicBool::new(false));
+    let snapshot = Arc::new(Mutex::new(None));
+    let mut overlay_buf = overlay::overlay_buffer();
+
+    let mut clock = (resolved.cycles_per_frame * 60) as u32;
+    let mut menu = Menu::new(
+        clock,
+        &resolved.quirks_name,
+        resolved.epx,
+        &resolved.palette_name,
+    );
+    menu.locked = runner.followed();
+    let mut menu_changed = true;
+    let mut menu_buf = menu::menu_buffer();
+    let mut notification_buf = menu::notification_buffer();
+    // The menu pauses the game, closing it restores this
+    let mut paused_before_menu = false;
+    let (menu_tx, menu_rx) = channel();
+    let (notification_tx, notification_rx) = channel();
+
+    let control = Arc::new(Mutex::new(RunControl::new(options.fast_forward)));
+
+    // Builds without the audio feature have nothing to play it on
+    let sounding = Arc::new(AtomicBool::new(false));
+    #[cfg(feature = "audio")]
+    let _audio = audio::open(sounding.clone())
+        .map_err(|e| eprintln!("No sound: {}", e))
+        .ok();
+
+    let link = CpuLink {
+        active: cpu_active.clone(),
+        sounding,
+        control: control.clone(),
+        overlay_enabled: overlay_enabled.clone(),
+        snapshot: snapshot.clone(),
+        frame_tx: cpu_tx.clone(),
+        menu_rx,
+        notification_tx,
+    };
+    let cpu_thread = thread::spawn(move || run_cpu(runner, link, cpu_writer));
+
+    let local_gpu_active = gpu_active.clone();
+    let local_gpu = gpu.clone();
+    let gpu_thread =
+        thread::spawn(move || run_gpu(local_gpu, local_gpu_active, cpu_rx, cpu_reader, epx_writer));
+
+    while let Some(e) = window.next() {
+        if epx_reader.update() {
+            textures.game = Some(alpha_texture(&mut texture_ctx, epx_reader.frame()));
+        }
+
+        if let Some(snapshot) = snapshot.lock().unwrap().take() {
+            snapshot.render(&mut overlay_buf);
+            textures.overlay = Some(alpha_texture(&mut texture_ctx, &overlay_buf));
+        }
+
+        while let Ok(result) = notification_rx.try_recv() {
+            if let Ok(Some(text)) | Err(text) = result {
+                menu.notify(text);
+                menu_changed = true;
+            }
+        }
+
+        if let Event::Input(Input::Button(args), _) = &e {
+            // The open menu takes key presses, releases still reach the
+            // keypad so no key stays held
+            let menu_was_open = menu.open;
+            let (taken, command) = match (args.button, args.state) {
+                (Button::Keyboard(Key::Escape), ButtonState::Press) => {
+                    menu.toggle();
+                    (true, None)
+                }
+                (Button::Keyboard(key), ButtonState::Press) if menu.open => {
+                    (true, menu_key(key).and_then(|key| menu.key(key)))
+                }
+                _ => (false, None),
+            };
+            menu_changed |= taken;
+
+            match command {
+                Some(menu::Command::LoadRom(path)) => {
+                    let loaded = load_picked(&path).and_then(|rom| {
+                        let settings = sources.settings(&rom, &path.display().to_string())?;
+                        Ok((rom, settings))
+                    });
+                    match loaded {
+                        Ok((rom, settings)) => {
+                            let name = path
+                                .file_name()
+                                .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+                            let _ = menu_tx.send(MenuRequest::LoadRom {
+                                program: rom.program,
+                                hash: rom.hash.clone(),
+                                name,
+                                quirks: settings.resolved.quirks,
+                                cycles_per_frame: settings.resolved.cycles_per_frame,
+                            });
+                            current = CurrentRom {
+                                path: Some(path),
+                                hash: rom.hash,
+                            };
+                            let (title, target) = rom_target(sources, &current, &settings);
+                            base_title = title;
+                            save = target;
+
+                            resolved = settings.resolved;
+                            clock = (resolved.cycles_per_frame * 60) as u32;
+                            menu.set_settings(
+                                clock,
+                                &resolved.quirks_name,
+                                resolved.epx,
+                                &resolved.palette_name,
+                            );
+                            view.palette = resolved.palette;
+                            view.scale_mode = resolved.scale_mode;
+                            gpu.lock().unwrap().enabled = resolved.epx;
+                        }
+                        Err(e) => menu.notify(e),
+                    }
+                }
+                Some(menu::Command::Reset) => {
+                    let _ = menu_tx.send(MenuRequest::Reset);
+                }
+                Some(menu::Command::SetClock(hz)) => {
+                    let _ = menu_tx.send(MenuRequest::Clock((hz as u64 + 30) / 60));
+                }
+                Some(menu::Command::SetQuirks(name)) => {
+                    let quirks = chip8::Quirks::parse(name).unwrap();
+                    let _ = menu_tx.send(MenuRequest::Quirks(quirks));
+                }
+                Some(menu::Command::SetScaler(epx)) => {
+                    gpu.lock().unwrap().enabled = epx;
+                    let _ = cpu_tx.send(());
+                }
+                Some(menu::Command::SetPalette(name)) => {
+                    view.palette = Palette::parse(name).unwrap()
+                }
+                Some(menu::Command::SaveState(slot)) => match state_path(&current.hash, slot) {
+                    Some(path) => {
+                        let _ = menu_tx.send(MenuRequest::SaveState(path, slot));
+                    }
+                    None => menu.notify(NO_STATE_DIR),
+                },
+                Some(menu::Command::LoadState(slot)) => match state_path(&current.hash, slot) {
+                    Some(path) => {
+                        let _ = menu_tx.send(MenuRequest::LoadState(path, slot));
+                    }
+                    None => menu.notify(NO_STATE_DIR),
+                },
+                None => {}
+            }
+
+            // The game waits while the menu is open
+            if menu.open != menu_was_open {
+                let mut control = control.lock().unwrap();
+                if menu.open {
+                    paused_before_menu = control.paused;
+                    control.paused = true;
+                    let dir = current
+                        .path
+                        .as_deref()
+                        .and_then(Path::parent)
+                        .filter(|dir| !dir.as_os_str().is_empty())
+                        .unwrap_or_else(|| Path::new("."));
+                    let roms = menu::list_roms(dir).unwrap_or_default();
+                    menu.set_roms(roms, current.path.as_deref());
+                } else {
+                    control.paused = paused_before_menu;
+                }
+            }
+            if taken {
+                window.set_title(title(&base_title, &control.lock().unwrap()));
+            }
+
+            let pressed = args.state == ButtonState::Press;
+            let res = match args.button {
+                _ if taken => None,
+                Button::Keyboard(val) => match (resolved.keymap.keys.get(&val), val) {
+                    (Some(&key), _) => Some(key),
+                    (None, Key::G) if pressed => {
+                        let mut g = gpu.lock().unwrap();
+                        g.enabled = !g.enabled;
+                        menu.epx = g.enabled;
+                        menu_changed = true;
+                        // Redoes the frame while paused
+                        let _ = cpu_tx.send(());
+                        None
+                    }
+                    (None, Key::P) | (None, Key::N) | (None, Key::B) | (None, Key::U)
+                        if pressed =>
+                    {
+                        let mut control = control.lock().unwrap();
+                        match val {
+                            Key::P => control.toggle_pause(),
+                            Key::N => control.advance_frame(),
+                            Key::B => control.step(),
+                            _ => control.uncapped = !control.uncapped,
+                        }
+                        window.set_title(title(&base_title, &control));
+                        None
+                    }
+                    (None, Key::F11) if pressed => {
+                        view.fullscreen = !view.fullscreen;
+                        let glutin = window.window.ctx.window();
+                        glutin.set_fullscreen(if view.fullscreen {
+                            Some(glutin.get_current_monitor())
+                        } else {
+                            None
+                        });
+                        None
+                    }
+                    (None, Key::M) if pressed => {
+                        view.scale_mode = match view.scale_mode {
+                            ScaleMode::Integer => ScaleMode::Fit,
+                            ScaleMode::Fit => ScaleMode::Integer,
+                        };
+                        None
+                    }
+                    (None, Key::F5) if pressed => {
+                        if save_settings(save.as_ref(), &menu, clock, view.scale_mode) {
+                            menu.notify("Settings saved");
+                            menu_changed = true;
+                        }
+                        None
+                    }
+                    (None, Key::Tab) => {
+                        let mut control = control.lock().unwrap();
+                        control.fast_forward = pressed;
+                        window.set_title(title(&base_title, &control));
+                        None
+                    }
+                    (None, Key::O) if pressed => {
+                        let enabled = !overlay_enabled.load(Ordering::Relaxed);
+                        overlay_enabled.store(enabled, Ordering::Relaxed);
+                        // Fullscreen windows make room for it instead
+                        if !view.fullscreen {
+                            let size = window.size();
+                            let width = if enabled {
+                                size.width + view.overlay_width
+                            } else {
+                                (size.width - view.overlay_width).max(1.0)
+                            };
+                            window.set_size((width, size.height));
+                        }
+                        None
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            match (res, args.state, playing) {
+                (Some(idx), ButtonState::Press, false) => keyboard.lock().unwrap().press_key(idx),
+                (Some(idx), ButtonState::Release, false) => {
+                    keyboard.lock().unwrap().release_key(idx)
+                }
+                _ => {}
+            }
+        }
+
+        if menu_changed {
+            menu.render(&mut menu_buf);
+            textures.menu = Some(alpha_texture(&mut texture_ctx, &menu_buf));
+            menu.render_notification(&mut notification_buf);
+            textures.notification = Some(alpha_texture(&mut texture_ctx, &notification_buf));
+            menu_changed = false;
+        }
+
+        let overlay_shown = overlay_enabled.load(Ordering::Relaxed);
+        window.draw_2d(&e, |c, g, _| {
+            draw(c, g, &view, &textures, overlay_shown, &menu)
+        });
+    }
+
+    cpu_active.store(false, Ordering::Relaxed);
+    gpu_active.store(false, Ordering::Relaxed);
+
+    // Send signal to unblock gpu thread
+    let _ = cpu_tx.send(());
+
+    gpu_thread.join().unwrap();
+    cpu_thread.join().unwrap()
+}
+
+// The CPU thread, returns whether the CPU halted
+fn run_cpu(mut runner: Runner, link: CpuLink, mut writer: FrameWriter<u8>) -> bool {
+    let capture = |cpu: &chip8::CPU| {
+        if link.overlay_enabled.load(Ordering::Relaxed) {
+            *link.snapshot.lock().unwrap() = Some(overlay::Snapshot::capture(cpu));
+        }
+    };
+
+    let mut pacer = Pacer::new(60); // Hz
+    let mut last_publish = Instant::now();
+    let mut halted = false;
+
+    while link.active.load(Ordering::Relaxed) {
+        while let Ok(request) = link.menu_rx.try_recv() {
+            let _ = link.notification_tx.send(runner.menu(request));
+            // Shows resets and loaded states while paused too
+            if runner.cpu.frame_buf.handle_draw() {
+                writer.publish(&runner.cpu.frame_buf);
+                let _ = link.frame_tx.send(());
+            }
+        }
+
+        let (action, sound) = {
+            let mut control = link.control.lock().unwrap();
+            let action = control.next_action();
+            (action, control.buzzer(runner.cpu.timers[chip8::SOUND]))
+        };
+        link.sounding.store(sound, Ordering::Relaxed);
+        let cycles = match action {
+            Action::Wait => {
+                thread::sleep(PAUSE_POLL_INTERVAL);
+                pacer.reset();
+                continue;
+            }
+            Action::Step => 1,
+            Action::Frame(_) => runner.remaining(),
+        };
+
+        let drawn = match runner.run(cycles) {
+            Ok(drawn) => drawn,
+            Err(Stop::Paused) => {
+                // Shows the state the debugger stopped in
+                capture(&runner.cpu);
+                pacer.reset();
+                continue;
+            }
+            Err(Stop::Halted) => {
+                halted = true;
+                break;
+            }
+            Err(Stop::Finished) => break,
+        };
+        capture(&runner.cpu);
+
+        // Faster than real time there's no point in showing every frame
+        let speed = match action {
+            Action::Frame(speed) => speed,
+            _ => Speed::Normal,
+        };
+        if drawn && (speed == Speed::Normal || last_publish.elapsed() >= pacer.frame()) {
+            writer.publish(&runner.cpu.frame_buf);
+            let _ = link.frame_tx.send(());
+            last_publish = Instant::now();
+        } else if drawn {
+            // Keep it for the next publish
+            runner.cpu.frame_buf.request_draw();
+        }
+
+        if let Action::Frame(speed) = action {
+            pacer.wait(speed);
+        }
+    }
+
+    link.sounding.store(false, Ordering::Relaxed);
+    runner.finish();
+    halted
+}
+
+// The GPU thread scales every frame the CPU thread publishes
+fn run_gpu(
+    gpu: Arc<Mutex<EpxGPU>>,
+    active: Arc<AtomicBool>,
+    frames: Receiver<()>,
+    mut reader: FrameReader<u8>,
+    mut writer: FrameWriter<u8>,
+) {
+    let mut epx_buf = FrameBuffer::new(128, 64, 0u8);
+    let mut epx = gpu.lock().unwrap().enabled;
+
+    while active.load(Ordering::Relaxed) {
+        if let Ok(()) = frames.recv() {
+            // Toggling the scaler redoes the last frame, the game may be
+            // paused
+            let enabled = gpu.lock().unwrap().enabled;
+            if !reader.update() && enabled == epx {
+                continue;
+            }
+            epx = enabled;
+
+            // The scaler output is twice the CHIP-8 resolution, which may
+            // change between frames
+            let screen = reader.frame();
+            if epx_buf.width() != screen.width() * 2 || epx_buf.height() != screen.height() * 2 {
+                epx_buf = FrameBuffer::new(screen.width() * 2, screen.height() * 2, 0u8);
+            }
+
+            gpu.lock().unwrap().process(reader.frame(), &mut epx_buf);
+
+            if epx_buf.handle_draw() {
+                writer.publish(&epx_buf);
+            }
+        }
+    }
+}
+
+// The window title for a ROM and where its settings are saved
+fn rom_target(
+    sources: &Sources,
+    current: &CurrentRom,
+    rom: &RomSettings,
+) -> (String, Option<SaveTarget>) {
+    let name = current
+        .path
+        .as_deref()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned());
+    let title = match rom.title.as_ref().or(name.as_ref()) {
+        Some(title) => format!("{} - {}", WINDOW_TITLE, title),
+        None => WINDOW_TITLE.to_string(),
+    };
+
+    let save = sources.config_path.clone().map(|path| {
+        let mut settings = rom.settings.clone();
+        settings.name = name;
+        SaveTarget {
+            path,
+            hash: current.hash.clone(),
+            settings,
+        }
+    });
+    (title, save)
+}
+
+// Writes what the menu and keys changed into the ROM's section of the
+// config, returns whether it worked
+fn save_settings(
+    save: Option<&SaveTarget>,
+    menu: &Menu,
+    clock: u32,
+    scale_mode: ScaleMode,
+) -> bool {
+    let save = match save {
+        Some(save) => save,
+        None => {
+            eprintln!("No config file to save settings to");
+            return false;
+        }
+    };
+
+    // Only the clock the menu changed, the config's may not be a multiple
+    // of 60
+    let changed = Settings {
+        clock: Some(menu.clock).filter(|&hz| hz != clock),
+        quirks: menu.quirks.map(|i| menu::QUIRK_PRESETS[i].to_string()),
+        scaler: Some(if menu.epx { "epx" } else { "none" }.to_string()),
+        palette: menu.palette.map(|i| menu::PALETTES[i].to_string()),
+        scale_mode: Some(scale_mode.name().to_string()),
+        ..Settings::default()
+    };
+    match save.save(&changed) {
+        Ok(()) => {
+            eprintln!("Saved settings to {}", save.path.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Can't save settings to {}: {}", save.path.display(), e);
+            false
+        }
+    }
+}
+
+fn draw(
+    c: Context,
+    g: &mut G2d,
+    view: &View,
+    textures: &Textures,
+    overlay_shown: bool,
+    menu: &Menu,
+) {
+    clear(LETTERBOX, g);
+    // Recomputed every frame, the window or the image may have changed size
+    // since the last one
+    let [width, height] = c.get_view_size();
+    let game_width = if overlay_shown {
+        (width - view.overlay_width).max(0.0)
+    } else {
+        width
+    };
+
+    if let Some(tex) = &textures.game {
+        let game = Viewport::new(tex.get_size(), (game_width, height), view.scale_mode);
+        rectangle(
+            view.palette.background,
+            [game.x, game.y, game.width, game.height],
+            c.transform,
+            g,
+        );
+        Image::new_color(view.palette.foreground).draw(
+            tex,
+            &c.draw_state,
+            c.transform.trans(game.x, game.y).zoom(game.scale),
+            g,
+        );
+    }
+    if let (true, Some(tex)) = (overlay_shown, &textures.overlay) {
+        let transform = c
+            .transform
+            .trans(game_width + OVERLAY_MARGIN, OVERLAY_MARGIN)
+            .zoom(OVERLAY_ZOOM);
+        image(tex, transform, g);
+    }
+
+    // The menu as large as whole pixels allow over the game image
+    if let (true, Some(tex)) = (menu.open, &textures.menu) {
+        let (w, h) = tex.get_size();
+        let size = (w + MENU_BORDER * 2, h + MENU_BORDER * 2);
+        let area = Viewport::new(size, (game_width, height), ScaleMode::Integer);
+        rectangle(
+            MENU_BACKGROUND,
+            [area.x, area.y, area.width, area.height],
+            c.transform,
+            g,
+        );
+        let border = MENU_BORDER as f64 * area.scale;
+        let transform = c
+            .transform
+            .trans(area.x + border, area.y + border)
+            .zoom(area.scale);
+        image(tex, transform, g);
+    }
+
+    // Notifications go into the bottom left corner
+    if let (Some(text), Some(tex)) = (menu.notification(), &textures.notification) {
+        let columns = text
+            .chars()
+            .count()
+            .min(menu::NOTIFICATION_COLUMNS as usize);
+        let size = (
+            (columns as u32 * CELL_WIDTH + MENU_BORDER * 2) as f64 * OVERLAY_ZOOM,
+            (CELL_HEIGHT + MENU_BORDER * 2) as f64 * OVERLAY_ZOOM,
+        );
+        let (x, y) = (OVERLAY_MARGIN, height - OVERLAY_MARGIN - size.1);
+        rectangle(MENU_BACKGROUND, [x, y, size.0, size.1], c.transform, g);
+        let border = MENU_BORDER as f64 * OVERLAY_ZOOM;
+        let transform = c.transform.trans(x + border, y + border).zoom(OVERLAY_ZOOM);
+        image(tex, transform, g);
+    }
+}
+
+// Keys the open menu handles
+fn menu_key(key: Key) -> Option<MenuKey> {
+    match key {
+        Key::Up => Some(MenuKey::Up),
+        Key::Down => Some(MenuKey::Down),
+        Key::Left => Some(MenuKey::Left),
+        Key::Right => Some(MenuKey::Right),
+        Key::Return => Some(MenuKey::Enter),
+        _ => None,
+    }
+}
+
+// Nearest filtered, so game pixels and the built in font stay sharp
+fn alpha_texture(context: &mut G2dTextureContext, buf: &FrameBuffer<u8>) -> G2dTexture {
+    let settings = TextureSettings::new().filter(Filter::Nearest);
+    Texture::from_memory_alpha(context, buf.frame(), buf.width(), buf.height(), &settings).unwrap()
+}
+
+// winit panics instead of failing when there's no display to connect to
+fn open_window(settings: WindowSettings) -> Result<PistonWindow, String> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let window = panic::catch_unwind(AssertUnwindSafe(|| settings.build::<PistonWindow>()));
+    panic::set_hook(hook);
+
+    match window {
+        Ok(window) => window.map_err(|e| e.to_string()),
+        Err(panic) => Err(match panic.downcast::<String>() {
+            Ok(message) => *message,
+            Err(panic) => panic
+                .downcast_ref::<&str>()
+                .map_or("unknown error".to_string(), |message| message.to_string()),
+        }),
+    }
+}
+
+fn title(base: &str, control: &RunControl) -> String {
+    match control.status() {
+        status if status.is_empty() => base.to_string(),
+        status => format!("{} [{}]", base, status),
+    }
+}
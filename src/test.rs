@@ -1,213 +1,238 @@
-use super::*;
+#![allow(non_snake_case)]
+
+use super::emu::arch::chip8;
+use super::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+fn create_cpu() -> chip8::CPU {
+    chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    )
+}
+
+fn create_cpu_with(quirks: chip8::Quirks) -> chip8::CPU {
+    let mut cpu = create_cpu();
+    cpu.quirks = quirks;
+    cpu
+}
 
 #[test]
 fn test_opcode_00E0() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
+    let mut cpu = create_cpu();
 
-    for y in 0..chip8::GFX_BUF_HEIGHT {
-        for x in 0..chip8::GFX_BUF_WIDTH {
-            gpu.borrow_mut().write(x, y, 255);
+    for y in 0..cpu.frame_buf.height() {
+        for x in 0..cpu.frame_buf.width() {
+            cpu.frame_buf.write(x, y, 255);
         }
     }
 
-    let mut cpu = chip8::CPU::new(&gpu);
-    cpu.load_program(&[0x00, 0xE0]);
-    cpu.execute();
+    cpu.load_program(&[0x00, 0xE0]).unwrap();
+    cpu.execute().unwrap();
 
-    for y in 0..chip8::GFX_BUF_HEIGHT {
-        for x in 0..chip8::GFX_BUF_WIDTH {
-            assert_eq!(gpu.borrow().read(x, y), 0);
+    for y in 0..cpu.frame_buf.height() {
+        for x in 0..cpu.frame_buf.width() {
+            assert_eq!(cpu.frame_buf.read(x, y), 0);
         }
     }
+    assert!(cpu.frame_buf.handle_draw());
+    assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 }
 
 #[test]
 fn test_opcode_00EE() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     // Store address on stack
-    cpu.stack[cpu.sp as usize] = 0xFFFF;
+    cpu.stack[cpu.sp as usize] = 0xFFF;
     cpu.sp += 1;
 
-    cpu.load_program(&[0x00, 0xEE]);
-    cpu.execute();
+    cpu.load_program(&[0x00, 0xEE]).unwrap();
+    cpu.execute().unwrap();
 
-    assert_eq!(cpu.pc, 0xFFFF);
+    assert_eq!(cpu.pc, 0xFFF);
     assert_eq!(cpu.sp, 0);
 }
 
 #[test]
 fn test_opcode_1NNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x1F, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0x1F, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, 0x0FFF);
 }
 
 #[test]
 fn test_opcode_2NNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x2F, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0x2F, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.sp, 1);
     assert_eq!(cpu.stack[0], chip8::PROGRAM_ENTRY + 2);
     assert_eq!(cpu.pc, 0x0FFF);
 }
 
+#[test]
+fn test_opcode_2NNN_00EE_roundtrip() {
+    let mut cpu = create_cpu();
+
+    // 0x200: call 0x206, 0x202: V0 = 1, 0x206: return
+    cpu.load_program(&[0x22, 0x06, 0x60, 0x01, 0x00, 0x00, 0x00, 0xEE])
+        .unwrap();
+    cpu.execute().unwrap();
+    cpu.execute().unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
+    assert_eq!(cpu.regs[chip8::V0], 1);
+}
+
 #[test]
 fn test_opcode_3XNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x30, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0x30, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 
-    cpu.pc = 0x200;
+    cpu.pc = chip8::PROGRAM_ENTRY;
     cpu.regs[chip8::V0] = 0xFF;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 }
 
 #[test]
 fn test_opcode_4XNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x40, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0x40, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 
-    cpu.pc = 0x200;
+    cpu.pc = chip8::PROGRAM_ENTRY;
     cpu.regs[chip8::V0] = 0xFF;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 }
 
 #[test]
 fn test_opcode_5XY0() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x50, 0x10]);
-    cpu.execute();
+    cpu.load_program(&[0x50, 0x10]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 
     cpu.regs[chip8::V0] = 0xFF;
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 }
 
 #[test]
 fn test_opcode_6XNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0x60, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0x60, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0xFF);
 }
 
 #[test]
 fn test_opcode_7XNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 10;
-    cpu.load_program(&[0x70, 20]);
-    cpu.execute();
+    cpu.load_program(&[0x70, 20]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 10 + 20);
+
+    // Wraps around without touching VF
+    cpu.regs[chip8::V0] = 0xFF;
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.regs[chip8::V0], 19);
+    assert_eq!(cpu.regs[chip8::VF], 0);
 }
 
 #[test]
 fn test_opcode_8XY0() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V1] = 0xFF;
-    cpu.load_program(&[0x80, 0x10]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x10]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0xFF);
 }
 
 #[test]
 fn test_opcode_8XY1() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0b01010101;
     cpu.regs[chip8::V1] = 0b10101010;
-
-    cpu.load_program(&[0x80, 0x11]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x11]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0xFF);
 }
 
 #[test]
 fn test_opcode_8XY2() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0b01010101;
     cpu.regs[chip8::V1] = 0b00000001;
-
-    cpu.load_program(&[0x80, 0x12]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x12]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 1);
 }
 
 #[test]
 fn test_opcode_8XY3() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0b01010101;
     cpu.regs[chip8::V1] = 0b10101010;
-
-    cpu.load_program(&[0x80, 0x13]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x13]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0xFF);
 }
 
 #[test]
 fn test_opcode_8XY4() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 10;
     cpu.regs[chip8::V1] = 20;
-
-    cpu.load_program(&[0x80, 0x14]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x14]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 10 + 20);
     assert_eq!(cpu.regs[chip8::VF], 0);
 
     cpu.regs[chip8::V0] = 255;
     cpu.regs[chip8::V1] = 1;
-
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0);
     assert_eq!(cpu.regs[chip8::VF], 1);
@@ -215,37 +240,33 @@ fn test_opcode_8XY4() {
 
 #[test]
 fn test_opcode_8XY5() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 20;
     cpu.regs[chip8::V1] = 10;
+    cpu.load_program(&[0x80, 0x15]).unwrap();
+    cpu.execute().unwrap();
 
-    cpu.load_program(&[0x80, 0x15]);
-    cpu.execute();
-
+    // VF is set when there is no borrow
     assert_eq!(cpu.regs[chip8::V0], 20 - 10);
-    assert_eq!(cpu.regs[chip8::VF], 0);
+    assert_eq!(cpu.regs[chip8::VF], 1);
 
     cpu.regs[chip8::V0] = 0;
     cpu.regs[chip8::V1] = 1;
-
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 255);
-    assert_eq!(cpu.regs[chip8::VF], 1);
+    assert_eq!(cpu.regs[chip8::VF], 0);
 }
 
 #[test]
 fn test_opcode_8XY6() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0b00000001;
-
-    cpu.load_program(&[0x80, 0x16]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x16]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0);
     assert_eq!(cpu.regs[chip8::VF], 1);
@@ -253,173 +274,198 @@ fn test_opcode_8XY6() {
 
 #[test]
 fn test_opcode_8XY7() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 5;
     cpu.regs[chip8::V1] = 10;
-
-    cpu.load_program(&[0x80, 0x17]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x17]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 5);
+    assert_eq!(cpu.regs[chip8::VF], 1);
+
     cpu.regs[chip8::V0] = 11;
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 255);
-    assert_eq!(cpu.regs[chip8::VF], 1);
+    assert_eq!(cpu.regs[chip8::VF], 0);
 }
 
 #[test]
 fn test_opcode_8XYE() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0b10000000;
-
-    cpu.load_program(&[0x80, 0x1E]);
-    cpu.execute();
+    cpu.load_program(&[0x80, 0x1E]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 0);
     assert_eq!(cpu.regs[chip8::VF], 1);
 }
 
+#[test]
+fn test_opcode_8XYF_flag_register() {
+    let mut cpu = create_cpu();
+
+    // The flag wins when VF is the destination
+    cpu.regs[chip8::VF] = 0b10000001;
+    cpu.load_program(&[0x8F, 0xF6]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.regs[chip8::VF], 1);
+}
+
 #[test]
 fn test_opcode_9XY0() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0;
     cpu.regs[chip8::V1] = 0;
-
-    cpu.load_program(&[0x90, 0x10]);
-    cpu.execute();
+    cpu.load_program(&[0x90, 0x10]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
+
     cpu.regs[chip8::V1] = 1;
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 }
 
 #[test]
 fn test_opcode_ANNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0xAF, 0xFF]);
-    cpu.execute();
+    cpu.load_program(&[0xAF, 0xFF]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.i, 0xFFF);
 }
 
 #[test]
 fn test_opcode_BNNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0xF;
-    cpu.load_program(&[0xBF, 0xF0]);
-    cpu.execute();
+    cpu.load_program(&[0xBF, 0xF0]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, 0xFFF);
 }
 
 #[test]
-fn test_opcode_CNNN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+fn test_opcode_CXNN() {
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0xC0, 0x10]);
-    cpu.execute();
+    cpu.load_program(&[0xC0, 0x10]).unwrap();
 
-    assert!(cpu.regs[chip8::V0] <= 0x10);
+    for _ in 0..100 {
+        cpu.pc = chip8::PROGRAM_ENTRY;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.regs[chip8::V0] & !0x10, 0);
+    }
 }
 
 #[test]
 fn test_opcode_DXYN() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0;
     cpu.regs[chip8::V1] = 0;
     cpu.i = 0xFFF;
     cpu.memory[cpu.i as usize] = 0b11000011;
-    cpu.load_program(&[0xD0, 0x11]);
-    cpu.execute();
+    cpu.load_program(&[0xD0, 0x11]).unwrap();
+    cpu.execute().unwrap();
+
+    let expected = [255, 255, 0, 0, 0, 0, 255, 255];
+    for (x, pixel) in expected.iter().enumerate() {
+        assert_eq!(cpu.frame_buf.read(x as u32, 0), *pixel);
+    }
+    assert!(cpu.frame_buf.handle_draw());
+    assert_eq!(cpu.regs[chip8::VF], 0);
+
+    // Drawing the same sprite again erases it
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    cpu.execute().unwrap();
+
+    for x in 0..8 {
+        assert_eq!(cpu.frame_buf.read(x, 0), 0);
+    }
+    assert_eq!(cpu.regs[chip8::VF], 1);
+}
+
+#[test]
+fn test_opcode_DXYN_start_position_wraps() {
+    let mut cpu = create_cpu();
+
+    cpu.regs[chip8::V0] = 64 + 1;
+    cpu.regs[chip8::V1] = 32 + 2;
+    cpu.i = 0xFFF;
+    cpu.memory[cpu.i as usize] = 0b10000000;
+    cpu.load_program(&[0xD0, 0x11]).unwrap();
+    cpu.execute().unwrap();
 
-    assert_eq!(gpu.borrow().read(0, 0), 255);
-    assert_eq!(gpu.borrow().read(1, 0), 255);
-    assert_eq!(gpu.borrow().read(2, 0), 0);
-    assert_eq!(gpu.borrow().read(3, 0), 0);
-    assert_eq!(gpu.borrow().read(4, 0), 0);
-    assert_eq!(gpu.borrow().read(5, 0), 0);
-    assert_eq!(gpu.borrow().read(6, 0), 255);
-    assert_eq!(gpu.borrow().read(7, 0), 255);
+    assert_eq!(cpu.frame_buf.read(1, 2), 255);
 }
 
 #[test]
 fn test_opcode_EX9E() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0xE0, 0x9E]);
-    cpu.execute();
+    cpu.load_program(&[0xE0, 0x9E]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 
-    cpu.keyboard[0] = true;
+    cpu.keyboard.lock().unwrap().press_key(0);
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 }
 
 #[test]
 fn test_opcode_EXA1() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0xE0, 0xA1]);
-    cpu.execute();
+    cpu.load_program(&[0xE0, 0xA1]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 4);
 
-    cpu.keyboard[0] = true;
+    cpu.keyboard.lock().unwrap().press_key(0);
     cpu.pc = chip8::PROGRAM_ENTRY;
-    cpu.execute();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
 }
 
 #[test]
 fn test_opcode_FX07() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.timers[chip8::DELAY] = 10;
-    cpu.load_program(&[0xF0, 0x07]);
-    cpu.execute();
+    cpu.load_program(&[0xF0, 0x07]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 10);
 }
 
 #[test]
 fn test_opcode_FX0A() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
-    cpu.load_program(&[0xF0, 0x0A]);
+    cpu.load_program(&[0xF0, 0x0A]).unwrap();
 
-    for i in 0..100 {
-        cpu.execute();
+    for _ in 0..100 {
+        cpu.execute().unwrap();
         assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY);
     }
 
-    cpu.press_key(1);
-    cpu.execute();
+    cpu.keyboard.lock().unwrap().press_key(1);
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.regs[chip8::V0], 1);
     assert_eq!(cpu.pc, chip8::PROGRAM_ENTRY + 2);
@@ -427,80 +473,445 @@ fn test_opcode_FX0A() {
 
 #[test]
 fn test_opcode_FX15() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0xFF;
-    cpu.load_program(&[0xF0, 0x15]);
-    cpu.execute();
+    cpu.load_program(&[0xF0, 0x15]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.timers[chip8::DELAY], 0xFF);
 }
 
 #[test]
 fn test_opcode_FX18() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0xFF;
-    cpu.load_program(&[0xF0, 0x18]);
-    cpu.execute();
+    cpu.load_program(&[0xF0, 0x18]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.timers[chip8::SOUND], 0xFF);
 }
 
+#[test]
+fn test_opcode_FX1E() {
+    let mut cpu = create_cpu();
+
+    cpu.i = 0x100;
+    cpu.regs[chip8::V0] = 0x10;
+    cpu.load_program(&[0xF0, 0x1E]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.i, 0x110);
+}
+
 #[test]
 fn test_opcode_FX29() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     cpu.regs[chip8::V0] = 0xF;
-    cpu.load_program(&[0xF0, 0x29]);
-    cpu.execute();
+    cpu.load_program(&[0xF0, 0x29]).unwrap();
+    cpu.execute().unwrap();
 
     assert_eq!(cpu.i, 0xF * 5);
+    assert_eq!(
+        cpu.memory[cpu.i as usize..cpu.i as usize + 5],
+        chip8::FONT_CHARMAP[75..80]
+    );
 }
 
 #[test]
 fn test_opcode_FX33() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
-
-    // TODO
-    assert!(false);
+    let mut cpu = create_cpu();
+
+    cpu.regs[chip8::V0] = 123;
+    cpu.i = 0x300;
+    cpu.load_program(&[0xF0, 0x33]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.memory[0x300..0x303], [1, 2, 3]);
+
+    for (val, digits) in [
+        (0, [0, 0, 0]),
+        (105, [1, 0, 5]),
+        (120, [1, 2, 0]),
+        (200, [2, 0, 0]),
+    ]
+    .iter()
+    {
+        cpu.regs[chip8::V0] = *val;
+        cpu.pc = chip8::PROGRAM_ENTRY;
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.memory[0x300..0x303], *digits);
+    }
 }
 
 #[test]
 fn test_opcode_FX55() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     for i in 0..(chip8::VF + 1) {
         cpu.regs[i] = i as u8;
     }
 
-    cpu.load_program(&[0xFF, 0x55]);
-    cpu.execute();
+    cpu.i = 0x300;
+    cpu.load_program(&[0xFF, 0x55]).unwrap();
+    cpu.execute().unwrap();
 
     for i in 0..(chip8::VF + 1) {
-        assert_eq!(cpu.memory[i], i as u8);
+        assert_eq!(cpu.memory[0x300 + i], i as u8);
     }
+    assert_eq!(cpu.i, 0x300);
 }
 
 #[test]
 fn test_opcode_FX65() {
-    let gpu: chip8::GPUref = RefCell::new(Box::new(chip8::ArrayGPU::new()));
-    let mut cpu = chip8::CPU::new(&gpu);
+    let mut cpu = create_cpu();
 
     for i in 0..(chip8::VF + 1) {
         cpu.regs[i] = i as u8;
     }
 
     cpu.i = 0xFFF;
-    cpu.load_program(&[0xFF, 0x65]);
-    cpu.execute();
+    cpu.load_program(&[0xFF, 0x65]).unwrap();
+    cpu.execute().unwrap();
 
     for i in 0..(chip8::VF + 1) {
         assert_eq!(cpu.regs[i], 0);
     }
+    assert_eq!(cpu.i, 0xFFF);
+}
+
+#[test]
+fn test_timers() {
+    let mut cpu = create_cpu();
+
+    cpu.timers[chip8::DELAY] = 2;
+    cpu.timers[chip8::SOUND] = 1;
+    cpu.tick();
+
+    assert_eq!(cpu.timers, [0, 1]);
+
+    cpu.tick();
+    cpu.tick();
+
+    assert_eq!(cpu.timers, [0, 0]);
+}
+
+#[test]
+fn test_quirk_presets() {
+    for name in chip8::QUIRK_PRESETS.iter() {
+        assert!(chip8::Quirks::preset(name).is_some());
+    }
+    assert_eq!(
+        chip8::Quirks::preset("default"),
+        Some(chip8::Quirks::default())
+    );
+    assert_eq!(chip8::Quirks::preset("unknown"), None);
+}
+
+#[test]
+fn test_quirk_lists() {
+    let quirks = chip8::Quirks::parse("vf_reset, wrap_sprites").unwrap();
+    assert_eq!(
+        quirks,
+        chip8::Quirks {
+            vf_reset: true,
+            wrap_sprites: true,
+            ..chip8::Quirks::default()
+        }
+    );
+    assert_eq!(quirks.to_string(), "vf_reset,wrap_sprites");
+
+    for name in chip8::QUIRK_PRESETS.iter() {
+        let quirks = chip8::Quirks::parse(name).unwrap();
+        assert_eq!(quirks.to_string(), *name);
+    }
+    for name in chip8::QUIRK_NAMES.iter() {
+        let quirks = chip8::Quirks::parse(name).unwrap();
+        assert_eq!(chip8::Quirks::parse(&quirks.to_string()), Some(quirks));
+    }
+    assert_eq!(chip8::Quirks::parse("vf_reset,unknown"), None);
+    assert_eq!(chip8::Quirks::parse(""), None);
+}
+
+#[test]
+fn test_quirk_vf_reset() {
+    for opcode in [0x11u8, 0x12, 0x13].iter() {
+        let mut cpu = create_cpu_with(chip8::Quirks::chip8());
+
+        cpu.regs[chip8::VF] = 1;
+        cpu.load_program(&[0x80, *opcode]).unwrap();
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.regs[chip8::VF], 0);
+
+        let mut cpu = create_cpu_with(chip8::Quirks::schip());
+
+        cpu.regs[chip8::VF] = 1;
+        cpu.load_program(&[0x80, *opcode]).unwrap();
+        cpu.execute().unwrap();
+
+        assert_eq!(cpu.regs[chip8::VF], 1);
+    }
+}
+
+#[test]
+fn test_quirk_shift_vy() {
+    let mut cpu = create_cpu_with(chip8::Quirks::chip8());
+
+    cpu.regs[chip8::V0] = 0;
+    cpu.regs[chip8::V1] = 0b00000011;
+    cpu.load_program(&[0x80, 0x16, 0x82, 0x1E]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.regs[chip8::V0], 0b00000001);
+    assert_eq!(cpu.regs[chip8::V1], 0b00000011);
+    assert_eq!(cpu.regs[chip8::VF], 1);
+
+    cpu.regs[chip8::V1] = 0b10000001;
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.regs[chip8::V2], 0b00000010);
+    assert_eq!(cpu.regs[chip8::VF], 1);
+
+    let mut cpu = create_cpu_with(chip8::Quirks::schip());
+
+    cpu.regs[chip8::V0] = 0b00000100;
+    cpu.regs[chip8::V1] = 0b00000011;
+    cpu.load_program(&[0x80, 0x16]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.regs[chip8::V0], 0b00000010);
+    assert_eq!(cpu.regs[chip8::VF], 0);
+}
+
+#[test]
+fn test_quirk_load_store_inc_i() {
+    let mut cpu = create_cpu_with(chip8::Quirks::chip8());
+
+    cpu.i = 0x300;
+    cpu.load_program(&[0xF3, 0x55, 0xF1, 0x65]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.i, 0x304);
+
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.i, 0x306);
+
+    let mut cpu = create_cpu_with(chip8::Quirks::schip());
+
+    cpu.i = 0x300;
+    cpu.load_program(&[0xF3, 0x55]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.i, 0x300);
+}
+
+#[test]
+fn test_quirk_jump_vx() {
+    let mut cpu = create_cpu_with(chip8::Quirks::schip());
+
+    cpu.regs[chip8::V0] = 0x1;
+    cpu.regs[chip8::V3] = 0x4;
+    cpu.load_program(&[0xB3, 0x00]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.pc, 0x304);
+
+    let mut cpu = create_cpu_with(chip8::Quirks::chip8());
+
+    cpu.regs[chip8::V0] = 0x1;
+    cpu.regs[chip8::V3] = 0x4;
+    cpu.load_program(&[0xB3, 0x00]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.pc, 0x301);
+}
+
+#[test]
+fn test_quirk_wrap_sprites() {
+    let mut cpu = create_cpu_with(chip8::Quirks {
+        wrap_sprites: true,
+        ..chip8::Quirks::default()
+    });
+
+    cpu.regs[chip8::V0] = 62;
+    cpu.regs[chip8::V1] = 31;
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0b11110000;
+    cpu.memory[0x301] = 0b11110000;
+    cpu.load_program(&[0xD0, 0x12]).unwrap();
+    cpu.execute().unwrap();
+
+    for (x, y) in [(62, 31), (63, 31), (0, 31), (1, 31), (62, 0), (1, 0)].iter() {
+        assert_eq!(cpu.frame_buf.read(*x, *y), 255);
+    }
+
+    let mut cpu = create_cpu();
+
+    cpu.regs[chip8::V0] = 62;
+    cpu.regs[chip8::V1] = 31;
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0b11110000;
+    cpu.memory[0x301] = 0b11110000;
+    cpu.load_program(&[0xD0, 0x12]).unwrap();
+    cpu.execute().unwrap();
+
+    assert_eq!(cpu.frame_buf.read(62, 31), 255);
+    assert_eq!(cpu.frame_buf.read(63, 31), 255);
+    assert_eq!(cpu.frame_buf.read(0, 31), 0);
+    assert_eq!(cpu.frame_buf.read(62, 0), 0);
+}
+
+#[test]
+fn test_cached_execution_invalidates_fx55() {
+    let mut cpu = create_cpu();
+
+    // I = 0x208, V0 = 0x71, V1 = 0x05, store V0-V1 at 0x208, 0x208: V2 = 1
+    cpu.load_program(&[0xA2, 0x08, 0x60, 0x71, 0x61, 0x05, 0xF1, 0x55, 0x62, 0x01])
+        .unwrap();
+
+    // Get the original instruction at 0x208 into the cache
+    cpu.pc = 0x208;
+    cpu.execute_cached().unwrap();
+    assert_eq!(cpu.regs[chip8::V2], 1);
+
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    cpu.execute_cached_n(5).unwrap();
+
+    // 0x208 now holds 0x7105 (V1 += 5)
+    assert_eq!(cpu.regs[chip8::V1], 10);
+    assert_eq!(cpu.pc, 0x20A);
+}
+
+#[test]
+fn test_cached_execution_invalidates_fx33() {
+    let mut cpu = create_cpu();
+
+    // I = 0x209, BCD(V0) at I, jump 0x208, 0x208: V2 = 5
+    cpu.load_program(&[0xA2, 0x09, 0xF0, 0x33, 0x12, 0x08, 0x00, 0x00, 0x62, 0x05])
+        .unwrap();
+
+    cpu.pc = 0x208;
+    cpu.execute_cached().unwrap();
+    assert_eq!(cpu.regs[chip8::V2], 5);
+
+    // The hundreds digit lands in the operand byte of 0x208
+    cpu.regs[chip8::V0] = 9;
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    cpu.execute_cached_n(4).unwrap();
+
+    assert_eq!(cpu.regs[chip8::V2], 0);
+}
+
+#[test]
+fn test_cached_execution_invalidate_cache() {
+    let mut cpu = create_cpu();
+
+    cpu.load_program(&[0x60, 0x01]).unwrap();
+    cpu.execute_cached().unwrap();
+
+    // Direct writes to memory need an explicit invalidation
+    cpu.memory[0x201] = 0x02;
+    cpu.invalidate_cache();
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    cpu.execute_cached().unwrap();
+
+    assert_eq!(cpu.regs[chip8::V0], 2);
+}
+
+#[test]
+fn test_cached_execution_idle_loop() {
+    let mut cpu = create_cpu();
+
+    // V0 += 1, then spin at 0x202
+    cpu.load_program(&[0x70, 0x01, 0x12, 0x02]).unwrap();
+    // Only the addition ran
+    assert_eq!(cpu.execute_cached_n(1000).unwrap(), 1);
+
+    assert_eq!(cpu.pc, 0x202);
+    assert_eq!(cpu.regs[chip8::V0], 1);
+}
+
+#[test]
+fn test_error_keeps_state() {
+    let mut cpu = create_cpu();
+
+    // V0 = 1, return with an empty stack
+    cpu.load_program(&[0x60, 0x01, 0x00, 0xEE]).unwrap();
+    cpu.execute().unwrap();
+
+    let error = cpu.execute().unwrap_err();
+    assert_eq!(error, chip8::Error::StackUnderflow { pc: 0x202 });
+    assert_eq!(cpu.pc, 0x202);
+    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.regs[chip8::V0], 1);
+
+    // Faults repeat instead of running past them
+    assert_eq!(cpu.execute_cached(), Err(error));
+}
+
+#[test]
+fn test_error_fetch_past_memory() {
+    let mut cpu = create_cpu();
+    cpu.pc = 0xFFFE;
+
+    let expected = chip8::Error::InvalidAddress {
+        pc: 0xFFFE,
+        address: 0xFFFF,
+    };
+    assert_eq!(cpu.execute(), Err(expected));
+    assert_eq!(cpu.execute_cached(), Err(expected));
+}
+
+#[test]
+fn test_error_stack_overflow() {
+    let mut cpu = create_cpu();
+
+    // Calls itself forever
+    cpu.load_program(&[0x22, 0x00]).unwrap();
+    for _ in 0..cpu.stack.len() {
+        cpu.execute().unwrap();
+    }
+
+    assert_eq!(
+        cpu.execute(),
+        Err(chip8::Error::StackOverflow { pc: 0x200 })
+    );
+    assert_eq!(cpu.sp as usize, cpu.stack.len());
+}
+
+#[test]
+fn test_error_rom_too_large() {
+    let mut cpu = create_cpu();
+
+    let rom = vec![0xAA; 0xFFFF - 0x200 + 1];
+    assert!(cpu.load_program(&rom).is_err());
+    assert!(cpu.load_program(&rom[1..]).is_ok());
+}
+
+#[test]
+fn test_disassemble() {
+    let cases = [
+        (0x00E0, "CLS"),
+        (0x00EE, "RET"),
+        (0x1234, "JP 0x234"),
+        (0x3A0F, "SE VA, 0x0F"),
+        (0x5120, "SE V1, V2"),
+        (0x8AB6, "SHR VA, VB"),
+        (0xB300, "JP V0, 0x300"),
+        (0xD125, "DRW V1, V2, 5"),
+        (0xF00A, "LD V0, K"),
+        (0xF133, "LD B, V1"),
+        (0xFF65, "LD VF, [I]"),
+        (0x0123, "DW 0x0123"),
+    ];
+
+    for (value, text) in cases.iter() {
+        let instruction = chip8::Instruction::decode(&chip8::Opcode { value: *value });
+        assert_eq!(instruction.to_string(), *text);
+    }
 }
@@ -0,0 +1,127 @@
+use emu_rs::emu::arch::chip8::analysis::{analyze, Platform};
+use emu_rs::emu::arch::chip8::assemble;
+
+fn analyze_source(source: &str) -> emu_rs::emu::arch::chip8::analysis::Analysis {
+    analyze(&assemble(source).unwrap())
+}
+
+#[test]
+fn test_no_findings() {
+    let analysis = analyze_source("loop: ADD V0, 1\nJP loop");
+
+    assert_eq!(analysis.platform, Platform::Chip8);
+    assert_eq!(analysis.preset, "default");
+    assert_eq!(analysis.confidence, 0.0);
+    assert_eq!(analysis.reasons, vec!["No quirk dependent code found"]);
+}
+
+#[test]
+fn test_schip_opcodes() {
+    let analysis = analyze_source(
+        "
+            DW 0x00FF       ; high resolution
+            DRW V0, V1, 0
+        end: JP end
+        ",
+    );
+
+    assert_eq!(analysis.platform, Platform::SuperChip);
+    assert_eq!(analysis.preset, "schip");
+    assert_eq!(analysis.confidence, 1.0);
+    assert_eq!(
+        analysis.reasons,
+        vec!["SUPER-CHIP instructions: 00FF at 0200, D010 at 0202"]
+    );
+}
+
+#[test]
+fn test_unreachable_data_is_ignored() {
+    let analysis = analyze_source("end: JP end\nDW 0x00FF, 0xF030");
+    assert_eq!(analysis.platform, Platform::Chip8);
+}
+
+#[test]
+fn test_vx_only_shifts() {
+    let analysis = analyze_source("LD V1, 0x10\nSHR V1, V7\nend: JP end");
+
+    assert_eq!(analysis.preset, "default");
+    assert_eq!(analysis.confidence, 1.0);
+    assert_eq!(
+        analysis.reasons,
+        vec!["8176 at 0202 shifts V1 while V7 is never set"]
+    );
+}
+
+#[test]
+fn test_vy_shifts() {
+    let analysis = analyze_source("LD V2, 0x10\nSHL V1, V2\nend: JP end");
+
+    assert_eq!(analysis.preset, "chip8");
+    assert_eq!(analysis.confidence, 1.0);
+}
+
+#[test]
+fn test_load_store_loop() {
+    // Copies V0 into consecutive bytes, I is only set before the loop
+    let analysis = analyze_source(
+        "
+                LD I, 0x300
+        loop:   LD [I], V0
+                ADD V0, 1
+                JP loop
+        ",
+    );
+
+    assert_eq!(analysis.preset, "chip8");
+    assert_eq!(
+        analysis.reasons,
+        vec!["F055 at 0202 runs in the loop 0202-0206, which relies on I advancing"]
+    );
+
+    let analysis = analyze_source(
+        "
+        loop:   LD I, 0x300
+                LD [I], V0
+                JP loop
+        ",
+    );
+    assert_eq!(analysis.confidence, 0.0);
+}
+
+#[test]
+fn test_edge_sprites() {
+    let analysis = analyze_source(
+        "
+            LD V0, 60
+            LD V1, 10
+            DRW V0, V1, 5
+        end: JP end
+        ",
+    );
+
+    assert_eq!(analysis.confidence, 0.0);
+    assert_eq!(
+        analysis.reasons,
+        vec![
+            "No quirk dependent code found",
+            "Sprites cross the screen edge, wrap_sprites decides whether they wrap: D015 at 0204"
+        ]
+    );
+}
+
+#[test]
+fn test_mixed_findings() {
+    let analysis = analyze_source(
+        "
+                LD I, 0x300
+        loop:   LD V0, [I]
+                SHR V0, V9
+                JP loop
+        ",
+    );
+
+    // The load loop outweighs the shift
+    assert_eq!(analysis.preset, "chip8");
+    assert_eq!(analysis.confidence, 0.6);
+    assert_eq!(analysis.reasons.len(), 2);
+}
@@ -0,0 +1,70 @@
+use emu_rs::emu::arch::chip8::{assemble, disassemble, AsmError};
+
+use std::fs;
+
+#[test]
+fn test_roundtrip_fixtures() {
+    for name in &["flags.ch8", "font.ch8", "quirks.ch8"] {
+        let rom = fs::read(format!("tests/fixtures/{}", name)).unwrap();
+        let source = disassemble(&rom);
+        assert_eq!(assemble(&source).unwrap(), rom, "{}", name);
+    }
+}
+
+#[test]
+fn test_roundtrip_every_opcode() {
+    for value in 0..=0xFFFFu16 {
+        let rom = value.to_be_bytes();
+        let source = disassemble(&rom);
+        assert_eq!(assemble(&source).unwrap(), rom, "{}", source);
+    }
+    assert_eq!(assemble(&disassemble(&[0xAB])).unwrap(), vec![0xAB]);
+}
+
+#[test]
+fn test_labels_and_data() {
+    let source = "
+        ; Draws a sprite forever
+        start:  LD I, sprite
+                ld v0, #0A
+                DRW V0, V0, 2
+        loop:   JP loop
+                CALL start
+                SHR V3
+        sprite: DB 0b11110000, 0x90
+                DW 0x1234, 5
+    ";
+
+    assert_eq!(
+        assemble(source).unwrap(),
+        vec![
+            0xA2, 0x0C, 0x60, 0x0A, 0xD0, 0x02, 0x12, 0x06, 0x22, 0x00, 0x83, 0x36, 0xF0, 0x90,
+            0x12, 0x34, 0x00, 0x05,
+        ]
+    );
+}
+
+#[test]
+fn test_errors() {
+    let error = |source: &str| assemble(source).unwrap_err();
+
+    assert_eq!(
+        error("CLS\nFOO V0"),
+        AsmError {
+            line: 2,
+            message: "Unknown instruction 'FOO'".to_string(),
+        }
+    );
+    assert_eq!(error("JP nowhere").message, "Unknown value 'nowhere'");
+    assert_eq!(error("LD V0, 0x100").message, "'0x100' doesn't fit into FF");
+    assert_eq!(error("ADD V0").message, "Expected 2 operands, got 1");
+    assert_eq!(
+        error("JP V1, 0x300").message,
+        "Offset jumps are relative to V0"
+    );
+    assert_eq!(
+        error("a:\na: CLS").to_string(),
+        "Line 2: Duplicate label 'a'"
+    );
+    assert_eq!(error("v1: CLS").message, "Invalid label 'v1'");
+}
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+fn bench(rom: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_emu_rs"))
+        .arg("bench")
+        .arg(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(rom),
+        )
+        .args(["--cycles", "900"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The instruction count of an interpreter's line
+fn executed(report: &str, name: &str) -> u64 {
+    let line = report
+        .lines()
+        .find(|line| line.starts_with(name))
+        .unwrap_or_else(|| panic!("No {} in:\n{}", name, report));
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let position = words
+        .iter()
+        .position(|&word| word == "instructions")
+        .unwrap();
+    words[position - 1].parse().unwrap()
+}
+
+#[test]
+fn test_bench_busy_rom() {
+    let report = bench("busy-alu.ch8");
+
+    assert_eq!(executed(&report, "interpreter"), 900);
+    assert_eq!(executed(&report, "cached"), 900);
+    assert!(report.contains("speedup"), "{}", report);
+}
+
+#[test]
+fn test_bench_idle_rom() {
+    let report = bench("flags.ch8");
+
+    // Skipped cycles don't count as instructions
+    assert_eq!(executed(&report, "interpreter"), 900);
+    assert!(executed(&report, "cached") < 900);
+    assert!(report.contains("skipped"), "{}", report);
+}
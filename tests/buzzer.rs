@@ -0,0 +1,44 @@
+use emu_rs::emu::core::Buzzer;
+
+#[test]
+fn test_buzzer_square_wave() {
+    // 8 samples per period
+    let mut buzzer = Buzzer::new(1000.0, 0.5);
+    let mut out = [0.0; 12];
+    buzzer.fill(&mut out, 8000, true);
+
+    assert_eq!(
+        out,
+        [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5, 0.5, 0.5, 0.5, 0.5]
+    );
+}
+
+#[test]
+fn test_buzzer_off() {
+    let mut buzzer = Buzzer::new(1000.0, 0.5);
+    let mut out = [1.0; 4];
+    buzzer.fill(&mut out, 8000, false);
+    assert_eq!(out, [0.0; 4]);
+
+    // Switched back on the wave continues where it stopped
+    let mut out = [0.0; 6];
+    buzzer.fill(&mut out, 8000, true);
+    buzzer.fill(&mut [0.0; 100], 8000, false);
+    let mut rest = [0.0; 4];
+    buzzer.fill(&mut rest, 8000, true);
+    assert_eq!(out, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5]);
+    assert_eq!(rest, [-0.5, -0.5, 0.5, 0.5]);
+}
+
+#[test]
+fn test_buzzer_channels() {
+    // Stereo frames carry the same sample twice
+    let mut buzzer = Buzzer::new(2000.0, 0.5);
+    let mut out = [0.0; 8];
+    buzzer.fill_channels(&mut out, 2, 8000, true);
+    assert_eq!(out, [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5]);
+
+    let mut out = [1.0; 4];
+    buzzer.fill_channels(&mut out, 2, 8000, false);
+    assert_eq!(out, [0.0; 4]);
+}
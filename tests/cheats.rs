@@ -0,0 +1,163 @@
+use emu_rs::emu::arch::chip8::cheats::{Cheats, Code, Filter, Search, Target};
+use emu_rs::emu::arch::chip8::Keyboard;
+use emu_rs::emu::arch::chip8::CPU;
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const ROM: [u8; 6] = [
+    0x60, 0x03, // V0 = 3
+    0x70, 0xFF, // V0 -= 1
+    0x12, 0x02, // jump back to 202
+];
+
+fn cpu() -> CPU {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.load_program(&ROM).unwrap();
+    cpu
+}
+
+#[test]
+fn test_search() {
+    let mut cpu = cpu();
+    let mut search = Search::new(&cpu);
+    assert_eq!(search.results.len(), 16 + 0xFFFF);
+
+    cpu.execute().unwrap();
+    search.filter(&cpu, Filter::Equal(3));
+    assert!(search.results.contains(&(Target::Register(0), 3)));
+    // The ROM's own byte
+    assert!(search.results.contains(&(Target::Memory(0x201), 3)));
+
+    search.filter(&cpu, Filter::Unchanged);
+    assert_eq!(search.results.len(), 2);
+
+    // Lives going down
+    cpu.execute().unwrap();
+    search.filter(&cpu, Filter::Decreased);
+    assert_eq!(search.results, vec![(Target::Register(0), 2)]);
+
+    search.filter(&cpu, Filter::Changed);
+    assert!(search.results.is_empty());
+
+    let mut search = Search::new(&cpu);
+    cpu.regs[5] = 9;
+    search.filter(&cpu, Filter::Increased);
+    assert_eq!(search.results, vec![(Target::Register(5), 9)]);
+}
+
+#[test]
+fn test_codes() {
+    let text = "\
+# Infinite lives
+freeze V0 03
+freeze 0300 7f  # Somewhere else
+patch 0204 1200
+";
+    let cheats = Cheats::parse(text).unwrap();
+    assert_eq!(
+        cheats.codes,
+        vec![
+            Code::Freeze {
+                target: Target::Register(0),
+                value: 3
+            },
+            Code::Freeze {
+                target: Target::Memory(0x300),
+                value: 0x7F
+            },
+            Code::Patch {
+                address: 0x204,
+                bytes: vec![0x12, 0x00]
+            },
+        ]
+    );
+    assert_eq!(
+        cheats.to_string(),
+        "freeze V0 03\nfreeze 0300 7F\npatch 0204 1200\n"
+    );
+    assert_eq!(Cheats::parse(&cheats.to_string()).unwrap(), cheats);
+
+    // Patches apply once at load, freezes every frame
+    let mut cpu = cpu();
+    cheats.patch(&mut cpu);
+    assert_eq!(&cpu.memory[0x204..0x206], &[0x12, 0x00]);
+    assert_eq!(cpu.memory[0x300], 0);
+
+    // The patched jump goes back to 200, which keeps resetting V0
+    for _ in 0..5 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(cpu.regs[0], 2);
+    cheats.freeze(&mut cpu);
+    assert_eq!(cpu.regs[0], 3);
+    assert_eq!(cpu.memory[0x300], 0x7F);
+}
+
+#[test]
+fn test_code_errors() {
+    let error = |text| Cheats::parse(text).unwrap_err();
+
+    assert_eq!(
+        error("freeze V0 03\nfreeze VG 01"),
+        "Line 2: Invalid register or address 'VG'"
+    );
+    assert_eq!(error("freeze V0 100"), "Line 1: Invalid value '100'");
+    assert_eq!(error("patch V0 00"), "Line 1: Only memory can be patched");
+    assert_eq!(error("patch 0200 123"), "Line 1: Invalid bytes '123'");
+    assert_eq!(
+        error("patch FFFE 0000"),
+        "Line 1: The patch runs past the end of memory"
+    );
+    assert_eq!(error("poke 0200 00"), "Line 1: Invalid code 'poke 0200 00'");
+}
+
+#[test]
+fn test_commands() {
+    let mut cpu = cpu();
+    let mut cheats = Cheats::default();
+
+    assert_eq!(cheats.command(&mut cpu, "results"), "No search running\n");
+    assert_eq!(
+        cheats.command(&mut cpu, "search"),
+        "Searching 65551 registers and bytes\n"
+    );
+    cpu.execute().unwrap();
+    assert_eq!(cheats.command(&mut cpu, "search 3"), "2 left\n");
+    cpu.execute().unwrap();
+    assert_eq!(cheats.command(&mut cpu, "search decreased"), "1 left\n");
+    assert_eq!(cheats.command(&mut cpu, "results"), "V0 02\n");
+    assert_eq!(cheats.command(&mut cpu, "search 9"), "0 left\n");
+    assert_eq!(cheats.command(&mut cpu, "results"), "Nothing left\n");
+    assert_eq!(
+        cheats.command(&mut cpu, "search sideways"),
+        "Invalid search 'sideways'\n"
+    );
+
+    assert_eq!(cheats.command(&mut cpu, "list"), "No codes\n");
+    assert_eq!(cheats.command(&mut cpu, "freeze V0"), "1: freeze V0 02\n");
+    assert_eq!(
+        cheats.command(&mut cpu, "patch 0300 ABCD"),
+        "2: patch 0300 ABCD\n"
+    );
+    assert_eq!(&cpu.memory[0x300..0x302], &[0xAB, 0xCD]);
+    assert_eq!(
+        cheats.command(&mut cpu, "list"),
+        "1: freeze V0 02\n2: patch 0300 ABCD\n"
+    );
+    assert_eq!(cheats.command(&mut cpu, "remove 3"), "No code 3\n");
+    assert_eq!(
+        cheats.command(&mut cpu, "remove 1"),
+        "Removed freeze V0 02\n"
+    );
+    assert_eq!(cheats.to_string(), "patch 0300 ABCD\n");
+
+    assert!(cheats.command(&mut cpu, "help").contains("freeze <at>"));
+    assert_eq!(
+        cheats.command(&mut cpu, "poke 1"),
+        "Unknown command 'poke 1', try help\n"
+    );
+}
@@ -0,0 +1,98 @@
+use emu_rs::emu::arch::chip8::config::{rom_hash, Config, Settings};
+
+const CONFIG: &str = r#"
+[default]
+clock = 600
+palette = "amber"
+keys = { up = "5", down = "8" }
+
+[rom.a9993e364706816aba3e25717850c26c9cd0d89d]
+name = "abc.ch8"
+quirks = "chip8"
+keys = { up = "2", w = "5" }
+palette = "FFFFFF,000000"
+"#;
+
+#[test]
+fn test_rom_hash() {
+    assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn test_rom_settings_override_default() {
+    let config = Config::parse(CONFIG).unwrap();
+
+    let settings = config.settings(&rom_hash(b"abc"), &Settings::default());
+    assert_eq!(settings.name.as_deref(), Some("abc.ch8"));
+    assert_eq!(settings.clock, Some(600));
+    assert_eq!(settings.quirks.as_deref(), Some("chip8"));
+    assert_eq!(settings.palette.as_deref(), Some("FFFFFF,000000"));
+    let keys = settings.keys.unwrap();
+    assert_eq!(keys["up"], "2");
+    assert_eq!(keys["down"], "8");
+    assert_eq!(keys["w"], "5");
+
+    let other = config.settings(&rom_hash(b"other"), &Settings::default());
+    assert_eq!(other, config.default);
+    assert_eq!(other.quirks, None);
+}
+
+#[test]
+fn test_known_settings_between_default_and_rom() {
+    let config = Config::parse(CONFIG).unwrap();
+    let known = Settings {
+        clock: Some(900),
+        quirks: Some("schip".to_string()),
+        ..Settings::default()
+    };
+
+    let settings = config.settings(&rom_hash(b"abc"), &known);
+    assert_eq!(settings.clock, Some(900));
+    assert_eq!(settings.quirks.as_deref(), Some("chip8"));
+
+    let other = config.settings(&rom_hash(b"other"), &known);
+    assert_eq!(other.quirks.as_deref(), Some("schip"));
+    assert_eq!(other.palette.as_deref(), Some("amber"));
+}
+
+#[test]
+fn test_merge() {
+    let mut settings = Settings {
+        clock: Some(540),
+        scale: Some(10),
+        ..Settings::default()
+    };
+    settings.merge(&Settings {
+        clock: Some(1000),
+        scaler: Some("none".to_string()),
+        ..Settings::default()
+    });
+
+    assert_eq!(settings.clock, Some(1000));
+    assert_eq!(settings.scale, Some(10));
+    assert_eq!(settings.scaler.as_deref(), Some("none"));
+}
+
+#[test]
+fn test_write_back() {
+    let mut config = Config::parse(CONFIG).unwrap();
+    config.rom.insert(
+        rom_hash(b"other"),
+        Settings {
+            scale: Some(4),
+            ..Settings::default()
+        },
+    );
+
+    let text = config.to_toml();
+    assert_eq!(Config::parse(&text).unwrap(), config);
+    assert!(text.contains("[rom.a9993e364706816aba3e25717850c26c9cd0d89d]"));
+    assert!(!text.contains("scaler"));
+}
+
+#[test]
+fn test_invalid() {
+    assert!(Config::parse("[default]\nclok = 540").is_err());
+    assert!(Config::parse("[default]\nclock = \"fast\"").is_err());
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+}
@@ -0,0 +1,485 @@
+// Checks the documented semantics of every opcode under every quirk preset.
+//
+// Each case sets up a fresh CPU, executes a single instruction and lists the
+// values it expects afterwards. Expectations may depend on the active quirks.
+// All mismatches are collected so a regression shows up with full context.
+
+use emu_rs::emu::arch::chip8::{self, Quirks, PROGRAM_ENTRY, V0, V1, V2, VF};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const SCRATCH: u16 = 0x300;
+
+enum Check {
+    Reg(usize, u8),
+    I(u16),
+    Pc(u16),
+    Sp(u16),
+    Mem(u16, u8),
+    Pixel(u32, u32, u8),
+}
+
+struct Case {
+    name: &'static str,
+    program: &'static [u8],
+    setup: fn(&mut chip8::CPU),
+    expect: fn(&Quirks) -> Vec<Check>,
+}
+
+fn skip(taken: bool) -> Check {
+    Check::Pc(PROGRAM_ENTRY + if taken { 4 } else { 2 })
+}
+
+fn shift_source(q: &Quirks, vx: u8, vy: u8) -> u8 {
+    if q.shift_vy {
+        vy
+    } else {
+        vx
+    }
+}
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            name: "00E0 clears the screen",
+            program: &[0x00, 0xE0],
+            setup: |cpu| cpu.frame_buf.write(5, 5, 0xFF),
+            expect: |_| vec![Check::Pixel(5, 5, 0), Check::Pc(PROGRAM_ENTRY + 2)],
+        },
+        Case {
+            name: "00EE returns to the address on the stack",
+            program: &[0x00, 0xEE],
+            setup: |cpu| {
+                cpu.stack[0] = 0x345;
+                cpu.sp = 1;
+            },
+            expect: |_| vec![Check::Pc(0x345), Check::Sp(0)],
+        },
+        Case {
+            name: "1NNN jumps",
+            program: &[0x13, 0x45],
+            setup: |_| {},
+            expect: |_| vec![Check::Pc(0x345)],
+        },
+        Case {
+            name: "2NNN pushes the return address",
+            program: &[0x23, 0x45],
+            setup: |_| {},
+            expect: |_| vec![Check::Pc(0x345), Check::Sp(1)],
+        },
+        Case {
+            name: "3XNN skips when equal",
+            program: &[0x30, 0x42],
+            setup: |cpu| cpu.regs[V0] = 0x42,
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "3XNN doesn't skip when different",
+            program: &[0x30, 0x42],
+            setup: |_| {},
+            expect: |_| vec![skip(false)],
+        },
+        Case {
+            name: "4XNN skips when different",
+            program: &[0x40, 0x42],
+            setup: |_| {},
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "5XY0 skips when registers are equal",
+            program: &[0x50, 0x10],
+            setup: |cpu| {
+                cpu.regs[V0] = 7;
+                cpu.regs[V1] = 7;
+            },
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "6XNN loads a constant",
+            program: &[0x6F, 0x42],
+            setup: |_| {},
+            expect: |_| vec![Check::Reg(VF, 0x42)],
+        },
+        Case {
+            name: "7XNN wraps and leaves VF alone",
+            program: &[0x70, 0x02],
+            setup: |cpu| {
+                cpu.regs[V0] = 0xFF;
+                cpu.regs[VF] = 5;
+            },
+            expect: |_| vec![Check::Reg(V0, 1), Check::Reg(VF, 5)],
+        },
+        Case {
+            name: "8XY0 copies VY",
+            program: &[0x80, 0x10],
+            setup: |cpu| cpu.regs[V1] = 9,
+            expect: |_| vec![Check::Reg(V0, 9), Check::Reg(V1, 9)],
+        },
+        Case {
+            name: "8XY1 ors",
+            program: &[0x80, 0x11],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x0F;
+                cpu.regs[V1] = 0xF0;
+                cpu.regs[VF] = 1;
+            },
+            expect: |q| vec![Check::Reg(V0, 0xFF), Check::Reg(VF, !q.vf_reset as u8)],
+        },
+        Case {
+            name: "8XY2 ands",
+            program: &[0x80, 0x12],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x3C;
+                cpu.regs[V1] = 0x0F;
+                cpu.regs[VF] = 1;
+            },
+            expect: |q| vec![Check::Reg(V0, 0x0C), Check::Reg(VF, !q.vf_reset as u8)],
+        },
+        Case {
+            name: "8XY3 xors",
+            program: &[0x80, 0x13],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x3C;
+                cpu.regs[V1] = 0x0F;
+                cpu.regs[VF] = 1;
+            },
+            expect: |q| vec![Check::Reg(V0, 0x33), Check::Reg(VF, !q.vf_reset as u8)],
+        },
+        Case {
+            name: "8XY4 sets VF on carry",
+            program: &[0x80, 0x14],
+            setup: |cpu| {
+                cpu.regs[V0] = 0xFF;
+                cpu.regs[V1] = 0x02;
+            },
+            expect: |_| vec![Check::Reg(V0, 0x01), Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "8XY4 clears VF without carry",
+            program: &[0x80, 0x14],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x01;
+                cpu.regs[V1] = 0x02;
+                cpu.regs[VF] = 1;
+            },
+            expect: |_| vec![Check::Reg(V0, 0x03), Check::Reg(VF, 0)],
+        },
+        Case {
+            name: "8XY4 with VF as VX keeps the flag",
+            program: &[0x8F, 0x04],
+            setup: |cpu| {
+                cpu.regs[VF] = 0xFF;
+                cpu.regs[V0] = 0x02;
+            },
+            expect: |_| vec![Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "8XY5 sets VF when there is no borrow",
+            program: &[0x80, 0x15],
+            setup: |cpu| {
+                cpu.regs[V0] = 5;
+                cpu.regs[V1] = 3;
+            },
+            expect: |_| vec![Check::Reg(V0, 2), Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "8XY5 sets VF for equal operands",
+            program: &[0x80, 0x15],
+            setup: |cpu| {
+                cpu.regs[V0] = 3;
+                cpu.regs[V1] = 3;
+            },
+            expect: |_| vec![Check::Reg(V0, 0), Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "8XY5 clears VF on borrow",
+            program: &[0x80, 0x15],
+            setup: |cpu| {
+                cpu.regs[V0] = 3;
+                cpu.regs[V1] = 5;
+                cpu.regs[VF] = 1;
+            },
+            expect: |_| vec![Check::Reg(V0, 0xFE), Check::Reg(VF, 0)],
+        },
+        Case {
+            name: "8XY6 shifts right",
+            program: &[0x80, 0x16],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x05;
+                cpu.regs[V1] = 0x0C;
+            },
+            expect: |q| {
+                let src = shift_source(q, 0x05, 0x0C);
+                vec![Check::Reg(V0, src >> 1), Check::Reg(VF, src & 1)]
+            },
+        },
+        Case {
+            name: "8XY7 sets VF when there is no borrow",
+            program: &[0x80, 0x17],
+            setup: |cpu| {
+                cpu.regs[V0] = 3;
+                cpu.regs[V1] = 5;
+            },
+            expect: |_| vec![Check::Reg(V0, 2), Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "8XY7 clears VF on borrow",
+            program: &[0x80, 0x17],
+            setup: |cpu| {
+                cpu.regs[V0] = 5;
+                cpu.regs[V1] = 3;
+                cpu.regs[VF] = 1;
+            },
+            expect: |_| vec![Check::Reg(V0, 0xFE), Check::Reg(VF, 0)],
+        },
+        Case {
+            name: "8XYE shifts left",
+            program: &[0x80, 0x1E],
+            setup: |cpu| {
+                cpu.regs[V0] = 0x81;
+                cpu.regs[V1] = 0x40;
+            },
+            expect: |q| {
+                let src = shift_source(q, 0x81, 0x40);
+                vec![Check::Reg(V0, src << 1), Check::Reg(VF, src >> 7)]
+            },
+        },
+        Case {
+            name: "9XY0 skips when registers differ",
+            program: &[0x90, 0x10],
+            setup: |cpu| cpu.regs[V1] = 1,
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "ANNN loads I",
+            program: &[0xA3, 0x45],
+            setup: |_| {},
+            expect: |_| vec![Check::I(0x345)],
+        },
+        Case {
+            name: "BNNN jumps with offset",
+            program: &[0xB2, 0x10],
+            setup: |cpu| {
+                cpu.regs[V0] = 1;
+                cpu.regs[V2] = 2;
+            },
+            expect: |q| vec![Check::Pc(0x210 + if q.jump_vx { 2 } else { 1 })],
+        },
+        Case {
+            name: "CXNN masks the random value",
+            program: &[0xC0, 0x00],
+            setup: |cpu| cpu.regs[V0] = 0xFF,
+            expect: |_| vec![Check::Reg(V0, 0)],
+        },
+        Case {
+            name: "DXYN draws without collision",
+            program: &[0xD0, 0x11],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.memory[SCRATCH as usize] = 0x80;
+                cpu.regs[VF] = 1;
+            },
+            expect: |_| vec![Check::Pixel(0, 0, 0xFF), Check::Reg(VF, 0)],
+        },
+        Case {
+            name: "DXYN reports erased pixels",
+            program: &[0xD0, 0x11],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.memory[SCRATCH as usize] = 0x80;
+                cpu.frame_buf.write(0, 0, 0xFF);
+            },
+            expect: |_| vec![Check::Pixel(0, 0, 0), Check::Reg(VF, 1)],
+        },
+        Case {
+            name: "DXYN clips or wraps at the edge",
+            program: &[0xD0, 0x11],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.memory[SCRATCH as usize] = 0xC0;
+                cpu.regs[V0] = 63;
+            },
+            expect: |q| {
+                let wrapped = if q.wrap_sprites { 0xFF } else { 0 };
+                vec![Check::Pixel(63, 0, 0xFF), Check::Pixel(0, 0, wrapped)]
+            },
+        },
+        Case {
+            name: "EX9E skips when the key is pressed",
+            program: &[0xE0, 0x9E],
+            setup: |cpu| {
+                cpu.regs[V0] = 0xA;
+                cpu.keyboard.lock().unwrap().press_key(0xA);
+            },
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "EXA1 skips when the key is released",
+            program: &[0xE0, 0xA1],
+            setup: |cpu| cpu.regs[V0] = 0xA,
+            expect: |_| vec![skip(true)],
+        },
+        Case {
+            name: "FX07 reads the delay timer",
+            program: &[0xF0, 0x07],
+            setup: |cpu| cpu.timers[chip8::DELAY] = 33,
+            expect: |_| vec![Check::Reg(V0, 33)],
+        },
+        Case {
+            name: "FX0A blocks without a key",
+            program: &[0xF0, 0x0A],
+            setup: |_| {},
+            expect: |_| vec![Check::Pc(PROGRAM_ENTRY)],
+        },
+        Case {
+            name: "FX1E adds VX to I",
+            program: &[0xF0, 0x1E],
+            setup: |cpu| {
+                cpu.i = 0x100;
+                cpu.regs[V0] = 0x20;
+            },
+            expect: |_| vec![Check::I(0x120)],
+        },
+        Case {
+            name: "FX29 points I at the font glyph",
+            program: &[0xF0, 0x29],
+            setup: |cpu| cpu.regs[V0] = 0x8,
+            expect: |_| {
+                let mut checks = vec![Check::I(40)];
+                for (offset, byte) in chip8::FONT_CHARMAP[40..45].iter().enumerate() {
+                    checks.push(Check::Mem(40 + offset as u16, *byte));
+                }
+                checks
+            },
+        },
+        Case {
+            name: "FX33 stores three digits",
+            program: &[0xF0, 0x33],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.regs[V0] = 105;
+            },
+            expect: |_| {
+                vec![
+                    Check::Mem(SCRATCH, 1),
+                    Check::Mem(SCRATCH + 1, 0),
+                    Check::Mem(SCRATCH + 2, 5),
+                ]
+            },
+        },
+        Case {
+            name: "FX33 stores trailing zeros",
+            program: &[0xF0, 0x33],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.memory[SCRATCH as usize..SCRATCH as usize + 3].copy_from_slice(&[9, 9, 9]);
+                cpu.regs[V0] = 200;
+            },
+            expect: |_| {
+                vec![
+                    Check::Mem(SCRATCH, 2),
+                    Check::Mem(SCRATCH + 1, 0),
+                    Check::Mem(SCRATCH + 2, 0),
+                    Check::I(SCRATCH),
+                ]
+            },
+        },
+        Case {
+            name: "FX55 stores V0-VX",
+            program: &[0xF2, 0x55],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.regs[..4].copy_from_slice(&[1, 2, 3, 4]);
+            },
+            expect: |q| {
+                vec![
+                    Check::Mem(SCRATCH, 1),
+                    Check::Mem(SCRATCH + 2, 3),
+                    Check::Mem(SCRATCH + 3, 0),
+                    Check::I(SCRATCH + if q.load_store_inc_i { 3 } else { 0 }),
+                ]
+            },
+        },
+        Case {
+            name: "FX65 loads V0-VX",
+            program: &[0xF2, 0x65],
+            setup: |cpu| {
+                cpu.i = SCRATCH;
+                cpu.memory[SCRATCH as usize..SCRATCH as usize + 4].copy_from_slice(&[1, 2, 3, 4]);
+            },
+            expect: |q| {
+                vec![
+                    Check::Reg(V0, 1),
+                    Check::Reg(V2, 3),
+                    Check::Reg(3, 0),
+                    Check::I(SCRATCH + if q.load_store_inc_i { 3 } else { 0 }),
+                ]
+            },
+        },
+    ]
+}
+
+fn verify(cpu: &chip8::CPU, check: &Check) -> Option<String> {
+    let (what, actual, expected) = match *check {
+        Check::Reg(reg, val) => (format!("V{:X}", reg), cpu.regs[reg] as u16, val as u16),
+        Check::I(val) => ("I".to_string(), cpu.i, val),
+        Check::Pc(val) => ("PC".to_string(), cpu.pc, val),
+        Check::Sp(val) => ("SP".to_string(), cpu.sp, val),
+        Check::Mem(addr, val) => (
+            format!("[{:03X}]", addr),
+            cpu.memory[addr as usize] as u16,
+            val as u16,
+        ),
+        Check::Pixel(x, y, val) => (
+            format!("pixel ({}, {})", x, y),
+            cpu.frame_buf.read(x, y) as u16,
+            val as u16,
+        ),
+    };
+
+    if actual == expected {
+        None
+    } else {
+        Some(format!(
+            "{} = {:#X}, expected {:#X}",
+            what, actual, expected
+        ))
+    }
+}
+
+#[test]
+fn test_conformance() {
+    let mut failures = vec![];
+
+    for preset in chip8::QUIRK_PRESETS.iter() {
+        let quirks = Quirks::preset(preset).unwrap();
+
+        for cached in [false, true].iter() {
+            for case in cases() {
+                let mut cpu = chip8::CPU::new(
+                    FrameBuffer::new(64, 32, 0u8),
+                    Arc::new(Mutex::new(chip8::Keyboard::new())),
+                );
+                cpu.quirks = quirks;
+                cpu.load_program(case.program).unwrap();
+                (case.setup)(&mut cpu);
+
+                if *cached {
+                    cpu.execute_cached().unwrap();
+                } else {
+                    cpu.execute().unwrap();
+                }
+
+                for check in (case.expect)(&quirks) {
+                    if let Some(failure) = verify(&cpu, &check) {
+                        let mode = if *cached { "cached" } else { "interpreter" };
+                        failures.push(format!("{} [{}, {}]: {}", case.name, preset, mode, failure));
+                    }
+                }
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
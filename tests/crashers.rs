@@ -0,0 +1,131 @@
+// Regression corpus for inputs which used to crash the emulator, in the input
+// format of `chip8::fuzz::decode_input`. New crashers found by the fuzz
+// targets in fuzz/ go into tests/fixtures/crashers, every file there is run.
+//
+//   cargo +nightly fuzz run cpu
+
+use emu_rs::emu::arch::chip8::fuzz;
+use emu_rs::emu::arch::chip8::lockstep::Backend;
+use emu_rs::emu::arch::chip8::Error;
+
+use std::fs;
+use std::path::PathBuf;
+
+fn crasher(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("crashers")
+        .join(name)
+}
+
+fn backends() -> Vec<Backend> {
+    vec![
+        Backend::Interpreter,
+        Backend::Cached,
+        #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+        Backend::Jit,
+    ]
+}
+
+// Runs the input on every backend, they all have to agree
+fn run(data: &[u8]) -> Result<u64, Error> {
+    let results: Vec<_> = backends()
+        .into_iter()
+        .map(|backend| fuzz::run(data, backend))
+        .collect();
+
+    for result in &results[1..] {
+        assert_eq!(*result, results[0]);
+    }
+
+    results[0]
+}
+
+#[test]
+fn test_crashers() {
+    // Every file in the corpus has to run without panicking
+    for entry in fs::read_dir(crasher("")).unwrap() {
+        let path = entry.unwrap().path();
+        let _ = run(&fs::read(&path).unwrap());
+    }
+}
+
+#[test]
+fn test_crasher_errors() {
+    let cases = [
+        (
+            "stack-underflow.bin",
+            Some(Error::StackUnderflow { pc: 0x202 }),
+        ),
+        (
+            "stack-overflow.bin",
+            Some(Error::StackOverflow { pc: 0x200 }),
+        ),
+        (
+            "unknown-opcode.bin",
+            Some(Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x0000,
+            }),
+        ),
+        (
+            "skip-key-index.bin",
+            Some(Error::InvalidKey {
+                pc: 0x210,
+                key: 0x89,
+            }),
+        ),
+        (
+            "skip-not-key-index.bin",
+            Some(Error::InvalidKey {
+                pc: 0x202,
+                key: 0x80,
+            }),
+        ),
+        // FX29 with VX > 51 used to overflow, now the ROM runs into zeroed memory
+        (
+            "font-overflow.bin",
+            Some(Error::UnknownOpcode {
+                pc: 0x21C,
+                opcode: 0x0000,
+            }),
+        ),
+        // FX1E used to overflow once I passed 0xFFFF, now it wraps
+        ("index-overflow.bin", None),
+    ];
+
+    for (name, expected) in cases.iter() {
+        let data = fs::read(crasher(name)).unwrap();
+        assert_eq!(run(&data).err(), *expected, "{}", name);
+    }
+
+    // These move I to 0xFFFE, then access memory through it at 0x214
+    for name in &[
+        "draw-past-memory.bin",
+        "bcd-past-memory.bin",
+        "store-past-memory.bin",
+        "load-past-memory.bin",
+    ] {
+        let data = fs::read(crasher(name)).unwrap();
+        let expected = Error::InvalidAddress {
+            pc: 0x214,
+            address: 0xFFFF,
+        };
+        assert_eq!(run(&data).err(), Some(expected), "{}", name);
+    }
+}
+
+#[test]
+fn test_crasher_rom_too_large() {
+    let mut data = vec![0, 0];
+    data.resize(2 + 0xFFFF, 0x12);
+
+    assert_eq!(
+        run(&data),
+        Err(Error::RomTooLarge {
+            size: 0xFFFF,
+            max: 0xFFFF - 0x200
+        })
+    );
+}
@@ -0,0 +1,179 @@
+use emu_rs::emu::arch::chip8::config::rom_hash;
+use emu_rs::emu::arch::chip8::database::Database;
+use emu_rs::emu::arch::chip8::{Keyboard, Quirks, CPU};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+const DATABASE: &str = r#"{
+  "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+    "title": "ABC",
+    "author": "Someone",
+    "platform": "superchip",
+    "tickrate": 30,
+    "keys": { "up": 1, "down": 4, "fire": 6 },
+    "colors": { "foreground": "FFB000", "background": "000000" }
+  }
+}"#;
+
+#[test]
+fn test_builtin_knows_fixtures() {
+    let database = Database::builtin();
+    let rom = fs::read("tests/fixtures/quirks.ch8").unwrap();
+
+    let program = database.get(&rom).unwrap();
+    assert_eq!(program.title, "Quirks test");
+    // It shows the differences between all presets
+    assert_eq!(program.quirk_preset(), None);
+    assert!(database.get(b"unknown").is_none());
+}
+
+#[test]
+fn test_settings() {
+    let database = Database::parse(DATABASE).unwrap();
+    let program = database.get(b"abc").unwrap();
+    let settings = program.settings();
+
+    assert_eq!(settings.clock, Some(1800));
+    assert_eq!(settings.quirks.as_deref(), Some("schip"));
+    assert_eq!(settings.palette.as_deref(), Some("FFB000,000000"));
+
+    // Only directions map to host keys
+    let keys = settings.keys.unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys["up"], "1");
+    assert_eq!(keys["down"], "4");
+}
+
+#[test]
+fn test_load_known_program() {
+    let database = Database::parse(DATABASE).unwrap();
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+
+    let program = cpu.load_known_program(b"abc", &database).unwrap();
+    assert_eq!(program.unwrap().tickrate, Some(30));
+    assert_eq!(cpu.quirks, Quirks::schip());
+    assert_eq!(&cpu.memory[0x200..0x203], b"abc");
+
+    // Unknown ROMs keep the quirks
+    cpu.quirks = Quirks::chip8();
+    assert_eq!(cpu.load_known_program(b"abd", &database).unwrap(), None);
+    assert_eq!(cpu.quirks, Quirks::chip8());
+}
+
+#[test]
+fn test_extend() {
+    let mut database = Database::builtin();
+    let builtin = database.programs.len();
+    database.extend(Database::parse(DATABASE).unwrap());
+
+    assert_eq!(database.programs.len(), builtin + 1);
+    assert!(database.programs.contains_key(&rom_hash(b"abc")));
+}
+
+#[test]
+fn test_invalid() {
+    assert!(Database::parse("{\"abc\": {\"title\": \"Short hash\"}}").is_err());
+    assert!(Database::parse(&DATABASE.replace("tickrate", "speed")).is_err());
+    assert!(Database::parse(&DATABASE.replace("\"up\": 1", "\"up\": 16")).is_err());
+    let unknown_preset = DATABASE.replace("\"tickrate\"", "\"quirks\": \"xo\", \"tickrate\"");
+    assert!(Database::parse(&unknown_preset).is_err());
+    assert!(Database::parse(&DATABASE.replace("30", "4294967295")).is_err());
+    assert_eq!(
+        Database::parse(&DATABASE.replace("30", "0")).unwrap_err(),
+        "Tickrate 0 for ABC"
+    );
+    assert_eq!(
+        Database::parse(&DATABASE.replace("FFB000", "orange")).unwrap_err(),
+        "Invalid colors for ABC"
+    );
+}
+
+// Shaped like programs.json of the community database
+const COMMUNITY: &str = r##"[
+  {
+    "title": "ABC",
+    "description": "Not a real program",
+    "authors": ["Someone", "Someone else"],
+    "release": "2024",
+    "roms": {
+      "a9993e364706816aba3e25717850c26c9cd0d89d": {
+        "file": "abc.ch8",
+        "platforms": ["xochip", "superchip", "originalChip8"],
+        "tickrate": 30,
+        "keys": { "up": 1, "down": 4, "a": 6, "player2Up": 17 },
+        "colors": { "pixels": ["#000000", "#ffb000"], "buzzer": "#FFAA00" },
+        "screenRotation": 0
+      },
+      "0123456789abcdef0123456789abcdef01234567": {
+        "file": "abc-hires.sc8",
+        "platforms": ["megachip8"],
+        "tickrate": "fast",
+        "colors": { "pixels": ["#000000", "yellow"] }
+      }
+    }
+  },
+  { "title": "No ROMs" }
+]"##;
+
+#[test]
+fn test_community_format() {
+    let database = Database::parse(COMMUNITY).unwrap();
+    assert_eq!(database.programs.len(), 2);
+
+    let program = database.get(b"abc").unwrap();
+    assert_eq!(program.title, "ABC");
+    assert_eq!(program.author.as_deref(), Some("Someone, Someone else"));
+    // The first platform with a quirk preset
+    assert_eq!(program.platform.as_deref(), Some("superchip"));
+
+    let settings = program.settings();
+    assert_eq!(settings.clock, Some(1800));
+    assert_eq!(settings.quirks.as_deref(), Some("schip"));
+    assert_eq!(settings.palette.as_deref(), Some("FFB000,000000"));
+    assert_eq!(program.keys.len(), 3);
+
+    // Values of unexpected types are left out
+    let program = &database.programs["0123456789abcdef0123456789abcdef01234567"];
+    assert_eq!(program.platform.as_deref(), Some("megachip8"));
+    assert_eq!(program.tickrate, None);
+    assert_eq!(program.colors, None);
+    assert_eq!(program.quirk_preset(), None);
+}
+
+// Bad entries fail with an error naming the file, also without a config
+// directory to blame
+#[test]
+fn test_run_with_bad_database() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("bad_database");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("database.json");
+    let rom = fs::read("tests/fixtures/flags.ch8").unwrap();
+    let entry = format!(
+        r#"{{ "{}": {{ "title": "Flags", "tickrate": 0 }} }}"#,
+        rom_hash(&rom)
+    );
+    fs::write(&path, entry).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_emu_rs"))
+        .args([
+            "run",
+            "tests/fixtures/flags.ch8",
+            "--headless",
+            "--database",
+        ])
+        .arg(&path)
+        .env_remove("HOME")
+        .env_remove("XDG_CONFIG_HOME")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        format!("{}: Tickrate 0 for Flags\n", path.display())
+    );
+}
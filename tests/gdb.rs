@@ -0,0 +1,230 @@
+// Talks the remote serial protocol to the GDB stub like GDB would, with the
+// CPU running on its own thread the same way main.rs drives it.
+
+use emu_rs::emu::arch::chip8::cheats::Cheats;
+use emu_rs::emu::arch::chip8::{self, gdb};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const ROM: [u8; 8] = [
+    0x60, 0x05, // V0 = 5
+    0x70, 0x01, // V0 += 1
+    0x12, 0x02, // jump back to 202
+    0x00, 0x00, // unknown opcode
+];
+
+struct Client {
+    stream: TcpStream,
+    active: Arc<AtomicBool>,
+    cpu_thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Client {
+    fn start(rom: &[u8]) -> Self {
+        let mut debugger = gdb::listen("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(debugger.local_addr()).unwrap();
+
+        let mut cpu = chip8::CPU::new(
+            FrameBuffer::new(64, 32, 0u8),
+            Arc::new(Mutex::new(chip8::Keyboard::new())),
+        );
+        cpu.load_program(rom).unwrap();
+
+        let active = Arc::new(AtomicBool::new(true));
+        let local_active = active.clone();
+        let cpu_thread = thread::spawn(move || {
+            let mut cheats = Cheats::default();
+            let mut monitor = |cpu: &mut chip8::CPU, line: &str| cheats.command(cpu, line);
+            while local_active.load(Ordering::Relaxed) {
+                if !debugger.poll_with(&mut cpu, &mut monitor) {
+                    continue;
+                }
+                match cpu.execute() {
+                    Ok(()) => debugger.executed(),
+                    Err(e) => debugger.fault(e),
+                }
+            }
+        });
+
+        Self {
+            stream,
+            active,
+            cpu_thread: Some(cpu_thread),
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        self.stream.write_all(data).unwrap();
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.send_raw(format!("${}#{:02x}", packet, checksum).as_bytes());
+        assert_eq!(self.read_byte(), b'+');
+    }
+
+    fn receive(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                b'}' => data.push(self.read_byte() ^ 0x20),
+                byte => data.push(byte),
+            }
+        }
+        self.read_byte();
+        self.read_byte();
+        self.send_raw(b"+");
+        String::from_utf8(data).unwrap()
+    }
+
+    fn command(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.active.store(false, Ordering::Relaxed);
+        if let Some(cpu_thread) = self.cpu_thread.take() {
+            cpu_thread.join().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_gdb_target_description() {
+    let mut client = Client::start(&ROM);
+
+    assert!(client
+        .command("qSupported:multiprocess+")
+        .contains("qXfer:features:read+"));
+    assert_eq!(client.command("?"), "S05");
+
+    let mut xml = String::new();
+    loop {
+        let chunk = client.command(&format!(
+            "qXfer:features:read:target.xml:{:x},40",
+            xml.len()
+        ));
+        xml.push_str(&chunk[1..]);
+        if chunk.starts_with('l') {
+            break;
+        }
+        assert!(chunk.starts_with('m'));
+    }
+    assert_eq!(xml, gdb::TARGET_XML);
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+}
+
+#[test]
+fn test_gdb_registers_and_memory() {
+    let mut client = Client::start(&ROM);
+
+    // Paused at the entry point until resumed
+    let registers = client.command("g");
+    assert_eq!(registers.len(), 24 * 2);
+    assert_eq!(&registers[36..40], "0002");
+    assert_eq!(client.command("p11"), "0002");
+
+    assert_eq!(client.command("m200,6"), "600570011202");
+    assert_eq!(client.command("m10000,1"), "E01");
+    // Reads running past the end are cut short
+    assert_eq!(client.command("mfffd,4"), "0000");
+
+    // V0 = 5 becomes V0 = 9
+    assert_eq!(client.command("M201,1:09"), "OK");
+    assert_eq!(client.command("M10000,1:00"), "E01");
+
+    assert_eq!(client.command("Pa=42"), "OK");
+    assert_eq!(client.command("pa"), "42");
+    assert_eq!(client.command("P10=3412"), "OK");
+    assert_eq!(client.command("p10"), "3412");
+    // SP past the stack
+    assert_eq!(client.command("P12=ff00"), "E01");
+
+    assert_eq!(client.command("s"), "S05");
+    assert_eq!(client.command("p0"), "09");
+    assert_eq!(client.command("p11"), "0202");
+}
+
+#[test]
+fn test_gdb_breakpoints() {
+    let mut client = Client::start(&ROM);
+
+    assert_eq!(client.command("Z0,204,2"), "OK");
+    assert_eq!(client.command("c"), "S05");
+    assert_eq!(client.command("p11"), "0402");
+    assert_eq!(client.command("p0"), "06");
+
+    // Continuing from the breakpoint goes around the loop once
+    assert_eq!(client.command("c"), "S05");
+    assert_eq!(client.command("p11"), "0402");
+    assert_eq!(client.command("p0"), "07");
+
+    assert_eq!(client.command("z0,204,2"), "OK");
+    client.send("c");
+    thread::sleep(gdb::POLL_INTERVAL * 5);
+    client.send_raw(&[0x03]);
+    assert_eq!(client.receive(), "S02");
+    assert!(client.command("p0") != "07");
+}
+
+#[test]
+fn test_gdb_fault() {
+    // Jumps straight into the unknown opcode
+    let mut client = Client::start(&[0x12, 0x06, 0, 0, 0, 0, 0x00, 0x00]);
+
+    assert_eq!(client.command("c"), "S04");
+    assert_eq!(client.command("p11"), "0602");
+    assert_eq!(client.command("?"), "S04");
+
+    // Patching the faulting instruction lets it carry on
+    assert_eq!(client.command("M206,2:1206"), "OK");
+    assert_eq!(client.command("s"), "S05");
+    assert_eq!(client.command("p11"), "0602");
+
+    assert_eq!(client.command("D"), "OK");
+}
+
+fn hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn test_gdb_monitor() {
+    let mut client = Client::start(&ROM);
+
+    assert_eq!(
+        client.command(&format!("qRcmd,{}", hex("freeze V0 07"))),
+        hex("1: freeze V0 07\n")
+    );
+    assert_eq!(client.command("p0"), "07");
+    assert_eq!(
+        client.command(&format!("qRcmd,{}", hex("list"))),
+        hex("1: freeze V0 07\n")
+    );
+    assert_eq!(client.command("qRcmd,zz"), "E01");
+}
+
+#[test]
+fn test_gdb_bad_checksum() {
+    let mut client = Client::start(&ROM);
+
+    client.send_raw(b"$g#00");
+    assert_eq!(client.read_byte(), b'-');
+    assert_eq!(client.command("m200,2"), "6005");
+}
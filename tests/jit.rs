@@ -0,0 +1,265 @@
+// Runs ROMs on the JIT and on the plain interpreter side by side and compares
+// the complete machine state after every block.
+#![cfg(all(feature = "jit", target_arch = "x86_64"))]
+
+use emu_rs::emu::arch::chip8;
+use emu_rs::emu::core::FrameBuffer;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+
+    fs::read(path).unwrap()
+}
+
+fn create_cpu(program: &[u8], quirks: chip8::Quirks) -> chip8::CPU {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.quirks = quirks;
+    cpu.seed(0);
+    cpu.load_program(program).unwrap();
+    cpu
+}
+
+fn assert_same(jit: &chip8::CPU, reference: &chip8::CPU, cycle: usize) {
+    let context = format!("after {} cycles (pc {:04X})", cycle, reference.pc);
+
+    assert_eq!(jit.regs, reference.regs, "registers differ {}", context);
+    assert_eq!(jit.i, reference.i, "I differs {}", context);
+    assert_eq!(jit.pc, reference.pc, "PC differs {}", context);
+    assert_eq!(jit.sp, reference.sp, "SP differs {}", context);
+    assert_eq!(jit.timers, reference.timers, "timers differ {}", context);
+    assert_eq!(
+        &jit.stack[..],
+        &reference.stack[..],
+        "stack differs {}",
+        context
+    );
+    assert!(
+        jit.memory[..] == reference.memory[..],
+        "memory differs {}",
+        context
+    );
+
+    for y in 0..reference.frame_buf.height() {
+        for x in 0..reference.frame_buf.width() {
+            assert_eq!(
+                jit.frame_buf.read(x, y),
+                reference.frame_buf.read(x, y),
+                "pixel ({}, {}) differs {}",
+                x,
+                y,
+                context
+            );
+        }
+    }
+}
+
+fn run_lockstep(program: &[u8], quirks: chip8::Quirks, cycles: usize) {
+    let mut jit = chip8::Jit::new();
+    let mut jit_cpu = create_cpu(program, quirks);
+    let mut reference = create_cpu(program, quirks);
+    let mut cycle = 0;
+
+    while cycle < cycles {
+        // Stop at the next timer tick like the main loop does
+        let budget = 9 - cycle % 9;
+        let executed = jit.step(&mut jit_cpu, budget).unwrap();
+        assert!(executed >= 1 && executed <= budget);
+
+        for _ in 0..executed {
+            reference.execute().unwrap();
+        }
+        cycle += executed;
+
+        if cycle % 9 == 0 {
+            jit_cpu.tick();
+            reference.tick();
+        }
+
+        assert_same(&jit_cpu, &reference, cycle);
+    }
+}
+
+// Random ALU code mixed with a few skips and memory accesses, looping back to
+// the start
+fn random_program(seed: u64) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut program = Vec::new();
+
+    for _ in 0..200 {
+        let x = rng.gen_range(0, 16) as u16;
+        let y = rng.gen_range(0, 16) as u16;
+        let nn = rng.gen::<u8>() as u16;
+
+        let opcode = match rng.gen_range(0, 14) {
+            0 => 0x6000 | x << 8 | nn,
+            1 => 0x7000 | x << 8 | nn,
+            2 => 0x8000 | x << 8 | y << 4 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0, 9)],
+            3 => 0xF01E | x << 8,
+            // Keeps the stores below away from the code
+            4 => 0xA400 | rng.gen_range(0, 64),
+            5 => 0xF033 | x << 8,
+            6 => 0xF055 | (x & 0x3) << 8,
+            7 => 0xF065 | (x & 0x3) << 8,
+            8 => 0x3000 | x << 8 | nn,
+            9 => 0xC000 | x << 8 | nn,
+            _ => 0x8000 | x << 8 | y << 4 | 4,
+        };
+
+        program.push((opcode >> 8) as u8);
+        program.push(opcode as u8);
+    }
+
+    // Reset I so it can't run off the end of memory, then loop
+    program.extend_from_slice(&[0xA4, 0x00, 0x12, 0x00]);
+    program
+}
+
+#[test]
+fn test_jit_fixtures() {
+    for name in &["flags.ch8", "quirks.ch8", "font.ch8"] {
+        for preset in &chip8::QUIRK_PRESETS {
+            let quirks = chip8::Quirks::preset(preset).unwrap();
+            run_lockstep(&fixture(name), quirks, 1000);
+        }
+    }
+}
+
+#[test]
+fn test_jit_random_programs() {
+    for seed in 0..20 {
+        for preset in &chip8::QUIRK_PRESETS {
+            let quirks = chip8::Quirks::preset(preset).unwrap();
+            run_lockstep(&random_program(seed), quirks, 2000);
+        }
+    }
+}
+
+#[test]
+fn test_jit_self_modifying_code() {
+    let program = [
+        0x60, 0x65, // V0 = 0x65
+        0x61, 0x42, // V1 = 0x42
+        0x22, 0x10, // call 0x210, compiles the block there
+        0xA2, 0x10, // I = 0x210
+        0xF1, 0x55, // overwrite it with 6542
+        0x22, 0x10, // call 0x210 again
+        0x12, 0x0C, // loop forever
+        0x00, 0x00, //
+        0x65, 0x77, // V5 = 0x77
+        0x00, 0xEE, // return
+    ];
+
+    run_lockstep(&program, chip8::Quirks::default(), 20);
+
+    let mut jit = chip8::Jit::new();
+    let mut cpu = create_cpu(&program, chip8::Quirks::default());
+    jit.run(&mut cpu, 20).unwrap();
+    assert_eq!(cpu.regs[5], 0x42);
+}
+
+#[test]
+fn test_jit_quirk_change() {
+    let program = random_program(42);
+    let mut jit = chip8::Jit::new();
+    let mut jit_cpu = create_cpu(&program, chip8::Quirks::default());
+    let mut reference = create_cpu(&program, chip8::Quirks::default());
+
+    jit.run(&mut jit_cpu, 100).unwrap();
+    for _ in 0..100 {
+        reference.execute().unwrap();
+    }
+
+    // Blocks compiled for the old quirks must not be reused
+    jit_cpu.quirks = chip8::Quirks::chip8();
+    reference.quirks = chip8::Quirks::chip8();
+
+    let executed = jit.run(&mut jit_cpu, 1000).unwrap();
+    for _ in 0..executed {
+        reference.execute().unwrap();
+    }
+
+    assert_same(&jit_cpu, &reference, 100 + executed);
+}
+
+#[test]
+fn test_jit_jump_past_memory() {
+    // The last two bytes are one short of an opcode
+    let mut jit = chip8::Jit::new();
+    let mut cpu = create_cpu(&[], chip8::Quirks::default());
+    cpu.pc = 0xFFFE;
+
+    let error = jit.run(&mut cpu, 10).unwrap_err();
+    assert_eq!(
+        error,
+        chip8::Error::InvalidAddress {
+            pc: 0xFFFE,
+            address: 0xFFFF
+        }
+    );
+}
+
+#[test]
+fn test_jit_notices_memory_changes() {
+    let program = [
+        0x60, 0x01, // V0 = 1
+        0x61, 0x02, // V1 = 2
+        0x12, 0x00, // loop
+    ];
+    let mut jit = chip8::Jit::new();
+    let mut cpu = create_cpu(&program, chip8::Quirks::default());
+    jit.run(&mut cpu, 3).unwrap();
+    let state = chip8::SaveState::capture(&cpu);
+
+    // A cheat or a JSON-RPC client patches the compiled block
+    cpu.write_memory(0x201, 0x07);
+    jit.run(&mut cpu, 3).unwrap();
+    assert_eq!(cpu.regs[0], 7);
+
+    // Restoring a state brings the old code back
+    state.restore(&mut cpu);
+    cpu.regs[0] = 0;
+    jit.run(&mut cpu, 3).unwrap();
+    assert_eq!(cpu.regs[0], 1);
+
+    // Reloading the program does too
+    cpu.write_memory(0x201, 0x07);
+    jit.run(&mut cpu, 3).unwrap();
+    cpu.load_program(&program).unwrap();
+    cpu.pc = chip8::PROGRAM_ENTRY;
+    jit.run(&mut cpu, 3).unwrap();
+    assert_eq!(cpu.regs[0], 1);
+}
+
+#[test]
+fn test_jit_backend_headless() {
+    let run = |backend: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_emu_rs"))
+            .arg("run")
+            .arg(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/quirks.ch8"))
+            .args(["--headless", "--frames", "120", "--quirks", "schip"])
+            .args(["--backend", backend])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    };
+
+    assert_eq!(run("jit"), run("interpreter"));
+}
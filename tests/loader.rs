@@ -0,0 +1,192 @@
+use emu_rs::emu::arch::chip8::analysis::Platform;
+use emu_rs::emu::arch::chip8::config::{rom_hash, Settings};
+use emu_rs::emu::arch::chip8::loader::{decode, Format, Options};
+use emu_rs::emu::arch::chip8::Error;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{Cursor, Write};
+
+fn rom() -> Vec<u8> {
+    fs::read("tests/fixtures/flags.ch8").unwrap()
+}
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        writer
+            .start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+// Two bits per pixel over two frames of 128x16
+fn cartridge(json: &str) -> Vec<u8> {
+    let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(json.as_bytes());
+
+    let mut pixels: Vec<u8> = bytes
+        .iter()
+        .flat_map(|&byte| vec![byte >> 6, byte >> 4 & 3, byte >> 2 & 3, byte & 3])
+        .collect();
+    pixels.resize(128 * 32, 0);
+
+    let mut gif = Vec::new();
+    {
+        let palette = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255];
+        let mut encoder = gif::Encoder::new(&mut gif, 128, 16, &palette).unwrap();
+        for frame in pixels.chunks(128 * 16) {
+            let frame = gif::Frame::from_indexed_pixels(128, 16, frame, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+    }
+    gif
+}
+
+#[test]
+fn test_raw() {
+    let loaded = decode(rom()).unwrap();
+
+    assert_eq!(loaded.format, Format::Raw);
+    assert_eq!(loaded.program, rom());
+    assert_eq!(loaded.hash, rom_hash(&rom()));
+    assert_eq!(loaded.cartridge, None);
+}
+
+#[test]
+fn test_gzip() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&rom()).unwrap();
+    let loaded = decode(encoder.finish().unwrap()).unwrap();
+
+    assert_eq!(loaded.format, Format::Gzip);
+    assert_eq!(loaded.program, rom());
+    assert_eq!(loaded.hash, rom_hash(&rom()));
+
+    // Anything unpacking to more than fits into memory is refused
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&vec![0; 0x20000]).unwrap();
+    assert_eq!(
+        decode(encoder.finish().unwrap()).unwrap_err(),
+        "Unpacks to more than 65536 bytes"
+    );
+}
+
+#[test]
+fn test_zip() {
+    let loaded = decode(zip(&[("game.bin", &rom())])).unwrap();
+    assert_eq!(loaded.format, Format::Zip("game.bin".to_string()));
+    assert_eq!(loaded.program, rom());
+
+    // Next to other files the ROM is told by its extension
+    let files: &[(&str, &[u8])] = &[("readme.txt", b"Hi"), ("roms/flags.ch8", &rom())];
+    let loaded = decode(zip(files)).unwrap();
+    assert_eq!(loaded.format.to_string(), "zip, roms/flags.ch8");
+    assert_eq!(loaded.program, rom());
+
+    let files: &[(&str, &[u8])] = &[("a.ch8", &[0]), ("b.sc8", &[1])];
+    assert_eq!(
+        decode(zip(files)).unwrap_err(),
+        "More than one ROM: a.ch8, b.sc8"
+    );
+    let files: &[(&str, &[u8])] = &[("a.txt", &[0]), ("b.txt", &[1])];
+    assert_eq!(decode(zip(files)).unwrap_err(), "No ROM among a.txt, b.txt");
+    assert_eq!(decode(zip(&[])).unwrap_err(), "The archive is empty");
+}
+
+#[test]
+fn test_octo_cartridge() {
+    let json = r#"{
+        "program": ": main\n  v0 := 1\n  loop again",
+        "options": { "tickrate": 20, "shiftQuirks": true }
+    }"#;
+    let loaded = decode(cartridge(json)).unwrap();
+
+    assert_eq!(loaded.format, Format::OctoCartridge);
+    assert_eq!(loaded.program, vec![0x12, 0x02, 0x60, 0x01, 0x12, 0x04]);
+    let options = loaded.cartridge.unwrap().options;
+    assert_eq!(options.tickrate, Some(20));
+    assert_eq!(options.shift_quirks, Some(true));
+
+    // A tickrate the clock can't hold is refused
+    let json = r#"{ "program": ": main", "options": { "tickrate": 4294967295 } }"#;
+    assert_eq!(
+        decode(cartridge(json)).unwrap_err(),
+        "Invalid cartridge data: tickrate 4294967295 is too high"
+    );
+    let json = r#"{ "program": ": main", "options": { "tickrate": 0 } }"#;
+    assert_eq!(
+        decode(cartridge(json)).unwrap_err(),
+        "Invalid cartridge data: tickrate 0 is too low"
+    );
+    let json = r#"{ "program": ": main", "options": { "fillColor": "orange" } }"#;
+    assert_eq!(
+        decode(cartridge(json)).unwrap_err(),
+        "Invalid cartridge data: fillColor 'orange' isn't a #RRGGBB color"
+    );
+    let json = r##"{ "program": ": main", "options": { "backgroundColor": "#12345" } }"##;
+    assert_eq!(
+        decode(cartridge(json)).unwrap_err(),
+        "Invalid cartridge data: backgroundColor '#12345' isn't a #RRGGBB color"
+    );
+
+    let error = decode(cartridge(r#"{ "program": "clear" }"#)).unwrap_err();
+    assert_eq!(
+        error,
+        "Can't compile the cartridge's program: Line 1: The program has no 'main' label"
+    );
+    let error = decode(cartridge("[]")).unwrap_err();
+    assert!(error.starts_with("Invalid cartridge data"), "{}", error);
+}
+
+#[test]
+fn test_size() {
+    let loaded = decode(vec![0; 0xE00]).unwrap();
+    assert_eq!(loaded.check_size(Platform::Chip8), Ok(()));
+
+    let loaded = decode(vec![0; 0xE01]).unwrap();
+    assert_eq!(
+        loaded.check_size(Platform::SuperChip),
+        Err(Error::RomTooLarge {
+            size: 0xE01,
+            max: 0xE00
+        })
+    );
+    assert_eq!(loaded.check_size(Platform::XoChip), Ok(()));
+}
+
+#[test]
+fn test_octo_options() {
+    let options = |json: &str| serde_json::from_str::<Options>(json).unwrap();
+
+    assert_eq!(options("{}").settings(), Settings::default());
+
+    // Everything Octo saves, with what emu_rs doesn't use
+    let settings = options(
+        r##"{
+            "tickrate": 7, "fillColor": "#FFFFFF", "fillColor2": "#FF00FF",
+            "blendColor": "#00FFFF", "backgroundColor": "#000000",
+            "buzzColor": "#FFAA00", "quietColor": "#000000", "shiftQuirks": false,
+            "loadStoreQuirks": false, "vfOrderQuirks": false, "clipQuirks": true,
+            "vBlankQuirks": true, "jumpQuirks": false, "logicQuirks": true,
+            "screenRotation": 0, "maxSize": 3215, "touchInputMode": "none",
+            "fontStyle": "octo"
+        }"##,
+    )
+    .settings();
+    assert_eq!(settings.clock, Some(420));
+    assert_eq!(settings.quirks.as_deref(), Some("chip8"));
+    assert_eq!(settings.palette.as_deref(), Some("#FFFFFF,#000000"));
+
+    // Missing quirks and colors are Octo's defaults
+    let settings = options(r##"{ "jumpQuirks": true, "fillColor": "#123456" }"##).settings();
+    assert_eq!(
+        settings.quirks.as_deref(),
+        Some("shift_vy,load_store_inc_i,jump_vx,wrap_sprites")
+    );
+    assert_eq!(settings.palette.as_deref(), Some("#123456,#996600"));
+    assert_eq!(settings.clock, None);
+}
@@ -0,0 +1,228 @@
+use emu_rs::emu::arch::chip8::lockstep::{self, Machine, Options, Side};
+use emu_rs::emu::arch::chip8::{self, Movie};
+
+use std::fs;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+
+    fs::read(path).unwrap()
+}
+
+fn side(spec: &str) -> Side {
+    Side::parse(spec).unwrap()
+}
+
+fn options(frames: u64) -> Options {
+    Options {
+        frames,
+        ..Options::default()
+    }
+}
+
+// Waits for a key and draws its font glyph in the top left corner
+const KEY_ROM: [u8; 8] = [
+    0xF0, 0x0A, // V0 = key
+    0xF0, 0x29, // I = glyph of V0
+    0xD1, 0x15, // draw at V1, V1
+    0x12, 0x06, // loop forever
+];
+
+#[test]
+fn test_lockstep_interpreter_cached() {
+    for name in &["flags.ch8", "quirks.ch8", "font.ch8"] {
+        for preset in &chip8::QUIRK_PRESETS {
+            let a = side(&format!("interpreter:{}", preset));
+            let b = side(&format!("cached:{}", preset));
+            let result = lockstep::run(&fixture(name), &Movie::new(), a, b, &options(120)).unwrap();
+
+            if let Err(divergence) = result {
+                panic!("{} ({}): {}", name, preset, divergence);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lockstep_quirk_divergence() {
+    let rom = fixture("quirks.ch8");
+    let a = side("interpreter:default");
+    let b = side("interpreter:chip8");
+
+    let divergence = lockstep::run(&rom, &Movie::new(), a, b, &options(120))
+        .unwrap()
+        .unwrap_err();
+
+    // The first quirk hit is 8XY1 resetting VF
+    assert_eq!(divergence.opcode & 0xF00F, 0x8001);
+    assert_eq!(divergence.registers, vec!["VF: 01 != 00".to_string()]);
+    assert!(divergence.to_string().contains("8011 Or(0, 1)"));
+}
+
+#[test]
+fn test_lockstep_screen_diff() {
+    let rom = [
+        0x60, 0x3C, // V0 = 60
+        0xA0, 0x00, // I = glyph 0
+        0xD0, 0x05, // draw at V0, V0, the last row only shows when wrapping
+        0x12, 0x06, // loop forever
+    ];
+    let a = side("interpreter");
+    let b = Side {
+        quirks: chip8::Quirks {
+            wrap_sprites: true,
+            ..chip8::Quirks::default()
+        },
+        ..a
+    };
+
+    let divergence = lockstep::run(&rom, &Movie::new(), a, b, &options(10))
+        .unwrap()
+        .unwrap_err();
+
+    assert_eq!(divergence.pc, 0x204);
+    assert!(divergence.registers.is_empty());
+    assert!(divergence.to_string().contains("screen differs"));
+    let screen = divergence.screen.unwrap();
+    let rows: Vec<&str> = screen.lines().collect();
+    assert_eq!(rows.len(), 32);
+    assert_eq!(&rows[0][60..], "bbbb");
+    assert_eq!(&rows[28][60..], "####");
+}
+
+#[test]
+fn test_movie_parse() {
+    let movie = Movie::parse("# comment\n10 +7\n\n12 -7\n11 +F\n").unwrap();
+
+    assert_eq!(movie.events.len(), 3);
+    assert_eq!(movie.events[1].frame, 11);
+    assert_eq!(movie.events[1].key, 0xF);
+    assert_eq!(movie.len(), 13);
+    assert_eq!(movie.to_string(), "10 +7\n11 +F\n12 -7\n");
+    assert_eq!(Movie::parse(&movie.to_string()).unwrap(), movie);
+
+    assert!(Movie::parse("10 7").is_err());
+    assert!(Movie::parse("10 +G").is_err());
+    assert!(Movie::parse("x +1").is_err());
+    assert!(Movie::parse("10 +1 2").is_err());
+}
+
+#[test]
+fn test_movie_replay() {
+    let movie = Movie::parse("10 +7\n12 -7\n").unwrap();
+    let mut machine =
+        Machine::new(&KEY_ROM, side("interpreter"), &movie, &Options::default()).unwrap();
+
+    while machine.frame() < 10 {
+        machine.step(1).unwrap();
+    }
+    assert_eq!(machine.cpu.pc, 0x200);
+
+    while machine.frame() < 20 {
+        machine.step(1).unwrap();
+    }
+    assert_eq!(machine.cpu.regs[0], 7);
+    assert_eq!(machine.cpu.pc, 0x206);
+    // Top row of the 7 glyph
+    assert_ne!(machine.cpu.frame_buf.read(0, 0), 0);
+    assert_ne!(machine.cpu.frame_buf.read(3, 0), 0);
+
+    let result = lockstep::run(
+        &KEY_ROM,
+        &movie,
+        side("interpreter"),
+        side("cached"),
+        &options(20),
+    );
+    assert!(result.unwrap().is_ok());
+}
+
+#[test]
+fn test_lockstep_trace() {
+    let rom = fixture("quirks.ch8");
+    let mut trace = Vec::new();
+
+    let cycles = lockstep::record(
+        &rom,
+        &Movie::new(),
+        side("interpreter:schip"),
+        &options(60),
+        &mut trace,
+    )
+    .unwrap();
+    assert_eq!(cycles, 60 * 9);
+
+    let result = lockstep::replay(
+        &rom,
+        &Movie::new(),
+        side("cached:schip"),
+        &options(60),
+        &mut &trace[..],
+    )
+    .unwrap();
+    assert_eq!(result.unwrap(), cycles);
+
+    let divergence = lockstep::replay(
+        &rom,
+        &Movie::new(),
+        side("cached:chip8"),
+        &options(60),
+        &mut &trace[..],
+    )
+    .unwrap()
+    .unwrap_err();
+    assert!(divergence.cycle < cycles);
+
+    let garbage = lockstep::replay(
+        &rom,
+        &Movie::new(),
+        side("cached"),
+        &options(60),
+        &mut &b"not a trace"[..],
+    );
+    assert!(garbage.is_err());
+}
+
+#[test]
+fn test_side_parse() {
+    assert_eq!(side("cached").quirks, chip8::Quirks::default());
+    assert_eq!(side("interpreter:schip").quirks, chip8::Quirks::schip());
+    assert!(Side::parse("cached:foo").is_err());
+    assert!(Side::parse("foo").is_err());
+}
+
+#[cfg(all(feature = "jit", target_arch = "x86_64"))]
+#[test]
+fn test_lockstep_jit_blocks() {
+    // Straight line register loads run as one native block
+    let rom = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x00];
+    let movie = Movie::new();
+    let mut machine = Machine::new(&rom, side("jit"), &movie, &Options::default()).unwrap();
+    assert!(machine.step(9).unwrap() > 1);
+
+    for name in &["flags.ch8", "quirks.ch8", "font.ch8"] {
+        for preset in &chip8::QUIRK_PRESETS {
+            let a = side(&format!("interpreter:{}", preset));
+            let b = side(&format!("jit:{}", preset));
+            let rom = fixture(name);
+
+            // Either side may lead
+            for (a, b) in &[(a, b), (b, a)] {
+                let result = lockstep::run(&rom, &Movie::new(), *a, *b, &options(120)).unwrap();
+                if let Err(divergence) = result {
+                    panic!("{} ({}): {}", name, preset, divergence);
+                }
+            }
+
+            let mut trace = Vec::new();
+            let cycles =
+                lockstep::record(&rom, &Movie::new(), a, &options(60), &mut trace).unwrap();
+            let result = lockstep::replay(&rom, &Movie::new(), b, &options(60), &mut &trace[..]);
+            assert_eq!(result.unwrap().unwrap(), cycles, "{} ({})", name, preset);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use emu_rs::emu::arch::chip8::menu::{self, Command, Menu, MenuKey};
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+fn menu() -> Menu {
+    let mut menu = Menu::new(540, "schip", true, "ff0000,000000");
+    menu.set_roms(
+        vec![PathBuf::from("roms/a.ch8"), PathBuf::from("roms/b.ch8")],
+        Some(Path::new("b.ch8")),
+    );
+    menu.toggle();
+    menu
+}
+
+// Moves the selection down to the entry named `name`
+fn select(menu: &mut Menu, name: &str) {
+    menu.selected = 0;
+    while !menu.lines()[menu.selected + 2].text.starts_with(name) {
+        assert_eq!(menu.key(MenuKey::Down), None);
+    }
+}
+
+#[test]
+fn test_menu_lines() {
+    let menu = menu();
+    let lines = menu.lines();
+    let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(
+        text,
+        vec![
+            "Menu",
+            "",
+            "ROM       < b.ch8 >",
+            "Reset",
+            "Speed     < 540 Hz >",
+            "Quirks    < schip >",
+            "Scaler    < epx >",
+            "Palette   < custom >",
+            "Slot      < 1 >",
+            "Save state",
+            "Load state",
+            "",
+            "Arrows, Enter, Esc closes",
+        ]
+    );
+    assert_eq!(lines.len() as u32, menu::MENU_ROWS);
+    assert_eq!(lines[2].highlight, Some((0, menu::MENU_COLUMNS as usize)));
+    assert_eq!(lines[3].highlight, None);
+}
+
+#[test]
+fn test_menu_navigation() {
+    let mut menu = menu();
+    assert_eq!(menu.key(MenuKey::Up), None);
+    assert!(menu.lines()[menu.selected + 2]
+        .text
+        .starts_with("Load state"));
+    assert_eq!(menu.key(MenuKey::Down), None);
+    assert_eq!(menu.selected, 0);
+
+    // The picker wraps around and loads on Enter, which closes the menu
+    assert_eq!(menu.key(MenuKey::Right), None);
+    assert!(menu.lines()[2].text.contains("a.ch8"));
+    assert!(menu.open);
+    assert_eq!(
+        menu.key(MenuKey::Enter),
+        Some(Command::LoadRom(PathBuf::from("roms/a.ch8")))
+    );
+    assert!(!menu.open);
+}
+
+#[test]
+fn test_menu_values() {
+    let mut menu = menu();
+
+    select(&mut menu, "Speed");
+    assert_eq!(menu.key(MenuKey::Right), Some(Command::SetClock(600)));
+    for _ in 0..20 {
+        menu.key(MenuKey::Left);
+    }
+    assert_eq!(menu.clock, 60);
+    assert_eq!(menu.key(MenuKey::Left), None);
+
+    select(&mut menu, "Quirks");
+    assert_eq!(
+        menu.key(MenuKey::Right),
+        Some(Command::SetQuirks("default"))
+    );
+    assert_eq!(menu.key(MenuKey::Left), Some(Command::SetQuirks("schip")));
+
+    select(&mut menu, "Scaler");
+    assert_eq!(menu.key(MenuKey::Enter), Some(Command::SetScaler(false)));
+    assert_eq!(menu.key(MenuKey::Left), Some(Command::SetScaler(true)));
+
+    // Custom palettes start over at either end
+    select(&mut menu, "Palette");
+    assert_eq!(menu.key(MenuKey::Left), Some(Command::SetPalette("lcd")));
+    assert_eq!(menu.key(MenuKey::Right), Some(Command::SetPalette("white")));
+    assert!(menu.open);
+}
+
+#[test]
+fn test_menu_states() {
+    let mut menu = menu();
+    select(&mut menu, "Slot");
+    menu.key(MenuKey::Left);
+    assert_eq!(menu.slot, menu::SLOTS);
+    menu.key(MenuKey::Right);
+    menu.key(MenuKey::Right);
+    assert_eq!(menu.slot, 2);
+
+    select(&mut menu, "Save state");
+    assert_eq!(menu.key(MenuKey::Enter), Some(Command::SaveState(2)));
+    assert!(!menu.open);
+
+    menu.toggle();
+    select(&mut menu, "Load state");
+    assert_eq!(menu.key(MenuKey::Enter), Some(Command::LoadState(2)));
+}
+
+#[test]
+fn test_menu_locked() {
+    let mut menu = menu();
+    menu.locked = true;
+
+    for (name, key) in [
+        ("ROM", MenuKey::Enter),
+        ("Reset", MenuKey::Enter),
+        ("Speed", MenuKey::Right),
+        ("Quirks", MenuKey::Left),
+        ("Load state", MenuKey::Enter),
+    ] {
+        select(&mut menu, name);
+        assert_eq!(menu.key(key), None, "{}", name);
+    }
+    assert_eq!(menu.clock, 540);
+    assert!(menu.open);
+    assert_eq!(
+        menu.notification(),
+        Some("Not with netplay, gdb, rpc or movies")
+    );
+
+    select(&mut menu, "Palette");
+    assert_eq!(menu.key(MenuKey::Right), Some(Command::SetPalette("white")));
+    select(&mut menu, "Save state");
+    assert_eq!(menu.key(MenuKey::Enter), Some(Command::SaveState(1)));
+}
+
+#[test]
+fn test_menu_notification() {
+    let mut menu = Menu::new(540, "default", false, "white");
+    assert_eq!(menu.notification(), None);
+    menu.notify("State saved to slot 2");
+    assert_eq!(menu.notification(), Some("State saved to slot 2"));
+
+    let mut buf = menu::notification_buffer();
+    menu.render_notification(&mut buf);
+    assert!(buf.frame().iter().any(|&pixel| pixel != 0));
+
+    // Without ROMs there's nothing to load
+    menu.toggle();
+    assert!(menu.lines()[2].text.contains("none found"));
+    assert_eq!(menu.key(MenuKey::Enter), None);
+    assert_eq!(menu.key(MenuKey::Right), None);
+}
+
+#[test]
+fn test_list_roms() {
+    let dir = env::temp_dir().join(format!("emu_rs_menu_{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub.ch8")).unwrap();
+    for name in ["pong.ch8", "b.ZIP", "notes.txt", "a.sc8"] {
+        fs::write(dir.join(name), [0x12, 0x00]).unwrap();
+    }
+
+    let roms = menu::list_roms(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let names: Vec<_> = roms
+        .iter()
+        .map(|rom| rom.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a.sc8", "b.ZIP", "pong.ch8"]);
+}
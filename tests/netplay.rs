@@ -0,0 +1,246 @@
+use emu_rs::emu::arch::chip8::netplay::{self, Session};
+use emu_rs::emu::arch::chip8::{Keyboard, SaveState, CPU};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, fs, thread};
+
+// Counts frames with key 1 held in V1 and with key C held in V2, and draws
+// random numbers into V4
+const ROM: [u8; 22] = [
+    0x6E, 0x01, // VE = 1
+    0xEE, 0x9E, // skip if key VE is held
+    0x12, 0x08, // jump to 208
+    0x71, 0x01, // V1 += 1
+    0x6E, 0x0C, // VE = C
+    0xEE, 0x9E, // skip if key VE is held
+    0x12, 0x10, // jump to 210
+    0x72, 0x01, // V2 += 1
+    0xC3, 0xFF, // V3 = random
+    0x84, 0x34, // V4 += V3
+    0x12, 0x00, // jump to 200
+];
+
+// Instructions per frame, one pass through the loop
+const CYCLES: usize = 9;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn cpu() -> CPU {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.seed(3);
+    cpu.load_program(&ROM).unwrap();
+    cpu
+}
+
+fn run_frame(cpu: &mut CPU) -> Result<(), String> {
+    for _ in 0..CYCLES {
+        cpu.execute().map_err(|e| e.to_string())?;
+    }
+    cpu.tick();
+    Ok(())
+}
+
+// A session on a free port and the port
+fn bind(mask: u16, game: u64) -> (Session, u16) {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    drop(socket);
+    (
+        Session::connect(&format!("127.0.0.1:{}", port), "127.0.0.1:9", mask, game).unwrap(),
+        port,
+    )
+}
+
+// Two sessions talking to each other
+fn pair(masks: (u16, u16), games: (u64, u64)) -> (Session, Session) {
+    let (a, a_port) = bind(masks.0, games.0);
+    let (b, b_port) = bind(masks.1, games.1);
+    drop((a, b));
+    let a = format!("127.0.0.1:{}", a_port);
+    let b = format!("127.0.0.1:{}", b_port);
+    (
+        Session::connect(&a, &b, masks.0, games.0).unwrap(),
+        Session::connect(&b, &a, masks.1, games.1).unwrap(),
+    )
+}
+
+// Runs one frame with `key` held if `held`, returns whether it started
+fn frame(session: &mut Session, cpu: &mut CPU, key: usize, held: bool) -> Result<bool, String> {
+    let mut keys = [false; 16];
+    keys[key] = held;
+    if !session.start_frame(cpu, &keys, run_frame)? {
+        return Ok(false);
+    }
+    run_frame(cpu)?;
+    Ok(true)
+}
+
+#[test]
+fn test_netplay_rollback() {
+    let game = netplay::game_id(&ROM, "");
+    let (mut a, mut b) = pair((0x0002, 0x1000), (game, game));
+    let (mut a_cpu, mut b_cpu) = (cpu(), cpu());
+
+    // A runs ahead, predicting B holds nothing while B holds C in frames 2-4
+    for f in 0..6 {
+        assert!(frame(&mut a, &mut a_cpu, 1, f % 2 == 0).unwrap());
+    }
+    for f in 0..12 {
+        assert!(frame(&mut b, &mut b_cpu, 0xC, (2..5).contains(&f)).unwrap());
+    }
+    for f in 6..12 {
+        assert!(frame(&mut a, &mut a_cpu, 1, f % 2 == 0).unwrap());
+    }
+
+    let finish_b = thread::spawn(move || {
+        b.finish(&mut b_cpu, run_frame, TIMEOUT).unwrap();
+        SaveState::capture(&b_cpu)
+    });
+    a.finish(&mut a_cpu, run_frame, TIMEOUT).unwrap();
+    let b_state = finish_b.join().unwrap();
+
+    assert!(a.rollbacks > 0);
+    assert_eq!(SaveState::capture(&a_cpu), b_state);
+    assert_eq!((a_cpu.regs[1], a_cpu.regs[2]), (6, 3));
+}
+
+#[test]
+fn test_netplay_waits_for_the_peer() {
+    let game = netplay::game_id(&ROM, "");
+    let (mut a, _b) = pair((0x0002, 0x1000), (game, game));
+    let mut cpu = cpu();
+
+    for _ in 0..netplay::MAX_ROLLBACK {
+        assert!(frame(&mut a, &mut cpu, 1, false).unwrap());
+    }
+    assert!(!frame(&mut a, &mut cpu, 1, false).unwrap());
+    assert_eq!(a.frame(), netplay::MAX_ROLLBACK);
+    assert!(a
+        .finish(&mut cpu, run_frame, Duration::from_millis(50))
+        .is_err());
+}
+
+#[test]
+fn test_netplay_desync() {
+    let game = netplay::game_id(&ROM, "");
+    let (mut a, mut b) = pair((0x0002, 0x1000), (game, game));
+    let (mut a_cpu, mut b_cpu) = (cpu(), cpu());
+    b_cpu.regs[5] = 1;
+
+    frame(&mut b, &mut b_cpu, 0xC, false).unwrap();
+    let error = frame(&mut a, &mut a_cpu, 1, false).unwrap_err();
+    assert_eq!(error, "Desync at frame 0, the peers' states differ");
+}
+
+// A check that can't be compared fails instead of being skipped
+#[test]
+fn test_netplay_missing_check() {
+    let game = netplay::game_id(&ROM, "");
+    let (a, a_port) = bind(0x0002, game);
+    let (_, b_port) = bind(0x1000, game);
+    drop(a);
+    let a = format!("127.0.0.1:{}", a_port);
+    let b = format!("127.0.0.1:{}", b_port);
+    let mut session = Session::connect(&a, &b, 0x0002, game).unwrap();
+    let mut cpu = cpu();
+
+    // Checks frame 0, the next one is 30 frames later
+    frame(&mut session, &mut cpu, 1, false).unwrap();
+
+    // A peer claiming to have checked frame 1, with no keys
+    let mut packet = b"C8NP\x01".to_vec();
+    packet.extend_from_slice(&game.to_le_bytes());
+    packet.extend_from_slice(&0x1000u16.to_le_bytes());
+    packet.extend_from_slice(&0u64.to_le_bytes());
+    packet.push(0);
+    packet.extend_from_slice(&0u64.to_le_bytes());
+    packet.extend_from_slice(&1u64.to_le_bytes());
+    packet.extend_from_slice(&0u64.to_le_bytes());
+    let peer = std::net::UdpSocket::bind(&b).unwrap();
+    peer.send_to(&packet, &a).unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(
+        frame(&mut session, &mut cpu, 1, false).unwrap_err(),
+        "No checksum of frame 1 to compare with the peer's"
+    );
+}
+
+#[test]
+fn test_netplay_mismatched_peers() {
+    let game = netplay::game_id(&ROM, "");
+    let other = netplay::game_id(&ROM, "schip");
+    assert_ne!(game, other);
+
+    let (mut a, mut b) = pair((0x0002, 0x1000), (game, other));
+    let (mut a_cpu, mut b_cpu) = (cpu(), cpu());
+    frame(&mut b, &mut b_cpu, 0xC, false).unwrap();
+    assert_eq!(
+        frame(&mut a, &mut a_cpu, 1, false).unwrap_err(),
+        "The peer runs a different ROM or different settings"
+    );
+
+    let (mut a, mut b) = pair((0x0012, 0x1010), (game, game));
+    frame(&mut b, &mut b_cpu, 0xC, false).unwrap();
+    assert_eq!(
+        frame(&mut a, &mut a_cpu, 1, false).unwrap_err(),
+        "Both players own key 4"
+    );
+}
+
+// Two emulator processes playing each other headless end on the same screen
+#[test]
+fn test_netplay_processes() {
+    let dir = env::temp_dir().join(format!("emu_rs_netplay_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // Draws the digit of the last key pressed
+    let rom = [
+        0xF0, 0x0A, // V0 = key
+        0xF0, 0x29, // I = glyph of V0
+        0x00, 0xE0, // clear the screen
+        0xD1, 0x15, // draw at V1, V1
+        0x12, 0x00, // loop
+    ];
+    fs::write(dir.join("keys.ch8"), rom).unwrap();
+    fs::write(dir.join("a.txt"), "10 +1\n14 -1\n").unwrap();
+    fs::write(dir.join("b.txt"), "40 +C\n44 -C\n").unwrap();
+
+    let (_, a_port) = bind(0, 0);
+    let (_, b_port) = bind(0, 0);
+    let run = |movie: &str, keys: &str, port: u16, peer: u16| {
+        Command::new(env!("CARGO_BIN_EXE_emu_rs"))
+            .arg("run")
+            .arg(dir.join("keys.ch8"))
+            .args(["--headless", "--frames", "120", "--config"])
+            .arg(dir.join("config.toml"))
+            .arg("--cheats")
+            .arg(dir.join("cheats.txt"))
+            .arg("--play")
+            .arg(dir.join(movie))
+            .args(["--netplay", &format!("127.0.0.1:{}", peer)])
+            .args(["--netplay-port", &port.to_string()])
+            .args(["--netplay-keys", keys])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    };
+
+    let b = run("b.txt", "89ABCDEF", b_port, a_port);
+    let a = run("a.txt", "01234567", a_port, b_port);
+    let (a, b) = (a.wait_with_output().unwrap(), b.wait_with_output().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(a.status.success(), "{}", String::from_utf8_lossy(&a.stderr));
+    assert!(b.status.success(), "{}", String::from_utf8_lossy(&b.stderr));
+    assert_eq!(a.stdout, b.stdout);
+    // The C from the second player
+    let screen = String::from_utf8(a.stdout).unwrap();
+    assert!(screen.starts_with("####."), "{}", screen);
+}
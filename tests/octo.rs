@@ -0,0 +1,142 @@
+use emu_rs::emu::arch::chip8::octo::compile;
+
+#[test]
+fn test_statements() {
+    let source = "
+        : main
+            clear
+            v0 := 5
+            i := sprite     # forward reference
+            sprite v0 v0 1
+            loop again
+        : sprite 0xFF
+    ";
+
+    assert_eq!(
+        compile(source).unwrap(),
+        vec![0x12, 0x02, 0x00, 0xE0, 0x60, 0x05, 0xA2, 0x0C, 0xD0, 0x01, 0x12, 0x0A, 0xFF]
+    );
+}
+
+#[test]
+fn test_control_flow() {
+    let source = "
+        : main
+            if v1 == 3 then v2 += 1
+            if v1 != v2 begin
+                v3 := 1
+            else
+                v3 := 2
+            end
+            loop
+                while v0 < 10
+                v0 += 1
+            again
+            ;
+    ";
+
+    assert_eq!(
+        compile(source).unwrap(),
+        vec![
+            0x12, 0x02, 0x41, 0x03, 0x72, 0x01, 0x91, 0x20, 0x12, 0x0E, 0x63, 0x01, 0x12, 0x10,
+            0x63, 0x02, 0x6F, 0x0A, 0x8F, 0x07, 0x4F, 0x01, 0x12, 0x1C, 0x70, 0x01, 0x12, 0x10,
+            0x00, 0xEE,
+        ]
+    );
+}
+
+#[test]
+fn test_directives() {
+    let source = "
+        :const SPEED 3
+        :alias px v4
+        :macro twice op { op op }
+        : main
+            px += SPEED
+            twice clear
+            :unpack 0xA data
+            :next target
+            v5 := 0
+            i := long data
+            sub
+        : data 1 2
+        : sub
+            return
+            i := target
+    ";
+
+    assert_eq!(
+        compile(source).unwrap(),
+        vec![
+            0x12, 0x02, 0x74, 0x03, 0x00, 0xE0, 0x00, 0xE0, 0x60, 0xA2, 0x61, 0x14, 0x65, 0x00,
+            0xF0, 0x00, 0x02, 0x14, 0x22, 0x16, 0x01, 0x02, 0x00, 0xEE, 0xA2, 0x0D,
+        ]
+    );
+}
+
+#[test]
+fn test_calc() {
+    let source = "
+        :const WIDTH 64
+        :calc HALF { WIDTH / 2 }
+        # No precedence, right to left
+        :calc EIGHT { 2 * 3 + 1 }
+        :calc SEVEN { ( 2 * 3 ) + 1 }
+        :calc QUARTER { 0.25 }
+        :calc ONE { QUARTER * 4 }
+        : main
+            v0 := HALF
+            v1 := EIGHT
+            v2 := SEVEN
+            v3 := ONE
+        : data
+            :byte { 0xFF & data + 1 }
+            :byte { - 1 }
+            :byte { floor sqrt 17 }
+            :byte { 1 << 4 max 3 }
+            :byte { @ data }
+        :assert \"data fits\" { HERE <= 0x300 }
+    ";
+
+    assert_eq!(
+        compile(source).unwrap(),
+        vec![
+            0x12, 0x02, 0x60, 0x20, 0x61, 0x08, 0x62, 0x07, 0x63, 0x01, 0x0B, 0xFF, 0x04, 0x10,
+            0x0B,
+        ]
+    );
+}
+
+#[test]
+fn test_errors() {
+    let error = |source: &str| compile(source).unwrap_err().to_string();
+
+    assert_eq!(error(": main\nv0 := 300"), "Line 2: 300 is out of range");
+    assert_eq!(
+        error(": main\n\njump nowhere"),
+        "Line 3: Unknown label 'nowhere'"
+    );
+    assert_eq!(error("clear"), "Line 1: The program has no 'main' label");
+    assert_eq!(
+        error(": main\nloop\nclear"),
+        "Line 2: 'loop' without 'again'"
+    );
+    assert_eq!(
+        error(": main\n:stringmode x \"ab\" { :byte CHAR }"),
+        "Line 2: Unsupported directive ':stringmode'"
+    );
+    assert_eq!(
+        error(": main\n:calc x { later }\n: later"),
+        "Line 2: Unknown value 'later'"
+    );
+    assert_eq!(
+        error(": main\n:assert \"too big\" { HERE < 0x201 }"),
+        "Line 2: Assertion failed: too big"
+    );
+    assert_eq!(error(": main\n:assert { 0 }"), "Line 2: Assertion failed");
+    assert_eq!(
+        error(": main\n:byte { 2 pow 8 }"),
+        "Line 2: 256 doesn't fit into a byte"
+    );
+    assert_eq!(error(": main\n: main"), "Line 2: Duplicate label 'main'");
+}
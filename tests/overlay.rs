@@ -0,0 +1,122 @@
+use emu_rs::emu::arch::chip8::overlay::{self, Snapshot};
+use emu_rs::emu::arch::chip8::{self, DELAY};
+use emu_rs::emu::core::{draw_text, FrameBuffer, CELL_HEIGHT, CELL_WIDTH};
+
+use std::sync::{Arc, Mutex};
+
+fn cpu(rom: &[u8]) -> chip8::CPU {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.load_program(rom).unwrap();
+    cpu
+}
+
+// Renders the buffer with '#' for lit pixels
+fn render(buf: &FrameBuffer<u8>) -> Vec<String> {
+    (0..buf.height())
+        .map(|y| {
+            (0..buf.width())
+                .map(|x| if buf.read(x, y) != 0 { '#' } else { '.' })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_draw_text() {
+    let mut buf = FrameBuffer::new(CELL_WIDTH * 3, CELL_HEIGHT, 0u8);
+    draw_text(&mut buf, 0, 0, "1a", 255, false);
+    draw_text(&mut buf, 2, 0, " ", 255, true);
+
+    assert_eq!(
+        render(&buf),
+        vec![
+            ".#...#..####",
+            "##..#.#.####",
+            ".#..###.####",
+            ".#..#.#.####",
+            "###.#.#.####",
+            "........####",
+        ]
+    );
+
+    // Text running off the buffer is cut off
+    draw_text(&mut buf, 2, 0, "0123", 255, false);
+    assert_eq!(render(&buf)[0], ".#...#..###.");
+}
+
+#[test]
+fn test_overlay_lines() {
+    let mut cpu = cpu(&[
+        0x22, 0x04, // call 204
+        0x00, 0x00, //
+        0xA3, 0x05, // I = 305
+        0x60, 0x12, // V0 = 12
+        0xF0, 0x15, // DT = V0
+        0x12, 0x0A, // loop forever
+    ]);
+    for _ in 0..4 {
+        cpu.execute().unwrap();
+    }
+    cpu.memory[0x305] = 0xAB;
+    cpu.keyboard.lock().unwrap().press_key(0xC);
+
+    let snapshot = Snapshot::capture(&cpu);
+    assert_eq!(snapshot.stack, vec![0x202]);
+    assert_eq!(snapshot.timers[DELAY], 0x12);
+
+    let lines = snapshot.lines();
+    let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(lines.len(), overlay::OVERLAY_ROWS as usize);
+
+    assert_eq!(text[0], "PC 020A I 0305 SP 01");
+    assert_eq!(text[1], "V0 12 V1 00 V2 00 V3 00");
+    assert_eq!(text[4], "VC 00 VD 00 VE 00 VF 00");
+    assert_eq!(text[5], "DT 12 ST 00");
+    assert_eq!(text[6], "KEYS ............C...");
+    assert_eq!(text[7], "STACK 0202");
+
+    // The hex view starts two rows before I, which is highlighted
+    assert_eq!(text[9], "02F0 00 00 00 00 00 00 00 00");
+    assert_eq!(text[11], "0300 00 00 00 00 00 AB 00 00");
+    assert_eq!(lines[11].highlight, Some((20, 2)));
+
+    // Four instructions before PC
+    assert_eq!(text[15], "0202 0000 DW 0x0000");
+    assert_eq!(text[19], "020A 120A JP 0x20A");
+    assert_eq!(lines[19].highlight, Some((0, 32)));
+    assert!(lines
+        .iter()
+        .enumerate()
+        .all(|(row, line)| line.highlight.is_none() || row == 11 || row == 19));
+
+    let mut buf = overlay::overlay_buffer();
+    snapshot.render(&mut buf);
+    assert!(buf.handle_draw());
+    // Highlighted PC row is lit behind the text
+    assert_ne!(buf.read(buf.width() - 1, 19 * CELL_HEIGHT), 0);
+    assert_eq!(buf.read(buf.width() - 1, 18 * CELL_HEIGHT), 0);
+}
+
+#[test]
+fn test_overlay_memory_edges() {
+    let mut cpu = cpu(&[0x00, 0xE0]);
+    cpu.i = 0xFFFE;
+    cpu.pc = 0xFFFC;
+
+    let snapshot = Snapshot::capture(&cpu);
+    assert_eq!(
+        snapshot.memory_start + snapshot.memory.len(),
+        cpu.memory.len()
+    );
+    assert_eq!(snapshot.code_start + snapshot.code.len(), cpu.memory.len());
+
+    cpu.i = 0;
+    cpu.pc = 0;
+    let snapshot = Snapshot::capture(&cpu);
+    assert_eq!(snapshot.memory_start, 0);
+    assert_eq!(snapshot.code_start, 0);
+    assert_eq!(snapshot.lines()[15].highlight, Some((0, 32)));
+}
@@ -0,0 +1,125 @@
+use emu_rs::emu::arch::chip8::profile::{Profiler, Subroutine};
+use emu_rs::emu::arch::chip8::trace::{Options, Tracer};
+use emu_rs::emu::arch::chip8::{self, Error};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+fn cpu(rom: &[u8]) -> chip8::CPU {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.load_program(rom).unwrap();
+    cpu
+}
+
+// Runs `cycles` instructions with a frame every 5
+fn profile(rom: &[u8], cycles: usize) -> (chip8::CPU, Profiler) {
+    let mut cpu = cpu(rom);
+    let mut profiler = Profiler::new(rom.len());
+
+    for cycle in 1..=cycles {
+        profiler.execute(&mut cpu).unwrap();
+        if cycle % 5 == 0 {
+            cpu.tick();
+            profiler.tick();
+        }
+    }
+
+    (cpu, profiler)
+}
+
+const ROM: [u8; 12] = [
+    0x22, 0x06, // call 206
+    0xD0, 0x15, // draw
+    0x12, 0x00, // jump back to 200
+    0x70, 0x01, // V0 += 1
+    0x00, 0xEE, // return
+    0x12, 0x34, // never executed
+];
+
+#[test]
+fn test_profile_counts() {
+    let (cpu, profiler) = profile(&ROM, 10);
+
+    for pc in (0x200..0x20A).step_by(2) {
+        assert_eq!(profiler.counts[pc], 2, "{:04X}", pc);
+    }
+    assert_eq!(profiler.counts[0x20A], 0);
+    assert_eq!(profiler.draws, vec![1, 1]);
+    assert_eq!(
+        profiler.subroutines()[&0x206],
+        Subroutine {
+            calls: 2,
+            inclusive: 4,
+            exclusive: 4,
+        }
+    );
+    assert_eq!(profiler.coverage(), (10, 12));
+
+    let report = profiler.report(&cpu.memory);
+    assert!(report.starts_with("10 cycles in 2 frames\n"));
+    assert!(report.contains("Coverage: 10 of 12 ROM bytes executed (83.3%)"));
+    assert!(report.contains("       2  20.0%  0200  CALL 0x206"));
+    assert!(report.contains("       2          4  40.0%          4  40.0%  0206"));
+    assert!(report.contains("Draws: 2 total, 1.0 per frame, at most 1 in frame 0"));
+
+    let annotated = profiler.annotate(&cpu.memory);
+    let lines: Vec<&str> = annotated.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[1], "       2  0202  D015  DRW V0, V1, 5");
+    assert_eq!(lines[5], "       -  020A  12 34");
+}
+
+#[test]
+fn test_profile_nested_calls() {
+    let rom = [
+        0x22, 0x04, // call 204
+        0x12, 0x02, // loop forever
+        0x22, 0x08, // call 208
+        0x00, 0xEE, // return
+        0x00, 0xEE, // return
+    ];
+    let (_, profiler) = profile(&rom, 4);
+    let subroutines = profiler.subroutines();
+
+    assert_eq!(
+        subroutines[&0x204],
+        Subroutine {
+            calls: 1,
+            inclusive: 3,
+            exclusive: 2,
+        }
+    );
+    assert_eq!(
+        subroutines[&0x208],
+        Subroutine {
+            calls: 1,
+            inclusive: 1,
+            exclusive: 1,
+        }
+    );
+
+    // A call which never returns counts up to now
+    let rom = [0x22, 0x02, 0x12, 0x02];
+    let (_, profiler) = profile(&rom, 5);
+    assert_eq!(profiler.subroutines()[&0x202].inclusive, 4);
+    assert!(profiler.subroutines.get(&0x202).unwrap().inclusive == 0);
+}
+
+#[test]
+fn test_profile_with_tracer() {
+    let mut cpu = cpu(&[0x00, 0xEE]);
+    let mut profiler = Profiler::new(2);
+    let mut tracer = Tracer::new(Vec::new(), Options::default()).unwrap();
+
+    let result = profiler.execute_with(&mut cpu, |cpu| tracer.execute(cpu));
+    assert_eq!(result, Err(Error::StackUnderflow { pc: 0x200 }));
+    // Faults aren't counted, but still show up in the trace
+    assert_eq!(profiler.cycle, 0);
+    assert_eq!(profiler.coverage(), (0, 2));
+
+    let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    assert_eq!(trace, "! Stack underflow at 0200\n");
+}
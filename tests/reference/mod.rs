@@ -0,0 +1,245 @@
+// A deliberately simple CHIP-8 interpreter written from the instruction
+// descriptions in Cowgod's technical reference and the quirk notes of the
+// Timendus test suite. It shares no code with emu_rs and serves as the
+// reference the golden images come from.
+//
+// Keys are never pressed, CXNN isn't supported and FX1E leaves VF alone.
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
+    0x10, 0xF0, 0x10, 0xF0, 0x90, 0x90, 0xF0, 0x10, 0x10, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80,
+    0xF0, 0x90, 0xF0, 0xF0, 0x10, 0x20, 0x40, 0x40, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0,
+    0x10, 0xF0, 0x60, 0x90, 0xF0, 0x90, 0x90, 0xE0, 0x90, 0xE0, 0x90, 0xE0, 0xF0, 0x80, 0x80, 0x80,
+    0xF0, 0xE0, 0x90, 0x90, 0x90, 0xE0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+];
+
+// The behaviours interpreters disagree on, named after the platform that
+// has them
+#[derive(Debug, Clone, Copy)]
+pub struct Behaviour {
+    // 8XY6/8XYE copy VY into VX before shifting
+    pub shift_copies_vy: bool,
+    // FX55/FX65 advance I by X + 1
+    pub memory_advances_i: bool,
+    // BNNN adds VX instead of V0
+    pub jump_adds_vx: bool,
+    // 8XY1/8XY2/8XY3 clear VF
+    pub logic_clears_vf: bool,
+    // Sprites continue on the opposite edge instead of being cut off
+    pub sprites_wrap: bool,
+}
+
+impl Behaviour {
+    pub fn named(name: &str) -> Self {
+        let modern = Self {
+            shift_copies_vy: false,
+            memory_advances_i: false,
+            jump_adds_vx: false,
+            logic_clears_vf: false,
+            sprites_wrap: false,
+        };
+
+        match name {
+            "default" => modern,
+            "chip8" => Self {
+                shift_copies_vy: true,
+                memory_advances_i: true,
+                logic_clears_vf: true,
+                ..modern
+            },
+            "schip" => Self {
+                jump_adds_vx: true,
+                ..modern
+            },
+            _ => panic!("Unknown behaviour {}", name),
+        }
+    }
+}
+
+pub struct Reference {
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: usize,
+    pub pc: usize,
+    pub stack: Vec<usize>,
+    pub delay: u8,
+    pub sound: u8,
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    behaviour: Behaviour,
+}
+
+impl Reference {
+    pub fn new(rom: &[u8], behaviour: Behaviour) -> Self {
+        let mut memory = vec![0; 0x10000];
+        memory[..FONT.len()].copy_from_slice(&FONT);
+        memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+
+        Self {
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            screen: [[false; WIDTH]; HEIGHT],
+            behaviour,
+        }
+    }
+
+    // Runs `cycles` instructions with the timers counting down after every
+    // ninth, starting with the first
+    pub fn run(&mut self, cycles: usize) {
+        for cycle in 0..cycles {
+            self.step();
+            if cycle % 9 == 0 {
+                self.delay = self.delay.saturating_sub(1);
+                self.sound = self.sound.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for row in self.screen.iter() {
+            out.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    fn step(&mut self) {
+        let op = (self.memory[self.pc] as usize) << 8 | self.memory[self.pc + 1] as usize;
+        let x = op >> 8 & 0xF;
+        let y = op >> 4 & 0xF;
+        let n = op & 0xF;
+        let nn = (op & 0xFF) as u8;
+        let nnn = op & 0xFFF;
+        let mut next = self.pc + 2;
+
+        match (op >> 12, n) {
+            (0x0, _) if op == 0x00E0 => self.screen = [[false; WIDTH]; HEIGHT],
+            (0x0, _) if op == 0x00EE => next = self.stack.pop().expect("Return without call"),
+            (0x1, _) => next = nnn,
+            (0x2, _) => {
+                self.stack.push(next);
+                next = nnn;
+            }
+            (0x3, _) if self.v[x] == nn => next += 2,
+            (0x4, _) if self.v[x] != nn => next += 2,
+            (0x5, 0) if self.v[x] == self.v[y] => next += 2,
+            (0x9, 0) if self.v[x] != self.v[y] => next += 2,
+            (0x3, _) | (0x4, _) | (0x5, 0) | (0x9, 0) => {}
+            (0x6, _) => self.v[x] = nn,
+            (0x7, _) => self.v[x] = self.v[x].wrapping_add(nn),
+            (0x8, 0x0) => self.v[x] = self.v[y],
+            (0x8, 0x1..=0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if self.behaviour.logic_clears_vf {
+                    self.v[0xF] = 0;
+                }
+            }
+            (0x8, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = (sum > 0xFF) as u8;
+            }
+            (0x8, 0x5) => {
+                let no_borrow = self.v[x] >= self.v[y];
+                self.v[x] = self.v[x].wrapping_sub(self.v[y]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            (0x8, 0x7) => {
+                let no_borrow = self.v[y] >= self.v[x];
+                self.v[x] = self.v[y].wrapping_sub(self.v[x]);
+                self.v[0xF] = no_borrow as u8;
+            }
+            (0x8, 0x6) | (0x8, 0xE) => {
+                let value = if self.behaviour.shift_copies_vy {
+                    self.v[y]
+                } else {
+                    self.v[x]
+                };
+                let (result, out) = if n == 0x6 {
+                    (value >> 1, value & 1)
+                } else {
+                    (value << 1, value >> 7)
+                };
+                self.v[x] = result;
+                self.v[0xF] = out;
+            }
+            (0xA, _) => self.i = nnn,
+            (0xB, _) => {
+                let offset = if self.behaviour.jump_adds_vx { x } else { 0 };
+                next = nnn + self.v[offset] as usize;
+            }
+            (0xD, _) => self.draw(self.v[x] as usize, self.v[y] as usize, n),
+            (0xE, _) if nn == 0x9E => {}
+            (0xE, _) if nn == 0xA1 => next += 2,
+            (0xF, _) => match nn {
+                0x07 => self.v[x] = self.delay,
+                // Nothing is ever pressed
+                0x0A => next = self.pc,
+                0x15 => self.delay = self.v[x],
+                0x18 => self.sound = self.v[x],
+                0x1E => self.i += self.v[x] as usize,
+                0x29 => self.i = (self.v[x] & 0xF) as usize * 5,
+                0x33 => {
+                    self.memory[self.i] = self.v[x] / 100;
+                    self.memory[self.i + 1] = self.v[x] / 10 % 10;
+                    self.memory[self.i + 2] = self.v[x] % 10;
+                }
+                0x55 | 0x65 => {
+                    for register in 0..=x {
+                        if nn == 0x55 {
+                            self.memory[self.i + register] = self.v[register];
+                        } else {
+                            self.v[register] = self.memory[self.i + register];
+                        }
+                    }
+                    if self.behaviour.memory_advances_i {
+                        self.i += x + 1;
+                    }
+                }
+                _ => panic!("Unsupported opcode {:04X} at {:03X}", op, self.pc),
+            },
+            _ => panic!("Unsupported opcode {:04X} at {:03X}", op, self.pc),
+        }
+
+        self.pc = next;
+    }
+
+    fn draw(&mut self, x: usize, y: usize, rows: usize) {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        self.v[0xF] = 0;
+
+        for row in 0..rows {
+            let bits = self.memory[self.i + row];
+            for column in 0..8 {
+                if bits & 0x80 >> column == 0 {
+                    continue;
+                }
+
+                let (mut px, mut py) = (x + column, y + row);
+                if self.behaviour.sprites_wrap {
+                    px %= WIDTH;
+                    py %= HEIGHT;
+                } else if px >= WIDTH || py >= HEIGHT {
+                    continue;
+                }
+
+                if self.screen[py][px] {
+                    self.v[0xF] = 1;
+                }
+                self.screen[py][px] = !self.screen[py][px];
+            }
+        }
+    }
+}
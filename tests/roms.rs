@@ -0,0 +1,151 @@
+// Runs test ROMs headless and compares the final screen with a golden image.
+//
+// Golden images are plain text, one line per row with '#' for lit pixels.
+// They come from the reference interpreter in tests/reference, not from
+// emu_rs, and `test_golden_images_from_reference` keeps them that way. After
+// changing a ROM, copy the screen that test prints into the golden image.
+//
+// Only the ROMs in tests/fixtures are covered, no public test suite is
+// bundled.
+
+mod reference;
+
+use emu_rs::emu::arch::chip8;
+use emu_rs::emu::core::FrameBuffer;
+use reference::{Behaviour, Reference};
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const CYCLES: usize = 1000;
+
+// ROM, quirk preset and golden image
+const GOLDEN: [(&str, &str, &str); 5] = [
+    ("flags.ch8", "default", "flags.txt"),
+    ("quirks.ch8", "default", "quirks-default.txt"),
+    ("quirks.ch8", "chip8", "quirks-chip8.txt"),
+    ("quirks.ch8", "schip", "quirks-schip.txt"),
+    ("font.ch8", "default", "font.txt"),
+];
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn render(frame_buf: &FrameBuffer<u8>) -> String {
+    let mut out = String::new();
+
+    for y in 0..frame_buf.height() {
+        for x in 0..frame_buf.width() {
+            out.push(if frame_buf.read(x, y) != 0 { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+fn run_rom(program: &[u8], quirks: chip8::Quirks, cycles: usize, cached: bool) -> String {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.quirks = quirks;
+    cpu.load_program(program).unwrap();
+
+    for cycle in 0..cycles {
+        if cached {
+            cpu.execute_cached().unwrap();
+        } else {
+            cpu.execute().unwrap();
+        }
+
+        if cycle % 9 == 0 {
+            cpu.tick();
+        }
+    }
+
+    render(&cpu.frame_buf)
+}
+
+fn run_reference(program: &[u8], preset: &str, cycles: usize) -> String {
+    let mut reference = Reference::new(program, Behaviour::named(preset));
+    reference.run(cycles);
+    reference.render()
+}
+
+// Runs the ROM on both interpreters of emu_rs, which have to agree
+fn run_emu_rs(program: &[u8], preset: &str, cycles: usize) -> String {
+    let quirks = chip8::Quirks::preset(preset).unwrap();
+    let screen = run_rom(program, quirks, cycles, false);
+
+    assert_eq!(
+        screen,
+        run_rom(program, quirks, cycles, true),
+        "Interpreter and cached interpreter differ ({})",
+        preset
+    );
+    screen
+}
+
+fn check_golden(rom: &str, preset: &str, golden: &str) {
+    let program = fs::read(fixture(rom)).unwrap();
+    let screen = run_emu_rs(&program, preset, CYCLES);
+    let expected = fs::read_to_string(fixture(golden)).unwrap();
+
+    assert!(
+        screen == expected,
+        "{} ({}) differs from {}:\n{}",
+        rom,
+        preset,
+        golden,
+        screen
+    );
+}
+
+#[test]
+fn test_golden_images_from_reference() {
+    for &(rom, preset, golden) in GOLDEN.iter() {
+        let program = fs::read(fixture(rom)).unwrap();
+        let screen = run_reference(&program, preset, CYCLES);
+        let expected = fs::read_to_string(fixture(golden)).unwrap();
+
+        assert!(
+            screen == expected,
+            "The reference draws something else than {} for {} ({}):\n{}",
+            golden,
+            rom,
+            preset,
+            screen
+        );
+    }
+}
+
+#[test]
+fn test_rom_flags() {
+    check_golden("flags.ch8", "default", "flags.txt");
+}
+
+#[test]
+fn test_rom_quirks_default() {
+    check_golden("quirks.ch8", "default", "quirks-default.txt");
+}
+
+#[test]
+fn test_rom_quirks_chip8() {
+    check_golden("quirks.ch8", "chip8", "quirks-chip8.txt");
+}
+
+#[test]
+fn test_rom_quirks_schip() {
+    check_golden("quirks.ch8", "schip", "quirks-schip.txt");
+}
+
+#[test]
+fn test_rom_font() {
+    check_golden("font.ch8", "default", "font.txt");
+}
@@ -0,0 +1,274 @@
+// Talks JSON-RPC to the server like test tooling would, with the CPU running
+// on its own thread the same way the run subcommand drives it.
+
+use emu_rs::emu::arch::chip8::{self, rpc};
+use emu_rs::emu::core::FrameBuffer;
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const ROM: [u8; 10] = [
+    0xA0, 0x00, // I = font 0
+    0xD0, 0x15, // draw it at V0, V1
+    0x60, 0x05, // V0 = 5
+    0x70, 0x01, // V0 += 1
+    0x12, 0x06, // jump back to 206
+];
+
+// Instructions per frame
+const CYCLES: u64 = 10;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    active: Arc<AtomicBool>,
+    cpu_thread: Option<thread::JoinHandle<()>>,
+}
+
+// Runs the CPU until the server quits or the client goes away
+fn run_cpu(mut server: rpc::Server, active: Arc<AtomicBool>) {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.load_program(&ROM).unwrap();
+
+    let (mut frame, mut cycle) = (0, 0);
+    while active.load(Ordering::Relaxed) && !server.quit() {
+        if !server.poll(&mut cpu, frame) {
+            continue;
+        }
+        match cpu.execute() {
+            Ok(()) => {
+                cycle += 1;
+                let frame_ended = cycle == CYCLES;
+                if frame_ended {
+                    cycle = 0;
+                    frame += 1;
+                    cpu.tick();
+                }
+                server.executed(&cpu, frame, frame_ended);
+            }
+            Err(e) => server.fault(e),
+        }
+    }
+}
+
+impl Client {
+    fn start() -> Self {
+        let server = rpc::listen("127.0.0.1:0", &ROM).unwrap();
+        let stream = TcpStream::connect(server.address()).unwrap();
+
+        let active = Arc::new(AtomicBool::new(true));
+        let local_active = active.clone();
+        let cpu_thread = thread::spawn(move || run_cpu(server, local_active));
+
+        Self {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            active,
+            cpu_thread: Some(cpu_thread),
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Value {
+        writeln!(self.writer, "{}", line).unwrap();
+        let mut response = String::new();
+        self.reader.read_line(&mut response).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+        let response = self.send(&request.to_string());
+        assert_eq!(response["id"], 7);
+        response
+    }
+
+    fn result(&mut self, method: &str, params: Value) -> Value {
+        let response = self.call(method, params);
+        assert!(response.get("error").is_none(), "{}", response);
+        response["result"].clone()
+    }
+
+    fn error(&mut self, method: &str, params: Value) -> (i64, String) {
+        let error = &self.call(method, params)["error"];
+        (
+            error["code"].as_i64().unwrap(),
+            error["message"].as_str().unwrap().to_string(),
+        )
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.active.store(false, Ordering::Relaxed);
+        if let Some(cpu_thread) = self.cpu_thread.take() {
+            cpu_thread.join().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_rpc_run_control() {
+    let mut client = Client::start();
+
+    let paused = json!({ "paused": true, "frame": 0, "pc": 0x200 });
+    assert_eq!(client.result("status", json!({})), paused);
+    assert_eq!(
+        client.result("step", json!({ "count": 3 })),
+        json!({ "paused": true, "frame": 0, "pc": 0x206 })
+    );
+
+    let registers = client.result("getRegisters", json!({}));
+    assert_eq!(registers["v"][0], 5);
+    assert_eq!(registers["pc"], 0x206);
+    assert_eq!(registers["stack"], json!([]));
+
+    // Three instructions into the first frame, two frames end 17 later
+    assert_eq!(
+        client.result("runFrames", json!({ "count": 2 })),
+        json!({ "paused": true, "frame": 2, "pc": 0x208 })
+    );
+    assert_eq!(client.result("getRegisters", json!({}))["v"][0], 14);
+
+    let status = client.result("resume", json!({}));
+    assert_eq!(status["paused"], false);
+    let status = client.result("pause", json!({}));
+    assert_eq!(status["paused"], true);
+    assert!(status["frame"].as_u64().unwrap() >= 2);
+
+    client.result("quit", json!({}));
+}
+
+#[test]
+fn test_rpc_memory_and_screen() {
+    let mut client = Client::start();
+    client.result("step", json!({ "count": 2 }));
+
+    assert_eq!(
+        client.result("readMemory", json!({ "address": 0x200, "length": 4 })),
+        json!({ "data": base64::encode([0xA0, 0x00, 0xD0, 0x15]) })
+    );
+    client.result(
+        "writeMemory",
+        json!({ "address": 0x205, "data": base64::encode([0x42]) }),
+    );
+    client.result("step", json!({ "count": 1 }));
+    assert_eq!(client.result("getRegisters", json!({}))["v"][0], 0x42);
+
+    assert_eq!(
+        client.error("readMemory", json!({ "address": 0xFFFE, "length": 2 })),
+        (-32000, "Outside of memory".to_string())
+    );
+
+    // The font's 0 in the top left corner
+    let screen = client.result("getFramebuffer", json!({}));
+    assert_eq!(
+        (screen["width"].clone(), screen["height"].clone()),
+        (json!(64), json!(32))
+    );
+    let pixels = base64::decode(screen["data"].as_str().unwrap()).unwrap();
+    assert_eq!(&pixels[..5], &[255, 255, 255, 255, 0]);
+    assert_eq!(&pixels[64..69], &[255, 0, 0, 255, 0]);
+
+    client.result("setKey", json!({ "key": 3, "pressed": true }));
+    assert_eq!(
+        client.error("setKey", json!({ "key": 16, "pressed": true })),
+        (-32602, "Invalid key 16".to_string())
+    );
+}
+
+#[test]
+fn test_rpc_states_and_roms() {
+    let mut client = Client::start();
+    client.result("runFrames", json!({ "count": 1 }));
+
+    let state = client.result("saveState", json!({}))["state"].clone();
+    let saved = client.result("getRegisters", json!({}));
+    client.result("runFrames", json!({ "count": 3 }));
+    assert_ne!(client.result("getRegisters", json!({})), saved);
+    client.result("loadState", json!({ "state": state }));
+    assert_eq!(client.result("getRegisters", json!({})), saved);
+
+    assert_eq!(
+        client.error("loadState", json!({ "state": base64::encode("C8SV") })),
+        (-32602, "Truncated save state".to_string())
+    );
+
+    client.result("reset", json!({}));
+    assert_eq!(client.result("getRegisters", json!({}))["pc"], 0x200);
+
+    // V2 = 9, then loop
+    let rom = base64::encode([0x62, 0x09, 0x12, 0x02]);
+    client.result("loadRom", json!({ "data": rom }));
+    client.result("step", json!({}));
+    assert_eq!(client.result("getRegisters", json!({}))["v"][2], 9);
+
+    let (code, message) = client.error("loadRom", json!({ "path": "/nonexistent.ch8" }));
+    assert_eq!(code, -32000);
+    assert!(message.starts_with("Can't read /nonexistent.ch8"));
+}
+
+#[test]
+fn test_rpc_protocol_errors() {
+    let mut client = Client::start();
+
+    let response = client.send("{");
+    assert_eq!(response["error"]["code"], -32700);
+    assert_eq!(response["id"], Value::Null);
+    let response = client.send(r#"{"id": 1, "method": "status"}"#);
+    assert_eq!(response["error"]["code"], -32600);
+    assert_eq!(response["id"], 1);
+
+    assert_eq!(client.error("warp", json!({})).0, -32601);
+    assert_eq!(
+        client.error("step", json!({ "count": -1 })),
+        (-32602, "count must be a non-negative integer".to_string())
+    );
+    assert_eq!(
+        client.error("readMemory", json!({ "address": 0 })),
+        (-32602, "Missing length".to_string())
+    );
+    assert_eq!(client.call("status", json!([1]))["error"]["code"], -32602);
+
+    // Notifications aren't answered
+    let response = client.send(concat!(
+        r#"{"jsonrpc": "2.0", "method": "step"}"#,
+        "\n",
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "status"}"#
+    ));
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["pc"], 0x202);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rpc_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("emu_rs_rpc_{}.sock", std::process::id()));
+    let address = format!("unix:{}", path.display());
+    let server = rpc::listen(&address, &ROM).unwrap();
+    assert_eq!(server.address(), address);
+
+    let active = Arc::new(AtomicBool::new(true));
+    let local_active = active.clone();
+    let cpu_thread = thread::spawn(move || run_cpu(server, local_active));
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    writeln!(stream, r#"{{"jsonrpc": "2.0", "id": 1, "method": "quit"}}"#).unwrap();
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&response).unwrap(),
+        json!({ "jsonrpc": "2.0", "id": 1, "result": null })
+    );
+
+    cpu_thread.join().unwrap();
+    assert!(!path.exists());
+}
@@ -0,0 +1,106 @@
+use emu_rs::emu::core::{Action, Pacer, RunControl, Speed};
+
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_run_control_pause() {
+    let mut control = RunControl::default();
+    assert_eq!(control.next_action(), Action::Frame(Speed::Normal));
+    assert!(!control.muted());
+    assert_eq!(control.status(), "");
+
+    control.toggle_pause();
+    assert_eq!(control.next_action(), Action::Wait);
+    assert_eq!(control.next_action(), Action::Wait);
+    assert!(control.muted());
+    assert_eq!(control.status(), "paused");
+
+    control.toggle_pause();
+    assert_eq!(control.next_action(), Action::Frame(Speed::Normal));
+}
+
+#[test]
+fn test_run_control_advance() {
+    let mut control = RunControl::default();
+
+    // Stepping pauses a running CPU first
+    control.step();
+    control.step();
+    control.advance_frame();
+    assert!(control.paused);
+    assert_eq!(control.next_action(), Action::Step);
+    assert_eq!(control.next_action(), Action::Step);
+    // Frame advance doesn't wait for the wall clock
+    assert_eq!(control.next_action(), Action::Frame(Speed::Uncapped));
+    assert_eq!(control.next_action(), Action::Wait);
+
+    // Resuming drops requests which haven't run yet
+    control.advance_frame();
+    control.toggle_pause();
+    control.toggle_pause();
+    assert_eq!(control.next_action(), Action::Wait);
+}
+
+#[test]
+fn test_run_control_speed() {
+    let mut control = RunControl::new(8);
+
+    control.fast_forward = true;
+    assert_eq!(control.next_action(), Action::Frame(Speed::FastForward(8)));
+    assert!(control.muted());
+    assert_eq!(control.status(), "8x");
+
+    control.uncapped = true;
+    assert_eq!(control.next_action(), Action::Frame(Speed::Uncapped));
+    assert_eq!(control.status(), "uncapped");
+
+    control.uncapped = false;
+    control.fast_forward = false;
+    assert!(!control.muted());
+
+    // A multiplier of 1 is just normal speed
+    let mut control = RunControl::new(0);
+    control.fast_forward = true;
+    assert_eq!(control.speed(), Speed::Normal);
+}
+
+// Wall clock time `frames` paced frames take
+fn paced(frames: u32, speed: Speed) -> Duration {
+    let mut pacer = Pacer::new(60);
+    let start = Instant::now();
+    for _ in 0..frames {
+        pacer.wait(speed);
+    }
+    start.elapsed()
+}
+
+#[test]
+fn test_pacer() {
+    let frame = Pacer::new(60).frame();
+
+    // Sleeping only ever overshoots, the upper bounds are generous for
+    // loaded machines
+    let normal = paced(6, Speed::Normal);
+    assert!(normal >= frame * 6);
+    assert!(normal < frame * 30);
+
+    let fast = paced(12, Speed::FastForward(4));
+    assert!(fast >= frame * 3);
+    assert!(fast < frame * 15);
+
+    assert!(paced(1000, Speed::Uncapped) < frame);
+}
+
+#[test]
+fn test_run_control_buzzer() {
+    let mut control = RunControl::new(8);
+    assert!(control.buzzer(1));
+    assert!(!control.buzzer(0));
+
+    // Silent while paused or faster than real time
+    control.toggle_pause();
+    assert!(!control.buzzer(1));
+    control.toggle_pause();
+    control.fast_forward = true;
+    assert!(!control.buzzer(1));
+}
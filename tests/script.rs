@@ -0,0 +1,118 @@
+use emu_rs::emu::arch::chip8::script::{pbm, Script};
+use emu_rs::emu::arch::chip8::{Keyboard, CPU};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const ROM: [u8; 10] = [
+    0x60, 0x00, // V0 = 0
+    0xA3, 0x00, // I = 300
+    0x70, 0x01, // V0 += 1
+    0xF0, 0x55, // store V0 at 300
+    0x12, 0x02, // jump back to 202
+];
+
+// Instructions per frame in `run`
+const CYCLES: u64 = 4;
+
+fn cpu() -> CPU {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.load_program(&ROM).unwrap();
+    cpu
+}
+
+// Runs frames like the run subcommand does until the script stops
+fn run(cpu: &mut CPU, script: &mut Script, frames: u64) -> Result<u64, String> {
+    for frame in 0..frames {
+        script.frame(cpu, frame)?;
+        for _ in 0..CYCLES {
+            if script.stopped() {
+                return Ok(frame);
+            }
+            script.before(cpu, frame)?;
+            cpu.execute().unwrap();
+            script.after(cpu, frame)?;
+        }
+        cpu.tick();
+    }
+    Ok(frames)
+}
+
+#[test]
+fn test_script_machine() {
+    let mut cpu = cpu();
+    let mut script = Script::new(
+        r#"
+        poke(0x300, 0x42);
+        poke(0x301, peek(0x201) + 1);
+        set_reg(3, 7);
+        set_i(0x123);
+        press(5);
+        press(6);
+        release(6);
+        if pc() != 0x200 || frame() != 0 || pixel(0, 0) {
+            throw "wrong machine";
+        }
+        "#,
+    )
+    .unwrap();
+    script.start(&mut cpu).unwrap();
+
+    assert_eq!(&cpu.memory[0x300..0x302], &[0x42, 0x01]);
+    assert_eq!(cpu.regs[3], 7);
+    assert_eq!(cpu.i, 0x123);
+    let keyboard = cpu.keyboard.lock().unwrap();
+    assert!(keyboard.state[5]);
+    assert!(!keyboard.state[6]);
+}
+
+#[test]
+fn test_script_hooks() {
+    let mut cpu = cpu();
+    let mut script = Script::new(
+        r#"
+        on_frame(|frame| poke(0x320, frame));
+        on_pc(0x206, || set_reg(1, reg(1) + 1));
+        on_write(0x300, |address, old, value| poke(0x310, old * 16 + value));
+        after(3, || stop());
+        "#,
+    )
+    .unwrap();
+    script.start(&mut cpu).unwrap();
+
+    // Stops right at the start of frame 3
+    assert_eq!(run(&mut cpu, &mut script, 10), Ok(3));
+    assert_eq!(cpu.memory[0x320], 3);
+    // The store ran at 206 for V0 = 1, 2 and 3
+    assert_eq!(cpu.regs[0], 3);
+    assert_eq!(cpu.regs[1], 3);
+    assert_eq!(cpu.memory[0x300], 3);
+    assert_eq!(cpu.memory[0x310], 0x23);
+}
+
+#[test]
+fn test_script_errors() {
+    assert!(Script::new("on_frame(").is_err());
+
+    let mut cpu = cpu();
+    let mut script = Script::new("peek(0xFFFF)").unwrap();
+    assert!(script
+        .start(&mut cpu)
+        .unwrap_err()
+        .contains("Invalid address 65535"));
+
+    let mut script = Script::new(r#"on_frame(|frame| if frame == 2 { throw "lost" })"#).unwrap();
+    script.start(&mut cpu).unwrap();
+    assert!(run(&mut cpu, &mut script, 10).unwrap_err().contains("lost"));
+}
+
+#[test]
+fn test_pbm() {
+    let mut screen = FrameBuffer::new(3, 2, 0u8);
+    screen.write(0, 0, 1);
+    screen.write(2, 1, 1);
+    assert_eq!(pbm(&screen), "P1\n3 2\n1 0 0\n0 0 1\n");
+}
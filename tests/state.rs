@@ -0,0 +1,91 @@
+use emu_rs::emu::arch::chip8::{Keyboard, SaveState, CPU};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const ROM: [u8; 10] = [
+    0xA0, 0x00, // I = font 0
+    0xD0, 0x15, // draw it at V0, V1
+    0xC3, 0xFF, // V3 = random
+    0x22, 0x0A, // call 20A
+    0x12, 0x04, // jump back to 204
+];
+
+fn seeded_cpu(seed: u64) -> CPU {
+    let mut cpu = CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(Keyboard::new())),
+    );
+    cpu.seed(seed);
+    cpu.load_program(&ROM).unwrap();
+    // 20A: return
+    cpu.memory[0x20A..0x20C].copy_from_slice(&[0x00, 0xEE]);
+    cpu
+}
+
+fn random_values(cpu: &mut CPU, count: usize) -> Vec<u8> {
+    let mut values = Vec::new();
+    while values.len() < count {
+        cpu.execute().unwrap();
+        if cpu.pc == 0x206 {
+            values.push(cpu.regs[3]);
+        }
+    }
+    values
+}
+
+#[test]
+fn test_save_state_round_trip() {
+    let mut cpu = seeded_cpu(1);
+    for _ in 0..4 {
+        cpu.execute().unwrap();
+    }
+    cpu.timers = [3, 9];
+
+    let state = SaveState::capture(&cpu);
+    assert_eq!(state.sp, 1);
+    assert_eq!(state.screen[..4], [255, 255, 255, 255]);
+    let bytes = state.to_bytes();
+    assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+
+    // Into a CPU which ran something else
+    let mut other = seeded_cpu(2);
+    other.memory[0x300] = 0xAA;
+    state.restore(&mut other);
+    assert_eq!(SaveState::capture(&other), state);
+    assert_eq!(other.frame_buf.read(3, 0), 255);
+}
+
+#[test]
+fn test_save_state_random_numbers() {
+    let mut cpu = seeded_cpu(7);
+    random_values(&mut cpu, 3);
+
+    let state = SaveState::capture(&cpu);
+    let expected = random_values(&mut cpu, 5);
+    state.restore(&mut cpu);
+    assert_eq!(random_values(&mut cpu, 5), expected);
+
+    // Other seeds give other numbers, the state carries its own
+    let mut other = seeded_cpu(8);
+    state.restore(&mut other);
+    assert_eq!(random_values(&mut other, 5), expected);
+}
+
+#[test]
+fn test_save_state_errors() {
+    let bytes = SaveState::capture(&seeded_cpu(0)).to_bytes();
+    let error = |bytes: &[u8]| SaveState::from_bytes(bytes).unwrap_err();
+
+    assert_eq!(error(b"C8TR\x01"), "Not a save state");
+    assert_eq!(error(b"C8SV\x02"), "Unsupported save state version 2");
+    assert_eq!(error(&bytes[..100]), "Truncated save state");
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(error(&longer), "Trailing data after the save state");
+
+    let mut bad_sp = bytes;
+    bad_sp[25] = 65;
+    assert_eq!(error(&bad_sp), "Invalid stack pointer 65");
+}
@@ -0,0 +1,169 @@
+use emu_rs::emu::arch::chip8::trace::{self, Entry, Filter, Format, Options, Tracer};
+use emu_rs::emu::arch::chip8::{self, Error};
+use emu_rs::emu::core::FrameBuffer;
+
+use std::sync::{Arc, Mutex};
+
+const ROM: [u8; 8] = [
+    0x60, 0x05, // V0 = 5
+    0x70, 0x01, // V0 += 1
+    0xA3, 0x00, // I = 300
+    0x12, 0x02, // jump back to 202
+];
+
+fn cpu(rom: &[u8]) -> chip8::CPU {
+    let mut cpu = chip8::CPU::new(
+        FrameBuffer::new(64, 32, 0u8),
+        Arc::new(Mutex::new(chip8::Keyboard::new())),
+    );
+    cpu.load_program(rom).unwrap();
+    cpu
+}
+
+// Runs `cycles` instructions with a frame every 4, returns the trace output
+fn run(rom: &[u8], cycles: usize, options: Options) -> (Vec<u8>, Result<(), Error>) {
+    let mut cpu = cpu(rom);
+    let mut tracer = Tracer::new(Vec::new(), options).unwrap();
+
+    let mut result = Ok(());
+    for cycle in 1..=cycles {
+        result = tracer.execute(&mut cpu);
+        if result.is_err() {
+            break;
+        }
+        if cycle % 4 == 0 {
+            tracer.tick();
+        }
+    }
+
+    (tracer.finish().unwrap(), result)
+}
+
+fn text(output: Vec<u8>) -> Vec<String> {
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn test_trace_text() {
+    let (output, result) = run(&ROM, 5, Options::default());
+    assert!(result.is_ok());
+
+    assert_eq!(
+        text(output),
+        vec![
+            "       0      0 0200 6005 LD V0, 0x05        V0=05",
+            "       1      0 0202 7001 ADD V0, 0x01       V0=06",
+            "       2      0 0204 A300 LD I, 0x300        I=0300",
+            "       3      0 0206 1202 JP 0x202",
+            "       4      1 0202 7001 ADD V0, 0x01       V0=07",
+        ]
+    );
+}
+
+#[test]
+fn test_trace_binary() {
+    let options = Options {
+        format: Format::Binary,
+        ..Options::default()
+    };
+    let (output, _) = run(&ROM, 20, options);
+    assert_eq!(&output[..4], trace::TRACE_MAGIC);
+
+    let entries = trace::read(&mut &output[..]).unwrap();
+    let lines: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+    let (expected, _) = run(&ROM, 20, Options::default());
+    assert_eq!(lines, text(expected));
+
+    assert_eq!(
+        entries[2],
+        Entry {
+            cycle: 2,
+            frame: 0,
+            pc: 0x204,
+            opcode: 0xA300,
+            changes: vec![(16, 0x300)],
+        }
+    );
+
+    // Less compact than that would defeat the purpose
+    assert!(output.len() < text(run(&ROM, 20, Options::default()).0).concat().len() / 2);
+    assert!(trace::read(&mut &b"not a trace"[..]).is_err());
+}
+
+#[test]
+fn test_trace_filters() {
+    let filtered = |filter: Filter| {
+        let (output, _) = run(
+            &ROM,
+            20,
+            Options {
+                filter,
+                ..Options::default()
+            },
+        );
+        text(output)
+    };
+
+    let lines = filtered(Filter {
+        classes: Some(Filter::parse_classes("7,a").unwrap()),
+        ..Filter::default()
+    });
+    assert_eq!(lines.len(), 13);
+    assert!(lines
+        .iter()
+        .all(|line| line.contains(" 7001 ") || line.contains(" A300 ")));
+
+    let lines = filtered(Filter {
+        addresses: Some(Filter::parse_addresses("204-206").unwrap()),
+        ..Filter::default()
+    });
+    assert_eq!(lines.len(), 12);
+
+    let lines = filtered(Filter {
+        frames: Some(Filter::parse_frames("2-3").unwrap()),
+        addresses: Some(Filter::parse_addresses("202").unwrap()),
+        ..Filter::default()
+    });
+    assert_eq!(lines.len(), 2);
+    // Cycle numbers keep counting through filtered instructions
+    assert!(lines[0].starts_with("      10      2 0202"));
+
+    assert!(Filter::parse_classes("8,G").is_err());
+    assert!(Filter::parse_addresses("300-200").is_err());
+    assert!(Filter::parse_addresses("200-10000").is_err());
+    assert!(Filter::parse_frames("x").is_err());
+}
+
+#[test]
+fn test_trace_ring() {
+    let rom = [
+        0x60, 0x01, // V0 = 1
+        0x61, 0x02, // V1 = 2
+        0x62, 0x03, // V2 = 3
+        0x00, 0xEE, // return with an empty stack
+    ];
+    let options = Options {
+        ring: Some(2),
+        ..Options::default()
+    };
+
+    let (output, result) = run(&rom, 10, options);
+    assert_eq!(result, Err(Error::StackUnderflow { pc: 0x206 }));
+    assert_eq!(
+        text(output),
+        vec![
+            "       1      0 0202 6102 LD V1, 0x02        V1=02",
+            "       2      0 0204 6203 LD V2, 0x03        V2=03",
+            "! Stack underflow at 0206",
+        ]
+    );
+
+    // Nothing is written unless the CPU faults
+    let (output, result) = run(&ROM, 100, options);
+    assert!(result.is_ok());
+    assert!(output.is_empty());
+}
@@ -0,0 +1,75 @@
+use emu_rs::emu::core::{ScaleMode, Viewport};
+
+fn view(image: (u32, u32), area: (f64, f64), mode: ScaleMode) -> (f64, f64, f64, f64, f64) {
+    let view = Viewport::new(image, area, mode);
+    (view.x, view.y, view.width, view.height, view.scale)
+}
+
+#[test]
+fn test_viewport_exact() {
+    // The window the emulator opens by default
+    for &mode in &[ScaleMode::Integer, ScaleMode::Fit] {
+        assert_eq!(
+            view((128, 64), (640.0, 320.0), mode),
+            (0.0, 0.0, 640.0, 320.0, 5.0)
+        );
+    }
+}
+
+#[test]
+fn test_viewport_letterbox() {
+    // Too tall, bars above and below
+    assert_eq!(
+        view((128, 64), (640.0, 480.0), ScaleMode::Fit),
+        (0.0, 80.0, 640.0, 320.0, 5.0)
+    );
+    // Too wide, bars left and right
+    assert_eq!(
+        view((128, 64), (1000.0, 320.0), ScaleMode::Fit),
+        (180.0, 0.0, 640.0, 320.0, 5.0)
+    );
+    // 1080p fullscreen
+    assert_eq!(
+        view((128, 64), (1920.0, 1080.0), ScaleMode::Integer),
+        (0.0, 60.0, 1920.0, 960.0, 15.0)
+    );
+}
+
+#[test]
+fn test_viewport_integer() {
+    // Fit stretches to 5.5, integer stays at 5 and centers
+    assert_eq!(
+        view((128, 64), (704.0, 352.0), ScaleMode::Fit),
+        (0.0, 0.0, 704.0, 352.0, 5.5)
+    );
+    assert_eq!(
+        view((128, 64), (704.0, 352.0), ScaleMode::Integer),
+        (32.0, 16.0, 640.0, 320.0, 5.0)
+    );
+    // Smaller than the image, integer scaling can't shrink by whole pixels
+    assert_eq!(
+        view((128, 64), (64.0, 64.0), ScaleMode::Integer),
+        (0.0, 16.0, 64.0, 32.0, 0.5)
+    );
+}
+
+#[test]
+fn test_viewport_resolution_change() {
+    // A hi-res image in the same window gets half the scale
+    let area = (1280.0, 720.0);
+    assert_eq!(view((128, 64), area, ScaleMode::Integer).4, 10.0);
+    assert_eq!(
+        view((256, 128), area, ScaleMode::Integer),
+        (0.0, 40.0, 1280.0, 640.0, 5.0)
+    );
+    // Minimized windows have no room at all
+    assert_eq!(view((128, 64), (0.0, 0.0), ScaleMode::Fit).4, 0.0);
+}
+
+#[test]
+fn test_scale_mode_names() {
+    for &mode in &[ScaleMode::Integer, ScaleMode::Fit] {
+        assert_eq!(ScaleMode::parse(mode.name()), Some(mode));
+    }
+    assert_eq!(ScaleMode::parse("stretch"), None);
+}
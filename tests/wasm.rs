@@ -0,0 +1,229 @@
+use emu_rs::emu::arch::chip8::wasm::Emulator;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use wasmi::{Engine, Linker, Module, Store};
+
+// Waits for a key and draws its font glyph in the top left corner
+const KEY_ROM: [u8; 8] = [
+    0xF0, 0x0A, // V0 = key
+    0xF0, 0x29, // I = glyph of V0
+    0xD1, 0x15, // draw at V1, V1
+    0x12, 0x06, // loop forever
+];
+
+const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+const BLACK: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+// The RGBA pixel at x, y of the scaled image
+fn pixel(rgba: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+    let index = ((y * width + x) * 4) as usize;
+    [
+        rgba[index],
+        rgba[index + 1],
+        rgba[index + 2],
+        rgba[index + 3],
+    ]
+}
+
+#[test]
+fn test_wasm_emulator() {
+    let mut emulator = Emulator::new(1);
+    assert_eq!((emulator.width(), emulator.height()), (128, 64));
+    assert_eq!(emulator.rgba(), BLACK.repeat(128 * 64));
+
+    emulator.load_rom(&KEY_ROM).unwrap();
+    emulator.run_frame().unwrap();
+    emulator.set_key(7, true).unwrap();
+    emulator.run_frame().unwrap();
+
+    // The 7's top row is four pixels wide, eight once scaled
+    let rgba = emulator.rgba();
+    assert_eq!(pixel(&rgba, 128, 0, 0), WHITE);
+    assert_eq!(pixel(&rgba, 128, 7, 1), WHITE);
+    assert_eq!(pixel(&rgba, 128, 8, 0), BLACK);
+    assert!(!emulator.sound());
+
+    emulator.set_palette(0xFF8000, 0x000010);
+    let rgba = emulator.rgba();
+    assert_eq!(pixel(&rgba, 128, 0, 0), [0xFF, 0x80, 0x00, 0xFF]);
+    assert_eq!(pixel(&rgba, 128, 8, 0), [0x00, 0x00, 0x10, 0xFF]);
+
+    // Back to the empty screen, waiting for a key
+    emulator.reset();
+    emulator.run_frame().unwrap();
+    assert_eq!(pixel(&emulator.rgba(), 128, 0, 0), [0x00, 0x00, 0x10, 0xFF]);
+}
+
+#[test]
+fn test_wasm_emulator_database() {
+    let mut emulator = Emulator::new(1);
+    let flags = std::fs::read("tests/fixtures/flags.ch8").unwrap();
+    emulator.load_rom(&flags).unwrap();
+    assert_eq!(emulator.title().as_deref(), Some("Flags test"));
+
+    emulator.load_rom(&KEY_ROM).unwrap();
+    assert_eq!(emulator.title(), None);
+}
+
+// Sets only the sound or only the delay timer to V0
+fn timer_rom(timer: u8) -> [u8; 6] {
+    [
+        0x60, 0x0A, // V0 = 10
+        0xF0, timer, // timer = V0
+        0x12, 0x04, // loop forever
+    ]
+}
+
+#[test]
+fn test_wasm_emulator_sound() {
+    let mut emulator = Emulator::new(0);
+    emulator.load_rom(&timer_rom(0x18)).unwrap();
+    emulator.run_frame().unwrap();
+    assert!(emulator.sound());
+
+    emulator.load_rom(&timer_rom(0x15)).unwrap();
+    emulator.run_frame().unwrap();
+    assert!(!emulator.sound());
+}
+
+#[test]
+fn test_wasm_emulator_errors() {
+    let mut emulator = Emulator::new(0);
+    assert_eq!(emulator.set_key(16, true).unwrap_err(), "Invalid key 16");
+    assert_eq!(
+        emulator.set_clock(30).unwrap_err(),
+        "The clock has to be at least 60 Hz"
+    );
+    assert_eq!(
+        emulator.set_quirks("warp").unwrap_err(),
+        "Unknown quirks 'warp'"
+    );
+    emulator.set_quirks("schip").unwrap();
+    assert!(emulator.load_rom(&vec![0; 0x10000]).is_err());
+
+    // Faults stick until the next reset
+    emulator.load_rom(&[0xFF, 0xFF]).unwrap();
+    let fault = "Unknown opcode FFFF at 0200".to_string();
+    assert_eq!(emulator.run_frame(), Err(fault.clone()));
+    assert_eq!(emulator.run_frame(), Err(fault.clone()));
+    emulator.reset();
+    assert_eq!(emulator.run_frame(), Err(fault));
+}
+
+// Builds the wasm module, or returns None if the target isn't installed
+fn build_wasm() -> Option<PathBuf> {
+    let sysroot = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .unwrap();
+    let sysroot = String::from_utf8(sysroot.stdout).unwrap();
+    let target = Path::new(sysroot.trim()).join("lib/rustlib/wasm32-unknown-unknown");
+    if !target.exists() {
+        return None;
+    }
+
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let output = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--lib", "--target", "wasm32-unknown-unknown"])
+        .args(["--no-default-features", "--features", "wasm"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(target_dir.join("wasm32-unknown-unknown/debug/emu_rs.wasm"))
+}
+
+// Runs the wasm build without JS and compares it to the native one
+#[test]
+fn test_wasm_runtime() {
+    let path = match build_wasm() {
+        Some(path) => path,
+        None => {
+            eprintln!("Skipped, the wasm32-unknown-unknown target isn't installed");
+            return;
+        }
+    };
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &std::fs::read(path).unwrap()[..]).unwrap();
+    let mut store = Store::new(&engine, ());
+
+    // wasm-bindgen's imports are only called from its own exports
+    let mut linker = Linker::new(&engine);
+    for import in module.imports() {
+        if let Some(ty) = import.ty().func() {
+            let name = format!("{}.{}", import.module(), import.name());
+            linker
+                .func_new(
+                    import.module(),
+                    import.name(),
+                    ty.clone(),
+                    move |_, _, _| Err(wasmi::Error::new(format!("{} called", name))),
+                )
+                .unwrap();
+        }
+    }
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let memory = instance.get_memory(&store, "memory").unwrap();
+
+    let rom_buffer = instance
+        .get_typed_func::<u32, u32>(&store, "chip8_rom_buffer")
+        .unwrap();
+    let load_rom = instance
+        .get_typed_func::<u32, i32>(&store, "chip8_load_rom")
+        .unwrap();
+    let set_key = instance
+        .get_typed_func::<(u32, i32), i32>(&store, "chip8_set_key")
+        .unwrap();
+    let run_frame = instance
+        .get_typed_func::<(), i32>(&store, "chip8_run_frame")
+        .unwrap();
+    let width = instance
+        .get_typed_func::<(), u32>(&store, "chip8_width")
+        .unwrap();
+    let height = instance
+        .get_typed_func::<(), u32>(&store, "chip8_height")
+        .unwrap();
+    let rgba = instance
+        .get_typed_func::<(), u32>(&store, "chip8_rgba")
+        .unwrap();
+
+    let rom = rom_buffer.call(&mut store, KEY_ROM.len() as u32).unwrap();
+    memory.write(&mut store, rom as usize, &KEY_ROM).unwrap();
+    assert_eq!(load_rom.call(&mut store, 5).unwrap(), 1);
+
+    let mut native = Emulator::new(5);
+    native.load_rom(&KEY_ROM).unwrap();
+
+    for (frame, key) in [None, Some(0xA), None, Some(3)].iter().enumerate() {
+        if let Some(key) = *key {
+            assert_eq!(set_key.call(&mut store, (key, 1)).unwrap(), 1);
+            native.set_key(key as u8, true).unwrap();
+        }
+        assert_eq!(run_frame.call(&mut store, ()).unwrap(), 1);
+        native.run_frame().unwrap();
+
+        let size = (
+            width.call(&mut store, ()).unwrap(),
+            height.call(&mut store, ()).unwrap(),
+        );
+        assert_eq!(size, (128, 64));
+        let mut image = vec![0; 128 * 64 * 4];
+        let pointer = rgba.call(&mut store, ()).unwrap();
+        memory.read(&store, pointer as usize, &mut image).unwrap();
+        assert_eq!(image, native.rgba(), "frame {}", frame);
+    }
+    assert_eq!(set_key.call(&mut store, (16, 1)).unwrap(), 0);
+}